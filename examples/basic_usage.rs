@@ -32,6 +32,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         include_polyfills: vec![],
         exclude_dependencies: vec![],
         bundle_strategy: pakto::cli::BundleStrategy::Inline,
+        format: pakto::cli::OutputFormat::OutSystems,
+        defines: Default::default(),
+        exposed_packages: vec![],
+        report: false,
+        reproducible: false,
     };
 
     // Convert a simple package
@@ -0,0 +1,27 @@
+/**
+ * Context modules for dynamic requires
+ * Resolves a request built at runtime, such as './locale/' + lang, against
+ * the map of files the bundler found for its static prefix
+ */
+(function() {
+    'use strict';
+
+    var EXTENSIONS = ['', '.js', '.json', '.mjs', '.cjs', '/index.js', '/index.json'];
+
+    function RequireContext(map, request) {
+        for (var i = 0; i < EXTENSIONS.length; i++) {
+            var key = request + EXTENSIONS[i];
+            if (Object.prototype.hasOwnProperty.call(map, key)) {
+                return map[key]();
+            }
+        }
+
+        var error = new Error("Cannot find module '" + request + "'");
+        error.code = 'MODULE_NOT_FOUND';
+        throw error;
+    }
+
+    // Export polyfill
+    window.RequireContextPolyfill = RequireContext;
+
+})();
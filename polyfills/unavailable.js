@@ -0,0 +1,33 @@
+/**
+ * Stand-in for Node.js modules that cannot run in OutSystems
+ * Every known method throws a descriptive error when called and any other
+ * property reads as undefined, so code that merely requires the module, or
+ * reads constants from it at load, still loads. Optional modules throw as
+ * soon as they are required instead, like a module that is not installed
+ */
+(function() {
+    'use strict';
+
+    function UnavailableModule(moduleName, methods) {
+        var stub = {};
+
+        methods.forEach(function(method) {
+            stub[method] = function() {
+                throw new Error(moduleName + '.' + method + ' is not available in OutSystems');
+            };
+        });
+
+        return stub;
+    }
+
+    function MissingModule(moduleName) {
+        var error = new Error("Cannot find module '" + moduleName + "'");
+        error.code = 'MODULE_NOT_FOUND';
+        throw error;
+    }
+
+    // Export polyfill
+    window.UnavailableModulePolyfill = UnavailableModule;
+    window.MissingModulePolyfill = MissingModule;
+
+})();
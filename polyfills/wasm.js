@@ -0,0 +1,84 @@
+/**
+ * WebAssembly loader for browser environments
+ * Serves .wasm files that the bundle embeds as base64 or publishes as
+ * separate resources, and instantiates them asynchronously
+ */
+(function() {
+    'use strict';
+
+    // Filled in by the bundle, which may run after this polyfill
+    function lookup(name) {
+        var source = (window.__paktoWasmModules || {})[name];
+        if (!source) {
+            throw new Error('WebAssembly module ' + name + ' is not part of the bundle');
+        }
+        return source;
+    }
+
+    function decode(base64) {
+        var binary = atob(base64);
+        var bytes = new Uint8Array(binary.length);
+        for (var i = 0; i < binary.length; i++) {
+            bytes[i] = binary.charCodeAt(i);
+        }
+        return bytes;
+    }
+
+    var WasmLoader = {
+        // Embedded bytes, available synchronously
+        bytes: function(name) {
+            var source = lookup(name);
+            if (source.base64 === undefined) {
+                throw new Error('WebAssembly module ' + name + ' is published as ' + source.url +
+                    ' and must be loaded asynchronously');
+            }
+            if (!source.bytes) {
+                source.bytes = decode(source.base64);
+            }
+            return source.bytes;
+        },
+
+        load: function(name) {
+            var source;
+            try {
+                source = lookup(name);
+            } catch (error) {
+                return Promise.reject(error);
+            }
+
+            if (source.base64 !== undefined) {
+                return Promise.resolve(WasmLoader.bytes(name));
+            }
+
+            return fetch(source.url).then(function(response) {
+                if (!response.ok) {
+                    throw new Error('Failed to load WebAssembly module ' + source.url + ': ' + response.status);
+                }
+                return response.arrayBuffer();
+            });
+        },
+
+        instantiate: function(name, imports) {
+            return WasmLoader.load(name).then(function(bytes) {
+                return WebAssembly.instantiate(bytes, imports);
+            });
+        },
+
+        compile: function(name) {
+            return WasmLoader.load(name).then(function(bytes) {
+                return WebAssembly.compile(bytes);
+            });
+        },
+
+        // Stand-in for fetch() of the module file
+        response: function(name) {
+            return WasmLoader.load(name).then(function(bytes) {
+                return new Response(bytes, { headers: { 'Content-Type': 'application/wasm' } });
+            });
+        }
+    };
+
+    // Export polyfill
+    window.WasmPolyfill = WasmLoader;
+
+})();
@@ -1,12 +1,16 @@
-use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 use anyhow::{Context, Result};
+use rayon::prelude::*;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use tracing::{debug, warn, info};
 use swc_ecma_parser::{lexer::Lexer, Parser, StringInput, Syntax, TsConfig, EsConfig};
 use swc_ecma_ast::*;
 use swc_ecma_visit::{Visit, VisitWith};
 
+use crate::cache::{BuildCache, CacheKey};
 use crate::config::Config;
 use crate::converter::{
     PackageData, AnalysisResult, PackageInfo, DependencyAnalysis,
@@ -14,15 +18,27 @@ use crate::converter::{
 };
 use crate::errors::{PaktoError, Result as PaktoResult, CodeLocation};
 
+/// `require('x')` calls, for files that do not parse
+static REQUIRE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"require\s*\(\s*['"`]([^'"`]+)['"`]\s*\)"#).expect("valid require pattern")
+});
+
+/// `import ... from 'x'` statements, for files that do not parse
+static IMPORT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"import\s+.*?\s+from\s+['"`]([^'"`]+)['"`]"#).expect("valid import pattern")
+});
+
 /// Analyzes packages for OutSystems compatibility
 pub struct PackageAnalyzer {
     config: Config,
     node_apis: NodeApiRegistry,
+    cache: BuildCache,
 }
 
 /// Registry of Node.js APIs and their browser compatibility
-struct NodeApiRegistry {
+pub(crate) struct NodeApiRegistry {
     incompatible_apis: HashSet<String>,
+    unavailable_methods: HashMap<String, Vec<String>>,
     polyfillable_apis: HashMap<String, String>,
     replaceable_apis: HashMap<String, String>,
 }
@@ -34,10 +50,18 @@ struct CompatibilityVisitor {
     imports: Vec<String>,
     exports: Vec<String>,
     current_file: String,
+    wasm_reported: bool,
+    /// Modules listed in `optionalDependencies`
+    optional_modules: HashSet<String>,
+    /// Number of `try` blocks around the current node
+    try_depth: usize,
+    /// Number of `typeof require` / `typeof process` / `typeof window`
+    /// checks guarding the current node
+    environment_guard_depth: usize,
 }
 
 /// Analysis of a single file
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct FileAnalysis {
     path: String,
     syntax_type: SyntaxType,
@@ -49,7 +73,7 @@ struct FileAnalysis {
     estimated_size: usize,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 enum SyntaxType {
     JavaScript,
     TypeScript,
@@ -57,7 +81,7 @@ enum SyntaxType {
     Tsx,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 enum ModuleType {
     CommonJs,
     EsModules,
@@ -66,7 +90,7 @@ enum ModuleType {
     Unknown,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct ImportInfo {
     source: String,
     specifiers: Vec<String>,
@@ -74,21 +98,21 @@ struct ImportInfo {
     location: Option<CodeLocation>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct ExportInfo {
     name: Option<String>,
     is_default: bool,
     location: Option<CodeLocation>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct NodeApiUsage {
     api: String,
     usage_type: ApiUsageType,
     location: Option<CodeLocation>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 enum ApiUsageType {
     DirectCall,
     RequireStatement,
@@ -101,6 +125,7 @@ impl PackageAnalyzer {
         Self {
             config: config.clone(),
             node_apis: NodeApiRegistry::new(),
+            cache: BuildCache::new(&config.cache),
         }
     }
 
@@ -109,36 +134,70 @@ impl PackageAnalyzer {
 
         // Parse package.json
         let package_info = self.parse_package_info(&package_data.package_json)?;
+        let optional_modules: HashSet<String> = package_info.optional_dependencies.keys().cloned().collect();
 
         // Analyze all files
         let mut file_analyses = Vec::new();
         let mut all_issues = Vec::new();
-        let mut required_polyfills = HashSet::new();
+        let mut required_polyfills = BTreeSet::new();
+
+        // Optional dependencies are never bundled, requiring one throws
+        let mut optional_names: Vec<&String> = optional_modules.iter().collect();
+        optional_names.sort();
+        for name in &optional_names {
+            all_issues.push(CompatibilityIssue {
+                level: IssueLevel::Info,
+                message: format!("Optional dependency: {}", name),
+                location: Some(CodeLocation::new("package.json")),
+                suggestion: Some("Optional dependencies are not bundled; requiring one throws".to_string()),
+                api: None,
+            });
+        }
 
-        for (path, content) in &package_data.files {
-            if self.should_analyze_file(path) {
+        // Analyze files in parallel, then collect the results in path
+        // order so issues are reported the same way on every run
+        let mut paths: Vec<&PathBuf> = package_data.files.keys()
+            .filter(|path| self.should_analyze_file(path))
+            .collect();
+        paths.sort();
+        let analyses: Vec<(&PathBuf, Result<FileAnalysis>)> = paths.into_par_iter()
+            .map(|path| {
                 debug!("Analyzing file: {}", path.display());
-
-                match self.analyze_file(path, content).await {
-                    Ok(analysis) => {
-                        all_issues.extend(analysis.issues.clone());
-                        for usage in &analysis.node_api_usage {
-                            if let Some(polyfill) = self.node_apis.get_polyfill(&usage.api) {
-                                required_polyfills.insert(polyfill);
-                            }
+                let content = String::from_utf8_lossy(package_data.files[path].as_bytes());
+
+                let key = CacheKey::new("analysis")
+                    .with(path.to_string_lossy().as_bytes())
+                    .with(content.as_bytes())
+                    .with_json(&optional_names);
+                let analysis = match self.cache.get::<FileAnalysis>(&key) {
+                    Some(analysis) => Ok(analysis),
+                    None => self.analyze_file(path, &content, &optional_modules)
+                        .inspect(|analysis| self.cache.put(&key, analysis)),
+                };
+                (path, analysis)
+            })
+            .collect();
+
+        for (path, analysis) in analyses {
+            match analysis {
+                Ok(analysis) => {
+                    all_issues.extend(analysis.issues.clone());
+                    for usage in &analysis.node_api_usage {
+                        if let Some(polyfill) = self.node_apis.get_polyfill(&usage.api) {
+                            required_polyfills.insert(polyfill);
                         }
-                        file_analyses.push(analysis);
-                    }
-                    Err(e) => {
-                        warn!("Failed to analyze file {}: {}", path.display(), e);
-                        all_issues.push(CompatibilityIssue {
-                            level: IssueLevel::Warning,
-                            message: format!("Failed to parse file: {}", e),
-                            location: Some(CodeLocation::new(path)),
-                            suggestion: Some("File may contain syntax errors or unsupported features".to_string()),
-                            api: None,
-                        });
                     }
+                    file_analyses.push(analysis);
+                }
+                Err(e) => {
+                    warn!("Failed to analyze file {}: {}", path.display(), e);
+                    all_issues.push(CompatibilityIssue {
+                        level: IssueLevel::Warning,
+                        message: format!("Failed to parse file: {}", e),
+                        location: Some(CodeLocation::new(path)),
+                        suggestion: Some("File may contain syntax errors or unsupported features".to_string()),
+                        api: None,
+                    });
                 }
             }
         }
@@ -208,6 +267,15 @@ impl PackageAnalyzer {
             })
             .unwrap_or_default();
 
+        let optional_dependencies = package_json.get("optionalDependencies")
+            .and_then(|v| v.as_object())
+            .map(|obj| {
+                obj.iter()
+                    .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string())))
+                    .collect()
+            })
+            .unwrap_or_default();
+
         let keywords = package_json.get("keywords")
             .and_then(|v| v.as_array())
             .map(|arr| {
@@ -261,18 +329,20 @@ impl PackageAnalyzer {
             entry_points,
             dependencies,
             dev_dependencies,
+            optional_dependencies,
             keywords,
             license,
         })
     }
 
     /// Analyze a single file
-    async fn analyze_file(&self, path: &Path, content: &str) -> Result<FileAnalysis> {
+    fn analyze_file(&self, path: &Path, content: &str, optional_modules: &HashSet<String>) -> Result<FileAnalysis> {
         let syntax_type = self.detect_syntax_type(path, content);
         let module_type = self.detect_module_type(content);
 
         // Parse the file
-        let mut visitor = CompatibilityVisitor::new(path.to_string_lossy().to_string());
+        let mut visitor = CompatibilityVisitor::new(path.to_string_lossy().to_string())
+            .with_optional_modules(optional_modules.clone());
 
         match self.parse_and_visit(content, &syntax_type, &mut visitor) {
             Ok(_) => {
@@ -350,8 +420,7 @@ impl PackageAnalyzer {
         let mut node_api_usage = Vec::new();
 
         // Check for require() calls
-        let require_regex = Regex::new(r#"require\s*\(\s*['"`]([^'"`]+)['"`]\s*\)"#)?;
-        for cap in require_regex.captures_iter(content) {
+        for cap in REQUIRE_REGEX.captures_iter(content) {
             let module_name = &cap[1];
             imports.push(module_name.to_string());
 
@@ -375,8 +444,7 @@ impl PackageAnalyzer {
         }
 
         // Check for import statements
-        let import_regex = Regex::new(r#"import\s+.*?\s+from\s+['"`]([^'"`]+)['"`]"#)?;
-        for cap in import_regex.captures_iter(content) {
+        for cap in IMPORT_REGEX.captures_iter(content) {
             let module_name = &cap[1];
             imports.push(module_name.to_string());
         }
@@ -496,7 +564,14 @@ impl PackageAnalyzer {
         let mut browser_compatible = Vec::new();
         let mut needs_polyfills = Vec::new();
 
-        for (dep_name, _version) in &package_info.dependencies {
+        let mut dependencies: Vec<&String> = package_info.dependencies.keys().collect();
+        dependencies.sort();
+        for dep_name in dependencies {
+            // The package works without optional dependencies
+            if package_info.optional_dependencies.contains_key(dep_name) {
+                continue;
+            }
+
             if self.is_problematic_dependency(dep_name) {
                 problematic_dependencies.push(dep_name.clone());
             } else if self.is_browser_compatible(dep_name) {
@@ -519,7 +594,7 @@ impl PackageAnalyzer {
     }
 
     /// Calculate estimated bundle sizes
-    fn calculate_estimated_sizes(&self, file_analyses: &[FileAnalysis], polyfills: &HashSet<String>) -> EstimatedSize {
+    fn calculate_estimated_sizes(&self, file_analyses: &[FileAnalysis], polyfills: &BTreeSet<String>) -> EstimatedSize {
         let base_size: usize = file_analyses.iter().map(|f| f.estimated_size).sum();
         let polyfill_size: usize = polyfills.len() * 2048; // Estimate 2KB per polyfill
 
@@ -583,8 +658,9 @@ impl PackageAnalyzer {
 }
 
 impl NodeApiRegistry {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         let mut incompatible_apis = HashSet::new();
+        let mut unavailable_methods = HashMap::new();
         let mut polyfillable_apis = HashMap::new();
         let mut replaceable_apis = HashMap::new();
 
@@ -593,6 +669,38 @@ impl NodeApiRegistry {
             incompatible_apis.insert(api.to_string());
         }
 
+        // Methods of incompatible APIs that are stubbed to throw when called
+        let methods: [(&str, &[&str]); 8] = [
+            ("fs", &[
+                "access", "accessSync", "appendFile", "appendFileSync", "chmod", "chmodSync",
+                "close", "closeSync", "copyFile", "copyFileSync", "createReadStream",
+                "createWriteStream", "exists", "existsSync", "lstat", "lstatSync", "mkdir",
+                "mkdirSync", "open", "openSync", "readFile", "readFileSync", "readdir",
+                "readdirSync", "realpath", "realpathSync", "rename", "renameSync", "rm",
+                "rmSync", "rmdir", "rmdirSync", "stat", "statSync", "unlink", "unlinkSync",
+                "watch", "watchFile", "writeFile", "writeFileSync",
+            ]),
+            ("child_process", &[
+                "exec", "execFile", "execFileSync", "execSync", "fork", "spawn", "spawnSync",
+            ]),
+            ("cluster", &["disconnect", "fork", "setupPrimary", "setupMaster"]),
+            ("worker_threads", &["Worker", "MessageChannel", "receiveMessageOnPort"]),
+            ("os", &[
+                "arch", "cpus", "endianness", "freemem", "homedir", "hostname", "loadavg",
+                "networkInterfaces", "platform", "release", "tmpdir", "totalmem", "type",
+                "uptime", "userInfo",
+            ]),
+            ("net", &["connect", "createConnection", "createServer", "Server", "Socket"]),
+            ("http", &["Agent", "createServer", "get", "request"]),
+            ("https", &["Agent", "createServer", "get", "request"]),
+        ];
+        for (api, names) in methods {
+            unavailable_methods.insert(
+                api.to_string(),
+                names.iter().map(|name| name.to_string()).collect(),
+            );
+        }
+
         // Polyfillable APIs
         polyfillable_apis.insert("crypto".to_string(), "crypto".to_string());
         polyfillable_apis.insert("buffer".to_string(), "buffer".to_string());
@@ -607,19 +715,25 @@ impl NodeApiRegistry {
 
         Self {
             incompatible_apis,
+            unavailable_methods,
             polyfillable_apis,
             replaceable_apis,
         }
     }
 
-    fn is_node_api(&self, api: &str) -> bool {
+    pub(crate) fn is_node_api(&self, api: &str) -> bool {
         self.incompatible_apis.contains(api) || self.polyfillable_apis.contains_key(api)
     }
 
-    fn is_incompatible(&self, api: &str) -> bool {
+    pub(crate) fn is_incompatible(&self, api: &str) -> bool {
         self.incompatible_apis.contains(api)
     }
 
+    /// Methods of an incompatible API that a stand-in module should expose
+    pub(crate) fn unavailable_methods(&self, api: &str) -> &[String] {
+        self.unavailable_methods.get(api).map(Vec::as_slice).unwrap_or(&[])
+    }
+
     fn get_polyfill(&self, api: &str) -> Option<String> {
         self.polyfillable_apis.get(api).cloned()
     }
@@ -637,7 +751,81 @@ impl CompatibilityVisitor {
             imports: Vec::new(),
             exports: Vec::new(),
             current_file: file_path,
+            wasm_reported: false,
+            optional_modules: HashSet::new(),
+            try_depth: 0,
+            environment_guard_depth: 0,
+        }
+    }
+
+    fn with_optional_modules(mut self, optional_modules: HashSet<String>) -> Self {
+        self.optional_modules = optional_modules;
+        self
+    }
+
+    /// Whether a require of a module may fail without breaking the package:
+    /// it is guarded by try/catch or an environment check, or the module is
+    /// an optional dependency
+    fn is_optional_require(&self, module_name: &str) -> bool {
+        self.try_depth > 0
+            || self.environment_guard_depth > 0
+            || self.optional_modules.contains(module_name)
+    }
+
+    /// Visit a node guarded by an environment check
+    fn visit_guarded<N: VisitWith<Self>>(&mut self, node: &N) {
+        self.environment_guard_depth += 1;
+        node.visit_with(self);
+        self.environment_guard_depth -= 1;
+    }
+
+    /// Note WebAssembly usage once per file
+    fn report_wasm_usage(&mut self, detail: String) {
+        if self.wasm_reported {
+            return;
         }
+        self.wasm_reported = true;
+        self.issues.push(CompatibilityIssue {
+            level: IssueLevel::Info,
+            message: format!("WebAssembly usage: {}", detail),
+            location: Some(CodeLocation::new(&self.current_file)),
+            suggestion: Some("WebAssembly modules are embedded in the bundle or emitted next to it, see bundle.wasm".to_string()),
+            api: None,
+        });
+    }
+}
+
+/// Whether an expression checks the environment with `typeof require`,
+/// `typeof process` or `typeof window`
+pub(crate) fn is_environment_check(expr: &Expr) -> bool {
+    struct TypeofFinder {
+        found: bool,
+    }
+
+    impl Visit for TypeofFinder {
+        fn visit_unary_expr(&mut self, unary: &UnaryExpr) {
+            if unary.op == UnaryOp::TypeOf
+                && matches!(unary.arg.as_ref(), Expr::Ident(ident) if matches!(ident.sym.as_ref(), "require" | "process" | "window"))
+            {
+                self.found = true;
+            }
+            unary.visit_children_with(self);
+        }
+    }
+
+    let mut finder = TypeofFinder { found: false };
+    expr.visit_with(&mut finder);
+    finder.found
+}
+
+/// Name of a `WebAssembly.*` member
+fn webassembly_member(member: &MemberExpr) -> Option<&str> {
+    if !matches!(member.obj.as_ref(), Expr::Ident(obj) if obj.sym == *"WebAssembly") {
+        return None;
+    }
+    match &member.prop {
+        MemberProp::Ident(prop) => Some(prop.sym.as_ref()),
+        _ => None,
     }
 }
 
@@ -653,17 +841,28 @@ impl Visit for CompatibilityVisitor {
 
                         // Check if it's a Node.js API
                         if matches!(module_name.as_str(), "fs" | "crypto" | "child_process" | "os") {
-                            self.issues.push(CompatibilityIssue {
-                                level: if matches!(module_name.as_str(), "fs" | "child_process") {
-                                    IssueLevel::Error
-                                } else {
-                                    IssueLevel::Warning
-                                },
-                                message: format!("Node.js API usage: {}", module_name),
-                                location: Some(CodeLocation::new(&self.current_file)),
-                                suggestion: Some("Consider using browser-compatible alternatives".to_string()),
-                                api: Some(module_name),
-                            });
+                            let issue = if self.is_optional_require(&module_name) {
+                                CompatibilityIssue {
+                                    level: IssueLevel::Info,
+                                    message: format!("Optional Node.js API usage: {}", module_name),
+                                    location: Some(CodeLocation::new(&self.current_file)),
+                                    suggestion: Some("Guarded require is replaced with a module that throws when required".to_string()),
+                                    api: Some(module_name),
+                                }
+                            } else {
+                                CompatibilityIssue {
+                                    level: if matches!(module_name.as_str(), "fs" | "child_process") {
+                                        IssueLevel::Error
+                                    } else {
+                                        IssueLevel::Warning
+                                    },
+                                    message: format!("Node.js API usage: {}", module_name),
+                                    location: Some(CodeLocation::new(&self.current_file)),
+                                    suggestion: Some("Consider using browser-compatible alternatives".to_string()),
+                                    api: Some(module_name),
+                                }
+                            };
+                            self.issues.push(issue);
                         }
                     }
                 }
@@ -680,7 +879,9 @@ impl Visit for CompatibilityVisitor {
         // Check for Node.js API imports
         if matches!(source.as_str(), "fs" | "crypto" | "child_process" | "os") {
             self.issues.push(CompatibilityIssue {
-                level: if matches!(source.as_str(), "fs" | "child_process") {
+                level: if self.optional_modules.contains(&source) {
+                    IssueLevel::Info
+                } else if matches!(source.as_str(), "fs" | "child_process") {
                     IssueLevel::Error
                 } else {
                     IssueLevel::Warning
@@ -695,6 +896,82 @@ impl Visit for CompatibilityVisitor {
         import.visit_children_with(self);
     }
 
+    fn visit_try_stmt(&mut self, stmt: &TryStmt) {
+        self.try_depth += 1;
+        stmt.block.visit_with(self);
+        self.try_depth -= 1;
+
+        stmt.handler.visit_with(self);
+        stmt.finalizer.visit_with(self);
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &IfStmt) {
+        let guarded = is_environment_check(&stmt.test);
+        if guarded {
+            self.environment_guard_depth += 1;
+        }
+        stmt.visit_children_with(self);
+        if guarded {
+            self.environment_guard_depth -= 1;
+        }
+    }
+
+    fn visit_cond_expr(&mut self, cond: &CondExpr) {
+        if is_environment_check(&cond.test) {
+            self.visit_guarded(&cond.cons);
+            self.visit_guarded(&cond.alt);
+            return;
+        }
+        cond.visit_children_with(self);
+    }
+
+    fn visit_bin_expr(&mut self, bin: &BinExpr) {
+        // typeof require === 'function' && require('x')
+        if matches!(bin.op, BinaryOp::LogicalAnd | BinaryOp::LogicalOr) && is_environment_check(&bin.left) {
+            bin.left.visit_with(self);
+            self.visit_guarded(&bin.right);
+            return;
+        }
+        bin.visit_children_with(self);
+    }
+
+    fn visit_new_expr(&mut self, new: &NewExpr) {
+        // Browsers refuse to compile large modules synchronously on the
+        // main thread
+        let constructor = match new.callee.as_ref() {
+            Expr::Member(member) => webassembly_member(member),
+            _ => None,
+        };
+        if let Some(constructor @ ("Module" | "Instance")) = constructor {
+            self.issues.push(CompatibilityIssue {
+                level: IssueLevel::Warning,
+                message: format!("Synchronous WebAssembly instantiation: new WebAssembly.{}", constructor),
+                location: Some(CodeLocation::new(&self.current_file)),
+                suggestion: Some(
+                    "Browsers limit synchronous compilation on the main thread to 4KB modules; \
+                     use WebAssembly.instantiate instead".to_string()
+                ),
+                api: None,
+            });
+        }
+
+        new.visit_children_with(self);
+    }
+
+    fn visit_member_expr(&mut self, member: &MemberExpr) {
+        if let Some(name) = webassembly_member(member) {
+            self.report_wasm_usage(format!("WebAssembly.{}", name));
+        }
+
+        member.visit_children_with(self);
+    }
+
+    fn visit_str(&mut self, s: &Str) {
+        if s.value.to_lowercase().ends_with(".wasm") {
+            self.report_wasm_usage(format!("loads {}", s.value));
+        }
+    }
+
     fn visit_export_decl(&mut self, export: &ExportDecl) {
         match &export.decl {
             Decl::Fn(fn_decl) => {
@@ -741,6 +1018,47 @@ mod tests {
         assert_eq!(analyzer.detect_module_type("(function (global, factory)"), ModuleType::Umd);
     }
 
+    #[test]
+    fn test_flags_webassembly_usage() {
+        let config = Config::default();
+        let analyzer = PackageAnalyzer::new(&config);
+        let code = "const bytes = fs.readFileSync(__dirname + '/core.wasm');\n\
+                    const instance = new WebAssembly.Instance(new WebAssembly.Module(bytes), {});";
+
+        let mut visitor = CompatibilityVisitor::new("index.js".to_string());
+        analyzer.parse_and_visit(code, &SyntaxType::JavaScript, &mut visitor).unwrap();
+
+        let info: Vec<_> = visitor.issues.iter().filter(|i| i.level == IssueLevel::Info).collect();
+        assert_eq!(info.len(), 1);
+        assert!(info[0].message.contains("core.wasm"));
+
+        let warnings: Vec<_> = visitor.issues.iter().filter(|i| i.level == IssueLevel::Warning).collect();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings.iter().any(|w| w.message.contains("new WebAssembly.Module")));
+    }
+
+    #[test]
+    fn test_guarded_requires_are_info() {
+        let config = Config::default();
+        let analyzer = PackageAnalyzer::new(&config);
+        let code = "try { require('fs'); } catch (e) {}\n\
+                    if (typeof process !== 'undefined') { require('child_process'); }\n\
+                    var os = typeof require === 'function' && require('os');\n\
+                    var hash = typeof window === 'undefined' ? require('crypto') : null;\n\
+                    require('fs');";
+
+        let mut visitor = CompatibilityVisitor::new("index.js".to_string());
+        analyzer.parse_and_visit(code, &SyntaxType::JavaScript, &mut visitor).unwrap();
+
+        let levels: Vec<_> = visitor.issues.iter().map(|i| i.level.clone()).collect();
+        assert_eq!(levels, vec![IssueLevel::Info, IssueLevel::Info, IssueLevel::Info, IssueLevel::Info, IssueLevel::Error]);
+
+        let mut visitor = CompatibilityVisitor::new("index.js".to_string())
+            .with_optional_modules(["fs".to_string()].into_iter().collect());
+        analyzer.parse_and_visit("require('fs');", &SyntaxType::JavaScript, &mut visitor).unwrap();
+        assert_eq!(visitor.issues[0].level, IssueLevel::Info);
+    }
+
     #[test]
     fn test_node_api_registry() {
         let registry = NodeApiRegistry::new();
@@ -751,5 +1069,7 @@ mod tests {
         assert!(!registry.is_incompatible("crypto"));
         assert!(registry.get_polyfill("crypto").is_some());
         assert!(registry.get_polyfill("fs").is_none());
+        assert!(registry.unavailable_methods("fs").iter().any(|m| m == "readFile"));
+        assert!(registry.unavailable_methods("crypto").is_empty());
     }
 }
\ No newline at end of file
@@ -0,0 +1,357 @@
+//! JSON, text and binary asset modules
+//!
+//! Files that are not JavaScript enter the bundle when code imports them.
+//! The loader chosen by file extension turns each one into a JavaScript
+//! value: JSON is parsed, text becomes a string and binary files become
+//! base64 data URLs. Stylesheets and WebAssembly modules are collected
+//! instead, so the bundler can deliver them. Extensions can be remapped
+//! through the `[loaders]` table in `pakto.toml`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use swc_ecma_ast::*;
+use swc_ecma_parser::Syntax;
+use swc_ecma_visit::{Visit, VisitWith};
+
+use crate::converter::FileContent;
+use crate::fs_inline::normalize_path;
+use crate::js::{self, SourceEdits};
+use crate::wasm;
+
+/// How an imported non-JavaScript file becomes a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetLoader {
+    /// Parsed JSON value
+    Json,
+    /// File contents as a string
+    Text,
+    /// Base64 `data:` URL
+    DataUrl,
+    /// Stylesheet imported for its side effect, delivered by the bundler
+    Css,
+    /// WebAssembly bytes, embedded or published by the bundler
+    Wasm,
+}
+
+/// Loaders used when the configuration does not override an extension
+const DEFAULT_LOADERS: &[(&str, AssetLoader)] = &[
+    ("json", AssetLoader::Json),
+    ("css", AssetLoader::Css),
+    ("txt", AssetLoader::Text),
+    ("md", AssetLoader::Text),
+    ("html", AssetLoader::Text),
+    ("svg", AssetLoader::DataUrl),
+    ("png", AssetLoader::DataUrl),
+    ("jpg", AssetLoader::DataUrl),
+    ("jpeg", AssetLoader::DataUrl),
+    ("gif", AssetLoader::DataUrl),
+    ("webp", AssetLoader::DataUrl),
+    ("ico", AssetLoader::DataUrl),
+    ("woff", AssetLoader::DataUrl),
+    ("woff2", AssetLoader::DataUrl),
+    ("ttf", AssetLoader::DataUrl),
+    ("otf", AssetLoader::DataUrl),
+    ("eot", AssetLoader::DataUrl),
+    ("wasm", AssetLoader::Wasm),
+];
+
+/// Loader for a file, checking configured overrides before the defaults.
+/// Override keys may be written with or without the leading dot.
+pub fn loader_for(path: &Path, overrides: &HashMap<String, AssetLoader>) -> Option<AssetLoader> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+
+    overrides.iter()
+        .find(|(key, _)| key.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+        .map(|(_, loader)| *loader)
+        .or_else(|| {
+            DEFAULT_LOADERS.iter()
+                .find(|(ext, _)| *ext == extension)
+                .map(|(_, loader)| *loader)
+        })
+}
+
+/// MIME type used in data URLs
+fn mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("eot") => "application/vnd.ms-fontobject",
+        Some("json") => "application/json",
+        Some("txt") | Some("md") => "text/plain",
+        Some("html") => "text/html",
+        Some("css") => "text/css",
+        Some("wasm") => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+/// JavaScript expression for an asset
+pub fn asset_expression(loader: AssetLoader, path: &Path, content: &FileContent) -> Result<String> {
+    match loader {
+        AssetLoader::Json => {
+            let text = String::from_utf8_lossy(content.as_bytes());
+            let value: serde_json::Value = serde_json::from_str(text.trim_start_matches('\u{feff}'))
+                .with_context(|| format!("Invalid JSON in {}", path.display()))?;
+            Ok(format!("({})", value))
+        }
+        AssetLoader::Text => {
+            let text = String::from_utf8_lossy(content.as_bytes());
+            Ok(serde_json::Value::String(text.into_owned()).to_string())
+        }
+        AssetLoader::DataUrl => {
+            let url = format!("data:{};base64,{}", mime_type(path), BASE64_STANDARD.encode(content.as_bytes()));
+            Ok(serde_json::Value::String(url).to_string())
+        }
+        // Stylesheets have no value of their own
+        AssetLoader::Css => Ok("{}".to_string()),
+        AssetLoader::Wasm => Ok(wasm::bytes_expression(path)),
+    }
+}
+
+/// Resolve a relative import specifier to a package file path
+pub fn resolve_relative(module_path: &Path, specifier: &str) -> Option<PathBuf> {
+    if !specifier.starts_with("./") && !specifier.starts_with("../") {
+        return None;
+    }
+
+    let directory = module_path.parent()
+        .map(|p| p.to_string_lossy().replace('\\', "/"))
+        .unwrap_or_default();
+    let joined = normalize_path(&format!("{}/{}", directory, specifier));
+    Some(PathBuf::from(joined.trim_start_matches('/')))
+}
+
+/// A module with its asset imports replaced by values
+#[derive(Debug)]
+pub struct InlinedAssets {
+    pub code: String,
+    /// Asset files that were inlined
+    pub assets: Vec<PathBuf>,
+    /// Stylesheets imported by the module, in import order
+    pub styles: Vec<PathBuf>,
+    /// WebAssembly files imported by the module
+    pub wasm_modules: Vec<PathBuf>,
+}
+
+/// Replace `require()`, `import` and `import()` of asset files with the
+/// values their loaders produce
+pub fn inline_asset_imports(
+    code: &str,
+    syntax: Syntax,
+    module_path: &Path,
+    files: &HashMap<PathBuf, FileContent>,
+    overrides: &HashMap<String, AssetLoader>,
+) -> Result<InlinedAssets> {
+    let program = js::parse_program(code, syntax)?;
+
+    let mut inliner = AssetInliner {
+        module_path,
+        files,
+        overrides,
+        edits: SourceEdits::new(),
+        assets: Vec::new(),
+        styles: Vec::new(),
+        wasm_modules: Vec::new(),
+        error: None,
+    };
+    program.visit_with(&mut inliner);
+
+    if let Some(e) = inliner.error {
+        return Err(e);
+    }
+
+    inliner.assets.sort();
+    inliner.assets.dedup();
+    inliner.wasm_modules.sort();
+    inliner.wasm_modules.dedup();
+
+    Ok(InlinedAssets {
+        code: inliner.edits.apply(code),
+        assets: inliner.assets,
+        styles: inliner.styles,
+        wasm_modules: inliner.wasm_modules,
+    })
+}
+
+/// Visitor that swaps asset imports for their values
+struct AssetInliner<'a> {
+    module_path: &'a Path,
+    files: &'a HashMap<PathBuf, FileContent>,
+    overrides: &'a HashMap<String, AssetLoader>,
+    edits: SourceEdits,
+    assets: Vec<PathBuf>,
+    styles: Vec<PathBuf>,
+    wasm_modules: Vec<PathBuf>,
+    error: Option<anyhow::Error>,
+}
+
+impl AssetInliner<'_> {
+    /// Value expression for an asset specifier, if it names a package
+    /// file with a loader
+    fn asset_value(&mut self, specifier: &str) -> Option<String> {
+        let path = resolve_relative(self.module_path, specifier)?;
+        let loader = loader_for(&path, self.overrides)?;
+        let content = self.files.get(&path)?;
+
+        if loader == AssetLoader::Css && !self.styles.contains(&path) {
+            self.styles.push(path.clone());
+        }
+        if loader == AssetLoader::Wasm {
+            self.wasm_modules.push(path.clone());
+        }
+
+        match asset_expression(loader, &path, content) {
+            Ok(expression) => {
+                self.assets.push(path);
+                Some(expression)
+            }
+            Err(e) => {
+                self.error.get_or_insert(e);
+                None
+            }
+        }
+    }
+}
+
+/// String argument of a single-argument call
+fn string_argument(call: &CallExpr) -> Option<&str> {
+    match call.args.first().map(|arg| arg.expr.as_ref()) {
+        Some(Expr::Lit(Lit::Str(s))) => Some(s.value.as_ref()),
+        _ => None,
+    }
+}
+
+impl Visit for AssetInliner<'_> {
+    fn visit_call_expr(&mut self, call: &CallExpr) {
+        let replacement = match &call.callee {
+            Callee::Expr(callee) if matches!(callee.as_ref(), Expr::Ident(ident) if ident.sym == *"require") => {
+                string_argument(call)
+                    .map(str::to_string)
+                    .and_then(|specifier| self.asset_value(&specifier))
+            }
+            Callee::Import(_) => string_argument(call)
+                .map(str::to_string)
+                .and_then(|specifier| self.asset_value(&specifier))
+                .map(|value| format!("Promise.resolve({{ default: {} }})", value)),
+            _ => None,
+        };
+
+        match replacement {
+            Some(value) => self.edits.replace(call.span, value),
+            None => call.visit_children_with(self),
+        }
+    }
+
+    fn visit_import_decl(&mut self, import: &ImportDecl) {
+        if import.type_only {
+            return;
+        }
+        let Some(value) = self.asset_value(&import.src.value) else { return };
+
+        let declarations: Vec<String> = import.specifiers.iter()
+            .map(|specifier| match specifier {
+                ImportSpecifier::Default(s) => format!("var {} = {};", s.local.sym, value),
+                ImportSpecifier::Namespace(s) => format!("var {} = {{ default: {} }};", s.local.sym, value),
+                ImportSpecifier::Named(s) => {
+                    let imported = match &s.imported {
+                        Some(ModuleExportName::Ident(ident)) => ident.sym.to_string(),
+                        Some(ModuleExportName::Str(name)) => name.value.to_string(),
+                        None => s.local.sym.to_string(),
+                    };
+                    format!(
+                        "var {} = {}[{}];",
+                        s.local.sym,
+                        value,
+                        serde_json::Value::String(imported)
+                    )
+                }
+            })
+            .collect();
+
+        self.edits.replace(import.span, declarations.join(" "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> HashMap<PathBuf, FileContent> {
+        let mut files = HashMap::new();
+        files.insert(PathBuf::from("data/config.json"), FileContent::Text("{ \"debug\": false }".to_string()));
+        files.insert(PathBuf::from("data/readme.txt"), FileContent::Text("hello".to_string()));
+        files.insert(PathBuf::from("icons/dot.png"), FileContent::Binary(vec![0x89, 0x50, 0x4e, 0x47]));
+        files.insert(PathBuf::from("styles/main.css"), FileContent::Text(".a { color: red; }".to_string()));
+        files
+    }
+
+    fn inline(code: &str, overrides: &HashMap<String, AssetLoader>) -> InlinedAssets {
+        let syntax = js::syntax_for_path(Path::new("index.js"));
+        inline_asset_imports(code, syntax, Path::new("lib/index.js"), &files(), overrides).unwrap()
+    }
+
+    #[test]
+    fn test_loader_for_extension() {
+        let mut overrides = HashMap::new();
+        overrides.insert(".svg".to_string(), AssetLoader::Text);
+
+        assert_eq!(loader_for(Path::new("a.json"), &overrides), Some(AssetLoader::Json));
+        assert_eq!(loader_for(Path::new("a.SVG"), &overrides), Some(AssetLoader::Text));
+        assert_eq!(loader_for(Path::new("a.png"), &HashMap::new()), Some(AssetLoader::DataUrl));
+        assert_eq!(loader_for(Path::new("a.wasm"), &overrides), Some(AssetLoader::Wasm));
+        assert_eq!(loader_for(Path::new("a.js"), &overrides), None);
+    }
+
+    #[test]
+    fn test_inlines_json_and_text() {
+        let code = "var config = require('../data/config.json');\nimport readme from '../data/readme.txt';";
+        let result = inline(code, &HashMap::new());
+        assert!(result.code.contains("var config = ({\"debug\":false});"));
+        assert!(result.code.contains("var readme = \"hello\";"));
+        assert_eq!(result.assets.len(), 2);
+    }
+
+    #[test]
+    fn test_inlines_binary_as_data_url() {
+        let code = "import icon from '../icons/dot.png';";
+        let result = inline(code, &HashMap::new());
+        assert_eq!(result.code, "var icon = \"data:image/png;base64,iVBORw==\";");
+    }
+
+    #[test]
+    fn test_loader_override() {
+        let mut overrides = HashMap::new();
+        overrides.insert("json".to_string(), AssetLoader::Text);
+        let result = inline("var raw = require('../data/config.json');", &overrides);
+        assert_eq!(result.code, "var raw = \"{ \\\"debug\\\": false }\";");
+    }
+
+    #[test]
+    fn test_collects_stylesheets() {
+        let code = "import '../styles/main.css';\nrequire('../styles/main.css');\nvar x = 1;";
+        let result = inline(code, &HashMap::new());
+        assert_eq!(result.styles, vec![PathBuf::from("styles/main.css")]);
+        assert!(!result.code.contains("main.css"));
+        assert!(js::parse_program(&result.code, js::syntax_for_path(Path::new("index.js"))).is_ok());
+    }
+
+    #[test]
+    fn test_leaves_unknown_imports() {
+        let code = "var other = require('./other');\nvar missing = require('./missing.json');";
+        let result = inline(code, &HashMap::new());
+        assert_eq!(result.code, code);
+        assert!(result.assets.is_empty());
+    }
+}
@@ -1,26 +1,254 @@
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use anyhow::{Context, Result};
 use tracing::{debug, info, warn};
 use regex::Regex;
+use base64::prelude::*;
 
-use crate::config::Config;
-use crate::converter::{TransformedPackage, BundledCode};
-use crate::cli::BundleStrategy;
+use crate::config::{ChunkLoading, Config, CssMode, WasmMode};
+use crate::converter::{TransformedPackage, BundledCode, ModuleSource, Stylesheet, WasmModule, Resource, Chunk, EntryGroup, DependencyDecision};
+use crate::duplicates::DuplicatePackage;
+use crate::cli::{BundleStrategy, EsTarget};
 use crate::errors::{PaktoError, Result as PaktoResult};
+use crate::resolver::{self, Resolution};
+use crate::{js, syntax, wasm};
+
+/// Loads modules from `__pakto_modules__`, whose entries pair a factory
+/// with the ids its require specifiers resolve to. Module 0 is the
+/// package entry; its exports are what the bundle's wrapper returns.
+/// `require.import()` waits for `__pakto_ensure__` to load the chunks a
+/// module needs before requiring it. A module whose factory throws is
+/// dropped from the cache, so requiring it again runs it again instead of
+/// returning its partial exports.
+const MODULE_RUNTIME: &str = r#"var __pakto_cache__ = {};
+function __pakto_link__(definition, request) {
+  if (!Object.prototype.hasOwnProperty.call(definition[1], request)) {
+    var error = new Error("Cannot find module '" + request + "'");
+    error.code = 'MODULE_NOT_FOUND';
+    throw error;
+  }
+  return definition[1][request];
+}
+function __pakto_require__(id) {
+  var cached = __pakto_cache__[id];
+  if (cached) {
+    return cached.exports;
+  }
+  var definition = __pakto_modules__[id];
+  var module = __pakto_cache__[id] = { id: id, exports: {} };
+  var require = function (request) {
+    return __pakto_require__(__pakto_link__(definition, request));
+  };
+  require.import = function (request) {
+    return Promise.resolve().then(function () {
+      var target = __pakto_link__(definition, request);
+      return __pakto_ensure__(target).then(function () {
+        return __pakto_require__(target);
+      });
+    });
+  };
+  try {
+    definition[0].call(module.exports, module, module.exports, require);
+  } catch (error) {
+    delete __pakto_cache__[id];
+    throw error;
+  }
+  return module.exports;
+}
+var module = { exports: __pakto_require__(0) };
+"#;
+
+/// `__pakto_ensure__` of a bundle without chunks: every module is present
+const STATIC_LOADER: &str = r#"function __pakto_ensure__() {
+  return Promise.resolve();
+}
+"#;
+
+/// Installs chunks pushed onto the page-wide `__pakto_chunks__` registry
+/// as `[bundle, chunk, modules]`, and loads missing chunks with a script
+/// tag. Expects `__pakto_global__`, `__pakto_bundle__`, `__pakto_chunk_urls__`,
+/// `__pakto_chunk_deps__` (the chunks each split entry module needs) and
+/// `__pakto_groups__` (the module ids of each entry group).
+const CHUNK_LOADER: &str = r#"var __pakto_installed__ = {};
+var __pakto_loading__ = {};
+function __pakto_install__(chunk) {
+  if (chunk[0] !== __pakto_bundle__ || __pakto_installed__[chunk[1]]) {
+    return;
+  }
+  for (var id in chunk[2]) {
+    __pakto_modules__[id] = chunk[2][id];
+  }
+  __pakto_installed__[chunk[1]] = true;
+}
+(function (registry) {
+  var push = registry.push;
+  for (var i = 0; i < registry.length; i++) {
+    __pakto_install__(registry[i]);
+  }
+  registry.push = function (chunk) {
+    __pakto_install__(chunk);
+    return push.apply(this, arguments);
+  };
+})(__pakto_global__.__pakto_chunks__ = __pakto_global__.__pakto_chunks__ || []);
+function __pakto_load_chunk__(name) {
+  if (!__pakto_loading__[name]) {
+    __pakto_loading__[name] = new Promise(function (resolve, reject) {
+      if (__pakto_installed__[name]) {
+        return resolve();
+      }
+      var url = __pakto_chunk_urls__[name];
+      var script = document.createElement('script');
+      script.src = url;
+      script.async = true;
+      script.onload = function () {
+        if (__pakto_installed__[name]) {
+          resolve();
+        } else {
+          delete __pakto_loading__[name];
+          reject(new Error("Chunk '" + name + "' loaded from " + url + " did not register its modules"));
+        }
+      };
+      script.onerror = function () {
+        delete __pakto_loading__[name];
+        reject(new Error("Failed to load chunk '" + name + "' from " + url));
+      };
+      (document.head || document.getElementsByTagName('head')[0]).appendChild(script);
+    });
+  }
+  return __pakto_loading__[name];
+}
+function __pakto_ensure__(id) {
+  var chunks = __pakto_chunk_deps__[id] || [];
+  var loads = [];
+  for (var i = 0; i < chunks.length; i++) {
+    loads.push(__pakto_load_chunk__(chunks[i]));
+  }
+  return Promise.all(loads);
+}
+function __pakto_load_group__(name) {
+  if (!Object.prototype.hasOwnProperty.call(__pakto_groups__, name)) {
+    return Promise.reject(new Error("Unknown chunk '" + name + "'"));
+  }
+  var group = __pakto_groups__[name];
+  var loads = [];
+  for (var entry in group) {
+    loads.push(__pakto_ensure__(group[entry]));
+  }
+  return Promise.all(loads).then(function () {
+    var exports = {};
+    for (var entry in group) {
+      exports[entry] = __pakto_require__(group[entry]);
+    }
+    return exports;
+  });
+}
+"#;
+
+/// Exposes entry groups as `loadChunk(name)` on the bundle's exports,
+/// resolving to the exports of each entry keyed by its specifier
+const GROUP_EXPORT: &str = r#"if (module.exports !== null && (typeof module.exports === 'object' || typeof module.exports === 'function') && Object.isExtensible(module.exports) && !('loadChunk' in module.exports)) {
+  Object.defineProperty(module.exports, 'loadChunk', { value: __pakto_load_group__ });
+}
+"#;
+
+/// The page's global object, where chunks find the registry
+const GLOBAL_OBJECT: &str = "typeof globalThis !== 'undefined' ? globalThis : typeof self !== 'undefined' ? self : window";
 
 /// Handles dependency bundling and module resolution
 pub struct Bundler {
     config: Config,
 }
 
+/// Identity of a module table entry
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ModuleKey {
+    File(PathBuf),
+    External(String),
+    Builtin(String),
+    Empty,
+}
+
+/// An entry of the bundle's module table
+#[derive(Debug)]
+enum BundleModule<'a> {
+    /// A transformed package module
+    Source(&'a ModuleSource),
+    /// A dependency expected as a global, by specifier
+    External(String),
+    /// A core module without a polyfill
+    Builtin(String),
+    /// A module the browser field disables
+    Empty,
+}
+
+impl BundleModule<'_> {
+    fn describe(&self) -> String {
+        match self {
+            Self::Source(source) => source.path.display().to_string(),
+            Self::External(specifier) => format!("external {}", specifier),
+            Self::Builtin(name) => format!("node:{}", name),
+            Self::Empty => "empty module".to_string(),
+        }
+    }
+}
+
+/// The modules reachable from the package's entries, numbered from the
+/// package entry
+struct ModuleTable<'a> {
+    modules: Vec<BundleModule<'a>>,
+    /// Id each of a module's specifiers resolves to
+    links: Vec<BTreeMap<String, usize>>,
+    /// Ids a module only loads through `import()`
+    dynamic: Vec<BTreeSet<usize>>,
+    /// Ids of each entry group's entries, by specifier
+    groups: Vec<(String, Vec<(String, usize)>)>,
+    bundled_deps: BTreeSet<String>,
+}
+
+impl ModuleTable<'_> {
+    /// Ids reachable from some roots, following `import()` only when it
+    /// does not start a chunk of its own
+    fn closure(&self, roots: &[usize], follow_dynamic: bool) -> HashSet<usize> {
+        let mut reached: HashSet<usize> = roots.iter().copied().collect();
+        let mut stack = roots.to_vec();
+        while let Some(id) = stack.pop() {
+            for &target in self.links[id].values() {
+                if (follow_dynamic || !self.dynamic[id].contains(&target)) && reached.insert(target) {
+                    stack.push(target);
+                }
+            }
+        }
+        reached
+    }
+}
+
+/// Modules of the table moved out of the bundle into a chunk
+#[derive(Debug)]
+struct ChunkPlan {
+    name: String,
+    ids: Vec<usize>,
+}
+
+/// How the module table is split into chunks
+#[derive(Debug)]
+struct SplitPlan {
+    /// Chunk each module is defined in, `None` for the bundle itself
+    chunk_of: Vec<Option<usize>>,
+    chunks: Vec<ChunkPlan>,
+    /// Chunks to load before requiring each split entry module
+    needs: BTreeMap<usize, Vec<usize>>,
+}
+
 /// Bundle optimization options
 #[derive(Debug, Clone)]
 struct BundleOptions {
     tree_shake: bool,
-    deduplicate: bool,
+    /// Packages never left out of the bundle
+    bundled_packages: Vec<String>,
     inline_small_modules: bool,
     max_inline_size: usize,
+    /// Dependency packages the Hybrid strategy inlines
+    hybrid_inlined: BTreeSet<String>,
     exclude_patterns: Vec<Regex>,
 }
 
@@ -35,42 +263,178 @@ impl Bundler {
         &self,
         transformed: &TransformedPackage,
         strategy: &BundleStrategy,
+        target: &EsTarget,
         exclude_dependencies: &[String],
+        file_stem: &str,
     ) -> PaktoResult<BundledCode> {
         info!("Starting dependency bundling with strategy: {:?}", strategy);
 
         // Create bundle options based on strategy
-        let bundle_options = self.create_bundle_options(strategy, exclude_dependencies)?;
+        let mut bundle_options = self.create_bundle_options(strategy, exclude_dependencies)?;
+        bundle_options.bundled_packages = transformed.bundled_packages.clone();
 
-        // Process the code based on strategy
-        let (processed_code, bundled_deps) = match strategy {
-            BundleStrategy::Inline => {
-                self.bundle_inline(&transformed.code, &bundle_options).await?
-            }
-            BundleStrategy::Selective => {
-                self.bundle_selective(&transformed.code, &bundle_options).await?
-            }
-            BundleStrategy::External => {
-                self.bundle_external(&transformed.code, &bundle_options).await?
-            }
-            BundleStrategy::Hybrid => {
-                self.bundle_hybrid(&transformed.code, &bundle_options).await?
-            }
+        let dependency_decisions = match strategy {
+            BundleStrategy::Hybrid => self.hybrid_decisions(&transformed.modules, &bundle_options),
+            _ => Vec::new(),
         };
+        bundle_options.hybrid_inlined = dependency_decisions.iter()
+            .filter(|decision| decision.inlined)
+            .map(|decision| decision.package.clone())
+            .collect();
+
+        let split = &self.config.bundle.split;
+        if split.is_enabled() && split.loading == ChunkLoading::Resource && split.resource_module.is_empty() {
+            return Err(PaktoError::ConfigError {
+                message: "bundle.split.resource_module is required to load chunks as OutSystems resources".to_string(),
+                source: None,
+            });
+        }
+
+        // Link the reachable modules under the module runtime, after the
+        // polyfills they use
+        let (modules_code, bundled_deps, chunks) = self.link_modules(
+            &transformed.modules,
+            &transformed.entry_groups,
+            strategy,
+            &bundle_options,
+            file_stem,
+        );
+        let processed_code = format!("{}{}", transformed.code, modules_code);
+
+        // Copies of packages the strategy leaves out cost nothing
+        let duplicate_packages: Vec<DuplicatePackage> = transformed.duplicate_packages.iter()
+            .filter(|duplicate| bundled_deps.contains(&duplicate.name))
+            .cloned()
+            .collect();
+        for duplicate in &duplicate_packages {
+            warn!(
+                "{} is bundled at {} versions ({}), adding {} bytes",
+                duplicate.name,
+                duplicate.copies.len(),
+                duplicate.versions(),
+                duplicate.wasted_size
+            );
+        }
+        if self.config.bundle.fail_on_duplicates && !duplicate_packages.is_empty() {
+            return Err(PaktoError::DuplicatePackages {
+                packages: duplicate_packages.iter()
+                    .map(|duplicate| format!("{} ({})", duplicate.name, duplicate.versions()))
+                    .collect(),
+            });
+        }
+
+        // Inject or extract imported stylesheets
+        let (processed_code, stylesheet) = self.deliver_styles(processed_code, &transformed.styles);
 
-        // Optimize the bundle
-        let optimized_code = self.optimize_bundle(&processed_code, &bundle_options)?;
+        // Embed WebAssembly modules or emit them as separate files
+        let (processed_code, resources) = self.deliver_wasm(processed_code, &transformed.wasm_modules);
 
-        // Validate the final bundle
-        self.validate_bundle(&optimized_code)?;
+        // The output generator parses the bundle once it is wrapped; chunk
+        // scripts are written as they are. Module code is left byte for
+        // byte, as whitespace inside strings and templates is significant.
+        self.validate_bundle(&processed_code)?;
+
+        let chunks = chunks.into_iter()
+            .map(|chunk| {
+                self.validate_bundle(&chunk.code)?;
+                syntax::check_script(&chunk.code, target, Path::new(&chunk.file_name))?;
+                Ok(chunk)
+            })
+            .collect::<PaktoResult<Vec<_>>>()?;
 
         Ok(BundledCode {
-            code: optimized_code,
-            bundled_dependencies: bundled_deps,
             unminified_size: processed_code.len(),
+            code: processed_code,
+            bundled_dependencies: bundled_deps,
+            stylesheet,
+            resources,
+            chunks,
+            dependency_decisions,
+            duplicate_packages,
+            exports: entry_exports(&transformed.modules),
         })
     }
 
+    /// Deliver imported stylesheets according to `bundle.css`: either
+    /// prepend code that adds them to the page once, or return them for a
+    /// separate `.css` file
+    fn deliver_styles(&self, code: String, styles: &[Stylesheet]) -> (String, Option<String>) {
+        if styles.is_empty() {
+            return (code, None);
+        }
+
+        let css = styles.iter()
+            .map(|style| format!("/* {} */\n{}\n", style.path.display(), style.content.trim_end()))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        match self.config.bundle.css {
+            CssMode::Extract => {
+                debug!("Extracting {} stylesheet(s)", styles.len());
+                (code, Some(css))
+            }
+            CssMode::Inject => {
+                debug!("Injecting {} stylesheet(s)", styles.len());
+                (format!("{}\n{}", Self::style_injector(&css), code), None)
+            }
+        }
+    }
+
+    /// Deliver WebAssembly modules according to `bundle.wasm`: either embed
+    /// them as base64 or return them as files, registering where the
+    /// `wasm` polyfill finds each one
+    fn deliver_wasm(&self, code: String, modules: &[WasmModule]) -> (String, Vec<Resource>) {
+        if modules.is_empty() {
+            return (code, Vec::new());
+        }
+
+        let mut sources = Vec::new();
+        let mut resources: Vec<Resource> = Vec::new();
+
+        for module in modules {
+            let name = wasm::module_name(&module.path);
+            let source = match self.config.bundle.wasm {
+                WasmMode::Embed => serde_json::json!({ "base64": BASE64_STANDARD.encode(&module.content) }),
+                WasmMode::Separate => {
+                    let file_name = module.path.file_name()
+                        .map(|n| n.to_string_lossy().into_owned())
+                        .filter(|n| !resources.iter().any(|r| &r.file_name == n))
+                        .unwrap_or_else(|| name.replace('/', "-"));
+                    let url = format!("{}{}", self.config.bundle.wasm_base_url, file_name);
+                    resources.push(Resource { file_name, content: module.content.clone() });
+                    serde_json::json!({ "url": url })
+                }
+            };
+            sources.push((name, source));
+        }
+
+        debug!("Delivering {} WebAssembly module(s) as {:?}", modules.len(), self.config.bundle.wasm);
+        (format!("{}\n{}", wasm::registry_code(&sources), code), resources)
+    }
+
+    /// Code that adds a `<style>` tag, guarded by an id derived from the
+    /// CSS so loading the bundle twice does not duplicate it
+    fn style_injector(css: &str) -> String {
+        // FNV-1a keeps the id stable across builds and Rust versions
+        let hash = css.bytes().fold(0x811c9dc5u32, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        });
+        let id = format!("pakto-style-{:08x}", hash);
+        let css = serde_json::Value::String(css.to_string());
+
+        format!(
+            "(function() {{\n  \
+             if (typeof document === 'undefined' || document.getElementById('{id}')) return;\n  \
+             var style = document.createElement('style');\n  \
+             style.id = '{id}';\n  \
+             style.appendChild(document.createTextNode({css}));\n  \
+             (document.head || document.getElementsByTagName('head')[0]).appendChild(style);\n\
+             }})();\n",
+            id = id,
+            css = css
+        )
+    }
+
     /// Create bundle options based on strategy
     fn create_bundle_options(
         &self,
@@ -83,291 +447,388 @@ impl Bundler {
             .collect::<Result<Vec<_>, _>>()?;
 
         Ok(BundleOptions {
-            tree_shake: matches!(strategy, BundleStrategy::Selective | BundleStrategy::Hybrid),
-            deduplicate: true,
+            tree_shake: strategy.tree_shakes(),
+            bundled_packages: Vec::new(),
             inline_small_modules: matches!(strategy, BundleStrategy::Inline | BundleStrategy::Hybrid),
-            max_inline_size: self.config.bundle.max_size / 10,
+            max_inline_size: self.config.bundle.max_inline_size.unwrap_or(self.config.bundle.max_size / 10),
+            hybrid_inlined: BTreeSet::new(),
             exclude_patterns,
         })
     }
 
-    /// Bundle all dependencies inline
-    async fn bundle_inline(
+    /// Number the modules reachable from the entries and emit them as a
+    /// module table with the runtime that loads them. Dependency packages
+    /// the strategy leaves out become lookups of their globals. With
+    /// `bundle.split` enabled, modules only entry groups or `import()`
+    /// need are moved to chunk scripts named after `file_stem`.
+    fn link_modules(
         &self,
-        code: &str,
+        modules: &[ModuleSource],
+        entry_groups: &[EntryGroup],
+        strategy: &BundleStrategy,
         options: &BundleOptions,
-    ) -> PaktoResult<(String, Vec<String>)> {
-        debug!("Bundling with inline strategy");
-
-        let dependencies = self.extract_dependencies(code)?;
-        let mut bundled_code = String::new();
-        let mut bundled_deps = Vec::new();
-
-        // Add comment header
-        bundled_code.push_str("// === Inline Bundle ===\n");
-        bundled_code.push_str("// All dependencies are included in this bundle\n\n");
-
-        // Add dependency stubs for now (in a real implementation, we'd fetch actual dependencies)
-        for dep in &dependencies {
-            if !self.should_exclude_dependency(dep, options) {
-                bundled_code.push_str(&format!("// Dependency: {}\n", dep));
-                bundled_code.push_str(&self.create_dependency_stub(dep)?);
-                bundled_code.push_str("\n\n");
-                bundled_deps.push(dep.clone());
-            }
+        file_stem: &str,
+    ) -> (String, Vec<String>, Vec<Chunk>) {
+        if modules.is_empty() {
+            return (String::new(), Vec::new(), Vec::new());
         }
+        debug!("Linking modules with {:?} strategy", strategy);
 
-        // Add the main code
-        bundled_code.push_str("// === Main Code ===\n");
-        bundled_code.push_str(code);
-
-        Ok((bundled_code, bundled_deps))
-    }
-
-    /// Bundle with selective inclusion (tree-shaking)
-    async fn bundle_selective(
-        &self,
-        code: &str,
-        options: &BundleOptions,
-    ) -> PaktoResult<(String, Vec<String>)> {
-        debug!("Bundling with selective strategy");
-
-        let dependencies = self.extract_dependencies(code)?;
-        let used_exports = self.analyze_used_exports(code, &dependencies)?;
-
-        let mut bundled_code = String::new();
-        let mut bundled_deps = Vec::new();
+        let table = self.module_table(modules, entry_groups, strategy, options);
+        let plan = self.config.bundle.split.is_enabled().then(|| self.plan_split(&table));
+        let chunk_of = |id: usize| plan.as_ref().and_then(|plan| plan.chunk_of[id]);
 
-        bundled_code.push_str("// === Selective Bundle (Tree-shaken) ===\n");
-        bundled_code.push_str("// Only used exports are included\n\n");
-
-        for (dep, exports) in used_exports {
-            if !self.should_exclude_dependency(&dep, options) {
-                bundled_code.push_str(&format!("// Dependency: {} (exports: {:?})\n", dep, exports));
-                bundled_code.push_str(&self.create_selective_dependency_stub(&dep, &exports)?);
-                bundled_code.push_str("\n\n");
-                bundled_deps.push(dep);
+        let mut code = String::from("// === Modules ===\nvar __pakto_modules__ = [\n");
+        for (id, module) in table.modules.iter().enumerate() {
+            match (chunk_of(id), &plan) {
+                (Some(chunk), Some(plan)) => {
+                    code.push_str(&format!("// [{}] {} (chunk {})\nnull,\n", id, module.describe(), plan.chunks[chunk].name));
+                }
+                _ => {
+                    code.push_str(&format!("// [{}] {}\n", id, module.describe()));
+                    code.push_str(&format!("{},\n", self.table_entry(module, &table.links[id])));
+                }
             }
         }
+        code.push_str("];\n");
+
+        let mut chunks = Vec::new();
+        match &plan {
+            Some(plan) if !plan.chunks.is_empty() || !table.groups.is_empty() => {
+                let bundle = serde_json::Value::String(file_stem.to_string());
+                let mut urls = serde_json::Map::new();
+                for chunk in &plan.chunks {
+                    let file_name = format!("{}.{}.js", file_stem, chunk.name);
+                    urls.insert(chunk.name.clone(), serde_json::Value::String(self.chunk_url(&file_name)));
+
+                    let mut chunk_code = format!(
+                        "(function (global) {{\n(global.__pakto_chunks__ = global.__pakto_chunks__ || []).push([{}, {}, {{\n",
+                        bundle,
+                        serde_json::Value::String(chunk.name.clone())
+                    );
+                    for &id in &chunk.ids {
+                        let module = &table.modules[id];
+                        chunk_code.push_str(&format!("// [{}] {}\n", id, module.describe()));
+                        chunk_code.push_str(&format!("{}: {},\n", id, self.table_entry(module, &table.links[id])));
+                    }
+                    chunk_code.push_str(&format!("}}]);\n}})({});\n", GLOBAL_OBJECT));
+
+                    chunks.push(Chunk {
+                        name: chunk.name.clone(),
+                        file_name,
+                        code: chunk_code,
+                        modules: chunk.ids.iter()
+                            .filter_map(|&id| match table.modules[id] {
+                                BundleModule::Source(source) => Some(source.path.clone()),
+                                _ => None,
+                            })
+                            .collect(),
+                    });
+                }
 
-        bundled_code.push_str("// === Main Code ===\n");
-        bundled_code.push_str(code);
-
-        Ok((bundled_code, bundled_deps))
+                let needs: BTreeMap<String, Vec<&str>> = plan.needs.iter()
+                    .map(|(id, chunks)| (id.to_string(), chunks.iter().map(|&c| plan.chunks[c].name.as_str()).collect()))
+                    .collect();
+                let groups: serde_json::Map<String, serde_json::Value> = table.groups.iter()
+                    .map(|(name, entries)| {
+                        let entries = entries.iter()
+                            .map(|(specifier, id)| (specifier.clone(), serde_json::Value::from(*id)))
+                            .collect();
+                        (name.clone(), serde_json::Value::Object(entries))
+                    })
+                    .collect();
+
+                code.push_str(&format!("var __pakto_global__ = {};\n", GLOBAL_OBJECT));
+                code.push_str(&format!("var __pakto_bundle__ = {};\n", bundle));
+                code.push_str(&format!("var __pakto_chunk_urls__ = {};\n", serde_json::Value::Object(urls)));
+                code.push_str(&format!("var __pakto_chunk_deps__ = {};\n", serde_json::json!(needs)));
+                code.push_str(&format!("var __pakto_groups__ = {};\n", serde_json::Value::Object(groups)));
+                code.push_str(CHUNK_LOADER);
+            }
+            _ => code.push_str(STATIC_LOADER),
+        }
+        code.push_str(MODULE_RUNTIME);
+        if !table.groups.is_empty() {
+            code.push_str(GROUP_EXPORT);
+        }
+        code.push_str("// === End Modules ===\n");
+
+        debug!(
+            "Linked {} module(s), {} dependency package(s) bundled, {} chunk(s)",
+            table.modules.len(),
+            table.bundled_deps.len(),
+            chunks.len()
+        );
+        (code, table.bundled_deps.into_iter().collect(), chunks)
     }
 
-    /// Bundle with external dependencies
-    async fn bundle_external(
+    /// Number the modules reachable from the package entry and the entry
+    /// groups, breadth first
+    fn module_table<'a>(
         &self,
-        code: &str,
+        modules: &'a [ModuleSource],
+        entry_groups: &[EntryGroup],
+        strategy: &BundleStrategy,
         options: &BundleOptions,
-    ) -> PaktoResult<(String, Vec<String>)> {
-        debug!("Bundling with external strategy");
-
-        let dependencies = self.extract_dependencies(code)?;
-        let mut bundled_code = String::new();
-        let external_deps: Vec<String> = dependencies.into_iter()
-            .filter(|dep| !self.should_exclude_dependency(dep, options))
+    ) -> ModuleTable<'a> {
+        let sources: HashMap<&Path, &ModuleSource> = modules.iter()
+            .map(|module| (module.path.as_path(), module))
             .collect();
 
-        bundled_code.push_str("// === External Bundle ===\n");
-        bundled_code.push_str("// Dependencies are expected to be available externally\n\n");
-
-        if !external_deps.is_empty() {
-            bundled_code.push_str("// External dependency checks\n");
-            for dep in &external_deps {
-                let global_name = self.dependency_to_global_name(dep);
-                bundled_code.push_str(&format!(
-                    "if (typeof {} === 'undefined') {{\n",
-                    global_name
-                ));
-                bundled_code.push_str(&format!(
-                    "  throw new Error('External dependency not found: {} (expected global: {})');\n",
-                    dep, global_name
-                ));
-                bundled_code.push_str("}\n");
+        let mut table = vec![BundleModule::Source(&modules[0])];
+        let mut ids = HashMap::from([(ModuleKey::File(modules[0].path.clone()), 0)]);
+        let mut groups = Vec::new();
+        for group in entry_groups {
+            let mut entries = Vec::new();
+            for (specifier, path) in &group.entries {
+                let Some(source) = sources.get(path.as_path()) else { continue };
+                let id = *ids.entry(ModuleKey::File(path.clone())).or_insert_with(|| {
+                    table.push(BundleModule::Source(source));
+                    table.len() - 1
+                });
+                entries.push((specifier.clone(), id));
             }
-            bundled_code.push_str("\n");
+            groups.push((group.name.clone(), entries));
         }
 
-        bundled_code.push_str("// === Main Code ===\n");
-        bundled_code.push_str(code);
+        let mut links: Vec<BTreeMap<String, usize>> = Vec::new();
+        let mut dynamic: Vec<BTreeSet<usize>> = Vec::new();
+        let mut bundled_deps = BTreeSet::new();
+
+        let mut index = 0;
+        while index < table.len() {
+            let mut module_links = BTreeMap::new();
+            let mut module_dynamic = BTreeSet::new();
+
+            if let BundleModule::Source(module) = table[index] {
+                for (specifier, resolution) in &module.dependencies {
+                    let key = match resolution {
+                        Resolution::Module(path) => match resolver::package_name_of(path) {
+                            Some(package) if self.is_external(&package, strategy, options) => {
+                                ModuleKey::External(specifier.clone())
+                            }
+                            Some(package) => {
+                                bundled_deps.insert(package);
+                                ModuleKey::File(path.clone())
+                            }
+                            None => ModuleKey::File(path.clone()),
+                        },
+                        Resolution::Builtin(name) => ModuleKey::Builtin(name.clone()),
+                        Resolution::Empty => ModuleKey::Empty,
+                        Resolution::External => ModuleKey::External(specifier.clone()),
+                    };
+
+                    let id = match ids.get(&key) {
+                        Some(id) => *id,
+                        None => {
+                            let module = match &key {
+                                ModuleKey::File(path) => match sources.get(path.as_path()) {
+                                    Some(source) => BundleModule::Source(source),
+                                    None => continue,
+                                },
+                                ModuleKey::External(specifier) => BundleModule::External(specifier.clone()),
+                                ModuleKey::Builtin(name) => BundleModule::Builtin(name.clone()),
+                                ModuleKey::Empty => BundleModule::Empty,
+                            };
+                            table.push(module);
+                            ids.insert(key, table.len() - 1);
+                            table.len() - 1
+                        }
+                    };
+                    module_links.insert(specifier.clone(), id);
+                }
 
-        Ok((bundled_code, external_deps))
-    }
+                // A module loaded both ways is needed as soon as this one runs
+                for (specifier, id) in &module_links {
+                    if module.dynamic_imports.contains(specifier) {
+                        module_dynamic.insert(*id);
+                    }
+                }
+                for (specifier, id) in &module_links {
+                    if !module.dynamic_imports.contains(specifier) {
+                        module_dynamic.remove(id);
+                    }
+                }
+            }
 
-    /// Bundle with hybrid strategy
-    async fn bundle_hybrid(
-        &self,
-        code: &str,
-        options: &BundleOptions,
-    ) -> PaktoResult<(String, Vec<String>)> {
-        debug!("Bundling with hybrid strategy");
+            links.push(module_links);
+            dynamic.push(module_dynamic);
+            index += 1;
+        }
+
+        ModuleTable { modules: table, links, dynamic, groups, bundled_deps }
+    }
 
-        let dependencies = self.extract_dependencies(code)?;
-        let mut bundled_code = String::new();
-        let mut bundled_deps = Vec::new();
+    /// Assign the modules the bundle does not need at startup to chunks:
+    /// one per entry group and per module loaded with `import()`, and a
+    /// shared chunk for modules several of those need
+    fn plan_split(&self, table: &ModuleTable) -> SplitPlan {
+        let split_dynamic = self.config.bundle.split.dynamic_imports;
 
-        bundled_code.push_str("// === Hybrid Bundle ===\n");
-        bundled_code.push_str("// Mix of inline and external dependencies\n\n");
+        let mut entry_points: Vec<(String, Vec<usize>)> = vec![(String::new(), vec![0])];
+        for (name, entries) in &table.groups {
+            entry_points.push((name.clone(), entries.iter().map(|(_, id)| *id).collect()));
+        }
+        if split_dynamic {
+            let targets: BTreeSet<usize> = table.dynamic.iter().flatten().copied().collect();
+            for target in targets {
+                if entry_points.iter().all(|(_, roots)| !roots.contains(&target)) {
+                    let name = match &table.modules[target] {
+                        BundleModule::Source(source) => source.path.file_stem()
+                            .map(|stem| stem.to_string_lossy().into_owned())
+                            .unwrap_or_default(),
+                        _ => String::new(),
+                    };
+                    entry_points.push((name, vec![target]));
+                }
+            }
+        }
 
-        let mut inline_deps = Vec::new();
-        let mut external_deps = Vec::new();
+        let closures: Vec<HashSet<usize>> = entry_points.iter()
+            .map(|(_, roots)| table.closure(roots, !split_dynamic))
+            .collect();
 
-        // Categorize dependencies
-        for dep in dependencies {
-            if self.should_exclude_dependency(&dep, options) {
+        // Chunk i - 1 belongs to entry point i; the last one is shared
+        let shared = entry_points.len() - 1;
+        let mut members: Vec<Vec<usize>> = vec![Vec::new(); entry_points.len()];
+        for id in 0..table.modules.len() {
+            if closures[0].contains(&id) {
                 continue;
             }
-
-            if self.should_inline_dependency(&dep) {
-                inline_deps.push(dep);
-            } else {
-                external_deps.push(dep);
+            let owners: Vec<usize> = (1..entry_points.len())
+                .filter(|&point| closures[point].contains(&id))
+                .collect();
+            match owners.as_slice() {
+                [owner] => members[owner - 1].push(id),
+                _ => members[shared].push(id),
             }
         }
 
-        // Add inline dependencies
-        if !inline_deps.is_empty() {
-            bundled_code.push_str("// === Inline Dependencies ===\n");
-            for dep in &inline_deps {
-                bundled_code.push_str(&format!("// Dependency: {}\n", dep));
-                bundled_code.push_str(&self.create_dependency_stub(dep)?);
-                bundled_code.push_str("\n");
-                bundled_deps.push(dep.clone());
+        let mut chunk_of = vec![None; table.modules.len()];
+        let mut chunks: Vec<ChunkPlan> = Vec::new();
+        let mut names = HashSet::new();
+        let mut renumbered = vec![None; members.len()];
+        for (index, ids) in members.into_iter().enumerate() {
+            if ids.is_empty() {
+                continue;
             }
-            bundled_code.push_str("\n");
-        }
-
-        // Add external dependency checks
-        if !external_deps.is_empty() {
-            bundled_code.push_str("// === External Dependencies ===\n");
-            for dep in &external_deps {
-                let global_name = self.dependency_to_global_name(dep);
-                bundled_code.push_str(&format!(
-                    "var {} = (typeof {} !== 'undefined') ? {} : require('{}');\n",
-                    self.dependency_to_variable_name(dep),
-                    global_name,
-                    global_name,
-                    dep
-                ));
-                bundled_deps.push(dep.clone());
+            let base = match entry_points.get(index + 1) {
+                Some((name, _)) if index < shared => chunk_file_name(name),
+                _ => "shared".to_string(),
+            };
+            let mut name = base.clone();
+            let mut suffix = 2;
+            while !names.insert(name.clone()) {
+                name = format!("{}-{}", base, suffix);
+                suffix += 1;
             }
-            bundled_code.push_str("\n");
-        }
-
-        bundled_code.push_str("// === Main Code ===\n");
-        bundled_code.push_str(code);
-
-        Ok((bundled_code, bundled_deps))
-    }
-
-    /// Extract dependencies from code
-    fn extract_dependencies(&self, code: &str) -> PaktoResult<Vec<String>> {
-        let mut dependencies = HashSet::new();
-
-        // Extract require() calls
-        let require_regex = Regex::new(r#"require\s*\(\s*['"`]([^'"`]+)['"`]\s*\)"#)?;
-        for cap in require_regex.captures_iter(code) {
-            dependencies.insert(cap[1].to_string());
+            for &id in &ids {
+                chunk_of[id] = Some(chunks.len());
+            }
+            renumbered[index] = Some(chunks.len());
+            chunks.push(ChunkPlan { name, ids });
         }
 
-        // Extract import statements
-        let import_regex = Regex::new(r#"(?:import|from)\s+['"`]([^'"`]+)['"`]"#)?;
-        for cap in import_regex.captures_iter(code) {
-            dependencies.insert(cap[1].to_string());
+        let mut needs = BTreeMap::new();
+        for (point, (_, roots)) in entry_points.iter().enumerate().skip(1) {
+            let mut required: Vec<usize> = closures[point].iter()
+                .filter_map(|&id| chunk_of[id])
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect();
+            // Shared modules are defined before the chunks that use them
+            if let Some(shared) = renumbered[shared] {
+                if let Some(position) = required.iter().position(|&chunk| chunk == shared) {
+                    required.remove(position);
+                    required.insert(0, shared);
+                }
+            }
+            if !required.is_empty() {
+                for &root in roots {
+                    needs.insert(root, required.clone());
+                }
+            }
         }
 
-        Ok(dependencies.into_iter().collect())
+        info!("Split {} module(s) into {} chunk(s)", chunk_of.iter().flatten().count(), chunks.len());
+        SplitPlan { chunk_of, chunks, needs }
     }
 
-    /// Analyze which exports are actually used
-    fn analyze_used_exports(
-        &self,
-        code: &str,
-        dependencies: &[String],
-    ) -> PaktoResult<HashMap<String, Vec<String>>> {
-        let mut used_exports = HashMap::new();
-
-        for dep in dependencies {
-            let var_name = self.dependency_to_variable_name(dep);
-            let mut exports = Vec::new();
-
-            // Look for property access patterns
-            let usage_regex = Regex::new(&format!(r"{}\.(\w+)", regex::escape(&var_name)))?;
-            for cap in usage_regex.captures_iter(code) {
-                exports.push(cap[1].to_string());
-            }
-
-            // Look for destructuring patterns
-            let destructure_regex = Regex::new(&format!(
-                r"(?:const|let|var)\s*\{{\s*([^}}]+)\s*\}}\s*=\s*{}",
-                regex::escape(&var_name)
-            ))?;
-            if let Some(cap) = destructure_regex.captures(code) {
-                let destructured = cap[1].split(',')
-                    .map(|s| s.trim().to_string())
-                    .collect::<Vec<_>>();
-                exports.extend(destructured);
-            }
-
-            if !exports.is_empty() {
-                exports.sort();
-                exports.dedup();
-                used_exports.insert(dep.clone(), exports);
+    /// URL the chunk loader fetches a chunk file from
+    fn chunk_url(&self, file_name: &str) -> String {
+        let split = &self.config.bundle.split;
+        match split.loading {
+            ChunkLoading::Url => format!("{}{}", split.base_url, file_name),
+            ChunkLoading::Resource => {
+                let resource = file_name.strip_suffix(".js").unwrap_or(file_name);
+                format!("{}scripts/{}.{}.js", split.base_url, split.resource_module, chunk_resource_name(resource))
             }
         }
-
-        Ok(used_exports)
     }
 
-    /// Create a stub for a dependency
-    fn create_dependency_stub(&self, dep_name: &str) -> PaktoResult<String> {
-        let var_name = self.dependency_to_variable_name(dep_name);
+    /// Module table entry: a factory and the ids its specifiers resolve to
+    fn table_entry(&self, module: &BundleModule, links: &BTreeMap<String, usize>) -> String {
+        let links = serde_json::to_string(links).unwrap_or_else(|_| "{}".to_string());
+        format!("[{}, {}]", self.module_factory(module), links)
+    }
 
-        // Create basic stubs for common dependencies
-        let stub = match dep_name {
-            "lodash" => format!(
-                "var {} = {{\n  map: function(arr, fn) {{ return arr.map(fn); }},\n  filter: function(arr, fn) {{ return arr.filter(fn); }},\n  reduce: function(arr, fn, init) {{ return arr.reduce(fn, init); }}\n}};",
-                var_name
-            ),
-            "uuid" => format!(
-                "var {} = {{\n  v4: function() {{ return 'xxxxxxxx-xxxx-4xxx-yxxx-xxxxxxxxxxxx'.replace(/[xy]/g, function(c) {{\n    var r = Math.random() * 16 | 0, v = c == 'x' ? r : (r & 0x3 | 0x8);\n    return v.toString(16);\n  }}); }}\n}};",
-                var_name
-            ),
-            "moment" => format!(
-                "var {} = function(date) {{\n  return {{\n    format: function(fmt) {{ return new Date(date).toISOString(); }},\n    valueOf: function() {{ return new Date(date).getTime(); }}\n  }};\n}};",
-                var_name
-            ),
-            _ => format!(
-                "var {} = {{\n  // Stub for dependency: {}\n  // Add actual implementation or use external library\n}};",
-                var_name, dep_name
+    /// Factory function of a module table entry
+    fn module_factory(&self, module: &BundleModule) -> String {
+        match module {
+            BundleModule::Source(source) => {
+                // A hashbang is only valid at the start of a file
+                let code = match source.code.strip_prefix("#!") {
+                    Some(rest) => format!("//{}", rest),
+                    None => source.code.clone(),
+                };
+                format!("function (module, exports, require) {{\n{}\n}}", code)
+            }
+            BundleModule::External(specifier) => {
+                let global = match self.config.bundle.external_global(specifier) {
+                    Some(expression) => expression.clone(),
+                    None => self.dependency_to_global_name(specifier),
+                };
+                // The expression may name a global that was never declared,
+                // e.g. `window` outside a browser
+                let message = format!(
+                    "External dependency '{}' is not available: expected the global {} to be loaded before this bundle",
+                    specifier, global
+                );
+                format!(
+                    "function (module) {{\n  \
+                     var value;\n  \
+                     try {{ value = ({global}); }} catch (e) {{}}\n  \
+                     if (value === undefined || value === null) {{\n    \
+                     throw new Error({message});\n  \
+                     }}\n  \
+                     module.exports = value;\n\
+                     }}",
+                    global = global,
+                    message = serde_json::Value::String(message)
+                )
+            }
+            BundleModule::Builtin(name) => format!(
+                "function (module) {{\n  module.exports = UnavailableModulePolyfill({}, []);\n}}",
+                serde_json::Value::String(name.clone())
             ),
-        };
-
-        Ok(stub)
+            BundleModule::Empty => "function () {}".to_string(),
+        }
     }
 
-    /// Create a selective stub with only used exports
-    fn create_selective_dependency_stub(
-        &self,
-        dep_name: &str,
-        exports: &[String],
-    ) -> PaktoResult<String> {
-        let var_name = self.dependency_to_variable_name(dep_name);
-
-        let mut stub = format!("var {} = {{\n", var_name);
-
-        for export in exports {
-            stub.push_str(&format!(
-                "  {}: function() {{ /* Implementation for {} */ }},\n",
-                export, export
-            ));
+    /// Whether a dependency package is left out of the bundle and expected
+    /// as a global
+    fn is_external(&self, package: &str, strategy: &BundleStrategy, options: &BundleOptions) -> bool {
+        if options.bundled_packages.iter().any(|bundled| bundled == package) {
+            return false;
+        }
+        if self.should_exclude_dependency(package, options) {
+            return true;
+        }
+        match strategy {
+            BundleStrategy::Inline | BundleStrategy::Selective => false,
+            BundleStrategy::External => true,
+            BundleStrategy::Hybrid => !options.hybrid_inlined.contains(package),
         }
-
-        stub.push_str("};");
-
-        Ok(stub)
     }
 
     /// Check if dependency should be excluded
@@ -388,25 +849,47 @@ impl Bundler {
         matches!(dep_name, "fs" | "child_process" | "cluster" | "worker_threads" | "net" | "http" | "https")
     }
 
-    /// Check if dependency should be inlined in hybrid mode
-    fn should_inline_dependency(&self, dep_name: &str) -> bool {
-        // Force inline if specified in config
-        if self.config.bundle.force_inline.contains(&dep_name.to_string()) {
-            return true;
+    /// Decide which dependency packages the Hybrid strategy bundles: those
+    /// in `force_inline` and those whose modules minify to at most
+    /// `max_inline_size` bytes. The rest are read from their globals.
+    fn hybrid_decisions(&self, modules: &[ModuleSource], options: &BundleOptions) -> Vec<DependencyDecision> {
+        let mut sizes: BTreeMap<String, usize> = BTreeMap::new();
+        for module in modules {
+            if let Some(package) = resolver::package_name_of(&module.path) {
+                *sizes.entry(package).or_default() += minifier::js::minify(&module.code).to_string().len();
+            }
         }
 
-        // Inline small utility libraries
-        matches!(dep_name, 
-            "is-array" | "is-string" | "is-number" | "camelcase" | "kebab-case" |
-            "uuid" | "lodash.get" | "lodash.set" | "lodash.pick"
-        )
-    }
+        let mut decisions = Vec::new();
+        for (package, minified_size) in sizes {
+            if options.bundled_packages.contains(&package) {
+                continue;
+            }
+            let (inlined, reason) = if self.should_exclude_dependency(&package, options) {
+                (false, "excluded".to_string())
+            } else if self.config.bundle.force_inline.contains(&package) {
+                (true, "listed in force_inline".to_string())
+            } else if minified_size <= options.max_inline_size {
+                (true, format!("within the {} byte inline limit", options.max_inline_size))
+            } else {
+                (false, format!("over the {} byte inline limit", options.max_inline_size))
+            };
 
-    /// Convert dependency name to variable name
-    fn dependency_to_variable_name(&self, dep_name: &str) -> String {
-        dep_name
-            .replace(['/', '-', '.', '@'], "_")
-            .replace(|c: char| !c.is_alphanumeric() && c != '_', "")
+            if inlined {
+                info!("Hybrid: inlining {} ({} bytes minified, {})", package, minified_size, reason);
+            } else {
+                info!("Hybrid: leaving out {} ({} bytes minified, {})", package, minified_size, reason);
+                if !self.config.bundle.externals.contains_key(&package) {
+                    warn!(
+                        "No global configured for {} in [bundle.externals]; expecting `{}`",
+                        package,
+                        self.dependency_to_global_name(&package)
+                    );
+                }
+            }
+            decisions.push(DependencyDecision { package, minified_size, inlined, reason });
+        }
+        decisions
     }
 
     /// Convert dependency name to global name
@@ -416,97 +899,12 @@ impl Bundler {
             "jquery" => "$".to_string(),
             "moment" => "moment".to_string(),
             "uuid" => "uuid".to_string(),
-            _ => dep_name.replace(['/', '-'], "_"),
+            _ => dep_name.replace(['/', '-', '.', '@'], "_"),
         }
     }
 
-    /// Optimize the bundled code
-    fn optimize_bundle(&self, code: &str, options: &BundleOptions) -> PaktoResult<String> {
-        let mut optimized = code.to_string();
-
-        if options.deduplicate {
-            optimized = self.deduplicate_code(&optimized)?;
-        }
-
-        // Remove unnecessary comments in production
-        optimized = self.clean_comments(&optimized)?;
-
-        // Remove extra whitespace
-        optimized = self.clean_whitespace(&optimized);
-
-        Ok(optimized)
-    }
-
-    /// Remove duplicate code sections
-    fn deduplicate_code(&self, code: &str) -> PaktoResult<String> {
-        // Simple deduplication of variable declarations
-        let lines: Vec<&str> = code.lines().collect();
-        let mut seen_vars = HashSet::new();
-        let mut deduplicated_lines = Vec::new();
-
-        for line in lines {
-            let trimmed = line.trim();
-
-            // Check for variable declarations
-            if trimmed.starts_with("var ") {
-                if let Some(var_name) = trimmed.split_whitespace().nth(1) {
-                    let var_name = var_name.trim_end_matches(&['=', ';'][..]);
-                    if seen_vars.contains(var_name) {
-                        continue; // Skip duplicate variable declaration
-                    }
-                    seen_vars.insert(var_name.to_string());
-                }
-            }
-
-            deduplicated_lines.push(line);
-        }
-
-        Ok(deduplicated_lines.join("\n"))
-    }
-
-    /// Clean up comments for production
-    fn clean_comments(&self, code: &str) -> PaktoResult<String> {
-        // Remove single-line comments but keep important ones
-        let comment_regex = Regex::new(r"^\s*//(?!\s*===).*$")?;
-        let lines: Vec<String> = code.lines()
-            .map(|line| {
-                if comment_regex.is_match(line) && !line.contains("@") {
-                    String::new() // Remove non-essential comments
-                } else {
-                    line.to_string()
-                }
-            })
-            .collect();
-
-        Ok(lines.join("\n"))
-    }
-
-    /// Clean up whitespace
-    fn clean_whitespace(&self, code: &str) -> String {
-        // Remove trailing whitespace and excessive blank lines
-        let lines: Vec<String> = code.lines()
-            .map(|line| line.trim_end().to_string())
-            .collect();
-
-        let mut cleaned_lines = Vec::new();
-        let mut prev_was_empty = false;
-
-        for line in lines {
-            let is_empty = line.trim().is_empty();
-
-            if !is_empty || !prev_was_empty {
-                cleaned_lines.push(line);
-            }
-
-            prev_was_empty = is_empty;
-        }
-
-        cleaned_lines.join("\n")
-    }
-
-    /// Validate the bundled code
+    /// Check the bundled code against `bundle.max_size`
     fn validate_bundle(&self, code: &str) -> PaktoResult<()> {
-        // Check bundle size
         if code.len() > self.config.bundle.max_size {
             return Err(PaktoError::BundleTooLarge {
                 size: code.len(),
@@ -514,29 +912,33 @@ impl Bundler {
             });
         }
 
-        // Basic syntax validation
-        let open_braces = code.matches('{').count();
-        let close_braces = code.matches('}').count();
-        if open_braces != close_braces {
-            return Err(PaktoError::TransformError {
-                message: format!("Unbalanced braces in bundle: {} open, {} close", open_braces, close_braces),
-                source: None,
-            });
-        }
-
-        let open_parens = code.matches('(').count();
-        let close_parens = code.matches(')').count();
-        if open_parens != close_parens {
-            return Err(PaktoError::TransformError {
-                message: format!("Unbalanced parentheses in bundle: {} open, {} close", open_parens, close_parens),
-                source: None,
-            });
-        }
-
         Ok(())
     }
 }
 
+/// Names the package entry exports, for formats with named exports
+fn entry_exports(modules: &[ModuleSource]) -> Vec<String> {
+    modules.first()
+        .and_then(|entry| js::parse_program(&entry.code, js::syntax_for_path(&entry.path)).ok())
+        .map(|program| js::export_names(&program))
+        .unwrap_or_default()
+}
+
+/// Chunk name usable in a file name
+fn chunk_file_name(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect();
+    if name.is_empty() { "chunk".to_string() } else { name }
+}
+
+/// Name of the OutSystems Script resource holding a chunk file
+fn chunk_resource_name(stem: &str) -> String {
+    stem.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -550,29 +952,287 @@ mod tests {
 
         let transformed = TransformedPackage {
             files_processed: 1,
-            code: "const x = 1;".to_string(),
+            code: String::new(),
+            modules: vec![ModuleSource {
+                path: PathBuf::from("index.js"),
+                code: "const x = 1;".to_string(),
+                dependencies: BTreeMap::new(),
+                dynamic_imports: BTreeSet::new(),
+            }],
             source_map: None,
+            styles: vec![],
+            wasm_modules: vec![],
+            concatenated_modules: 0,
+            entry_groups: vec![],
+            bundled_packages: vec![],
+            duplicate_packages: vec![],
+            issues: vec![],
         };
 
-        let result = bundler.bundle(&transformed, &BundleStrategy::Inline, &[]).await;
+        let result = bundler.bundle(&transformed, &BundleStrategy::Inline, &EsTarget::Es5, &[], "index").await;
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_fail_on_duplicates() {
+        let modules = vec![
+            source("index.js", "require('tslib'); require('a');", &[
+                ("tslib", Resolution::Module(PathBuf::from("node_modules/tslib/tslib.js"))),
+                ("a", Resolution::Module(PathBuf::from("node_modules/a/index.js"))),
+            ]),
+            source("node_modules/tslib/tslib.js", "module.exports = 2;", &[]),
+            source("node_modules/a/index.js", "require('tslib');", &[
+                ("tslib", Resolution::Module(PathBuf::from("node_modules/a/node_modules/tslib/tslib.js"))),
+            ]),
+            source("node_modules/a/node_modules/tslib/tslib.js", "module.exports = 1;", &[]),
+        ];
+        let duplicate_packages = crate::duplicates::find_duplicates(&modules, |root| root.display().to_string());
+        let transformed = TransformedPackage {
+            files_processed: 4,
+            code: String::new(),
+            modules,
+            source_map: None,
+            styles: vec![],
+            wasm_modules: vec![],
+            concatenated_modules: 0,
+            entry_groups: vec![],
+            bundled_packages: vec![],
+            duplicate_packages,
+            issues: vec![],
+        };
+
+        let mut config = Config::default();
+        let bundled = Bundler::new(&config).bundle(&transformed, &BundleStrategy::Inline, &EsTarget::Es5, &[], "index").await.unwrap();
+        assert_eq!(bundled.duplicate_packages.len(), 1);
+        assert_eq!(bundled.duplicate_packages[0].name, "tslib");
+
+        // Left out of the bundle, the copies do not count
+        let bundled = Bundler::new(&config).bundle(&transformed, &BundleStrategy::External, &EsTarget::Es5, &[], "index").await.unwrap();
+        assert!(bundled.duplicate_packages.is_empty());
+
+        config.bundle.fail_on_duplicates = true;
+        let result = Bundler::new(&config).bundle(&transformed, &BundleStrategy::Inline, &EsTarget::Es5, &[], "index").await;
+        assert!(matches!(result, Err(PaktoError::DuplicatePackages { .. })));
+    }
+
     #[test]
-    fn test_dependency_extraction() {
-        let config = Config::default();
+    fn test_deliver_styles() {
+        let styles = vec![Stylesheet {
+            path: PathBuf::from("styles.css"),
+            content: ".a { color: red; }".to_string(),
+        }];
+
+        let bundler = Bundler::new(&Config::default());
+        let (code, stylesheet) = bundler.deliver_styles("run();".to_string(), &styles);
+        assert!(stylesheet.is_none());
+        assert!(code.contains("document.getElementById('pakto-style-"));
+        assert!(code.contains(".a { color: red; }"));
+        assert!(code.ends_with("run();"));
+
+        let mut config = Config::default();
+        config.bundle.css = CssMode::Extract;
         let bundler = Bundler::new(&config);
+        let (code, stylesheet) = bundler.deliver_styles("run();".to_string(), &styles);
+        assert_eq!(code, "run();");
+        assert_eq!(stylesheet.unwrap(), "/* styles.css */\n.a { color: red; }\n");
+    }
 
-        let code = r#"
-            const lodash = require('lodash');
-            import moment from 'moment';
-            const { v4 } = require('uuid');
-        "#;
+    #[test]
+    fn test_deliver_wasm() {
+        let modules = vec![WasmModule {
+            path: PathBuf::from("pkg/core.wasm"),
+            content: vec![0x00, 0x61, 0x73, 0x6d],
+        }];
+
+        let bundler = Bundler::new(&Config::default());
+        let (code, resources) = bundler.deliver_wasm("run();".to_string(), &modules);
+        assert!(resources.is_empty());
+        assert!(code.contains("modules[\"pkg/core.wasm\"] = {\"base64\":\"AGFzbQ==\"};"));
+        assert!(code.ends_with("run();"));
+
+        let mut config = Config::default();
+        config.bundle.wasm = WasmMode::Separate;
+        config.bundle.wasm_base_url = "/MyApp/".to_string();
+        let bundler = Bundler::new(&config);
+        let (code, resources) = bundler.deliver_wasm("run();".to_string(), &modules);
+        assert_eq!(resources.len(), 1);
+        assert_eq!(resources[0].file_name, "core.wasm");
+        assert!(code.contains("{\"url\":\"/MyApp/core.wasm\"}"));
+    }
 
-        let deps = bundler.extract_dependencies(code).unwrap();
-        assert!(deps.contains(&"lodash".to_string()));
-        assert!(deps.contains(&"moment".to_string()));
-        assert!(deps.contains(&"uuid".to_string()));
+    fn source(path: &str, code: &str, dependencies: &[(&str, Resolution)]) -> ModuleSource {
+        ModuleSource {
+            path: PathBuf::from(path),
+            code: code.to_string(),
+            dependencies: dependencies.iter()
+                .map(|(specifier, resolution)| (specifier.to_string(), resolution.clone()))
+                .collect(),
+            dynamic_imports: BTreeSet::new(),
+        }
+    }
+
+    fn options() -> BundleOptions {
+        BundleOptions {
+            tree_shake: false,
+            bundled_packages: vec![],
+            inline_small_modules: false,
+            max_inline_size: 1000,
+            hybrid_inlined: BTreeSet::new(),
+            exclude_patterns: vec![],
+        }
+    }
+
+    #[test]
+    fn test_link_modules() {
+        let lodash = PathBuf::from("node_modules/lodash/lodash.js");
+        let modules = vec![
+            source("index.js", "module.exports = require('./util')(require('lodash'));", &[
+                ("./util", Resolution::Module(PathBuf::from("util.js"))),
+                ("lodash", Resolution::Module(lodash.clone())),
+                ("stream", Resolution::Builtin("stream".to_string())),
+            ]),
+            source("util.js", "module.exports = function (_) { return _; };", &[]),
+            source("node_modules/lodash/lodash.js", "module.exports = {};", &[]),
+        ];
+
+        let bundler = Bundler::new(&Config::default());
+        let (code, bundled, chunks) = bundler.link_modules(&modules, &[], &BundleStrategy::Inline, &options(), "index");
+        assert_eq!(bundled, vec!["lodash".to_string()]);
+        assert!(chunks.is_empty());
+        assert!(code.contains("// [0] index.js\n[function (module, exports, require) {\nmodule.exports = require('./util')(require('lodash'));\n}, {\"./util\":1,\"lodash\":2,\"stream\":3}],"));
+        assert!(code.contains("// [2] node_modules/lodash/lodash.js"));
+        assert!(code.contains("UnavailableModulePolyfill(\"stream\", [])"));
+        assert!(code.contains("var module = { exports: __pakto_require__(0) };"));
+
+        let (code, bundled, _) = bundler.link_modules(&modules, &[], &BundleStrategy::External, &options(), "index");
+        assert!(bundled.is_empty());
+        assert!(code.contains("// [2] external lodash"));
+        assert!(code.contains("try { value = (_); } catch (e) {}"));
+        assert!(!code.contains("node_modules/lodash/lodash.js"));
+    }
+
+    #[test]
+    fn test_hybrid_decisions() {
+        let big = format!("module.exports = {:?};", "x".repeat(2000));
+        let modules = vec![
+            source("index.js", "module.exports = [require('tiny'), require('big')];", &[
+                ("tiny", Resolution::Module(PathBuf::from("node_modules/tiny/index.js"))),
+                ("big", Resolution::Module(PathBuf::from("node_modules/big/index.js"))),
+            ]),
+            source("node_modules/tiny/index.js", "module.exports   =   1;", &[]),
+            source("node_modules/big/index.js", &big, &[]),
+        ];
+
+        let mut config = Config::default();
+        config.bundle.externals.insert("big".to_string(), "window.Big".to_string());
+        let bundler = Bundler::new(&config);
+        let decisions = bundler.hybrid_decisions(&modules, &options());
+        let summary: Vec<(&str, bool)> = decisions.iter()
+            .map(|decision| (decision.package.as_str(), decision.inlined))
+            .collect();
+        assert_eq!(summary, vec![("big", false), ("tiny", true)]);
+        assert!(decisions[1].minified_size < "module.exports   =   1;".len());
+        assert_eq!(decisions[0].reason, "over the 1000 byte inline limit");
+
+        let mut options = options();
+        options.hybrid_inlined.insert("tiny".to_string());
+        let (code, bundled, _) = bundler.link_modules(&modules, &[], &BundleStrategy::Hybrid, &options, "index");
+        assert_eq!(bundled, vec!["tiny".to_string()]);
+        assert!(code.contains("// [1] external big"));
+        assert!(code.contains("try { value = (window.Big); }"));
+
+        config.bundle.force_inline.push("big".to_string());
+        let bundler = Bundler::new(&config);
+        let decisions = bundler.hybrid_decisions(&modules, &options);
+        assert!(decisions[0].inlined);
+        assert_eq!(decisions[0].reason, "listed in force_inline");
+    }
+
+    #[test]
+    fn test_configured_externals() {
+        let modules = vec![
+            source("index.js", "module.exports = require('react');", &[
+                ("react", Resolution::External),
+            ]),
+        ];
+
+        let mut config = Config::default();
+        config.bundle.externals.insert("react".to_string(), "window.React".to_string());
+        let bundler = Bundler::new(&config);
+        let (code, bundled, _) = bundler.link_modules(&modules, &[], &BundleStrategy::Inline, &options(), "index");
+        assert!(bundled.is_empty());
+        assert!(code.contains("// [1] external react\n[function (module) {\n  var value;\n  try { value = (window.React); } catch (e) {}"));
+        assert!(code.contains("throw new Error(\"External dependency 'react' is not available: expected the global window.React to be loaded before this bundle\");"));
+        assert!(code.contains("{\"react\":1}"));
+    }
+
+    #[test]
+    fn test_external_subpaths() {
+        let modules = vec![
+            source("index.js", "module.exports = require('react/jsx-runtime');", &[
+                ("react/jsx-runtime", Resolution::External),
+            ]),
+        ];
+
+        let mut config = Config::default();
+        config.bundle.externals.insert("react".to_string(), "window.React".to_string());
+        let bundler = Bundler::new(&config);
+        let (code, _, _) = bundler.link_modules(&modules, &[], &BundleStrategy::Inline, &options(), "index");
+        assert!(code.contains("// [1] external react/jsx-runtime\n[function (module) {\n  var value;\n  try { value = (window.React); } catch (e) {}"));
+        assert!(code.contains("{\"react/jsx-runtime\":1}"));
+    }
+
+    #[test]
+    fn test_split_chunks() {
+        let mut index = source("index.js", "require.import('./lazy'); require('./core');", &[
+            ("./lazy", Resolution::Module(PathBuf::from("lazy.js"))),
+            ("./core", Resolution::Module(PathBuf::from("core.js"))),
+        ]);
+        index.dynamic_imports.insert("./lazy".to_string());
+        let modules = vec![
+            index,
+            source("core.js", "module.exports = 1;", &[]),
+            source("charts.js", "require('./shared'); require('./core');", &[
+                ("./shared", Resolution::Module(PathBuf::from("shared.js"))),
+                ("./core", Resolution::Module(PathBuf::from("core.js"))),
+            ]),
+            source("lazy.js", "require('./shared');", &[
+                ("./shared", Resolution::Module(PathBuf::from("shared.js"))),
+            ]),
+            source("shared.js", "module.exports = 2;", &[]),
+        ];
+        let groups = vec![EntryGroup {
+            name: "charts".to_string(),
+            entries: vec![("./charts".to_string(), PathBuf::from("charts.js"))],
+        }];
+
+        let mut config = Config::default();
+        config.bundle.split.dynamic_imports = true;
+        config.bundle.split.base_url = "/MyApp/".to_string();
+        let bundler = Bundler::new(&config);
+        let (code, _, chunks) = bundler.link_modules(&modules, &groups, &BundleStrategy::Inline, &options(), "lib");
+
+        let names: Vec<&str> = chunks.iter().map(|chunk| chunk.name.as_str()).collect();
+        assert_eq!(names, vec!["charts", "lazy", "shared"]);
+        assert_eq!(chunks[0].file_name, "lib.charts.js");
+        assert_eq!(chunks[0].modules, vec![PathBuf::from("charts.js")]);
+        assert_eq!(chunks[2].modules, vec![PathBuf::from("shared.js")]);
+        assert!(chunks[1].code.starts_with("(function (global) {\n(global.__pakto_chunks__ = global.__pakto_chunks__ || []).push([\"lib\", \"lazy\", {\n"));
+
+        // The bundle keeps core.js and leaves holes for chunk modules
+        assert!(code.contains("// [1] charts.js (chunk charts)\nnull,"));
+        assert!(code.contains("// [2] core.js\n[function"));
+        assert!(code.contains("\"charts\":\"/MyApp/lib.charts.js\""));
+        assert!(code.contains("var __pakto_chunk_deps__ = {\"1\":[\"shared\",\"charts\"],\"3\":[\"shared\",\"lazy\"]};"));
+        assert!(code.contains("var __pakto_groups__ = {\"charts\":{\"./charts\":1}};"));
+        assert!(code.contains("Object.defineProperty(module.exports, 'loadChunk'"));
+
+        config.bundle.split.loading = ChunkLoading::Resource;
+        config.bundle.split.base_url = String::new();
+        config.bundle.split.resource_module = "MyApp".to_string();
+        let bundler = Bundler::new(&config);
+        let (code, _, _) = bundler.link_modules(&modules, &groups, &BundleStrategy::Inline, &options(), "my-lib");
+        assert!(code.contains("\"charts\":\"scripts/MyApp.my_lib_charts.js\""));
     }
 
     #[test]
@@ -580,26 +1240,17 @@ mod tests {
         let config = Config::default();
         let bundler = Bundler::new(&config);
 
-        assert_eq!(bundler.dependency_to_variable_name("lodash"), "lodash");
-        assert_eq!(bundler.dependency_to_variable_name("@types/node"), "_types_node");
-        assert_eq!(bundler.dependency_to_variable_name("./local-file"), "__local_file");
-
         assert_eq!(bundler.dependency_to_global_name("lodash"), "_");
         assert_eq!(bundler.dependency_to_global_name("jquery"), "$");
         assert_eq!(bundler.dependency_to_global_name("moment"), "moment");
+        assert_eq!(bundler.dependency_to_global_name("@scope/some.lib"), "_scope_some_lib");
     }
 
     #[test]
     fn test_should_exclude_dependency() {
         let config = Config::default();
         let bundler = Bundler::new(&config);
-        let options = BundleOptions {
-            tree_shake: false,
-            deduplicate: false,
-            inline_small_modules: false,
-            max_inline_size: 1000,
-            exclude_patterns: vec![],
-        };
+        let options = options();
 
         assert!(bundler.should_exclude_dependency("fs", &options));
         assert!(bundler.should_exclude_dependency("child_process", &options));
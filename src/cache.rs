@@ -0,0 +1,249 @@
+//! Persistent cache of per-file build results
+//!
+//! File analyses and transformed modules are stored under
+//! `<cache.directory>/build`, one JSON file per result, keyed by a SHA-256
+//! over the pakto version, the kind of result and everything it depends
+//! on: the file's path and content and the options that change it. A
+//! changed input gives a new key, so entries never go stale and are
+//! reused by any later conversion with the same inputs. Debug builds also
+//! key on the executable's modification time, so results from before a
+//! rebuild are not reused while pakto itself is being changed.
+//!
+//! Reading an entry refreshes its modification time. Entries left unused
+//! for longer than `cache.ttl` are ignored, and [`BuildCache::prune`]
+//! deletes them, so the cache only holds results of recent builds.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use crate::config::CacheConfig;
+use crate::converter::FileContent;
+
+/// Identifies the pakto build that produced a result
+static BUILD_ID: LazyLock<String> = LazyLock::new(|| {
+    let version = env!("CARGO_PKG_VERSION").to_string();
+    if !cfg!(debug_assertions) {
+        return version;
+    }
+    let modified = std::env::current_exe()
+        .and_then(std::fs::metadata)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("{}+{}", version, modified)
+});
+
+/// Cached build results, or nothing when `cache.enabled` is off
+#[derive(Debug, Default)]
+pub struct BuildCache {
+    directory: Option<PathBuf>,
+    /// How long an unused entry is kept
+    ttl: Duration,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+/// Key of a cached result, built from the inputs it depends on
+pub struct CacheKey {
+    hasher: Sha256,
+}
+
+impl CacheKey {
+    /// Start a key for one kind of result, e.g. `transform`
+    pub fn new(kind: &str) -> Self {
+        Self { hasher: Sha256::new() }
+            .with(BUILD_ID.as_bytes())
+            .with(kind)
+    }
+
+    /// Add an input. Inputs are length-prefixed, so `("ab", "c")` and
+    /// `("a", "bc")` give different keys.
+    pub fn with(mut self, input: impl AsRef<[u8]>) -> Self {
+        let input = input.as_ref();
+        self.hasher.update((input.len() as u64).to_le_bytes());
+        self.hasher.update(input);
+        self
+    }
+
+    /// Add an input through its JSON form
+    pub fn with_json(self, input: &impl Serialize) -> Self {
+        self.with(serde_json::to_vec(input).unwrap_or_default())
+    }
+
+    /// Add every file of a package, for results that read other files
+    /// than their own
+    pub fn with_files(mut self, files: &HashMap<PathBuf, FileContent>) -> Self {
+        let mut paths: Vec<&PathBuf> = files.keys().collect();
+        paths.sort();
+        for path in paths {
+            self = self.with(path.to_string_lossy().as_bytes()).with(files[path].as_bytes());
+        }
+        self
+    }
+
+    /// Hex digest of the inputs so far
+    pub fn digest(&self) -> String {
+        self.hasher.clone().finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+impl BuildCache {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            directory: config.enabled.then(|| config.directory.join("build")),
+            ttl: Duration::from_secs(config.ttl),
+            ..Self::default()
+        }
+    }
+
+    /// The result stored under a key, if any
+    pub fn get<T: DeserializeOwned>(&self, key: &CacheKey) -> Option<T> {
+        let path = self.entry_path(key)?;
+        let value = (!self.is_expired(&path))
+            .then(|| std::fs::read(&path).ok())
+            .flatten()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+        if value.is_some() {
+            touch(&path);
+        }
+        match value {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        value
+    }
+
+    /// Store a result. Failing to write only costs a later rebuild.
+    pub fn put<T: Serialize>(&self, key: &CacheKey, value: &T) {
+        let Some(path) = self.entry_path(key) else { return };
+        if let Err(e) = store(&path, value) {
+            debug!("Failed to write build cache entry {}: {}", path.display(), e);
+        }
+    }
+
+    /// Delete the entries left unused for longer than the TTL, returning
+    /// how many were removed
+    pub fn prune(&self) -> usize {
+        let Some(directory) = &self.directory else { return 0 };
+        let expired: Vec<PathBuf> = walkdir::WalkDir::new(directory)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file() && self.is_expired(entry.path()))
+            .map(|entry| entry.into_path())
+            .collect();
+        let removed = expired.iter()
+            .filter(|path| std::fs::remove_file(path).is_ok())
+            .count();
+        if removed > 0 {
+            debug!("Removed {} expired build cache entr{}", removed, if removed == 1 { "y" } else { "ies" });
+        }
+        removed
+    }
+
+    /// Results read from the cache so far
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Results computed because the cache had none
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Whether an entry was last used longer ago than the TTL
+    fn is_expired(&self, path: &Path) -> bool {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > self.ttl)
+    }
+
+    fn entry_path(&self, key: &CacheKey) -> Option<PathBuf> {
+        let key = key.digest();
+        Some(self.directory.as_ref()?.join(&key[..2]).join(format!("{}.json", key)))
+    }
+}
+
+/// Mark an entry as used now. Failing to only lets it expire sooner.
+fn touch(path: &Path) {
+    let result = std::fs::File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+    if let Err(e) = result {
+        debug!("Failed to refresh build cache entry {}: {}", path.display(), e);
+    }
+}
+
+/// Write an entry through a temporary file, so a concurrent reader never
+/// sees it half written
+fn store<T: Serialize>(path: &Path, value: &T) -> std::io::Result<()> {
+    let directory = path.parent().unwrap_or(Path::new("."));
+    std::fs::create_dir_all(directory)?;
+    let mut file = tempfile::NamedTempFile::new_in(directory)?;
+    serde_json::to_writer(&mut file, value)?;
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_build_cache() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            ..CacheConfig::default()
+        };
+        let cache = BuildCache::new(&config);
+
+        let key = || CacheKey::new("transform").with("index.js").with("module.exports = 1;");
+        assert_eq!(cache.get::<String>(&key()), None);
+        cache.put(&key(), &"cached".to_string());
+        assert_eq!(cache.get::<String>(&key()), Some("cached".to_string()));
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+        // Inputs are told apart by where they split
+        let other = CacheKey::new("transform").with("index.jsm").with("odule.exports = 1;");
+        assert_eq!(cache.get::<String>(&other), None);
+
+        let disabled = BuildCache::new(&CacheConfig { enabled: false, ..config });
+        assert_eq!(disabled.get::<String>(&key()), None);
+        disabled.put(&key(), &"ignored".to_string());
+    }
+
+    #[test]
+    fn test_expired_entries() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            ttl: 60,
+            ..CacheConfig::default()
+        };
+        let cache = BuildCache::new(&config);
+        let key = CacheKey::new("analysis").with("index.js");
+        cache.put(&key, &1);
+        assert_eq!(cache.prune(), 0);
+
+        // An entry unused for longer than the TTL is a miss and is pruned
+        let path = cache.entry_path(&key).unwrap();
+        let old = SystemTime::now() - Duration::from_secs(120);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(old).unwrap();
+        assert_eq!(cache.get::<u32>(&key), None);
+        assert_eq!(cache.prune(), 1);
+        assert!(!path.exists());
+    }
+}
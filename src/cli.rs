@@ -25,11 +25,13 @@ pub struct Cli {
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// Convert an NPM package to OutSystems-compatible JavaScript
+    /// Convert NPM packages to OutSystems-compatible JavaScript
     Convert {
-        /// NPM package name or local path
+        /// NPM package names or local paths; several are bundled together,
+        /// as are the `[bundle.packages]` in the configuration when none
+        /// is given
         #[arg(value_name = "PACKAGE")]
-        package: String,
+        packages: Vec<String>,
 
         /// Output file path
         #[arg(short, long, value_name = "FILE")]
@@ -63,6 +65,24 @@ pub enum Commands {
         #[arg(short, long, default_value = "inline")]
         strategy: BundleStrategy,
 
+        /// Module format of the bundle [default: output.format, or outsystems]
+        #[arg(short, long)]
+        format: Option<OutputFormat>,
+
+        /// Replace an expression with a constant at build time (repeatable)
+        #[arg(long = "define", value_name = "KEY=VALUE", value_parser = crate::defines::parse_define)]
+        defines: Vec<(String, serde_json::Value)>,
+
+        /// Write a size report next to the bundle: JSON and an HTML treemap
+        #[arg(long)]
+        report: bool,
+
+        /// Produce byte-identical output for identical inputs: the header
+        /// carries `SOURCE_DATE_EPOCH`, or the Unix epoch, instead of the
+        /// current time
+        #[arg(long)]
+        reproducible: bool,
+
         /// Perform dry run (analyze only, don't convert)
         #[arg(long)]
         dry_run: bool,
@@ -125,6 +145,42 @@ pub enum BundleStrategy {
     Hybrid,
 }
 
+/// How the bundle exposes the package's exports
+#[derive(Clone, Copy, Default, ValueEnum, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// UMD wrapper that sets the OutSystems global or namespace
+    #[default]
+    #[value(name = "outsystems")]
+    OutSystems,
+
+    /// CommonJS, AMD or a browser global, whichever is present
+    #[value(name = "umd")]
+    Umd,
+
+    /// Script that sets a browser global
+    #[value(name = "iife")]
+    Iife,
+
+    /// Native ES module with default and named exports
+    #[value(name = "esm")]
+    Esm,
+
+    /// CommonJS module setting `module.exports`
+    #[value(name = "cjs", alias = "commonjs")]
+    #[serde(alias = "commonjs")]
+    Cjs,
+
+    /// Anonymous AMD module
+    #[value(name = "amd")]
+    Amd,
+
+    /// SystemJS `System.register` module
+    #[value(name = "system", alias = "systemjs")]
+    #[serde(alias = "systemjs")]
+    System,
+}
+
 impl Default for EsTarget {
     fn default() -> Self {
         Self::Es5
@@ -137,6 +193,13 @@ impl Default for BundleStrategy {
     }
 }
 
+impl BundleStrategy {
+    /// Whether unused exports and modules are removed from the bundle
+    pub fn tree_shakes(&self) -> bool {
+        matches!(self, Self::Selective)
+    }
+}
+
 pub fn generate_completions(shell: Shell) {
     let mut cmd = Cli::command();
     let bin_name = cmd.get_name().to_string();
@@ -157,5 +220,6 @@ mod tests {
     fn test_default_values() {
         assert_eq!(EsTarget::default(), EsTarget::Es5);
         assert_eq!(BundleStrategy::default(), BundleStrategy::Inline);
+        assert_eq!(OutputFormat::default(), OutputFormat::OutSystems);
     }
 }
\ No newline at end of file
@@ -0,0 +1,339 @@
+//! Combining several packages into one bundle
+//!
+//! The packages are laid out as the dependencies of a generated root
+//! package, whose entry exports each of them under its own name. Their
+//! `node_modules` trees are merged the way npm would install them side by
+//! side: a dependency is hoisted to the top level unless a different
+//! version already sits there, in which case it stays nested under the
+//! package that needs it, together with the dependencies that require it.
+//! Copies of the same name and version are kept
+//! once, so shared dependencies and the polyfills they use are bundled
+//! once.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+use crate::converter::{FileContent, PackageData};
+
+/// A package to include in a combined bundle
+pub struct PackageEntry {
+    /// Property the package is exposed as
+    pub exposed_name: String,
+    pub data: PackageData,
+}
+
+/// A combined package tree
+pub struct CombinedPackage {
+    pub data: PackageData,
+    /// Installed copies left out because the same version was present
+    pub deduplicated: usize,
+}
+
+/// Lay out packages as the dependencies of a root package named `name`
+pub fn combine_packages(name: &str, packages: Vec<PackageEntry>) -> CombinedPackage {
+    let mut files: HashMap<PathBuf, FileContent> = HashMap::new();
+    let mut versions: HashMap<String, String> = HashMap::new();
+    let mut dependencies = serde_json::Map::new();
+    let mut exposed = Vec::new();
+    let mut deduplicated = 0;
+
+    // The requested packages take the top level first, so a dependency
+    // never displaces one of them
+    let mut trees = Vec::new();
+    for package in packages {
+        let package_name = manifest_name(&package.data.package_json)
+            .unwrap_or_else(|| package.exposed_name.clone());
+        let version = manifest_version(&package.data.package_json);
+        let directory = format!("node_modules/{}", package_name);
+        let (own, installed) = split_tree(package.data.files);
+
+        match versions.get(&directory) {
+            Some(existing) if *existing == version => deduplicated += 1,
+            Some(existing) => {
+                debug!("{} requested at {} and {}; keeping the first", package_name, existing, version);
+            }
+            None => {
+                insert_tree(&mut files, &directory, own);
+                versions.insert(directory, version.clone());
+            }
+        }
+
+        dependencies.insert(package_name.clone(), Value::String(version));
+        exposed.push((package.exposed_name, package_name.clone()));
+        trees.push((package_name, installed));
+    }
+
+    for (package_name, installed) in trees {
+        let nested = nested_packages(&installed, &versions);
+        for (directory, (version, tree)) in installed {
+            let top = format!("node_modules/{}", directory);
+            if nested.contains(&directory) {
+                let nested = format!("node_modules/{}/node_modules/{}", package_name, directory);
+                debug!("Nesting {}@{} at {}", directory, version, nested);
+                insert_tree(&mut files, &nested, tree);
+                continue;
+            }
+            match versions.get(&top) {
+                Some(_) => deduplicated += 1,
+                None => {
+                    insert_tree(&mut files, &top, tree);
+                    versions.insert(top, version);
+                }
+            }
+        }
+    }
+
+    let entry = exposed.iter()
+        .map(|(exposed_name, package_name)| format!(
+            "  {}: require({})",
+            Value::String(exposed_name.clone()),
+            Value::String(package_name.clone())
+        ))
+        .collect::<Vec<_>>()
+        .join(",\n");
+    files.insert(PathBuf::from("index.js"), FileContent::from(format!("module.exports = {{\n{}\n}};\n", entry)));
+
+    // The requested packages are what the bundle is for, so they are
+    // bundled whatever the strategy
+    let bundled: Vec<&String> = dependencies.keys().collect();
+    let package_json = json!({
+        "name": name,
+        "version": "0.0.0",
+        "main": "index.js",
+        "dependencies": dependencies,
+        "bundleDependencies": bundled,
+    });
+    files.insert(PathBuf::from("package.json"), FileContent::from(package_json.to_string()));
+
+    info!(
+        "Combined {} package(s), {} shared dependenc{} deduplicated",
+        exposed.len(),
+        deduplicated,
+        if deduplicated == 1 { "y" } else { "ies" }
+    );
+    CombinedPackage {
+        data: PackageData {
+            total_size: files.values().map(|content| content.len()).sum(),
+            files,
+            package_json,
+        },
+        deduplicated,
+    }
+}
+
+/// Property name a package specifier is exposed as by default:
+/// `@scope/some-lib@1.2.0` becomes `someLib`
+pub fn exposed_name(specifier: &str) -> String {
+    let name = match specifier.rfind('@') {
+        Some(index) if index > 0 => &specifier[..index],
+        _ => specifier,
+    };
+    let name = name.rsplit('/').next().unwrap_or(name);
+
+    let mut exposed = String::new();
+    let mut upper = false;
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' || c == '$' {
+            if upper && !exposed.is_empty() {
+                exposed.extend(c.to_uppercase());
+            } else {
+                exposed.push(c);
+            }
+            upper = false;
+        } else {
+            upper = true;
+        }
+    }
+    exposed
+}
+
+/// Files of a package tree split into the package's own files and the
+/// packages installed at the top of its `node_modules`, by directory name,
+/// with their versions
+type InstalledPackages = BTreeMap<String, (String, Vec<(PathBuf, FileContent)>)>;
+
+fn split_tree(files: HashMap<PathBuf, FileContent>) -> (Vec<(PathBuf, FileContent)>, InstalledPackages) {
+    let mut own = Vec::new();
+    let mut installed: InstalledPackages = BTreeMap::new();
+
+    for (path, content) in files {
+        let text = path.to_string_lossy().replace('\\', "/");
+        let Some(rest) = text.strip_prefix("node_modules/") else {
+            own.push((path, content));
+            continue;
+        };
+        let mut parts = rest.splitn(3, '/');
+        let directory = match (parts.next(), parts.next()) {
+            (Some(scope), Some(name)) if scope.starts_with('@') => format!("{}/{}", scope, name),
+            (Some(name), Some(_)) => name.to_string(),
+            _ => {
+                own.push((path, content));
+                continue;
+            }
+        };
+        let relative = PathBuf::from(&rest[directory.len() + 1..]);
+        installed.entry(directory).or_default().1.push((relative, content));
+    }
+
+    for (version, tree) in installed.values_mut() {
+        *version = tree.iter()
+            .find(|(path, _)| path == Path::new("package.json"))
+            .and_then(|(_, content)| serde_json::from_slice::<Value>(content.as_bytes()).ok())
+            .map(|manifest| manifest_version(&manifest))
+            .unwrap_or_default();
+    }
+    (own, installed)
+}
+
+/// Installed packages that have to stay nested under the package that
+/// installed them: those whose version differs from the one at the top
+/// level, and those that require a nested package, which would otherwise
+/// resolve the top-level version once hoisted
+fn nested_packages(installed: &InstalledPackages, versions: &HashMap<String, String>) -> BTreeSet<String> {
+    let mut nested: BTreeSet<String> = installed.iter()
+        .filter(|(directory, (version, _))| {
+            versions.get(&format!("node_modules/{}", directory))
+                .is_some_and(|existing| existing != version)
+        })
+        .map(|(directory, _)| directory.clone())
+        .collect();
+
+    let required: Vec<(&String, Vec<String>)> = installed.iter()
+        .map(|(directory, (_, tree))| (directory, required_packages(tree)))
+        .collect();
+    loop {
+        let before = nested.len();
+        for (directory, requires) in &required {
+            if requires.iter().any(|name| nested.contains(name)) {
+                nested.insert(directory.to_string());
+            }
+        }
+        if nested.len() == before {
+            return nested;
+        }
+    }
+}
+
+/// Packages a package tree's manifest depends on that it does not install
+/// in its own `node_modules`, and so resolves from its parent's
+fn required_packages(tree: &[(PathBuf, FileContent)]) -> Vec<String> {
+    let Some(manifest) = tree.iter()
+        .find(|(path, _)| path == Path::new("package.json"))
+        .and_then(|(_, content)| serde_json::from_slice::<Value>(content.as_bytes()).ok())
+    else {
+        return Vec::new();
+    };
+
+    ["dependencies", "optionalDependencies", "peerDependencies"].iter()
+        .filter_map(|field| manifest.get(field).and_then(|v| v.as_object()))
+        .flat_map(|dependencies| dependencies.keys())
+        .filter(|name| {
+            let own = Path::new("node_modules").join(name.as_str()).join("package.json");
+            !tree.iter().any(|(path, _)| *path == own)
+        })
+        .cloned()
+        .collect()
+}
+
+fn insert_tree(files: &mut HashMap<PathBuf, FileContent>, directory: &str, tree: Vec<(PathBuf, FileContent)>) {
+    for (path, content) in tree {
+        files.insert(Path::new(directory).join(path), content);
+    }
+}
+
+fn manifest_name(manifest: &Value) -> Option<String> {
+    manifest.get("name").and_then(|v| v.as_str()).map(str::to_string)
+}
+
+fn manifest_version(manifest: &Value) -> String {
+    manifest.get("version").and_then(|v| v.as_str()).unwrap_or("0.0.0").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, files: &[(&str, &str)]) -> PackageEntry {
+        let files: HashMap<PathBuf, FileContent> = files.iter()
+            .map(|(path, content)| (PathBuf::from(path), FileContent::from(content.to_string())))
+            .collect();
+        let package_json = serde_json::from_slice(files[Path::new("package.json")].as_bytes()).unwrap();
+        PackageEntry {
+            exposed_name: exposed_name(name),
+            data: PackageData { total_size: 0, files, package_json },
+        }
+    }
+
+    #[test]
+    fn test_exposed_name() {
+        assert_eq!(exposed_name("qrcode"), "qrcode");
+        assert_eq!(exposed_name("dayjs@1.11.0"), "dayjs");
+        assert_eq!(exposed_name("@scope/some-lib@^2"), "someLib");
+        assert_eq!(exposed_name("lodash.debounce"), "lodashDebounce");
+    }
+
+    #[test]
+    fn test_combine_packages() {
+        let combined = combine_packages("packages", vec![
+            package("a", &[
+                ("package.json", r#"{"name":"a","version":"1.0.0"}"#),
+                ("index.js", "module.exports = require('shared');"),
+                ("node_modules/shared/package.json", r#"{"name":"shared","version":"2.0.0"}"#),
+                ("node_modules/shared/index.js", "module.exports = 2;"),
+                ("node_modules/@s/util/package.json", r#"{"name":"@s/util","version":"1.0.0"}"#),
+                ("node_modules/@s/util/index.js", "module.exports = 'u';"),
+            ]),
+            package("b", &[
+                ("package.json", r#"{"name":"b","version":"3.0.0"}"#),
+                ("index.js", "module.exports = require('shared') + require('@s/util');"),
+                ("node_modules/shared/package.json", r#"{"name":"shared","version":"1.0.0"}"#),
+                ("node_modules/shared/index.js", "module.exports = 1;"),
+                ("node_modules/@s/util/package.json", r#"{"name":"@s/util","version":"1.0.0"}"#),
+                ("node_modules/@s/util/index.js", "module.exports = 'u';"),
+            ]),
+        ]);
+
+        let files = &combined.data.files;
+        assert_eq!(combined.deduplicated, 1);
+        assert!(files.contains_key(Path::new("node_modules/a/index.js")));
+        assert!(files.contains_key(Path::new("node_modules/b/index.js")));
+        assert!(files.contains_key(Path::new("node_modules/@s/util/index.js")));
+        assert_eq!(files[Path::new("node_modules/shared/index.js")].as_text(), Some("module.exports = 2;"));
+        assert_eq!(files[Path::new("node_modules/b/node_modules/shared/index.js")].as_text(), Some("module.exports = 1;"));
+        assert_eq!(
+            files[Path::new("index.js")].as_text(),
+            Some("module.exports = {\n  \"a\": require(\"a\"),\n  \"b\": require(\"b\")\n};\n")
+        );
+        assert_eq!(combined.data.package_json["dependencies"], json!({ "a": "1.0.0", "b": "3.0.0" }));
+        assert_eq!(combined.data.package_json["bundleDependencies"], json!(["a", "b"]));
+    }
+    #[test]
+    fn test_nests_transitive_dependents() {
+        let combined = combine_packages("packages", vec![
+            package("a", &[
+                ("package.json", r#"{"name":"a","version":"1.0.0"}"#),
+                ("node_modules/shared/package.json", r#"{"name":"shared","version":"1.0.0"}"#),
+            ]),
+            package("b", &[
+                ("package.json", r#"{"name":"b","version":"1.0.0"}"#),
+                ("node_modules/foo/package.json", r#"{"name":"foo","version":"1.0.0","dependencies":{"shared":"^2.0.0"}}"#),
+                ("node_modules/foo/index.js", "module.exports = require('shared');"),
+                ("node_modules/bar/package.json", r#"{"name":"bar","version":"1.0.0","dependencies":{"foo":"^1.0.0"}}"#),
+                ("node_modules/baz/package.json", r#"{"name":"baz","version":"1.0.0","dependencies":{"shared":"^1.0.0"}}"#),
+                ("node_modules/baz/node_modules/shared/package.json", r#"{"name":"shared","version":"1.0.0"}"#),
+                ("node_modules/shared/package.json", r#"{"name":"shared","version":"2.0.0"}"#),
+            ]),
+        ]);
+
+        // foo needs shared@2 and bar needs foo, so both stay with it under
+        // b; baz ships its own shared and is hoisted
+        let files = &combined.data.files;
+        assert!(files.contains_key(Path::new("node_modules/b/node_modules/shared/package.json")));
+        assert!(files.contains_key(Path::new("node_modules/b/node_modules/foo/index.js")));
+        assert!(files.contains_key(Path::new("node_modules/b/node_modules/bar/package.json")));
+        assert!(!files.contains_key(Path::new("node_modules/foo/index.js")));
+        assert!(files.contains_key(Path::new("node_modules/baz/node_modules/shared/package.json")));
+    }
+}
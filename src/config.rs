@@ -1,8 +1,10 @@
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use crate::cli::{BundleStrategy, EsTarget};
+use crate::assets::AssetLoader;
+use crate::cli::{BundleStrategy, EsTarget, OutputFormat};
+use crate::resolver::package_name;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -23,6 +25,15 @@ pub struct Config {
 
     /// Custom templates
     pub templates: TemplateConfig,
+
+    /// Compile-time defines (e.g. `"process.env.NODE_ENV" = "production"`)
+    #[serde(default)]
+    pub defines: HashMap<String, serde_json::Value>,
+
+    /// Loaders for imported non-JavaScript files, by extension
+    /// (e.g. `svg = "text"`)
+    #[serde(default)]
+    pub loaders: HashMap<String, AssetLoader>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,6 +75,10 @@ pub struct OutputConfig {
     /// Include source maps
     #[serde(default)]
     pub source_maps: bool,
+
+    /// Module format of the bundle, unless `--format` is given
+    #[serde(default)]
+    pub format: OutputFormat,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -101,6 +116,189 @@ pub struct BundleConfig {
     /// Dependencies to always inline
     #[serde(default)]
     pub force_inline: Vec<String>,
+
+    /// Largest minified size in bytes of a dependency package the Hybrid
+    /// strategy inlines; a tenth of `max_size` when unset
+    #[serde(default)]
+    pub max_inline_size: Option<usize>,
+
+    /// How imported CSS is delivered
+    #[serde(default)]
+    pub css: CssMode,
+
+    /// How WebAssembly modules are delivered
+    #[serde(default)]
+    pub wasm: WasmMode,
+
+    /// URL prefix for WebAssembly modules emitted as separate resources
+    #[serde(default)]
+    pub wasm_base_url: String,
+
+    /// Files that dynamic requires such as `require('./locale/' + lang)`
+    /// may load
+    #[serde(default)]
+    pub context: ContextConfig,
+
+    /// Strategies that concatenate ES modules into one scope
+    #[serde(default = "default_scope_hoisting")]
+    pub scope_hoisting: Vec<BundleStrategy>,
+
+    /// Splitting of the bundle into chunks loaded on demand
+    #[serde(default)]
+    pub split: SplitConfig,
+
+    /// Packages bundled together when `convert` names none, by the name
+    /// each is exposed as (e.g. `QRCode = "qrcode@1.5"`)
+    #[serde(default)]
+    pub packages: BTreeMap<String, String>,
+
+    /// Dependencies read from globals the page already loads instead of
+    /// being bundled, by the specifier they are required as
+    /// (e.g. `react = "window.React"`). Subpaths of a listed package, such
+    /// as `react/jsx-runtime`, read the package's global unless listed
+    /// themselves
+    #[serde(default)]
+    pub externals: BTreeMap<String, String>,
+
+    /// Load a package installed at several versions from the highest copy
+    /// each requiring package's declared range accepts
+    #[serde(default)]
+    pub dedupe: bool,
+
+    /// Fail the conversion when a package is bundled at more than one
+    /// version, e.g. in CI
+    #[serde(default)]
+    pub fail_on_duplicates: bool,
+
+    /// Size limits that fail the conversion when exceeded
+    #[serde(default)]
+    pub budgets: BudgetConfig,
+}
+
+/// Delivery of stylesheets imported by a package
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CssMode {
+    /// Add a `<style>` tag when the bundle loads
+    #[default]
+    Inject,
+    /// Write a sibling `.css` file, e.g. for an OutSystems theme
+    Extract,
+}
+
+/// Glob filters for files bundled into context modules. Patterns match
+/// paths relative to the package root, e.g. `locale/pt*.js`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContextConfig {
+    /// Only bundle files matching one of these globs; empty keeps all
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    /// Never bundle files matching these globs
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// Code splitting. Modules only some screens need move to chunk scripts
+/// that the bundle's loader fetches when they are first used.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SplitConfig {
+    /// Move modules loaded with `import()` to their own chunks
+    #[serde(default)]
+    pub dynamic_imports: bool,
+
+    /// Named groups of entry modules, each emitted as a chunk that
+    /// `loadChunk(name)` loads, e.g. `charts = ["./lib/charts.js"]`.
+    /// Specifiers resolve from the package root.
+    #[serde(default)]
+    pub entries: BTreeMap<String, Vec<String>>,
+
+    /// How the loader locates chunk files
+    #[serde(default)]
+    pub loading: ChunkLoading,
+
+    /// URL prefix of chunk files, for `url` loading
+    #[serde(default)]
+    pub base_url: String,
+
+    /// OutSystems module whose Script resources hold the chunks, for
+    /// `resource` loading
+    #[serde(default)]
+    pub resource_module: String,
+}
+
+impl SplitConfig {
+    /// Whether any chunks are split off the bundle
+    pub fn is_enabled(&self) -> bool {
+        self.dynamic_imports || !self.entries.is_empty()
+    }
+}
+
+/// How the chunk loader turns a chunk into a script URL
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkLoading {
+    /// `base_url` followed by the chunk's file name
+    #[default]
+    Url,
+    /// The URL OutSystems serves a Script resource named after the chunk
+    /// file from, `scripts/<resource_module>.<resource name>.js`
+    Resource,
+}
+
+/// Delivery of WebAssembly modules loaded by a package
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WasmMode {
+    /// Embed the bytes in the bundle as base64
+    #[default]
+    Embed,
+    /// Write each module next to the bundle and fetch it at runtime
+    Separate,
+}
+
+/// Size limits in bytes for the packages and modules of a bundle
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BudgetConfig {
+    /// Which size the limits apply to
+    #[serde(default)]
+    pub measure: SizeMeasure,
+
+    /// Limit for every package, including the converted one
+    #[serde(default)]
+    pub package: Option<usize>,
+
+    /// Limit for every module
+    #[serde(default)]
+    pub module: Option<usize>,
+
+    /// Limits for particular packages, by name
+    #[serde(default)]
+    pub packages: BTreeMap<String, usize>,
+
+    /// Limits for particular modules, by path in the package tree
+    /// (e.g. `"node_modules/moment/locale/pt.js" = 4096`)
+    #[serde(default)]
+    pub modules: BTreeMap<String, usize>,
+}
+
+impl BudgetConfig {
+    /// Whether any limit is set
+    pub fn is_enabled(&self) -> bool {
+        self.package.is_some() || self.module.is_some() || !self.packages.is_empty() || !self.modules.is_empty()
+    }
+}
+
+/// A size of bundled code
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeMeasure {
+    /// As emitted, before minification
+    Raw,
+    #[default]
+    Minified,
+    /// Minified and gzip-compressed, as served
+    Gzip,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -126,6 +324,10 @@ pub struct TemplateConfig {
     /// Template overrides
     #[serde(default)]
     pub overrides: HashMap<String, String>,
+
+    /// Values templates read as `{{custom.name}}`
+    #[serde(default)]
+    pub variables: HashMap<String, serde_json::Value>,
 }
 
 impl Default for Config {
@@ -137,6 +339,8 @@ impl Default for Config {
             bundle: BundleConfig::default(),
             cache: CacheConfig::default(),
             templates: TemplateConfig::default(),
+            defines: HashMap::new(),
+            loaders: HashMap::new(),
         }
     }
 }
@@ -160,6 +364,7 @@ impl Default for OutputConfig {
             minify: false,
             target: EsTarget::Es5,
             source_maps: false,
+            format: OutputFormat::OutSystems,
         }
     }
 }
@@ -192,10 +397,37 @@ impl Default for BundleConfig {
                 "node-gyp".to_string(),
             ],
             force_inline: Vec::new(),
+            max_inline_size: None,
+            css: CssMode::default(),
+            wasm: WasmMode::default(),
+            wasm_base_url: String::new(),
+            context: ContextConfig::default(),
+            scope_hoisting: default_scope_hoisting(),
+            split: SplitConfig::default(),
+            packages: BTreeMap::new(),
+            externals: BTreeMap::new(),
+            dedupe: false,
+            fail_on_duplicates: false,
+            budgets: BudgetConfig::default(),
         }
     }
 }
 
+impl BundleConfig {
+    /// Whether a strategy merges ES module graphs into shared scopes
+    pub fn hoists_scopes(&self, strategy: &BundleStrategy) -> bool {
+        self.scope_hoisting.contains(strategy)
+    }
+
+    /// Global expression a specifier is read from when it is external,
+    /// matching the exact specifier first and then its package name
+    pub fn external_global(&self, specifier: &str) -> Option<&String> {
+        self.externals.get(specifier).or_else(|| {
+            package_name(specifier).and_then(|package| self.externals.get(package))
+        })
+    }
+}
+
 impl Default for CacheConfig {
     fn default() -> Self {
         Self {
@@ -211,6 +443,7 @@ impl Default for TemplateConfig {
         Self {
             directory: None,
             overrides: HashMap::new(),
+            variables: HashMap::new(),
         }
     }
 }
@@ -310,6 +543,10 @@ fn default_max_size() -> usize {
     5 * 1024 * 1024 // 5MB
 }
 
+fn default_scope_hoisting() -> Vec<BundleStrategy> {
+    vec![BundleStrategy::Inline, BundleStrategy::Selective]
+}
+
 fn default_cache_dir() -> PathBuf {
     dirs::cache_dir()
         .unwrap_or_else(|| PathBuf::from(".cache"))
@@ -345,6 +582,15 @@ mod tests {
         assert_eq!(config.npm.registry, deserialized.npm.registry);
     }
 
+    #[test]
+    fn test_output_format() {
+        assert_eq!(OutputConfig::default().format, OutputFormat::OutSystems);
+        let output: OutputConfig = toml::from_str("format = \"esm\"").unwrap();
+        assert_eq!(output.format, OutputFormat::Esm);
+        let output: OutputConfig = toml::from_str("format = \"systemjs\"").unwrap();
+        assert_eq!(output.format, OutputFormat::System);
+    }
+
     #[test]
     fn test_config_init() {
         let temp_dir = TempDir::new().unwrap();
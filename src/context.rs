@@ -0,0 +1,317 @@
+//! Context modules for dynamic requires
+//!
+//! `require('./locale/' + lang)` names its module at runtime, so a bundler
+//! cannot follow it. When the start of the path is a string literal, every
+//! package file under that prefix is a candidate: the call is rewritten to
+//! look the request up in a map of static requires, the way webpack builds
+//! context modules. `[bundle.context]` include/exclude globs keep the map,
+//! and the bundle, down to the files that are actually wanted.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use swc_common::Spanned;
+use swc_ecma_ast::*;
+use swc_ecma_parser::Syntax;
+use swc_ecma_visit::{Visit, VisitWith};
+
+use crate::assets::resolve_relative;
+use crate::config::ContextConfig;
+use crate::converter::FileContent;
+use crate::js::{self, SourceEdits};
+
+/// Polyfill that resolves requests against a context map
+pub const CONTEXT_POLYFILL: &str = "context";
+
+/// Extensions of files a context module can load
+const CONTEXT_EXTENSIONS: &[&str] = &["js", "json", "mjs", "cjs"];
+
+/// Compiled include/exclude globs from `[bundle.context]`
+#[derive(Debug, Clone)]
+pub struct ContextFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl ContextFilter {
+    pub fn new(config: &ContextConfig) -> Result<Self> {
+        let include = if config.include.is_empty() {
+            None
+        } else {
+            Some(build_glob_set(&config.include)?)
+        };
+
+        Ok(Self {
+            include,
+            exclude: build_glob_set(&config.exclude)?,
+        })
+    }
+
+    /// Whether a package file may be bundled into a context module
+    pub fn allows(&self, path: &Path) -> bool {
+        self.include.as_ref().is_none_or(|include| include.is_match(path))
+            && !self.exclude.is_match(path)
+    }
+}
+
+impl Default for ContextFilter {
+    fn default() -> Self {
+        Self {
+            include: None,
+            exclude: GlobSet::empty(),
+        }
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// One dynamic require turned into a context module
+#[derive(Debug, Clone)]
+pub struct ContextModule {
+    /// Static start of the request, as written
+    pub prefix: String,
+    /// Package files the request may load
+    pub files: Vec<PathBuf>,
+}
+
+/// A module with its dynamic requires rewritten
+#[derive(Debug)]
+pub struct ContextRequires {
+    pub code: String,
+    pub contexts: Vec<ContextModule>,
+}
+
+/// Replace `require()` calls whose path starts with a static relative
+/// prefix with a lookup in a map of the matching package files
+pub fn rewrite_context_requires(
+    code: &str,
+    syntax: Syntax,
+    module_path: &Path,
+    files: &HashMap<PathBuf, FileContent>,
+    filter: &ContextFilter,
+) -> Result<ContextRequires> {
+    if !code.contains("require") {
+        return Ok(ContextRequires { code: code.to_string(), contexts: Vec::new() });
+    }
+
+    let program = js::parse_program(code, syntax)?;
+
+    let mut rewriter = ContextRewriter {
+        code,
+        module_path,
+        files,
+        filter,
+        declared_require: js::declared_names(&program).contains("require"),
+        edits: SourceEdits::new(),
+        contexts: Vec::new(),
+    };
+    program.visit_with(&mut rewriter);
+
+    Ok(ContextRequires {
+        code: rewriter.edits.apply(code),
+        contexts: rewriter.contexts,
+    })
+}
+
+/// Visitor that turns dynamic requires into context lookups
+struct ContextRewriter<'a> {
+    code: &'a str,
+    module_path: &'a Path,
+    files: &'a HashMap<PathBuf, FileContent>,
+    filter: &'a ContextFilter,
+    declared_require: bool,
+    edits: SourceEdits,
+    contexts: Vec<ContextModule>,
+}
+
+impl ContextRewriter<'_> {
+    /// Package files matching a request prefix and suffix, with the key
+    /// each is looked up by
+    fn matching_files(&self, prefix: &str, suffix: &str) -> Vec<(String, PathBuf)> {
+        // './locale/lang-' splits into the directory './locale/' and the
+        // start of a file name 'lang-'
+        let split = prefix.rfind('/').map(|i| i + 1).unwrap_or(prefix.len());
+        let (dir_prefix, name_prefix) = prefix.split_at(split);
+
+        let Some(directory) = resolve_relative(self.module_path, dir_prefix) else {
+            return Vec::new();
+        };
+        let directory = directory.to_string_lossy().trim_end_matches('/').to_string();
+
+        let mut matches: Vec<(String, PathBuf)> = self.files.keys()
+            .filter(|path| path.as_path() != self.module_path)
+            .filter(|path| {
+                path.extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| CONTEXT_EXTENSIONS.contains(&e))
+            })
+            .filter_map(|path| {
+                let relative = path.to_string_lossy().replace('\\', "/");
+                let rest = if directory.is_empty() {
+                    relative.as_str()
+                } else {
+                    relative.strip_prefix(&directory)?.strip_prefix('/')?
+                };
+                (rest.starts_with(name_prefix) && rest.ends_with(suffix) && self.filter.allows(path))
+                    .then(|| (format!("{}{}", dir_prefix, rest), path.clone()))
+            })
+            .collect();
+
+        matches.sort();
+        matches
+    }
+
+    fn rewrite(&mut self, call: &CallExpr, request: &Expr, prefix: String, suffix: String) {
+        let matches = self.matching_files(&prefix, &suffix);
+        if matches.is_empty() {
+            return;
+        }
+
+        let entries: Vec<String> = matches.iter()
+            .map(|(key, _)| {
+                let key = serde_json::Value::String(key.clone());
+                format!("{}: function () {{ return require({}); }}", key, key)
+            })
+            .collect();
+
+        self.edits.replace(call.span, format!(
+            "RequireContextPolyfill({{ {} }}, {})",
+            entries.join(", "),
+            js::span_text(self.code, request.span())
+        ));
+        self.contexts.push(ContextModule {
+            prefix,
+            files: matches.into_iter().map(|(_, path)| path).collect(),
+        });
+    }
+}
+
+/// Static prefix and suffix of a request built by concatenation or a
+/// template literal. `None` unless the prefix is a relative path and some
+/// part is dynamic.
+fn static_parts(expr: &Expr) -> Option<(String, String)> {
+    let (prefix, suffix) = match expr {
+        Expr::Paren(paren) => return static_parts(&paren.expr),
+        Expr::Bin(_) => {
+            let mut parts = Vec::new();
+            flatten_concatenation(expr, &mut parts);
+            let leading = parts.iter().take_while(|part| string_value(part).is_some()).count();
+            if leading == 0 || leading == parts.len() {
+                return None;
+            }
+            let trailing = parts[leading..].iter().rev().take_while(|part| string_value(part).is_some()).count();
+            let prefix: String = parts[..leading].iter().filter_map(|part| string_value(part)).collect();
+            let suffix: String = parts[parts.len() - trailing..].iter().filter_map(|part| string_value(part)).collect();
+            (prefix, suffix)
+        }
+        Expr::Tpl(tpl) if !tpl.exprs.is_empty() => {
+            let quasi = |element: &TplElement| {
+                element.cooked.as_ref().unwrap_or(&element.raw).to_string()
+            };
+            (quasi(tpl.quasis.first()?), quasi(tpl.quasis.last()?))
+        }
+        _ => return None,
+    };
+
+    (prefix.starts_with("./") || prefix.starts_with("../")).then_some((prefix, suffix))
+}
+
+/// Operands of a chain of `+`
+fn flatten_concatenation<'a>(expr: &'a Expr, parts: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Bin(bin) if bin.op == BinaryOp::Add => {
+            flatten_concatenation(&bin.left, parts);
+            flatten_concatenation(&bin.right, parts);
+        }
+        Expr::Paren(paren) => flatten_concatenation(&paren.expr, parts),
+        other => parts.push(other),
+    }
+}
+
+fn string_value(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Lit(Lit::Str(s)) => Some(s.value.as_ref()),
+        _ => None,
+    }
+}
+
+impl Visit for ContextRewriter<'_> {
+    fn visit_call_expr(&mut self, call: &CallExpr) {
+        if let Callee::Expr(callee) = &call.callee {
+            let is_require = !self.declared_require
+                && matches!(callee.as_ref(), Expr::Ident(ident) if ident.sym == *"require");
+            if let (true, [request]) = (is_require, call.args.as_slice()) {
+                if request.spread.is_none() {
+                    if let Some((prefix, suffix)) = static_parts(&request.expr) {
+                        self.rewrite(call, &request.expr, prefix, suffix);
+                        return;
+                    }
+                }
+            }
+        }
+        call.visit_children_with(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> HashMap<PathBuf, FileContent> {
+        let mut files = HashMap::new();
+        for path in ["moment.js", "locale/en.js", "locale/pt.js", "locale/pt-br.js", "locale/README.md", "data/a.json"] {
+            files.insert(PathBuf::from(path), FileContent::Text(String::new()));
+        }
+        files
+    }
+
+    fn rewrite(code: &str, filter: &ContextFilter) -> ContextRequires {
+        let syntax = js::syntax_for_path(Path::new("moment.js"));
+        rewrite_context_requires(code, syntax, Path::new("moment.js"), &files(), filter).unwrap()
+    }
+
+    #[test]
+    fn test_rewrites_prefixed_require() {
+        let result = rewrite("var locale = require('./locale/' + name);", &ContextFilter::default());
+        assert_eq!(result.contexts.len(), 1);
+        assert_eq!(result.contexts[0].files, vec![
+            PathBuf::from("locale/en.js"),
+            PathBuf::from("locale/pt-br.js"),
+            PathBuf::from("locale/pt.js"),
+        ]);
+        assert!(result.code.starts_with("var locale = RequireContextPolyfill({ \"./locale/en.js\": function () { return require(\"./locale/en.js\"); }, "));
+        assert!(result.code.ends_with("}, './locale/' + name);"));
+        assert!(!result.code.contains("README"));
+    }
+
+    #[test]
+    fn test_template_literal_with_suffix() {
+        let result = rewrite("require(`./data/${key}.json`);", &ContextFilter::default());
+        assert_eq!(result.contexts[0].files, vec![PathBuf::from("data/a.json")]);
+    }
+
+    #[test]
+    fn test_filter_globs() {
+        let filter = ContextFilter::new(&ContextConfig {
+            include: vec!["locale/pt*.js".to_string(), "locale/en.js".to_string()],
+            exclude: vec!["locale/pt-*.js".to_string()],
+        }).unwrap();
+        let result = rewrite("require('./locale/' + name);", &filter);
+        assert_eq!(result.contexts[0].files, vec![PathBuf::from("locale/en.js"), PathBuf::from("locale/pt.js")]);
+    }
+
+    #[test]
+    fn test_leaves_fully_dynamic_requires() {
+        let code = "require(name); require('./locale/en'); require('lodash/' + fn);";
+        let result = rewrite(code, &ContextFilter::default());
+        assert!(result.contexts.is_empty());
+        assert_eq!(result.code, code);
+    }
+}
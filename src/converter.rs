@@ -1,18 +1,28 @@
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use anyhow::{Context, Result};
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use tracing::{info, warn, debug};
 use uuid::Uuid;
 
 use crate::config::Config;
-use crate::cli::{BundleStrategy, EsTarget};
+use crate::cli::{BundleStrategy, EsTarget, OutputFormat};
 use crate::errors::{PaktoError, CompatibilityIssue, Warning};
 use crate::npm::NpmClient;
 use crate::analyzer::PackageAnalyzer;
 use crate::transformer::CodeTransformer;
 use crate::bundler::Bundler;
-use crate::output::OutputGenerator;
+use crate::cache::BuildCache;
+use crate::output::{self, OutputGenerator};
+use crate::naming::{self, NamingValues};
+use crate::resolver::{self, Resolution};
+use crate::report::{self, SizeReport};
+use crate::combine::{self, PackageEntry};
+use crate::duplicates::DuplicatePackage;
+
+/// Name of a multi-package bundle given no `--name`
+const COMBINED_PACKAGE_NAME: &str = "packages";
 
 /// Main converter that orchestrates the conversion process
 pub struct Converter {
@@ -35,6 +45,18 @@ pub struct ConvertOptions {
     pub include_polyfills: Vec<String>,
     pub exclude_dependencies: Vec<String>,
     pub bundle_strategy: BundleStrategy,
+    /// Module format of the bundle
+    pub format: OutputFormat,
+    /// Compile-time defines, merged over the `[defines]` config table
+    pub defines: HashMap<String, serde_json::Value>,
+    /// Names a multi-package bundle exposes its packages under; with a
+    /// namespace they are added to it directly
+    pub exposed_packages: Vec<String>,
+    /// Write a size report next to the bundle as JSON and an HTML treemap
+    pub report: bool,
+    /// Date the output with `SOURCE_DATE_EPOCH`, or the Unix epoch when it
+    /// is unset, instead of the current time
+    pub reproducible: bool,
 }
 
 /// Result of package conversion
@@ -60,6 +82,46 @@ pub struct ConvertResult {
 
     /// Unique conversion ID for tracking
     pub conversion_id: String,
+
+    /// Every file written by the conversion, starting with the bundle
+    pub emitted_files: Vec<PathBuf>,
+
+    /// Chunk scripts the bundle loads on demand
+    pub chunks: Vec<ChunkFile>,
+
+    /// Which dependency packages the Hybrid strategy inlined and why
+    pub dependency_decisions: Vec<DependencyDecision>,
+
+    /// Packages bundled at more than one version, with what each copy costs
+    pub duplicate_packages: Vec<DuplicatePackage>,
+
+    /// Size of each bundled package and module, when a report was asked
+    /// for or budgets are set
+    pub size_report: Option<SizeReport>,
+}
+
+/// How the Hybrid strategy treated a dependency package
+#[derive(Debug, Clone, Serialize)]
+pub struct DependencyDecision {
+    pub package: String,
+    /// Minified size of the package's modules in bytes
+    pub minified_size: usize,
+    /// Bundled, rather than read from a global
+    pub inlined: bool,
+    /// Why, e.g. `listed in force_inline`
+    pub reason: String,
+}
+
+/// A chunk script written next to the bundle
+#[derive(Debug, Serialize)]
+pub struct ChunkFile {
+    /// Name the bundle's loader knows the chunk by
+    pub name: String,
+    pub path: PathBuf,
+    /// Size of the file in bytes
+    pub size: usize,
+    /// Package modules the chunk defines
+    pub modules: Vec<PathBuf>,
 }
 
 /// Detailed conversion statistics
@@ -82,6 +144,13 @@ pub struct ConversionStats {
 
     /// Compatibility score (0.0 - 1.0)
     pub compatibility_score: f32,
+
+    /// ES modules merged into another module's scope
+    pub modules_concatenated: usize,
+
+    /// Installed packages of a multi-package bundle kept once because
+    /// another package needs the same version
+    pub dependencies_deduplicated: usize,
 }
 
 /// Package analysis result
@@ -118,6 +187,8 @@ pub struct PackageInfo {
     pub entry_points: Vec<String>,
     pub dependencies: HashMap<String, String>,
     pub dev_dependencies: HashMap<String, String>,
+    /// Dependencies the package works without; never bundled
+    pub optional_dependencies: HashMap<String, String>,
     pub keywords: Vec<String>,
     pub license: Option<String>,
 }
@@ -143,10 +214,11 @@ impl Converter {
     /// Create a new converter instance
     pub async fn new(config: Config) -> Result<Self> {
         let npm_client = NpmClient::new(&config.npm).await?;
+        BuildCache::new(&config.cache).prune();
         let analyzer = PackageAnalyzer::new(&config);
         let transformer = CodeTransformer::new(&config);
         let bundler = Bundler::new(&config);
-        let output_generator = OutputGenerator::new(&config);
+        let output_generator = OutputGenerator::new(&config)?;
 
         Ok(Self {
             config,
@@ -170,50 +242,176 @@ impl Converter {
         info!("Starting conversion of package: {}", package);
         debug!("Conversion ID: {}", conversion_id);
 
-        // Step 1: Analyze package
+        // Step 1: Download package
+        info!("Downloading package and dependencies...");
+        let package_data = self.npm_client.download_package(package).await?;
+
+        // Step 2: Analyze the downloaded package
+        info!("Analyzing package compatibility...");
+        let analysis = self.analyzer.analyze(&package_data).await?;
+        Self::check_feasible(&analysis)?;
+
+        self.build(package, &package_data, analysis, options, start_time, conversion_id).await
+    }
+
+    /// Convert several NPM packages into one bundle exposing each under
+    /// its name, given as `(name, package)` pairs. Dependencies they share
+    /// are bundled once per version.
+    pub async fn convert_packages(
+        &self,
+        packages: &[(String, String)],
+        mut options: ConvertOptions
+    ) -> Result<ConvertResult> {
+        let start_time = std::time::Instant::now();
+        let conversion_id = Uuid::new_v4().to_string();
+
+        info!("Starting conversion of {} packages", packages.len());
+        debug!("Conversion ID: {}", conversion_id);
+
+        // Step 1: Download packages
+        info!("Downloading packages and dependencies...");
+        let mut entries = Vec::new();
+        for (exposed_name, package) in packages {
+            entries.push(PackageEntry {
+                exposed_name: exposed_name.clone(),
+                data: self.npm_client.download_package(package).await?,
+            });
+        }
+        let name = options.name.clone().unwrap_or_else(|| COMBINED_PACKAGE_NAME.to_string());
+        let combined = combine::combine_packages(&name, entries);
+
+        // Step 2: Analyze the combined package
         info!("Analyzing package compatibility...");
-        let analysis = self.analyze(package).await?;
+        let analysis = self.analyzer.analyze(&combined.data).await?;
+        Self::check_feasible(&analysis)?;
 
+        options.exposed_packages = packages.iter().map(|(exposed_name, _)| exposed_name.clone()).collect();
+        let mut result = self.build(&name, &combined.data, analysis, options, start_time, conversion_id).await?;
+        result.stats.dependencies_deduplicated = combined.deduplicated;
+        Ok(result)
+    }
+
+    /// Refuse packages whose analysis found them unusable
+    fn check_feasible(analysis: &AnalysisResult) -> Result<()> {
         if !analysis.feasible {
             return Err(PaktoError::IncompatibleApi {
                 api: "Multiple incompatible APIs".to_string(),
                 suggestion: Some("This package is not suitable for OutSystems conversion".to_string()),
                 location: None,
-            });
+            }.into());
         }
+        Ok(())
+    }
 
-        // Step 2: Download package
-        info!("Downloading package and dependencies...");
-        let package_data = self.npm_client.download_package(package).await?;
-
+    /// Transform, bundle and write a downloaded package
+    async fn build(
+        &self,
+        package: &str,
+        package_data: &PackageData,
+        analysis: AnalysisResult,
+        options: ConvertOptions,
+        start_time: std::time::Instant,
+        conversion_id: String,
+    ) -> Result<ConvertResult> {
         // Step 3: Transform code
         info!("Transforming code for browser compatibility...");
         let transformed = self.transformer.transform_package(
-            &package_data,
+            package_data,
             &options,
             &analysis,
         ).await?;
 
         // Step 4: Bundle dependencies
         info!("Bundling dependencies...");
+        // Chunks are named before the output's hash is known
+        let file_stem = self.determine_output_path(package, &options, &analysis.package_info, None)?
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| package.to_string());
         let bundled = self.bundler.bundle(
             &transformed,
             &options.bundle_strategy,
+            &options.target_es_version,
             &options.exclude_dependencies,
+            &file_stem,
         ).await?;
 
         // Step 5: Generate output
         info!("Generating output file...");
-        let output_path = self.determine_output_path(package, &options)?;
         let final_code = self.output_generator.generate(
             &bundled,
             &options,
             &analysis.package_info,
         )?;
+        let hash = naming::content_hash(final_code.as_bytes());
+        let output_path = self.determine_output_path(package, &options, &analysis.package_info, Some(&hash))?;
+        if let Some(directory) = output_path.parent() {
+            std::fs::create_dir_all(directory)
+                .with_context(|| format!("Failed to create output directory: {}", directory.display()))?;
+        }
+
+        // Measure what each package contributes, for the report and the
+        // size budgets
+        let budgets = &self.config.bundle.budgets;
+        let size_report = (options.report || budgets.is_enabled()).then(|| {
+            let modules: Vec<&ModuleSource> = transformed.modules.iter()
+                .filter(|module| match resolver::package_name_of(&module.path) {
+                    Some(package) => bundled.bundled_dependencies.contains(&package),
+                    None => true,
+                })
+                .collect();
+            report::size_report(&analysis.package_info.name, &final_code, &modules, &transformed.code)
+        });
+        let mut report_files = Vec::new();
+        if let (true, Some(size_report)) = (options.report, &size_report) {
+            let json_path = output_path.with_extension("report.json");
+            let html_path = output_path.with_extension("report.html");
+            std::fs::write(&json_path, serde_json::to_string_pretty(size_report)?)
+                .with_context(|| format!("Failed to write report: {}", json_path.display()))?;
+            std::fs::write(&html_path, size_report.to_html())
+                .with_context(|| format!("Failed to write report: {}", html_path.display()))?;
+            report_files = vec![json_path, html_path];
+        }
+        if let Some(size_report) = &size_report {
+            let violations = size_report.budget_violations(budgets);
+            if !violations.is_empty() {
+                return Err(PaktoError::BudgetExceeded { violations }.into());
+            }
+        }
 
         // Step 6: Write file
         std::fs::write(&output_path, &final_code)
             .with_context(|| format!("Failed to write output file: {}", output_path.display()))?;
+        let mut emitted_files = vec![output_path.clone()];
+
+        if let Some(ref stylesheet) = bundled.stylesheet {
+            let stylesheet_path = output_path.with_extension("css");
+            std::fs::write(&stylesheet_path, stylesheet)
+                .with_context(|| format!("Failed to write stylesheet: {}", stylesheet_path.display()))?;
+            emitted_files.push(stylesheet_path);
+        }
+
+        for resource in &bundled.resources {
+            let resource_path = output_path.with_file_name(&resource.file_name);
+            std::fs::write(&resource_path, &resource.content)
+                .with_context(|| format!("Failed to write resource: {}", resource_path.display()))?;
+            emitted_files.push(resource_path);
+        }
+
+        let mut chunks = Vec::new();
+        for chunk in &bundled.chunks {
+            let chunk_path = output_path.with_file_name(&chunk.file_name);
+            std::fs::write(&chunk_path, &chunk.code)
+                .with_context(|| format!("Failed to write chunk: {}", chunk_path.display()))?;
+            emitted_files.push(chunk_path.clone());
+            chunks.push(ChunkFile {
+                name: chunk.name.clone(),
+                path: chunk_path,
+                size: chunk.code.len(),
+                modules: chunk.modules.clone(),
+            });
+        }
+        emitted_files.extend(report_files);
 
         let conversion_time = start_time.elapsed();
         let file_size = final_code.len();
@@ -225,6 +423,16 @@ impl Converter {
                 warnings.push(issue.message.clone());
             }
         }
+        for issue in &transformed.issues {
+            let level = match issue.level {
+                crate::errors::IssueLevel::Error => "error: ",
+                _ => "",
+            };
+            match &issue.location {
+                Some(location) => warnings.push(format!("{}: {}{}", location, level, issue.message)),
+                None => warnings.push(format!("{}{}", level, issue.message)),
+            }
+        }
 
         let result = ConvertResult {
             output_path,
@@ -243,8 +451,15 @@ impl Converter {
                 },
                 conversion_time_ms: conversion_time.as_millis() as u64,
                 compatibility_score: analysis.compatibility_score,
+                modules_concatenated: transformed.concatenated_modules,
+                dependencies_deduplicated: 0,
             },
             conversion_id,
+            emitted_files,
+            chunks,
+            dependency_decisions: bundled.dependency_decisions,
+            duplicate_packages: bundled.duplicate_packages,
+            size_report,
         };
 
         info!(
@@ -260,9 +475,6 @@ impl Converter {
     pub async fn analyze(&self, package: &str) -> Result<AnalysisResult> {
         info!("Analyzing package: {}", package);
 
-        // Get package metadata
-        let package_info = self.npm_client.get_package_info(package).await?;
-
         // Download for analysis (might use cache)
         let package_data = self.npm_client.download_package(package).await?;
 
@@ -272,24 +484,40 @@ impl Converter {
         Ok(analysis)
     }
 
-    /// Determine output path based on options and configuration
-    fn determine_output_path(&self, package: &str, options: &ConvertOptions) -> Result<PathBuf> {
+    /// Output path from `--output` or `output.naming_pattern`. Without
+    /// the output's hash, `{hash}` placeholders are left out.
+    fn determine_output_path(
+        &self,
+        package: &str,
+        options: &ConvertOptions,
+        package_info: &PackageInfo,
+        hash: Option<&str>,
+    ) -> Result<PathBuf> {
         if let Some(ref path) = options.output_path {
             return Ok(path.clone());
         }
 
-        let name = options.name
-            .as_deref()
-            .unwrap_or(package);
-
-        let filename = self.config.output.naming_pattern
-            .replace("{name}", name)
-            .replace("{package}", package);
+        let values = NamingValues {
+            name: options.name.clone().unwrap_or_else(|| package.to_string()),
+            package: package.to_string(),
+            version: package_info.version.clone(),
+            target: value_name(&options.target_es_version),
+            strategy: value_name(&options.bundle_strategy),
+            date: output::build_time(options.reproducible).format("%Y-%m-%d").to_string(),
+        };
+        let filename = naming::expand(&self.config.output.naming_pattern, &values, hash)?;
 
         Ok(self.config.output.directory.join(filename))
     }
 }
 
+/// Command-line name of a `--target` or `--strategy` value
+fn value_name(value: &impl ValueEnum) -> String {
+    value.to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
 impl Default for ConvertOptions {
     fn default() -> Self {
         Self {
@@ -301,6 +529,11 @@ impl Default for ConvertOptions {
             include_polyfills: Vec::new(),
             exclude_dependencies: Vec::new(),
             bundle_strategy: BundleStrategy::Inline,
+            format: OutputFormat::OutSystems,
+            defines: HashMap::new(),
+            exposed_packages: Vec::new(),
+            report: false,
+            reproducible: false,
         }
     }
 }
@@ -308,20 +541,149 @@ impl Default for ConvertOptions {
 // Placeholder structs that will be implemented in other modules
 pub struct PackageData {
     pub total_size: usize,
-    pub files: HashMap<PathBuf, String>,
+    pub files: HashMap<PathBuf, FileContent>,
     pub package_json: serde_json::Value,
 }
 
+/// Contents of a package file. Files that are not valid UTF-8 (images,
+/// fonts, WebAssembly) are kept as raw bytes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FileContent {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl FileContent {
+    /// Store bytes as text when they are valid UTF-8
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => Self::Text(text),
+            Err(e) => Self::Binary(e.into_bytes()),
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Self::Text(text) => Some(text),
+            Self::Binary(_) => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Text(text) => text.as_bytes(),
+            Self::Binary(bytes) => bytes,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl From<String> for FileContent {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
 pub struct TransformedPackage {
     pub files_processed: usize,
+    /// Polyfills the modules need, between the polyfill markers
     pub code: String,
+    /// Modules reachable from the package entry, entry first
+    pub modules: Vec<ModuleSource>,
     pub source_map: Option<String>,
+    /// Stylesheets imported by the package, in import order
+    pub styles: Vec<Stylesheet>,
+    /// WebAssembly modules loaded by the package
+    pub wasm_modules: Vec<WasmModule>,
+    /// ES modules merged into another module's scope
+    pub concatenated_modules: usize,
+    /// Configured entry groups, with the modules their entries resolve to
+    pub entry_groups: Vec<EntryGroup>,
+    /// Dependencies the package ships with, bundled whatever the strategy
+    pub bundled_packages: Vec<String>,
+    /// Packages the modules load from more than one installed copy
+    pub duplicate_packages: Vec<DuplicatePackage>,
+    /// Problems found while transforming, such as file reads that cannot
+    /// be inlined
+    pub issues: Vec<CompatibilityIssue>,
+}
+
+/// A named group of entry modules split into its own chunk
+#[derive(Debug, Clone)]
+pub struct EntryGroup {
+    pub name: String,
+    /// Each declared specifier and the module it resolves to
+    pub entries: Vec<(String, PathBuf)>,
+}
+
+/// A transformed module of the package graph
+#[derive(Debug, Clone)]
+pub struct ModuleSource {
+    pub path: PathBuf,
+    /// CommonJS code of the module
+    pub code: String,
+    /// What each specifier the module requires resolves to. Specifiers
+    /// that resolve to nothing are left out and throw when required.
+    pub dependencies: BTreeMap<String, Resolution>,
+    /// Specifiers the module only loads through `import()`
+    pub dynamic_imports: BTreeSet<String>,
+}
+
+/// A CSS file imported for its side effect
+#[derive(Debug, Clone)]
+pub struct Stylesheet {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+/// A WebAssembly binary loaded by the package
+#[derive(Debug, Clone)]
+pub struct WasmModule {
+    pub path: PathBuf,
+    pub content: Vec<u8>,
+}
+
+/// A file emitted next to the bundle
+#[derive(Debug, Clone)]
+pub struct Resource {
+    pub file_name: String,
+    pub content: Vec<u8>,
 }
 
 pub struct BundledCode {
     pub code: String,
     pub bundled_dependencies: Vec<String>,
     pub unminified_size: usize,
+    /// Extracted stylesheet to emit next to the bundle
+    pub stylesheet: Option<String>,
+    /// Other files to emit next to the bundle
+    pub resources: Vec<Resource>,
+    /// Scripts split off the bundle, loaded on demand
+    pub chunks: Vec<Chunk>,
+    /// Hybrid strategy decisions, one per dependency package
+    pub dependency_decisions: Vec<DependencyDecision>,
+    /// Packages bundled at more than one version
+    pub duplicate_packages: Vec<DuplicatePackage>,
+    /// Names the package entry exports, as far as they are known
+    /// statically
+    pub exports: Vec<String>,
+}
+
+/// A script split off the bundle
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub name: String,
+    pub file_name: String,
+    pub code: String,
+    /// Package modules the chunk defines
+    pub modules: Vec<PathBuf>,
 }
 
 #[cfg(test)]
@@ -344,15 +706,80 @@ mod tests {
         assert!(!options.minify);
     }
 
-    #[test]
-    fn test_output_path_determination() {
-        // This would require a full converter instance, 
-        // so we'll test the logic separately
-        let naming_pattern = "{name}-outsystems.js";
-        let result = naming_pattern
-            .replace("{name}", "test-package")
-            .replace("{package}", "test-package");
-
-        assert_eq!(result, "test-package-outsystems.js");
+    /// A package with a nested dependency tree, in a fresh `HashMap` so
+    /// every call starts from a different iteration order
+    fn sample_package() -> PackageData {
+        let files: HashMap<PathBuf, FileContent> = [
+            ("package.json", r#"{"name": "sample", "version": "1.0.0", "main": "index.js", "dependencies": {"left": "1", "right": "1"}}"#),
+            ("index.js", "var left = require('left');\nvar right = require('right');\nmodule.exports = function () { return left() + right(); };"),
+            ("node_modules/left/package.json", r#"{"name": "left", "version": "1.0.0", "dependencies": {"shared": "1"}}"#),
+            ("node_modules/left/index.js", "var shared = require('shared');\nmodule.exports = function () { return shared('left'); };"),
+            ("node_modules/right/package.json", r#"{"name": "right", "version": "1.0.0", "dependencies": {"shared": "2"}}"#),
+            ("node_modules/right/index.js", "var shared = require('shared');\nmodule.exports = function () { return shared('right'); };"),
+            ("node_modules/right/node_modules/shared/package.json", r#"{"name": "shared", "version": "2.0.0"}"#),
+            ("node_modules/right/node_modules/shared/index.js", "module.exports = function (name) { return name + 2; };"),
+            ("node_modules/shared/package.json", r#"{"name": "shared", "version": "1.0.0"}"#),
+            ("node_modules/shared/index.js", "module.exports = function (name) { return name + 1; };"),
+        ].into_iter()
+            .map(|(path, content)| (PathBuf::from(path), FileContent::from(content.to_string())))
+            .collect();
+        let package_json = serde_json::from_slice(files[Path::new("package.json")].as_bytes()).unwrap();
+        PackageData { total_size: 0, files, package_json }
+    }
+
+    #[tokio::test]
+    async fn test_reproducible_build() {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.cache.directory = temp_dir.path().join("cache");
+        let converter = Converter::new(config).await.unwrap();
+        let output_path = temp_dir.path().join("sample.js");
+
+        let mut hashes = Vec::new();
+        for _ in 0..2 {
+            let package_data = sample_package();
+            let analysis = converter.analyzer.analyze(&package_data).await.unwrap();
+            let options = ConvertOptions {
+                output_path: Some(output_path.clone()),
+                report: true,
+                reproducible: true,
+                ..Default::default()
+            };
+            let result = converter.build("sample", &package_data, analysis, options, std::time::Instant::now(), String::new())
+                .await
+                .unwrap();
+
+            let mut hasher = DefaultHasher::new();
+            for file in &result.emitted_files {
+                std::fs::read(file).unwrap().hash(&mut hasher);
+            }
+            result.polyfills_used.hash(&mut hasher);
+            result.warnings.hash(&mut hasher);
+            hashes.push(hasher.finish());
+        }
+        assert_eq!(hashes[0], hashes[1]);
+    }
+
+    #[tokio::test]
+    async fn test_output_path_determination() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.output.directory = temp_dir.path().join("dist/latest");
+        config.output.naming_pattern = "{name}-{version}.{target}.{hash:8}.js".to_string();
+        config.cache.directory = temp_dir.path().join("cache");
+        let converter = Converter::new(config).await.unwrap();
+
+        let package_data = sample_package();
+        let analysis = converter.analyzer.analyze(&package_data).await.unwrap();
+        let result = converter.build("sample", &package_data, analysis, ConvertOptions::default(), std::time::Instant::now(), String::new())
+            .await
+            .unwrap();
+
+        // The missing directory is created
+        let code = std::fs::read(&result.output_path).unwrap();
+        let hash = naming::content_hash(&code);
+        assert_eq!(result.output_path, temp_dir.path().join(format!("dist/latest/sample-1.0.0.es5.{}.js", &hash[..8])));
     }
 }
\ No newline at end of file
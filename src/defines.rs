@@ -0,0 +1,585 @@
+//! Compile-time defines and dead-code elimination
+//!
+//! Defined expressions such as `process.env.NODE_ENV` are replaced with
+//! literal values. Conditions that become constant are then folded and the
+//! unreachable branches of `if` statements, conditional expressions and
+//! logical operators are removed, so development-only code never reaches
+//! the bundle.
+
+use std::collections::{HashMap, HashSet};
+use anyhow::Result;
+use serde_json::Value;
+use swc_common::{BytePos, Spanned};
+use swc_ecma_ast::*;
+use swc_ecma_parser::Syntax;
+use swc_ecma_visit::{Visit, VisitWith};
+
+use crate::js::{self, SourceEdits};
+
+/// Define key that selects the build environment
+pub const NODE_ENV_DEFINE: &str = "process.env.NODE_ENV";
+
+/// Parse a `KEY=VALUE` define from the command line.
+///
+/// `VALUE` is read as JSON when possible (`true`, `42`, `"quoted"`), and
+/// otherwise taken as a plain string, so `process.env.NODE_ENV=production`
+/// defines the string `"production"`.
+pub fn parse_define(input: &str) -> std::result::Result<(String, Value), String> {
+    let (key, value) = input
+        .split_once('=')
+        .ok_or_else(|| format!("invalid define '{}': expected KEY=VALUE", input))?;
+
+    let key = key.trim();
+    if !is_valid_define_key(key) {
+        return Err(format!(
+            "invalid define key '{}': expected an identifier or dotted path",
+            key
+        ));
+    }
+
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+    Ok((key.to_string(), value))
+}
+
+/// Check that a define key is an identifier or a dotted identifier path
+pub fn is_valid_define_key(key: &str) -> bool {
+    !key.is_empty() && key.split('.').all(|part| {
+        let mut chars = part.chars();
+        matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_' || c == '$')
+            && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+    })
+}
+
+/// Substitute defines in a source file, fold the resulting constant
+/// conditions and drop unreachable branches
+pub fn apply_defines(code: &str, syntax: Syntax, defines: &HashMap<String, Value>) -> Result<String> {
+    // Skip parsing files that cannot reference any define
+    let referenced = defines.keys().any(|key| {
+        let root = key.split('.').next().unwrap_or(key);
+        code.contains(root)
+    });
+    if !referenced {
+        return Ok(code.to_string());
+    }
+
+    let program = js::parse_program(code, syntax)?;
+
+    let mut folder = DefineFolder {
+        defines,
+        declared: js::declared_names(&program),
+        list_statements: HashSet::new(),
+        edits: SourceEdits::new(),
+    };
+    program.visit_with(&mut folder);
+
+    if folder.edits.is_empty() {
+        return Ok(code.to_string());
+    }
+
+    Ok(folder.edits.apply(code))
+}
+
+/// 2^53, above which not every integer has an exact `f64`
+const MAX_EXACT_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// Constant value known at build time
+#[derive(Debug, Clone, PartialEq)]
+enum Const {
+    Str(String),
+    Num(f64),
+    Bool(bool),
+    Null,
+    Undefined,
+}
+
+impl Const {
+    fn from_json(value: &Value) -> Option<Self> {
+        match value {
+            Value::String(s) => Some(Self::Str(s.clone())),
+            Value::Number(n) => n.as_f64().map(Self::Num),
+            Value::Bool(b) => Some(Self::Bool(*b)),
+            Value::Null => Some(Self::Null),
+            Value::Array(_) | Value::Object(_) => None,
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        match self {
+            Self::Str(s) => !s.is_empty(),
+            Self::Num(n) => *n != 0.0 && !n.is_nan(),
+            Self::Bool(b) => *b,
+            Self::Null | Self::Undefined => false,
+        }
+    }
+
+    fn is_nullish(&self) -> bool {
+        matches!(self, Self::Null | Self::Undefined)
+    }
+
+    fn type_of(&self) -> &'static str {
+        match self {
+            Self::Str(_) => "string",
+            Self::Num(_) => "number",
+            Self::Bool(_) => "boolean",
+            Self::Null => "object",
+            Self::Undefined => "undefined",
+        }
+    }
+
+    fn strict_equals(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Num(a), Self::Num(b)) => a == b,
+            _ => self == other,
+        }
+    }
+
+    /// Loose equality, only where it does not depend on type coercion
+    fn loose_equals(&self, other: &Self) -> Option<bool> {
+        if self.is_nullish() || other.is_nullish() {
+            return Some(self.is_nullish() && other.is_nullish());
+        }
+        if std::mem::discriminant(self) == std::mem::discriminant(other) {
+            return Some(self.strict_equals(other));
+        }
+        None
+    }
+
+    fn render(&self) -> String {
+        match self {
+            Self::Str(s) => render_json(&Value::String(s.clone())),
+            Self::Num(n) if n.is_nan() => "NaN".to_string(),
+            Self::Num(n) => {
+                // Integers print without a fraction only where they are exact
+                let magnitude = n.abs();
+                let text = if magnitude.is_infinite() {
+                    "Infinity".to_string()
+                } else if magnitude.fract() == 0.0 && magnitude < MAX_EXACT_INTEGER {
+                    format!("{}", magnitude as i64)
+                } else {
+                    magnitude.to_string()
+                };
+                // Parenthesized so that `-FLAG` never renders as `--1`
+                if n.is_sign_negative() { format!("(-{})", text) } else { text }
+            }
+            Self::Bool(b) => b.to_string(),
+            Self::Null => "null".to_string(),
+            Self::Undefined => "void 0".to_string(),
+        }
+    }
+}
+
+/// Render a define value as a JavaScript expression
+fn render_json(value: &Value) -> String {
+    match value {
+        Value::Object(_) => format!("({})", value),
+        Value::Number(n) if n.as_f64().is_some_and(f64::is_sign_negative) => format!("({})", value),
+        _ => value.to_string(),
+    }
+}
+
+/// A `var` statement declaring the names a statement hoists to its
+/// function scope, followed by a space, or nothing when it hoists none
+fn hoisted_declaration(stmt: &Stmt) -> String {
+    let mut collector = HoistedNames { names: Vec::new(), binding: false };
+    stmt.visit_with(&mut collector);
+    if collector.names.is_empty() {
+        return String::new();
+    }
+    format!("var {}; ", collector.names.join(", "))
+}
+
+/// Collects the names of `var` and function declarations, without entering
+/// nested functions and classes
+struct HoistedNames {
+    names: Vec<String>,
+    /// Whether the visitor is inside the pattern of a `var` declarator
+    binding: bool,
+}
+
+impl HoistedNames {
+    fn insert(&mut self, name: &Ident) {
+        let name = name.sym.to_string();
+        if !self.names.contains(&name) {
+            self.names.push(name);
+        }
+    }
+}
+
+impl Visit for HoistedNames {
+    fn visit_var_decl(&mut self, decl: &VarDecl) {
+        if decl.kind != VarDeclKind::Var {
+            return;
+        }
+        for declarator in &decl.decls {
+            self.binding = true;
+            declarator.name.visit_with(self);
+            self.binding = false;
+            declarator.init.visit_with(self);
+        }
+    }
+
+    fn visit_binding_ident(&mut self, ident: &BindingIdent) {
+        if self.binding {
+            self.insert(&ident.id);
+        }
+    }
+
+    fn visit_assign_pat_prop(&mut self, prop: &AssignPatProp) {
+        if self.binding {
+            self.insert(&prop.key);
+        }
+        prop.value.visit_with(self);
+    }
+
+    fn visit_fn_decl(&mut self, decl: &FnDecl) {
+        self.insert(&decl.ident);
+    }
+
+    fn visit_function(&mut self, _function: &Function) {}
+
+    fn visit_arrow_expr(&mut self, _arrow: &ArrowExpr) {}
+
+    fn visit_class(&mut self, _class: &Class) {}
+}
+
+/// Visitor that records define substitutions and dead-branch removals
+struct DefineFolder<'a> {
+    defines: &'a HashMap<String, Value>,
+    declared: HashSet<String>,
+    /// Spans of statements that sit directly in a statement list and can be
+    /// removed without leaving an empty statement behind
+    list_statements: HashSet<(BytePos, BytePos)>,
+    edits: SourceEdits,
+}
+
+impl<'a> DefineFolder<'a> {
+    /// Dotted path of an identifier or static member chain
+    fn expression_path(expr: &Expr) -> Option<String> {
+        match expr {
+            Expr::Ident(ident) => Some(ident.sym.to_string()),
+            Expr::Member(member) => {
+                let object = Self::expression_path(&member.obj)?;
+                let property = match &member.prop {
+                    MemberProp::Ident(ident) => ident.sym.to_string(),
+                    MemberProp::Computed(computed) => match computed.expr.as_ref() {
+                        Expr::Lit(Lit::Str(s)) => s.value.to_string(),
+                        _ => return None,
+                    },
+                    MemberProp::PrivateName(_) => return None,
+                };
+                Some(format!("{}.{}", object, property))
+            }
+            _ => None,
+        }
+    }
+
+    /// Define value for an expression, unless its root is declared locally
+    fn lookup(&self, expr: &Expr) -> Option<&'a Value> {
+        let path = Self::expression_path(expr)?;
+        let root = path.split('.').next()?;
+        if self.declared.contains(root) {
+            return None;
+        }
+        self.defines.get(&path)
+    }
+
+    fn references_define(&self, expr: &Expr) -> bool {
+        if self.lookup(expr).is_some() {
+            return true;
+        }
+        match expr {
+            Expr::Paren(paren) => self.references_define(&paren.expr),
+            Expr::Unary(unary) => self.references_define(&unary.arg),
+            Expr::Bin(bin) => self.references_define(&bin.left) || self.references_define(&bin.right),
+            _ => false,
+        }
+    }
+
+    /// Evaluate an expression to a constant, if it has no runtime dependency
+    fn evaluate(&self, expr: &Expr) -> Option<Const> {
+        if let Some(value) = self.lookup(expr) {
+            return Const::from_json(value);
+        }
+
+        match expr {
+            Expr::Lit(Lit::Str(s)) => Some(Const::Str(s.value.to_string())),
+            Expr::Lit(Lit::Num(n)) => Some(Const::Num(n.value)),
+            Expr::Lit(Lit::Bool(b)) => Some(Const::Bool(b.value)),
+            Expr::Lit(Lit::Null(_)) => Some(Const::Null),
+            Expr::Tpl(tpl) if tpl.exprs.is_empty() => tpl.quasis.first()
+                .and_then(|q| q.cooked.as_ref())
+                .map(|s| Const::Str(s.to_string())),
+            Expr::Ident(ident) if ident.sym == *"undefined" && !self.declared.contains("undefined") => {
+                Some(Const::Undefined)
+            }
+            Expr::Paren(paren) => self.evaluate(&paren.expr),
+            Expr::Unary(unary) => {
+                let arg = self.evaluate(&unary.arg)?;
+                match unary.op {
+                    UnaryOp::Bang => Some(Const::Bool(!arg.is_truthy())),
+                    UnaryOp::TypeOf => Some(Const::Str(arg.type_of().to_string())),
+                    UnaryOp::Void => Some(Const::Undefined),
+                    UnaryOp::Minus => match arg {
+                        Const::Num(n) => Some(Const::Num(-n)),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+            Expr::Bin(bin) => match bin.op {
+                BinaryOp::LogicalAnd | BinaryOp::LogicalOr | BinaryOp::NullishCoalescing => {
+                    let left = self.evaluate(&bin.left)?;
+                    if Self::takes_right(bin.op, &left) {
+                        self.evaluate(&bin.right)
+                    } else {
+                        Some(left)
+                    }
+                }
+                BinaryOp::EqEqEq | BinaryOp::NotEqEq | BinaryOp::EqEq | BinaryOp::NotEq => {
+                    let left = self.evaluate(&bin.left)?;
+                    let right = self.evaluate(&bin.right)?;
+                    let equal = match bin.op {
+                        BinaryOp::EqEqEq | BinaryOp::NotEqEq => left.strict_equals(&right),
+                        _ => left.loose_equals(&right)?,
+                    };
+                    let negated = matches!(bin.op, BinaryOp::NotEqEq | BinaryOp::NotEq);
+                    Some(Const::Bool(equal != negated))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether a logical operator evaluates its right operand
+    fn takes_right(op: BinaryOp, left: &Const) -> bool {
+        match op {
+            BinaryOp::LogicalAnd => left.is_truthy(),
+            BinaryOp::LogicalOr => !left.is_truthy(),
+            _ => left.is_nullish(),
+        }
+    }
+
+    /// Expressions that change meaning when they end up at the start of a
+    /// statement, and therefore keep parentheses when unwrapped
+    fn needs_parens(expr: &Expr) -> bool {
+        matches!(expr, Expr::Object(_) | Expr::Fn(_) | Expr::Class(_))
+    }
+
+    /// Keep only `kept` out of the surrounding expression `outer`
+    fn unwrap_expr(&mut self, outer: swc_common::Span, kept: &Expr) {
+        let span = kept.span();
+        let parens = Self::needs_parens(kept);
+        self.edits.replace_between(outer.lo, span.lo, if parens { "(" } else { "" });
+        self.edits.replace_between(span.hi, outer.hi, if parens { ")" } else { "" });
+        kept.visit_with(self);
+    }
+}
+
+impl Visit for DefineFolder<'_> {
+    fn visit_module_items(&mut self, items: &[ModuleItem]) {
+        for item in items {
+            if let ModuleItem::Stmt(stmt) = item {
+                let span = stmt.span();
+                self.list_statements.insert((span.lo, span.hi));
+            }
+        }
+        items.visit_children_with(self);
+    }
+
+    fn visit_stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            let span = stmt.span();
+            self.list_statements.insert((span.lo, span.hi));
+        }
+        stmts.visit_children_with(self);
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &IfStmt) {
+        let Some(test) = self.evaluate(&stmt.test) else {
+            stmt.visit_children_with(self);
+            return;
+        };
+
+        let (kept, dead) = if test.is_truthy() {
+            (Some(stmt.cons.as_ref()), stmt.alt.as_deref())
+        } else {
+            (stmt.alt.as_deref(), Some(stmt.cons.as_ref()))
+        };
+
+        // `var` and function declarations of the dropped branch are hoisted
+        // out of it, so they stay declared
+        let hoisted = dead.map(hoisted_declaration).unwrap_or_default();
+        let in_list = self.list_statements.contains(&(stmt.span.lo, stmt.span.hi));
+
+        match kept {
+            Some(kept) => {
+                let (open, close) = if hoisted.is_empty() || in_list { ("", "") } else { ("{ ", " }") };
+                self.edits.replace_between(stmt.span.lo, kept.span().lo, format!("{}{}", open, hoisted));
+                self.edits.replace_between(kept.span().hi, stmt.span.hi, close);
+                kept.visit_with(self);
+            }
+            None if !hoisted.is_empty() => self.edits.replace(stmt.span, hoisted.trim_end()),
+            None => self.edits.replace(stmt.span, if in_list { "" } else { ";" }),
+        }
+    }
+
+    fn visit_cond_expr(&mut self, cond: &CondExpr) {
+        match self.evaluate(&cond.test) {
+            Some(test) if test.is_truthy() => self.unwrap_expr(cond.span, &cond.cons),
+            Some(_) => self.unwrap_expr(cond.span, &cond.alt),
+            None => cond.visit_children_with(self),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Some(value) = self.lookup(expr) {
+            self.edits.replace(expr.span(), render_json(value));
+            return;
+        }
+
+        match expr {
+            Expr::Bin(bin) if matches!(
+                bin.op,
+                BinaryOp::LogicalAnd | BinaryOp::LogicalOr | BinaryOp::NullishCoalescing
+            ) => {
+                if let Some(left) = self.evaluate(&bin.left) {
+                    if Self::takes_right(bin.op, &left) {
+                        self.unwrap_expr(bin.span, &bin.right);
+                    } else {
+                        self.edits.replace(bin.span, left.render());
+                    }
+                    return;
+                }
+            }
+            Expr::Bin(_) | Expr::Unary(_) if self.references_define(expr) => {
+                if let Some(value) = self.evaluate(expr) {
+                    self.edits.replace(expr.span(), value.render());
+                    return;
+                }
+            }
+            _ => {}
+        }
+
+        expr.visit_children_with(self);
+    }
+
+    fn visit_prop(&mut self, prop: &Prop) {
+        if let Prop::Shorthand(ident) = prop {
+            if let Some(value) = self.lookup(&Expr::Ident(ident.clone())) {
+                self.edits.replace(ident.span, format!("{}: {}", ident.sym, render_json(value)));
+                return;
+            }
+        }
+        prop.visit_children_with(self);
+    }
+
+    fn visit_assign_expr(&mut self, assign: &AssignExpr) {
+        // Never substitute the target of an assignment
+        assign.right.visit_with(self);
+    }
+
+    fn visit_update_expr(&mut self, _update: &UpdateExpr) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn apply(code: &str, defines: &[(&str, Value)]) -> String {
+        let defines = defines.iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect();
+        apply_defines(code, js::syntax_for_path(Path::new("test.js")), &defines).unwrap()
+    }
+
+    fn production() -> (&'static str, Value) {
+        (NODE_ENV_DEFINE, Value::String("production".to_string()))
+    }
+
+    #[test]
+    fn test_parse_define() {
+        assert_eq!(
+            parse_define("process.env.NODE_ENV=production").unwrap(),
+            ("process.env.NODE_ENV".to_string(), Value::String("production".to_string()))
+        );
+        assert_eq!(parse_define("DEBUG=false").unwrap().1, Value::Bool(false));
+        assert_eq!(parse_define("VERSION=\"1.0\"").unwrap().1, Value::String("1.0".to_string()));
+        assert!(parse_define("NO_VALUE").is_err());
+        assert!(parse_define("bad-key=1").is_err());
+    }
+
+    #[test]
+    fn test_removes_development_branch() {
+        let code = "if (process.env.NODE_ENV !== 'production') {\n  warn('dev');\n}\nrun();\n";
+        let result = apply(code, &[production()]);
+        assert!(!result.contains("warn"));
+        assert!(result.contains("run();"));
+    }
+
+    #[test]
+    fn test_keeps_taken_branch() {
+        let code = "if (process.env.NODE_ENV === 'production') { prod(); } else { dev(); }";
+        let result = apply(code, &[production()]);
+        assert!(result.contains("prod();"));
+        assert!(!result.contains("dev();"));
+        assert!(!result.contains("if"));
+    }
+
+    #[test]
+    fn test_folds_expressions() {
+        let code = "var a = process.env.NODE_ENV;\nvar b = DEBUG ? debug() : quiet();\nvar c = DEBUG && log();";
+        let result = apply(code, &[production(), ("DEBUG", Value::Bool(false))]);
+        assert!(result.contains("var a = \"production\";"));
+        assert!(result.contains("var b = quiet();"));
+        assert!(result.contains("var c = false;"));
+    }
+
+    #[test]
+    fn test_respects_local_declarations() {
+        let code = "function f(DEBUG) { if (DEBUG) { log(); } }";
+        let result = apply(code, &[("DEBUG", Value::Bool(false))]);
+        assert_eq!(result, code);
+    }
+
+    #[test]
+    fn test_hoists_declarations_of_dropped_branch() {
+        let code = "if (DEBUG) { var a = 1, { b } = c; function d() { var e; } let f; }\nuse(a, b, d);";
+        let result = apply(code, &[("DEBUG", Value::Bool(false))]);
+        assert!(result.starts_with("var a, b, d;\nuse"));
+
+        let code = "if (!DEBUG) { run(); } else { var a = 1; }";
+        let result = apply(code, &[("DEBUG", Value::Bool(false))]);
+        assert_eq!(result, "var a; { run(); }");
+
+        let code = "if (x) if (DEBUG) var a; else run();";
+        let result = apply(code, &[("DEBUG", Value::Bool(false))]);
+        assert_eq!(result, "if (x) { var a; run(); }");
+    }
+
+    #[test]
+    fn test_renders_numbers() {
+        assert_eq!(Const::Num(42.0).render(), "42");
+        assert_eq!(Const::Num(1.5).render(), "1.5");
+        assert_eq!(Const::Num(-1.0).render(), "(-1)");
+        assert_eq!(Const::Num(-0.0).render(), "(-0)");
+        assert_eq!(Const::Num(f64::INFINITY).render(), "Infinity");
+        assert_eq!(Const::Num(1e300).render(), format!("1{}", "0".repeat(300)));
+
+        let result = apply("var a = -FLAG;", &[("FLAG", serde_json::json!(-1))]);
+        assert_eq!(result, "var a = 1;");
+        let result = apply("var a = FLAG || 2;", &[("FLAG", serde_json::json!(-1))]);
+        assert_eq!(result, "var a = (-1);");
+        let result = apply("var a = x - FLAG;", &[("FLAG", serde_json::json!(-1))]);
+        assert_eq!(result, "var a = x - (-1);");
+    }
+
+    #[test]
+    fn test_else_if_chain_stays_valid() {
+        let code = "if (a) { one(); } else if (process.env.NODE_ENV === 'development') { two(); }";
+        let result = apply(code, &[production()]);
+        assert!(!result.contains("two"));
+        assert!(js::parse_program(&result, js::syntax_for_path(Path::new("test.js"))).is_ok());
+    }
+}
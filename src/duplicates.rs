@@ -0,0 +1,136 @@
+//! Detection of packages bundled at more than one version
+//!
+//! npm nests a dependency under the package that needs it when another
+//! version already sits at the top level, so a full tree often carries
+//! several copies of helpers such as `tslib` or `bn.js`. Each copy is
+//! reported with its version, its size in the bundle and the modules that
+//! require it, so the cost can be weighed against deduplicating.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+
+use crate::converter::ModuleSource;
+use crate::resolver::{self, Resolution};
+
+/// One installed copy of a package
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageCopy {
+    pub version: String,
+    /// Where the copy is installed, e.g. `node_modules/a/node_modules/tslib`
+    pub directory: PathBuf,
+    /// Size of the copy's modules in bytes
+    pub size: usize,
+    /// Modules outside the copy that require it
+    pub required_by: Vec<PathBuf>,
+}
+
+/// A package bundled at more than one version
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicatePackage {
+    pub name: String,
+    /// Copies by version, lowest first
+    pub copies: Vec<PackageCopy>,
+    /// Bytes the copies add beyond the largest one
+    pub wasted_size: usize,
+}
+
+impl DuplicatePackage {
+    /// Versions of the copies, e.g. `1.14.1, 2.6.2`
+    pub fn versions(&self) -> String {
+        self.copies.iter().map(|copy| copy.version.as_str()).collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// Packages whose modules come from more than one installed copy.
+/// `version_of` reads the version of the package installed at a directory.
+pub fn find_duplicates(modules: &[ModuleSource], version_of: impl Fn(&Path) -> String) -> Vec<DuplicatePackage> {
+    let mut sizes: BTreeMap<PathBuf, usize> = BTreeMap::new();
+    let mut required_by: BTreeMap<PathBuf, BTreeSet<PathBuf>> = BTreeMap::new();
+
+    for module in modules {
+        let root = resolver::package_root(&module.path);
+        if !root.as_os_str().is_empty() {
+            *sizes.entry(root.clone()).or_default() += module.code.len();
+        }
+        for resolution in module.dependencies.values() {
+            let Resolution::Module(target) = resolution else { continue };
+            let target_root = resolver::package_root(target);
+            if target_root != root {
+                required_by.entry(target_root).or_default().insert(module.path.clone());
+            }
+        }
+    }
+
+    let mut copies: BTreeMap<String, Vec<PackageCopy>> = BTreeMap::new();
+    for (directory, size) in sizes {
+        let Some(name) = resolver::package_name_of(&directory.join("package.json")) else { continue };
+        copies.entry(name).or_default().push(PackageCopy {
+            version: version_of(&directory),
+            required_by: required_by.remove(&directory).unwrap_or_default().into_iter().collect(),
+            directory,
+            size,
+        });
+    }
+
+    copies.into_iter()
+        .filter(|(_, copies)| copies.len() > 1)
+        .map(|(name, mut copies)| {
+            copies.sort_by(|a, b| compare_versions(&a.version, &b.version).then_with(|| a.directory.cmp(&b.directory)));
+            let total: usize = copies.iter().map(|copy| copy.size).sum();
+            let largest = copies.iter().map(|copy| copy.size).max().unwrap_or(0);
+            DuplicatePackage { name, copies, wasted_size: total - largest }
+        })
+        .collect()
+}
+
+fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    match (semver::Version::parse(a), semver::Version::parse(b)) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(path: &str, code: &str, dependencies: &[(&str, &str)]) -> ModuleSource {
+        ModuleSource {
+            path: PathBuf::from(path),
+            code: code.to_string(),
+            dependencies: dependencies.iter()
+                .map(|(specifier, target)| (specifier.to_string(), Resolution::Module(PathBuf::from(target))))
+                .collect(),
+            dynamic_imports: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let modules = vec![
+            module("index.js", "", &[
+                ("tslib", "node_modules/tslib/tslib.js"),
+                ("a", "node_modules/a/index.js"),
+            ]),
+            module("node_modules/tslib/tslib.js", "x".repeat(100).as_str(), &[]),
+            module("node_modules/a/index.js", "", &[("tslib", "node_modules/a/node_modules/tslib/tslib.js")]),
+            module("node_modules/a/node_modules/tslib/tslib.js", "x".repeat(30).as_str(), &[]),
+        ];
+        let versions = |directory: &Path| match directory.to_str() {
+            Some("node_modules/tslib") => "2.6.2".to_string(),
+            Some("node_modules/a/node_modules/tslib") => "1.14.1".to_string(),
+            _ => "1.0.0".to_string(),
+        };
+
+        let duplicates = find_duplicates(&modules, versions);
+        assert_eq!(duplicates.len(), 1);
+        let tslib = &duplicates[0];
+        assert_eq!(tslib.name, "tslib");
+        assert_eq!(tslib.versions(), "1.14.1, 2.6.2");
+        assert_eq!(tslib.wasted_size, 30);
+        assert_eq!(tslib.copies[0].directory, PathBuf::from("node_modules/a/node_modules/tslib"));
+        assert_eq!(tslib.copies[0].required_by, vec![PathBuf::from("node_modules/a/index.js")]);
+        assert_eq!(tslib.copies[1].required_by, vec![PathBuf::from("index.js")]);
+    }
+}
@@ -61,6 +61,16 @@ pub enum PaktoError {
         max: usize
     },
 
+    #[error("Size budget exceeded: {}", violations.join("; "))]
+    BudgetExceeded {
+        violations: Vec<String>,
+    },
+
+    #[error("Packages bundled at more than one version: {}", packages.join(", "))]
+    DuplicatePackages {
+        packages: Vec<String>,
+    },
+
     #[error("Missing required dependency: {dependency}")]
     MissingDependency {
         dependency: String,
@@ -228,7 +238,9 @@ impl PaktoError {
             Self::CircularDependency { .. } |
             Self::MissingDependency { .. } => ErrorCategory::Compatibility,
 
-            Self::BundleTooLarge { .. } => ErrorCategory::Bundle,
+            Self::BundleTooLarge { .. } |
+            Self::BudgetExceeded { .. } |
+            Self::DuplicatePackages { .. } => ErrorCategory::Bundle,
 
             Self::TemplateError { .. } => ErrorCategory::Template,
 
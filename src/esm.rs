@@ -0,0 +1,460 @@
+//! ES module to CommonJS conversion
+//!
+//! The bundle's module runtime links modules through `require()`, so
+//! `import` and `export` are rewritten in place. Imports become requires
+//! hoisted to the top of the module, and references to imported bindings
+//! read the required module object, which keeps them live across circular
+//! imports. Exports become getters on `exports`, defined before any
+//! dependency runs. `import()` becomes `require.import()`, which loads
+//! the module's chunk first when the bundle is split, and `import.meta`
+//! an object with the module's URL.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use anyhow::Result;
+use swc_common::Spanned;
+use swc_ecma_ast::*;
+use swc_ecma_parser::Syntax;
+use swc_ecma_visit::{Visit, VisitWith};
+
+use crate::js::{self, SourceEdits};
+
+/// Name of the variable holding an anonymous default export
+const DEFAULT_EXPORT: &str = "__pakto_default__";
+
+/// Rewrite a module's `import` and `export` syntax to CommonJS. Code
+/// without module syntax is returned unchanged.
+pub fn convert_to_commonjs(code: &str, syntax: Syntax, module_path: &Path) -> Result<String> {
+    if !code.contains("import") && !code.contains("export") {
+        return Ok(code.to_string());
+    }
+
+    let program = js::parse_program(code, syntax)?;
+    let items: &[ModuleItem] = match &program {
+        Program::Module(module) => &module.body,
+        Program::Script(_) => &[],
+    };
+
+    let mut converter = Converter {
+        module_path,
+        local_names: js::local_names(&program),
+        sources: Vec::new(),
+        bindings: HashMap::new(),
+        snapshots: Vec::new(),
+        exports: Vec::new(),
+        star_exports: Vec::new(),
+        edits: SourceEdits::new(),
+    };
+
+    let declarations: Vec<&ModuleDecl> = items.iter()
+        .filter_map(|item| match item {
+            ModuleItem::ModuleDecl(decl) => Some(decl),
+            ModuleItem::Stmt(_) => None,
+        })
+        .collect();
+
+    // Imports first, so exports of imported bindings can refer to them
+    for decl in &declarations {
+        if let ModuleDecl::Import(import) = decl {
+            converter.convert_import(import);
+        }
+    }
+    for decl in &declarations {
+        converter.convert_export(decl);
+    }
+
+    program.visit_with(&mut converter);
+
+    let header = converter.header(!declarations.is_empty());
+    let mut edits = converter.edits;
+    if !header.is_empty() {
+        edits.replace_between(program.span().lo, program.span().lo, header);
+    }
+    Ok(edits.apply(code))
+}
+
+/// A module required by an import or re-export
+struct Source {
+    specifier: String,
+    /// Whether a default import needs the interop wrapper
+    uses_default: bool,
+}
+
+/// Visitor that rewrites module syntax and references to imports
+struct Converter<'a> {
+    module_path: &'a Path,
+    /// Names bound by the module itself, which may shadow an import
+    local_names: HashSet<String>,
+    sources: Vec<Source>,
+    /// Expression each imported binding reads
+    bindings: HashMap<String, String>,
+    /// Imported bindings shadowed somewhere in the module, copied once
+    /// into a variable instead of being rewritten
+    snapshots: Vec<(String, String)>,
+    /// Exported name and the expression its getter returns
+    exports: Vec<(String, String)>,
+    /// Variables of modules re-exported with `export *`
+    star_exports: Vec<String>,
+    edits: SourceEdits,
+}
+
+impl Converter<'_> {
+    /// Variable holding the required module for a specifier
+    fn source(&mut self, specifier: &str) -> String {
+        let index = match self.sources.iter().position(|s| s.specifier == specifier) {
+            Some(index) => index,
+            None => {
+                self.sources.push(Source { specifier: specifier.to_string(), uses_default: false });
+                self.sources.len() - 1
+            }
+        };
+        format!("_pakto_import_{}", index)
+    }
+
+    /// Expression reading an export of a required module. A default export
+    /// of a CommonJS module is its whole `module.exports`.
+    fn import_expression(&mut self, specifier: &str, name: &str) -> String {
+        let module = self.source(specifier);
+        if name == "default" {
+            if let Some(source) = self.sources.iter_mut().find(|s| s.specifier == specifier) {
+                source.uses_default = true;
+            }
+            return format!("{}_default.default", module);
+        }
+        member(&module, name)
+    }
+
+    fn convert_import(&mut self, import: &ImportDecl) {
+        if import.type_only {
+            return;
+        }
+
+        let specifier = import.src.value.to_string();
+        self.source(&specifier);
+
+        for specifier_node in &import.specifiers {
+            let (local, expression) = match specifier_node {
+                ImportSpecifier::Named(s) if s.is_type_only => continue,
+                ImportSpecifier::Named(s) => {
+                    let imported = s.imported.as_ref()
+                        .map(export_name)
+                        .unwrap_or_else(|| s.local.sym.to_string());
+                    (s.local.sym.to_string(), self.import_expression(&specifier, &imported))
+                }
+                ImportSpecifier::Default(s) => {
+                    (s.local.sym.to_string(), self.import_expression(&specifier, "default"))
+                }
+                ImportSpecifier::Namespace(s) => (s.local.sym.to_string(), self.source(&specifier)),
+            };
+
+            if self.local_names.contains(&local) {
+                self.snapshots.push((local.clone(), expression.clone()));
+                self.bindings.insert(local.clone(), local);
+            } else {
+                self.bindings.insert(local, expression);
+            }
+        }
+
+        // The require is hoisted into the header
+        self.edits.replace(import.span, "");
+    }
+
+    fn convert_export(&mut self, decl: &ModuleDecl) {
+        match decl {
+            ModuleDecl::ExportDecl(export) => {
+                for name in declared_by(&export.decl) {
+                    self.exports.push((name.clone(), name));
+                }
+                self.edits.replace_between(export.span.lo, export.decl.span().lo, "");
+            }
+            ModuleDecl::ExportDefaultDecl(export) => {
+                let ident = match &export.decl {
+                    DefaultDecl::Fn(f) => f.ident.as_ref(),
+                    DefaultDecl::Class(c) => c.ident.as_ref(),
+                    DefaultDecl::TsInterfaceDecl(_) => return,
+                };
+                match ident {
+                    // A named declaration keeps its name and hoisting
+                    Some(ident) => {
+                        self.exports.push(("default".to_string(), ident.sym.to_string()));
+                        self.edits.replace_between(export.span.lo, export.decl.span().lo, "");
+                    }
+                    None => {
+                        self.exports.push(("default".to_string(), DEFAULT_EXPORT.to_string()));
+                        self.edits.replace_between(
+                            export.span.lo,
+                            export.decl.span().lo,
+                            format!("var {} = ", DEFAULT_EXPORT),
+                        );
+                        self.edits.replace_between(export.span.hi, export.span.hi, ";");
+                    }
+                }
+            }
+            ModuleDecl::ExportDefaultExpr(export) => {
+                self.exports.push(("default".to_string(), DEFAULT_EXPORT.to_string()));
+                self.edits.replace_between(
+                    export.span.lo,
+                    export.expr.span().lo,
+                    format!("var {} = ", DEFAULT_EXPORT),
+                );
+            }
+            ModuleDecl::ExportNamed(export) if !export.type_only => {
+                let source = export.src.as_ref().map(|src| src.value.to_string());
+                for specifier in &export.specifiers {
+                    match specifier {
+                        ExportSpecifier::Named(s) if s.is_type_only => {}
+                        ExportSpecifier::Named(s) => {
+                            let original = export_name(&s.orig);
+                            let exported = s.exported.as_ref().map(export_name).unwrap_or_else(|| original.clone());
+                            let expression = match &source {
+                                Some(source) => self.import_expression(source, &original),
+                                None => self.bindings.get(&original).cloned().unwrap_or(original),
+                            };
+                            self.exports.push((exported, expression));
+                        }
+                        ExportSpecifier::Namespace(s) => {
+                            if let Some(source) = &source {
+                                let module = self.source(source);
+                                self.exports.push((export_name(&s.name), module));
+                            }
+                        }
+                        ExportSpecifier::Default(s) => {
+                            if let Some(source) = &source {
+                                let expression = self.import_expression(source, "default");
+                                self.exports.push((s.exported.sym.to_string(), expression));
+                            }
+                        }
+                    }
+                }
+                self.edits.replace(export.span, "");
+            }
+            ModuleDecl::ExportAll(export) if !export.type_only => {
+                let module = self.source(&export.src.value);
+                self.star_exports.push(module);
+                self.edits.replace(export.span, "");
+            }
+            _ => {}
+        }
+    }
+
+    /// Code placed before the module body: export getters, then requires,
+    /// then re-exports and copies of shadowed imports
+    fn header(&self, is_module: bool) -> String {
+        if !is_module {
+            return String::new();
+        }
+
+        let mut lines = vec!["Object.defineProperty(exports, \"__esModule\", { value: true });".to_string()];
+
+        for (name, expression) in &self.exports {
+            lines.push(format!(
+                "Object.defineProperty(exports, {}, {{ enumerable: true, get: function () {{ return {}; }} }});",
+                serde_json::Value::String(name.clone()),
+                expression
+            ));
+        }
+
+        for (index, source) in self.sources.iter().enumerate() {
+            let module = format!("_pakto_import_{}", index);
+            lines.push(format!("var {} = require({});", module, serde_json::Value::String(source.specifier.clone())));
+            if source.uses_default {
+                lines.push(format!(
+                    "var {module}_default = {module} && {module}.__esModule ? {module} : {{ default: {module} }};",
+                    module = module
+                ));
+            }
+        }
+
+        for module in &self.star_exports {
+            lines.push(format!(
+                "Object.keys({module}).forEach(function (key) {{ \
+                 if (key === \"default\" || key === \"__esModule\" || Object.prototype.hasOwnProperty.call(exports, key)) return; \
+                 Object.defineProperty(exports, key, {{ enumerable: true, get: function () {{ return {module}[key]; }} }}); }});",
+                module = module
+            ));
+        }
+
+        for (local, expression) in &self.snapshots {
+            lines.push(format!("var {} = {};", local, expression));
+        }
+
+        lines.join("\n") + "\n"
+    }
+
+    /// Rewritten reference to an imported binding
+    fn reference(&self, ident: &Ident) -> Option<&String> {
+        self.bindings.get(ident.sym.as_ref())
+            .filter(|expression| expression.as_str() != ident.sym.as_ref())
+    }
+}
+
+impl Visit for Converter<'_> {
+    // Declarations were rewritten as a whole
+    fn visit_import_decl(&mut self, _: &ImportDecl) {}
+
+    fn visit_named_export(&mut self, _: &NamedExport) {}
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Ident(ident) = expr {
+            if let Some(expression) = self.reference(ident) {
+                self.edits.replace(ident.span, expression.clone());
+            }
+            return;
+        }
+        expr.visit_children_with(self);
+    }
+
+    fn visit_call_expr(&mut self, call: &CallExpr) {
+        match &call.callee {
+            // Imported functions are called without a receiver
+            Callee::Expr(callee) => {
+                if let Expr::Ident(ident) = callee.as_ref() {
+                    if let Some(expression) = self.reference(ident) {
+                        self.edits.replace(ident.span, format!("(0, {})", expression));
+                        call.args.visit_with(self);
+                        return;
+                    }
+                }
+            }
+            // import(x) resolves to the module once the chunk holding it
+            // has loaded
+            Callee::Import(_) => {
+                if let [argument] = call.args.as_slice() {
+                    self.edits.replace_between(call.span.lo, argument.expr.span().lo, "require.import(");
+                    self.edits.replace_between(argument.expr.span().hi, call.span.hi, ")");
+                    argument.visit_with(self);
+                    return;
+                }
+            }
+            _ => {}
+        }
+        call.visit_children_with(self);
+    }
+
+    fn visit_prop(&mut self, prop: &Prop) {
+        if let Prop::Shorthand(ident) = prop {
+            if let Some(expression) = self.reference(ident) {
+                self.edits.replace(ident.span, format!("{}: {}", ident.sym, expression));
+            }
+            return;
+        }
+        prop.visit_children_with(self);
+    }
+
+    fn visit_meta_prop_expr(&mut self, meta: &MetaPropExpr) {
+        if meta.kind == MetaPropKind::ImportMeta {
+            let url = format!("file:///{}", self.module_path.to_string_lossy().replace('\\', "/"));
+            self.edits.replace(meta.span, format!("({{ url: {} }})", serde_json::Value::String(url)));
+        }
+    }
+
+    fn visit_member_prop(&mut self, prop: &MemberProp) {
+        // obj.name is a property, not a reference
+        if let MemberProp::Computed(computed) = prop {
+            computed.visit_with(self);
+        }
+    }
+}
+
+/// Names a declaration binds
+fn declared_by(decl: &Decl) -> Vec<String> {
+    match decl {
+        Decl::Fn(f) => vec![f.ident.sym.to_string()],
+        Decl::Class(c) => vec![c.ident.sym.to_string()],
+        Decl::Var(var) => {
+            let mut collector = BindingCollector::default();
+            var.decls.iter().for_each(|d| d.name.visit_with(&mut collector));
+            collector.names
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[derive(Default)]
+struct BindingCollector {
+    names: Vec<String>,
+}
+
+impl Visit for BindingCollector {
+    fn visit_binding_ident(&mut self, ident: &BindingIdent) {
+        self.names.push(ident.id.sym.to_string());
+    }
+
+    // Default values inside patterns are expressions, not bindings
+    fn visit_expr(&mut self, _: &Expr) {}
+}
+
+fn export_name(name: &ModuleExportName) -> String {
+    match name {
+        ModuleExportName::Ident(ident) => ident.sym.to_string(),
+        ModuleExportName::Str(s) => s.value.to_string(),
+    }
+}
+
+/// Property access, bracketed when the name is not an identifier
+fn member(object: &str, name: &str) -> String {
+    let is_identifier = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == '$')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '$');
+    if is_identifier {
+        format!("{}.{}", object, name)
+    } else {
+        format!("{}[{}]", object, serde_json::Value::String(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(code: &str) -> String {
+        let path = Path::new("lib/index.mjs");
+        convert_to_commonjs(code, js::syntax_for_path(path), path).unwrap()
+    }
+
+    #[test]
+    fn test_imports_become_live_references() {
+        let result = convert("import def, { map as m } from 'lodash';\nimport * as ns from './ns';\nexport const out = m(def, ns.x, { m });");
+        assert!(result.contains("var _pakto_import_0 = require(\"lodash\");"));
+        assert!(result.contains("var _pakto_import_0_default = _pakto_import_0 && _pakto_import_0.__esModule ? _pakto_import_0 : { default: _pakto_import_0 };"));
+        assert!(result.contains("var _pakto_import_1 = require(\"./ns\");"));
+        assert!(result.contains("const out = (0, _pakto_import_0.map)(_pakto_import_0_default.default, _pakto_import_1.x, { m: _pakto_import_0.map });"));
+        assert!(result.contains("Object.defineProperty(exports, \"out\", { enumerable: true, get: function () { return out; } });"));
+        assert!(!result.contains("import "));
+    }
+
+    #[test]
+    fn test_shadowed_imports_are_copied() {
+        let result = convert("import { a } from './a';\nfunction f(a) { return a; }\nexport default a;");
+        assert!(result.contains("var a = _pakto_import_0.a;"));
+        assert!(result.contains("function f(a) { return a; }"));
+        assert!(result.contains("var __pakto_default__ = a;"));
+    }
+
+    #[test]
+    fn test_export_forms() {
+        let result = convert(
+            "export function f() {}\nexport default class {}\nexport { f as g };\nexport { x as y } from './x';\nexport * from './all';"
+        );
+        assert!(result.contains("\nfunction f() {}"));
+        assert!(result.contains("var __pakto_default__ = class {};"));
+        assert!(result.contains("Object.defineProperty(exports, \"g\", { enumerable: true, get: function () { return f; } });"));
+        assert!(result.contains("Object.defineProperty(exports, \"y\", { enumerable: true, get: function () { return _pakto_import_0.x; } });"));
+        assert!(result.contains("Object.keys(_pakto_import_1).forEach("));
+        assert!(!result.contains("export "));
+    }
+
+    #[test]
+    fn test_dynamic_import_and_import_meta() {
+        let result = convert("import('./chunk').then(run); console.log(import.meta.url);");
+        assert_eq!(
+            result,
+            "require.import('./chunk').then(run); console.log(({ url: \"file:///lib/index.mjs\" }).url);"
+        );
+    }
+
+    #[test]
+    fn test_commonjs_is_unchanged() {
+        let code = "var exported = require('./x'); module.exports = exported;";
+        assert_eq!(convert(code), code);
+    }
+}
@@ -0,0 +1,494 @@
+//! Static inlining of package files read through `fs`
+//!
+//! Many packages load files they ship themselves with
+//! `fs.readFileSync(path.join(__dirname, 'data.json'), 'utf8')`. When the
+//! path can be worked out at build time and the file is part of the
+//! package, the call is replaced with the file contents, the same way
+//! browserify's brfs transform does.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use swc_ecma_ast::*;
+use swc_ecma_parser::Syntax;
+use swc_ecma_visit::{Visit, VisitWith};
+
+use crate::converter::FileContent;
+use crate::errors::CodeLocation;
+use crate::js::{self, SourceEdits};
+use crate::wasm;
+
+/// A file read that could not be inlined
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnresolvedRead {
+    pub location: CodeLocation,
+    pub reason: String,
+}
+
+impl std::fmt::Display for UnresolvedRead {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.location, self.reason)
+    }
+}
+
+/// Result of inlining the file reads of one module
+#[derive(Debug)]
+pub struct InlinedReads {
+    pub code: String,
+    /// Package files whose contents were inlined
+    pub inlined: Vec<PathBuf>,
+    /// Reads that could not be resolved at build time
+    pub unresolved: Vec<UnresolvedRead>,
+    /// Whether an inlined read produces a Buffer
+    pub uses_buffer: bool,
+    /// WebAssembly files whose reads now go through the `wasm` polyfill
+    pub wasm_modules: Vec<PathBuf>,
+    /// Where those reads are, as they need the bytes synchronously
+    pub wasm_reads: Vec<CodeLocation>,
+}
+
+/// Replace statically resolvable `fs.readFileSync` calls in a module with
+/// the contents of the package file they read. `module_path` is the path of
+/// the module inside the package.
+pub fn inline_file_reads(
+    code: &str,
+    syntax: Syntax,
+    module_path: &Path,
+    files: &HashMap<PathBuf, FileContent>,
+) -> Result<InlinedReads> {
+    if !code.contains("readFileSync") {
+        return Ok(InlinedReads {
+            code: code.to_string(),
+            inlined: Vec::new(),
+            unresolved: Vec::new(),
+            uses_buffer: false,
+            wasm_modules: Vec::new(),
+            wasm_reads: Vec::new(),
+        });
+    }
+
+    let program = js::parse_program(code, syntax)?;
+
+    let mut bindings = ModuleBindings::default();
+    program.visit_with(&mut bindings);
+
+    let filename = normalize_path(&format!("/{}", module_path.to_string_lossy().replace('\\', "/")));
+    let dirname = match filename.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => filename[..index].to_string(),
+    };
+
+    let mut inliner = ReadInliner {
+        code,
+        module_path,
+        files,
+        bindings,
+        declared: js::declared_names(&program),
+        dirname,
+        filename,
+        edits: SourceEdits::new(),
+        inlined: Vec::new(),
+        unresolved: Vec::new(),
+        uses_buffer: false,
+        wasm_modules: Vec::new(),
+        wasm_reads: Vec::new(),
+    };
+    program.visit_with(&mut inliner);
+
+    Ok(InlinedReads {
+        code: inliner.edits.apply(code),
+        inlined: inliner.inlined,
+        unresolved: inliner.unresolved,
+        uses_buffer: inliner.uses_buffer,
+        wasm_modules: inliner.wasm_modules,
+        wasm_reads: inliner.wasm_reads,
+    })
+}
+
+/// Normalize a slash-separated path, resolving `.` and `..` segments
+pub(crate) fn normalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+
+    let joined = parts.join("/");
+    if path.starts_with('/') {
+        format!("/{}", joined)
+    } else {
+        joined
+    }
+}
+
+/// JavaScript string literal for a value
+fn json_string(value: &str) -> String {
+    serde_json::Value::String(value.to_string()).to_string()
+}
+
+/// Module name of a `require('...')` call
+fn required_module(expr: &Expr) -> Option<&str> {
+    let Expr::Call(call) = expr else { return None };
+    let Callee::Expr(callee) = &call.callee else { return None };
+    if !matches!(callee.as_ref(), Expr::Ident(ident) if ident.sym == *"require") {
+        return None;
+    }
+    match call.args.first().map(|arg| arg.expr.as_ref()) {
+        Some(Expr::Lit(Lit::Str(s))) => Some(s.value.as_ref()),
+        _ => None,
+    }
+}
+
+fn is_module(name: &str, module: &str) -> bool {
+    name == module || name.strip_prefix("node:") == Some(module)
+}
+
+/// Local names bound to the `fs` and `path` modules and their functions
+#[derive(Default)]
+struct ModuleBindings {
+    fs_objects: HashSet<String>,
+    read_functions: HashSet<String>,
+    path_objects: HashSet<String>,
+    /// Local name → `join` or `resolve`
+    path_functions: HashMap<String, String>,
+}
+
+impl ModuleBindings {
+    fn bind_object(&mut self, module: &str, local: &str) {
+        if is_module(module, "fs") {
+            self.fs_objects.insert(local.to_string());
+        } else if is_module(module, "path") {
+            self.path_objects.insert(local.to_string());
+        }
+    }
+
+    fn bind_function(&mut self, module: &str, imported: &str, local: &str) {
+        if is_module(module, "fs") && imported == "readFileSync" {
+            self.read_functions.insert(local.to_string());
+        } else if is_module(module, "path") && matches!(imported, "join" | "resolve") {
+            self.path_functions.insert(local.to_string(), imported.to_string());
+        }
+    }
+}
+
+impl Visit for ModuleBindings {
+    fn visit_var_declarator(&mut self, declarator: &VarDeclarator) {
+        if let Some(module) = declarator.init.as_deref().and_then(required_module) {
+            match &declarator.name {
+                Pat::Ident(ident) => self.bind_object(module, &ident.id.sym),
+                Pat::Object(object) => {
+                    for prop in &object.props {
+                        match prop {
+                            ObjectPatProp::KeyValue(kv) => {
+                                if let (PropName::Ident(key), Pat::Ident(value)) = (&kv.key, kv.value.as_ref()) {
+                                    self.bind_function(module, &key.sym, &value.id.sym);
+                                }
+                            }
+                            ObjectPatProp::Assign(assign) => {
+                                self.bind_function(module, &assign.key.sym, &assign.key.sym);
+                            }
+                            ObjectPatProp::Rest(_) => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        declarator.visit_children_with(self);
+    }
+
+    fn visit_import_decl(&mut self, import: &ImportDecl) {
+        let module = import.src.value.to_string();
+        for specifier in &import.specifiers {
+            match specifier {
+                ImportSpecifier::Default(s) => self.bind_object(&module, &s.local.sym),
+                ImportSpecifier::Namespace(s) => self.bind_object(&module, &s.local.sym),
+                ImportSpecifier::Named(s) => {
+                    let imported = match &s.imported {
+                        Some(ModuleExportName::Ident(ident)) => ident.sym.to_string(),
+                        Some(ModuleExportName::Str(s)) => s.value.to_string(),
+                        None => s.local.sym.to_string(),
+                    };
+                    self.bind_function(&module, &imported, &s.local.sym);
+                }
+            }
+        }
+    }
+}
+
+/// Visitor that replaces resolvable reads and records the rest
+struct ReadInliner<'a> {
+    code: &'a str,
+    module_path: &'a Path,
+    files: &'a HashMap<PathBuf, FileContent>,
+    bindings: ModuleBindings,
+    declared: HashSet<String>,
+    dirname: String,
+    filename: String,
+    edits: SourceEdits,
+    inlined: Vec<PathBuf>,
+    unresolved: Vec<UnresolvedRead>,
+    uses_buffer: bool,
+    wasm_modules: Vec<PathBuf>,
+    wasm_reads: Vec<CodeLocation>,
+}
+
+impl ReadInliner<'_> {
+    fn is_read_call(&self, call: &CallExpr) -> bool {
+        let Callee::Expr(callee) = &call.callee else { return false };
+        match callee.as_ref() {
+            Expr::Ident(ident) => self.bindings.read_functions.contains(&*ident.sym),
+            Expr::Member(member) => {
+                let is_read = matches!(&member.prop, MemberProp::Ident(prop) if prop.sym == *"readFileSync");
+                let is_fs = match member.obj.as_ref() {
+                    Expr::Ident(ident) => self.bindings.fs_objects.contains(&*ident.sym),
+                    other => required_module(other).is_some_and(|m| is_module(m, "fs")),
+                };
+                is_read && is_fs
+            }
+            _ => false,
+        }
+    }
+
+    /// Path helper (`join` or `resolve`) a call refers to
+    fn path_function(&self, call: &CallExpr) -> Option<&str> {
+        let Callee::Expr(callee) = &call.callee else { return None };
+        match callee.as_ref() {
+            Expr::Ident(ident) => self.bindings.path_functions.get(&*ident.sym).map(String::as_str),
+            Expr::Member(member) => {
+                let is_path = match member.obj.as_ref() {
+                    Expr::Ident(ident) => self.bindings.path_objects.contains(&*ident.sym),
+                    other => required_module(other).is_some_and(|m| is_module(m, "path")),
+                };
+                match &member.prop {
+                    MemberProp::Ident(prop) if is_path && matches!(&*prop.sym, "join" | "resolve") => {
+                        Some(if prop.sym == *"join" { "join" } else { "resolve" })
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Evaluate a string expression made of literals, `__dirname`,
+    /// `__filename`, concatenation and `path.join`/`path.resolve`
+    fn evaluate(&self, expr: &Expr) -> Option<String> {
+        match expr {
+            Expr::Lit(Lit::Str(s)) => Some(s.value.to_string()),
+            Expr::Ident(ident) if !self.declared.contains(&*ident.sym) => match &*ident.sym {
+                "__dirname" => Some(self.dirname.clone()),
+                "__filename" => Some(self.filename.clone()),
+                _ => None,
+            },
+            Expr::Paren(paren) => self.evaluate(&paren.expr),
+            Expr::Tpl(tpl) => {
+                let mut result = String::new();
+                for (index, quasi) in tpl.quasis.iter().enumerate() {
+                    result.push_str(quasi.cooked.as_ref()?);
+                    if let Some(expr) = tpl.exprs.get(index) {
+                        result.push_str(&self.evaluate(expr)?);
+                    }
+                }
+                Some(result)
+            }
+            Expr::Bin(bin) if bin.op == BinaryOp::Add => {
+                Some(format!("{}{}", self.evaluate(&bin.left)?, self.evaluate(&bin.right)?))
+            }
+            Expr::Call(call) => {
+                let function = self.path_function(call)?;
+                let parts = call.args.iter()
+                    .map(|arg| if arg.spread.is_some() { None } else { self.evaluate(&arg.expr) })
+                    .collect::<Option<Vec<_>>>()?;
+
+                let joined = if function == "resolve" {
+                    // Later absolute segments replace everything before them;
+                    // relative paths resolve from the package root
+                    parts.iter().fold("/".to_string(), |acc, part| {
+                        if part.starts_with('/') { part.clone() } else { format!("{}/{}", acc, part) }
+                    })
+                } else {
+                    parts.join("/")
+                };
+                Some(normalize_path(&joined))
+            }
+            _ => None,
+        }
+    }
+
+    /// Encoding passed as a string or as `{ encoding }` options
+    fn encoding(&self, arg: Option<&ExprOrSpread>) -> Result<Option<String>, ()> {
+        let Some(arg) = arg else { return Ok(None) };
+        match arg.expr.as_ref() {
+            Expr::Object(object) => {
+                for prop in &object.props {
+                    if let PropOrSpread::Prop(prop) = prop {
+                        if let Prop::KeyValue(kv) = prop.as_ref() {
+                            let is_encoding = matches!(&kv.key, PropName::Ident(key) if key.sym == *"encoding")
+                                || matches!(&kv.key, PropName::Str(key) if key.value == *"encoding");
+                            if is_encoding {
+                                return self.evaluate(&kv.value).map(Some).ok_or(());
+                            }
+                        }
+                    }
+                }
+                Ok(None)
+            }
+            Expr::Lit(Lit::Null(_)) => Ok(None),
+            other => self.evaluate(other).map(Some).ok_or(()),
+        }
+    }
+
+    fn location(&self, call: &CallExpr) -> CodeLocation {
+        let (line, column) = js::line_column(self.code, call.span.lo);
+        CodeLocation::new(self.module_path)
+            .with_line(line)
+            .with_column(column)
+    }
+
+    fn unresolved(&mut self, call: &CallExpr, reason: impl Into<String>) {
+        self.unresolved.push(UnresolvedRead {
+            location: self.location(call),
+            reason: reason.into(),
+        });
+    }
+
+    fn inline_read(&mut self, call: &CallExpr) {
+        let Some(path) = call.args.first().and_then(|arg| self.evaluate(&arg.expr)) else {
+            self.unresolved(call, "fs.readFileSync path cannot be determined at build time");
+            return;
+        };
+
+        let Ok(encoding) = self.encoding(call.args.get(1)) else {
+            self.unresolved(call, format!("fs.readFileSync encoding for '{}' cannot be determined at build time", path));
+            return;
+        };
+
+        let relative = PathBuf::from(normalize_path(&path).trim_start_matches('/'));
+        let Some(content) = self.files.get(&relative) else {
+            self.unresolved(call, format!("fs.readFileSync reads '{}', which is not part of the package", path));
+            return;
+        };
+
+        // WebAssembly binaries are delivered by the bundler instead of
+        // being copied into every module that reads them
+        if encoding.is_none() && wasm::is_wasm(&relative) {
+            self.edits.replace(call.span, wasm::bytes_expression(&relative));
+            self.wasm_reads.push(self.location(call));
+            self.wasm_modules.push(relative.clone());
+            self.inlined.push(relative);
+            return;
+        }
+
+        let buffer = match content {
+            FileContent::Text(text) => format!("BufferPolyfill.from({})", json_string(text)),
+            FileContent::Binary(bytes) => format!(
+                "BufferPolyfill.from({}, \"base64\")",
+                json_string(&BASE64_STANDARD.encode(bytes))
+            ),
+        };
+        let replacement = match encoding.as_deref().map(str::to_lowercase).as_deref() {
+            Some("utf8") | Some("utf-8") => json_string(&String::from_utf8_lossy(content.as_bytes())),
+            Some(other) => {
+                self.uses_buffer = true;
+                format!("{}.toString({})", buffer, json_string(other))
+            }
+            None => {
+                self.uses_buffer = true;
+                buffer
+            }
+        };
+
+        self.edits.replace(call.span, replacement);
+        self.inlined.push(relative);
+    }
+}
+
+impl Visit for ReadInliner<'_> {
+    fn visit_call_expr(&mut self, call: &CallExpr) {
+        if self.is_read_call(call) {
+            self.inline_read(call);
+            return;
+        }
+        call.visit_children_with(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> HashMap<PathBuf, FileContent> {
+        let mut files = HashMap::new();
+        files.insert(PathBuf::from("lib/index.js"), FileContent::Text(String::new()));
+        files.insert(PathBuf::from("data/words.txt"), FileContent::Text("alpha\nbeta".to_string()));
+        files.insert(PathBuf::from("data/blob.bin"), FileContent::Binary(vec![0xff, 0x00, 0x01]));
+        files.insert(PathBuf::from("lib/core.wasm"), FileContent::Binary(vec![0x00, 0x61, 0x73, 0x6d]));
+        files
+    }
+
+    fn inline(code: &str) -> InlinedReads {
+        inline_file_reads(code, js::syntax_for_path(Path::new("index.js")), Path::new("lib/index.js"), &files()).unwrap()
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/lib/../data/./words.txt"), "/data/words.txt");
+        assert_eq!(normalize_path("a//b/"), "a/b");
+    }
+
+    #[test]
+    fn test_inlines_dirname_join() {
+        let code = "var fs = require('fs');\nvar path = require('path');\n\
+                    var words = fs.readFileSync(path.join(__dirname, '../data/words.txt'), 'utf8');";
+        let result = inline(code);
+        assert!(result.unresolved.is_empty());
+        assert!(result.code.contains("var words = \"alpha\\nbeta\";"));
+        assert_eq!(result.inlined, vec![PathBuf::from("data/words.txt")]);
+        assert!(!result.uses_buffer);
+    }
+
+    #[test]
+    fn test_inlines_buffer_reads() {
+        let code = "const { readFileSync } = require('fs');\nconst data = readFileSync(__dirname + '/../data/words.txt');";
+        let result = inline(code);
+        assert!(result.code.contains("BufferPolyfill.from(\"alpha\\nbeta\")"));
+        assert!(result.uses_buffer);
+    }
+
+    #[test]
+    fn test_inlines_binary_files_as_base64() {
+        let code = "var fs = require('fs');\nvar blob = fs.readFileSync(__dirname + '/../data/blob.bin');";
+        let result = inline(code);
+        assert!(result.code.contains("BufferPolyfill.from(\"/wAB\", \"base64\")"));
+        assert!(result.uses_buffer);
+    }
+
+    #[test]
+    fn test_wasm_reads_use_loader() {
+        let code = "var fs = require('fs');\nvar bytes = fs.readFileSync(__dirname + '/core.wasm');";
+        let result = inline(code);
+        assert!(result.code.contains("var bytes = WasmPolyfill.bytes(\"lib/core.wasm\");"));
+        assert_eq!(result.wasm_modules, vec![PathBuf::from("lib/core.wasm")]);
+        assert_eq!(result.wasm_reads[0].line, Some(2));
+        assert!(!result.uses_buffer);
+    }
+
+    #[test]
+    fn test_reports_unresolved_reads() {
+        let code = "var fs = require('fs');\nfunction load(name) {\n  return fs.readFileSync(name, 'utf8');\n}\n\
+                    fs.readFileSync(__dirname + '/missing.txt', 'utf8');";
+        let result = inline(code);
+        assert_eq!(result.unresolved.len(), 2);
+        assert_eq!(result.unresolved[0].location.line, Some(3));
+        assert_eq!(result.unresolved[0].location.column, Some(10));
+        assert!(result.unresolved[1].reason.contains("not part of the package"));
+    }
+}
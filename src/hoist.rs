@@ -0,0 +1,1137 @@
+//! Scope hoisting for ES module graphs
+//!
+//! Wrapping every module in a factory costs a function, an exports object
+//! and a property read per imported binding. Groups of ES modules that only
+//! import one another statically are concatenated into their root module
+//! instead: imports inside the group become direct references, and
+//! top-level names that would collide are renamed. Imports of modules
+//! outside the group are hoisted to the top of the merged module, which is
+//! then linked like any other ES module.
+//!
+//! CommonJS modules stay wrapped, as do ES modules that are also required,
+//! loaded with `import()`, imported as a namespace, or read `import.meta`.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use anyhow::Result;
+use swc_common::{BytePos, Span, Spanned};
+use swc_ecma_ast::*;
+use swc_ecma_visit::{Visit, VisitWith};
+
+use crate::js::{self, SourceEdits};
+use crate::resolver::Resolution;
+use crate::treeshake::GraphModule;
+
+/// Key of the binding an anonymous default export is given
+const ANONYMOUS_DEFAULT: &str = "default";
+
+/// Modules merged into one scope
+#[derive(Debug)]
+pub struct Concatenation {
+    /// Graph index of the module the merged code replaces
+    pub root: usize,
+    /// Graph indices of the merged modules in evaluation order, ending
+    /// with the root
+    pub members: Vec<usize>,
+    pub code: String,
+}
+
+/// Find groups of ES modules that can share a scope and concatenate each
+pub fn hoist_scopes(modules: &[GraphModule], entries: &[usize]) -> Vec<Concatenation> {
+    let scopes: Vec<Option<ModuleScope>> = modules.iter()
+        .map(|module| analyze(module).ok().flatten())
+        .collect();
+    let graph = Graph {
+        modules,
+        index: modules.iter().enumerate().map(|(i, module)| (module.path, i)).collect(),
+        scopes,
+    };
+
+    let mut importers: Vec<Vec<(usize, &str)>> = vec![Vec::new(); modules.len()];
+    for (importer, module) in modules.iter().enumerate() {
+        for specifier in module.dependencies.keys() {
+            if let Some(target) = graph.target(importer, specifier) {
+                importers[target].push((importer, specifier));
+            }
+        }
+    }
+
+    // A module can join its importers' group when they all import it with
+    // plain static imports
+    let absorbable: Vec<bool> = (0..modules.len())
+        .map(|module| {
+            !entries.contains(&module)
+                && graph.scopes[module].is_some()
+                && !importers[module].is_empty()
+                && importers[module].iter().all(|(importer, specifier)| {
+                    graph.scopes[*importer].as_ref().is_some_and(|scope| scope.imports_statically(specifier))
+                })
+        })
+        .collect();
+
+    let mut grouped = vec![false; modules.len()];
+    let mut concatenations = Vec::new();
+
+    for root in 0..modules.len() {
+        if grouped[root] || graph.scopes[root].is_none() {
+            continue;
+        }
+
+        let mut members = HashSet::from([root]);
+        let mut specifiers: HashMap<&str, &Resolution> = modules[root].dependencies.iter()
+            .map(|(specifier, resolution)| (specifier.as_str(), resolution))
+            .collect();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            let current: Vec<usize> = members.iter().copied().collect();
+            for member in current {
+                let Some(scope) = &graph.scopes[member] else { continue };
+                for specifier in &scope.sources {
+                    let Some(target) = graph.target(member, specifier) else { continue };
+                    if members.contains(&target)
+                        || grouped[target]
+                        || !absorbable[target]
+                        || !importers[target].iter().all(|(importer, _)| members.contains(importer))
+                    {
+                        continue;
+                    }
+                    // One specifier must mean one module across the group
+                    let consistent = modules[target].dependencies.iter().all(|(specifier, resolution)| {
+                        specifiers.get(specifier.as_str()).is_none_or(|existing| *existing == resolution)
+                    });
+                    if !consistent {
+                        continue;
+                    }
+                    specifiers.extend(modules[target].dependencies.iter().map(|(s, r)| (s.as_str(), r)));
+                    members.insert(target);
+                    changed = true;
+                }
+            }
+        }
+
+        if members.len() < 2 {
+            continue;
+        }
+        for member in &members {
+            grouped[*member] = true;
+        }
+
+        let mut order = Vec::new();
+        graph.evaluation_order(root, &members, &mut HashSet::new(), &mut order);
+        let code = Concatenator::new(&graph, &order).concatenate();
+        concatenations.push(Concatenation { root, members: order, code });
+    }
+
+    concatenations
+}
+
+/// What a module exports under a name
+enum ExportBinding {
+    Local(String),
+    /// An export of another module; `*` for its namespace
+    ReExport { specifier: String, name: String },
+}
+
+/// Text edit turning a module's own syntax into plain statements
+enum ModuleEdit {
+    Remove(Span),
+    /// Drop an `export` or `export default` prefix
+    Strip { lo: BytePos, hi: BytePos },
+    /// Bind an anonymous default export to a variable
+    Default { lo: BytePos, hi: BytePos, end: Option<BytePos> },
+}
+
+/// A module's top-level bindings, imports and exports, and where its code
+/// refers to them
+#[derive(Default)]
+struct ModuleScope {
+    /// Imported bindings: local name, specifier and imported name (`*` for
+    /// a namespace)
+    imports: HashMap<String, (String, String)>,
+    /// Modules loaded by `import` and `export ... from`, in source order
+    sources: Vec<String>,
+    /// Specifiers imported or re-exported as a namespace
+    namespaces: HashSet<String>,
+    /// Specifiers loaded with `import()`
+    dynamic: HashSet<String>,
+    exports: HashMap<String, ExportBinding>,
+    stars: Vec<String>,
+    /// Names declared at the top level
+    declared: BTreeSet<String>,
+    /// Identifiers resolving to a top-level binding, and whether each is
+    /// an object shorthand
+    occurrences: Vec<(Span, String, bool)>,
+    /// Names bound in nested scopes or read as globals; a renamed binding
+    /// must not be captured by them
+    inner: HashSet<String>,
+    edits: Vec<ModuleEdit>,
+}
+
+impl ModuleScope {
+    fn imports_statically(&self, specifier: &str) -> bool {
+        self.sources.iter().any(|source| source == specifier)
+            && !self.namespaces.contains(specifier)
+            && !self.dynamic.contains(specifier)
+    }
+}
+
+struct Graph<'a> {
+    modules: &'a [GraphModule<'a>],
+    index: HashMap<&'a std::path::Path, usize>,
+    scopes: Vec<Option<ModuleScope>>,
+}
+
+impl Graph<'_> {
+    fn target(&self, module: usize, specifier: &str) -> Option<usize> {
+        match self.modules[module].dependencies.get(specifier)? {
+            Resolution::Module(path) => self.index.get(path.as_path()).copied(),
+            _ => None,
+        }
+    }
+
+    fn scope(&self, module: usize) -> &ModuleScope {
+        self.scopes[module].as_ref().expect("group members are analysed ES modules")
+    }
+
+    /// Dependencies before dependents, following import order
+    fn evaluation_order(&self, module: usize, members: &HashSet<usize>, visited: &mut HashSet<usize>, order: &mut Vec<usize>) {
+        if !visited.insert(module) {
+            return;
+        }
+        for specifier in &self.scope(module).sources {
+            if let Some(target) = self.target(module, specifier).filter(|t| members.contains(t)) {
+                self.evaluation_order(target, members, visited, order);
+            }
+        }
+        order.push(module);
+    }
+}
+
+/// Merges a group's modules into one ES module
+struct Concatenator<'a> {
+    graph: &'a Graph<'a>,
+    order: &'a [usize],
+    members: HashSet<usize>,
+    /// Every name appearing in the group, which fresh names avoid
+    names: HashSet<String>,
+    claimed: HashSet<String>,
+    /// Final name of each member's top-level declarations
+    locals: HashMap<(usize, String), String>,
+    /// Final name of each binding imported from outside the group, keyed
+    /// by specifier and imported name
+    externals: HashMap<(String, String), String>,
+    /// Specifiers loaded from outside the group, in evaluation order
+    external_sources: Vec<String>,
+}
+
+impl<'a> Concatenator<'a> {
+    fn new(graph: &'a Graph<'a>, order: &'a [usize]) -> Self {
+        let mut names = HashSet::new();
+        for &member in order {
+            let scope = graph.scope(member);
+            names.extend(scope.declared.iter().cloned());
+            names.extend(scope.inner.iter().cloned());
+            names.extend(scope.imports.keys().cloned());
+        }
+
+        let mut concatenator = Self {
+            graph,
+            order,
+            members: order.iter().copied().collect(),
+            names,
+            claimed: HashSet::new(),
+            locals: HashMap::new(),
+            externals: HashMap::new(),
+            external_sources: Vec::new(),
+        };
+        concatenator.assign_names();
+        concatenator
+    }
+
+    fn internal(&self, module: usize, specifier: &str) -> Option<usize> {
+        self.graph.target(module, specifier).filter(|target| self.members.contains(target))
+    }
+
+    /// Whether another member binds or reads a name in a nested scope
+    fn captured(&self, module: usize, name: &str) -> bool {
+        self.order.iter().any(|&other| other != module && self.graph.scope(other).inner.contains(name))
+    }
+
+    fn fresh(&mut self, base: &str) -> String {
+        let name = (1..)
+            .map(|i| format!("{}${}", base, i))
+            .find(|name| !self.claimed.contains(name) && !self.names.contains(name))
+            .expect("unbounded candidates");
+        self.claimed.insert(name.clone());
+        name
+    }
+
+    /// Keep each top-level name unless an earlier member took it or
+    /// another member would capture it
+    fn claim(&mut self, module: usize, name: &str) -> String {
+        if name != ANONYMOUS_DEFAULT && !self.claimed.contains(name) && !self.captured(module, name) {
+            self.claimed.insert(name.to_string());
+            return name.to_string();
+        }
+        let base = if name == ANONYMOUS_DEFAULT { "_default" } else { name };
+        self.fresh(base)
+    }
+
+    fn assign_names(&mut self) {
+        let graph = self.graph;
+        for &module in self.order {
+            let scope = graph.scope(module);
+
+            for name in &scope.declared {
+                let local = self.claim(module, name);
+                self.locals.insert((module, name.clone()), local);
+            }
+
+            let mut imports: Vec<(&String, &(String, String))> = scope.imports.iter().collect();
+            imports.sort();
+            for (local, (specifier, imported)) in imports {
+                if self.internal(module, specifier).is_some() {
+                    continue;
+                }
+                let key = (specifier.clone(), imported.clone());
+                if !self.externals.contains_key(&key) {
+                    let name = self.claim(module, local);
+                    self.externals.insert(key, name);
+                }
+            }
+
+            for specifier in &scope.sources {
+                if self.internal(module, specifier).is_none() && !self.external_sources.contains(specifier) {
+                    self.external_sources.push(specifier.clone());
+                }
+            }
+        }
+    }
+
+    fn external(&mut self, specifier: &str, name: &str) -> String {
+        let key = (specifier.to_string(), name.to_string());
+        if let Some(local) = self.externals.get(&key) {
+            return local.clone();
+        }
+        let base = if name == "*" || name == "default" || !is_identifier(name) { "_import" } else { name };
+        let local = self.fresh(base);
+        self.externals.insert(key, local.clone());
+        local
+    }
+
+    /// Final name a module's top-level name refers to; `None` for
+    /// globals and imports that do not resolve
+    fn binding(&mut self, module: usize, name: &str, visited: &mut HashSet<(usize, String)>) -> Option<String> {
+        if let Some(local) = self.locals.get(&(module, name.to_string())) {
+            return Some(local.clone());
+        }
+        let (specifier, imported) = self.graph.scope(module).imports.get(name)?.clone();
+        self.import(module, &specifier, &imported, visited)
+    }
+
+    fn import(&mut self, module: usize, specifier: &str, name: &str, visited: &mut HashSet<(usize, String)>) -> Option<String> {
+        match self.internal(module, specifier) {
+            Some(target) => self.export(target, name, visited),
+            None => Some(self.external(specifier, name)),
+        }
+    }
+
+    /// Final name of a member's export, following re-exports
+    fn export(&mut self, module: usize, name: &str, visited: &mut HashSet<(usize, String)>) -> Option<String> {
+        if !visited.insert((module, name.to_string())) {
+            return None;
+        }
+        let graph = self.graph;
+        let scope = graph.scope(module);
+        match scope.exports.get(name) {
+            Some(ExportBinding::Local(local)) => self.binding(module, local, visited),
+            Some(ExportBinding::ReExport { specifier, name }) => self.import(module, specifier, name, visited),
+            // `export *` never re-exports a default
+            None if name != "default" => {
+                for star in &scope.stars {
+                    match self.internal(module, star) {
+                        Some(target) => {
+                            if self.exported_names(target, &mut HashSet::new()).contains(name) {
+                                return self.export(target, name, visited);
+                            }
+                        }
+                        None => return Some(self.external(star, name)),
+                    }
+                }
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Names a member exports, including through `export *` of members
+    fn exported_names(&self, module: usize, visited: &mut HashSet<usize>) -> BTreeSet<String> {
+        if !visited.insert(module) {
+            return BTreeSet::new();
+        }
+        let scope = self.graph.scope(module);
+        let mut names: BTreeSet<String> = scope.exports.keys().cloned().collect();
+        for star in &scope.stars {
+            if let Some(target) = self.internal(module, star) {
+                names.extend(self.exported_names(target, visited).into_iter().filter(|name| name != "default"));
+            }
+        }
+        names
+    }
+
+    /// `export *` of modules outside the group, reached from a member
+    fn external_stars(&self, module: usize, visited: &mut HashSet<usize>, stars: &mut Vec<String>) {
+        if !visited.insert(module) {
+            return;
+        }
+        for star in &self.graph.scope(module).stars {
+            match self.internal(module, star) {
+                Some(target) => self.external_stars(target, visited, stars),
+                None if !stars.contains(star) => stars.push(star.clone()),
+                None => {}
+            }
+        }
+    }
+
+    /// A member's code with its module syntax removed and references
+    /// pointed at final names
+    fn member_code(&mut self, module: usize) -> String {
+        let graph = self.graph;
+        let code = graph.modules[module].code;
+        let scope = graph.scope(module);
+        let mut edits = SourceEdits::new();
+
+        for edit in &scope.edits {
+            match edit {
+                ModuleEdit::Remove(span) => edits.replace(*span, ""),
+                ModuleEdit::Strip { lo, hi } => edits.replace_between(*lo, *hi, ""),
+                ModuleEdit::Default { lo, hi, end } => {
+                    let local = &self.locals[&(module, ANONYMOUS_DEFAULT.to_string())];
+                    edits.replace_between(*lo, *hi, format!("var {} = ", local));
+                    if let Some(end) = end {
+                        edits.replace_between(*end, *end, ";");
+                    }
+                }
+            }
+        }
+
+        for (span, name, shorthand) in &scope.occurrences {
+            let replacement = self.binding(module, name, &mut HashSet::new())
+                .unwrap_or_else(|| "void 0".to_string());
+            if replacement == *name {
+                continue;
+            }
+            if *shorthand {
+                edits.replace(*span, format!("{}: {}", name, replacement));
+            } else {
+                edits.replace(*span, replacement);
+            }
+        }
+
+        let code = edits.apply(code);
+        let code = code.trim();
+        // A hashbang is only valid on the first line of a file
+        if code.starts_with("#!") {
+            format!("//{}", code)
+        } else {
+            code.to_string()
+        }
+    }
+
+    fn concatenate(mut self) -> String {
+        let order = self.order;
+        let root = *order.last().expect("groups have members");
+        let mut bodies = Vec::new();
+        for &module in order {
+            let code = self.member_code(module);
+            bodies.push(format!("// {}\n{}\n", self.graph.modules[module].path.display(), code));
+        }
+
+        let mut exports = Vec::new();
+        for name in self.exported_names(root, &mut HashSet::new()) {
+            if let Some(local) = self.export(root, &name, &mut HashSet::new()) {
+                let exported = if is_identifier(&name) { name.clone() } else { quote(&name) };
+                if local == exported {
+                    exports.push(local);
+                } else {
+                    exports.push(format!("{} as {}", local, exported));
+                }
+            }
+        }
+        let mut stars = Vec::new();
+        self.external_stars(root, &mut HashSet::new(), &mut stars);
+
+        let mut code = String::new();
+        for specifier in &self.external_sources {
+            let mut named = Vec::new();
+            let mut namespaces = Vec::new();
+            let mut bindings: Vec<(&(String, String), &String)> = self.externals.iter()
+                .filter(|((s, _), _)| s == specifier)
+                .collect();
+            bindings.sort();
+            for ((_, imported), local) in bindings {
+                if imported == "*" {
+                    namespaces.push(local);
+                } else if is_identifier(imported) {
+                    named.push(format!("{} as {}", imported, local));
+                } else {
+                    named.push(format!("{} as {}", quote(imported), local));
+                }
+            }
+
+            if named.is_empty() && namespaces.is_empty() {
+                code.push_str(&format!("import {};\n", quote(specifier)));
+            }
+            if !named.is_empty() {
+                code.push_str(&format!("import {{ {} }} from {};\n", named.join(", "), quote(specifier)));
+            }
+            for local in namespaces {
+                code.push_str(&format!("import * as {} from {};\n", local, quote(specifier)));
+            }
+        }
+
+        for body in bodies {
+            code.push_str(&body);
+        }
+        if !exports.is_empty() {
+            code.push_str(&format!("export {{ {} }};\n", exports.join(", ")));
+        }
+        for star in stars {
+            code.push_str(&format!("export * from {};\n", quote(&star)));
+        }
+        code
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == '$')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+fn quote(value: &str) -> String {
+    serde_json::Value::String(value.to_string()).to_string()
+}
+
+/// Collect a module's scope, or `None` when it cannot share one
+fn analyze(module: &GraphModule) -> Result<Option<ModuleScope>> {
+    let program = js::parse_program(module.code, js::syntax_for_path(module.path))?;
+    let Program::Module(program) = &program else {
+        return Ok(None);
+    };
+    if !program.body.iter().any(|item| matches!(item, ModuleItem::ModuleDecl(_))) {
+        return Ok(None);
+    }
+
+    let mut scope = ModuleScope::default();
+    let mut top_level = HashSet::new();
+    let mut hoisted = HoistedVars::default();
+    program.visit_with(&mut hoisted);
+    top_level.extend(hoisted.names);
+
+    for item in &program.body {
+        match item {
+            ModuleItem::Stmt(Stmt::Decl(decl)) => top_level.extend(lexical_names(decl)),
+            ModuleItem::Stmt(_) => {}
+            ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => {
+                scope.edits.push(ModuleEdit::Remove(import.span));
+                if import.type_only {
+                    continue;
+                }
+                let specifier = import.src.value.to_string();
+                for import_specifier in &import.specifiers {
+                    let (local, imported) = match import_specifier {
+                        ImportSpecifier::Named(s) if s.is_type_only => continue,
+                        ImportSpecifier::Named(s) => (
+                            &s.local,
+                            s.imported.as_ref().map(export_name).unwrap_or_else(|| s.local.sym.to_string()),
+                        ),
+                        ImportSpecifier::Default(s) => (&s.local, "default".to_string()),
+                        ImportSpecifier::Namespace(s) => {
+                            scope.namespaces.insert(specifier.clone());
+                            (&s.local, "*".to_string())
+                        }
+                    };
+                    scope.imports.insert(local.sym.to_string(), (specifier.clone(), imported));
+                }
+                scope.sources.push(specifier);
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export)) => {
+                let names = lexical_names(&export.decl);
+                for name in &names {
+                    scope.exports.insert(name.clone(), ExportBinding::Local(name.clone()));
+                }
+                if let Decl::Var(var) = &export.decl {
+                    for name in var_names(var) {
+                        scope.exports.insert(name.clone(), ExportBinding::Local(name));
+                    }
+                }
+                top_level.extend(names);
+                scope.edits.push(ModuleEdit::Strip { lo: export.span.lo, hi: export.decl.span().lo });
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultDecl(export)) => {
+                let ident = match &export.decl {
+                    DefaultDecl::Fn(function) => function.ident.as_ref(),
+                    DefaultDecl::Class(class) => class.ident.as_ref(),
+                    DefaultDecl::TsInterfaceDecl(_) => {
+                        scope.edits.push(ModuleEdit::Remove(export.span));
+                        continue;
+                    }
+                };
+                match ident {
+                    Some(ident) => {
+                        let name = ident.sym.to_string();
+                        top_level.insert(name.clone());
+                        scope.exports.insert("default".to_string(), ExportBinding::Local(name));
+                        scope.edits.push(ModuleEdit::Strip { lo: export.span.lo, hi: export.decl.span().lo });
+                    }
+                    None => {
+                        scope.declared.insert(ANONYMOUS_DEFAULT.to_string());
+                        scope.exports.insert("default".to_string(), ExportBinding::Local(ANONYMOUS_DEFAULT.to_string()));
+                        scope.edits.push(ModuleEdit::Default {
+                            lo: export.span.lo,
+                            hi: export.decl.span().lo,
+                            end: Some(export.span.hi),
+                        });
+                    }
+                }
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultExpr(export)) => {
+                scope.declared.insert(ANONYMOUS_DEFAULT.to_string());
+                scope.exports.insert("default".to_string(), ExportBinding::Local(ANONYMOUS_DEFAULT.to_string()));
+                scope.edits.push(ModuleEdit::Default { lo: export.span.lo, hi: export.expr.span().lo, end: None });
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(export)) => {
+                scope.edits.push(ModuleEdit::Remove(export.span));
+                if export.type_only {
+                    continue;
+                }
+                let source = export.src.as_ref().map(|src| src.value.to_string());
+                for specifier in &export.specifiers {
+                    let (exported, binding) = match (specifier, &source) {
+                        (ExportSpecifier::Named(s), _) if s.is_type_only => continue,
+                        (ExportSpecifier::Named(s), None) => {
+                            let orig = export_name(&s.orig);
+                            let exported = s.exported.as_ref().map(export_name).unwrap_or_else(|| orig.clone());
+                            (exported, ExportBinding::Local(orig))
+                        }
+                        (ExportSpecifier::Named(s), Some(source)) => {
+                            let orig = export_name(&s.orig);
+                            let exported = s.exported.as_ref().map(export_name).unwrap_or_else(|| orig.clone());
+                            (exported, ExportBinding::ReExport { specifier: source.clone(), name: orig })
+                        }
+                        (ExportSpecifier::Namespace(s), Some(source)) => {
+                            scope.namespaces.insert(source.clone());
+                            (export_name(&s.name), ExportBinding::ReExport { specifier: source.clone(), name: "*".to_string() })
+                        }
+                        (ExportSpecifier::Default(s), Some(source)) => (
+                            s.exported.sym.to_string(),
+                            ExportBinding::ReExport { specifier: source.clone(), name: "default".to_string() },
+                        ),
+                        _ => continue,
+                    };
+                    scope.exports.insert(exported, binding);
+                }
+                if let Some(source) = source {
+                    scope.sources.push(source);
+                }
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportAll(export)) => {
+                scope.edits.push(ModuleEdit::Remove(export.span));
+                if !export.type_only {
+                    scope.stars.push(export.src.value.to_string());
+                    scope.sources.push(export.src.value.to_string());
+                }
+            }
+            // TypeScript module forms keep their own wrapper
+            ModuleItem::ModuleDecl(_) => return Ok(None),
+        }
+    }
+    scope.declared.extend(top_level.iter().cloned());
+
+    let mut module_scope: HashSet<String> = top_level;
+    module_scope.extend(scope.imports.keys().cloned());
+    let mut visitor = ScopeVisitor {
+        scopes: vec![module_scope],
+        occurrences: Vec::new(),
+        inner: HashSet::new(),
+        dynamic: HashSet::new(),
+        import_meta: false,
+    };
+    for item in &program.body {
+        match item {
+            ModuleItem::Stmt(stmt) => stmt.visit_with(&mut visitor),
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export)) => export.decl.visit_with(&mut visitor),
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultDecl(export)) => match &export.decl {
+                // A named default declaration binds its name in the module
+                DefaultDecl::Fn(function) => {
+                    if let Some(ident) = &function.ident {
+                        visitor.resolve(ident, false);
+                    }
+                    function.function.visit_with(&mut visitor);
+                }
+                DefaultDecl::Class(class) => {
+                    if let Some(ident) = &class.ident {
+                        visitor.resolve(ident, false);
+                    }
+                    class.class.visit_with(&mut visitor);
+                }
+                DefaultDecl::TsInterfaceDecl(_) => {}
+            },
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultExpr(export)) => export.expr.visit_with(&mut visitor),
+            ModuleItem::ModuleDecl(_) => {}
+        }
+    }
+
+    // CommonJS, eval and import.meta tie code to its own module
+    let bound_elsewhere = |name: &str| scope.declared.contains(name) || scope.imports.contains_key(name);
+    let commonjs = ["module", "exports", "require", "eval"].iter()
+        .any(|name| visitor.inner.contains(*name) && !bound_elsewhere(name));
+    if commonjs || visitor.import_meta {
+        return Ok(None);
+    }
+
+    scope.occurrences = visitor.occurrences;
+    scope.inner = visitor.inner;
+    scope.dynamic = visitor.dynamic;
+    Ok(Some(scope))
+}
+
+fn export_name(name: &ModuleExportName) -> String {
+    match name {
+        ModuleExportName::Ident(ident) => ident.sym.to_string(),
+        ModuleExportName::Str(s) => s.value.to_string(),
+    }
+}
+
+/// Names a declaration binds in the block it appears in
+fn lexical_names(decl: &Decl) -> Vec<String> {
+    match decl {
+        Decl::Fn(function) => vec![function.ident.sym.to_string()],
+        Decl::Class(class) => vec![class.ident.sym.to_string()],
+        Decl::Var(var) if var.kind != VarDeclKind::Var => var_names(var),
+        Decl::Using(using) => pattern_names(using.decls.iter().map(|d| &d.name)),
+        Decl::TsEnum(e) => vec![e.id.sym.to_string()],
+        _ => Vec::new(),
+    }
+}
+
+fn var_names(var: &VarDecl) -> Vec<String> {
+    pattern_names(var.decls.iter().map(|d| &d.name))
+}
+
+fn pattern_names<'p>(patterns: impl Iterator<Item = &'p Pat>) -> Vec<String> {
+    let mut collector = BindingCollector::default();
+    for pattern in patterns {
+        pattern.visit_with(&mut collector);
+    }
+    collector.names
+}
+
+/// Names bound directly in a list of statements
+fn block_names(stmts: &[Stmt]) -> HashSet<String> {
+    stmts.iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::Decl(decl) => Some(lexical_names(decl)),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Names bound in a function body: its parameters, its `var`s and its
+/// block-level declarations
+fn function_names<N: VisitWith<BindingCollector>>(params: &[N], body: Option<&BlockStmt>) -> HashSet<String> {
+    let mut collector = BindingCollector::default();
+    for param in params {
+        param.visit_with(&mut collector);
+    }
+    let mut names: HashSet<String> = collector.names.into_iter().collect();
+    if let Some(body) = body {
+        let mut hoisted = HoistedVars::default();
+        body.stmts.visit_with(&mut hoisted);
+        names.extend(hoisted.names);
+        names.extend(block_names(&body.stmts));
+    }
+    names
+}
+
+/// Tracks nested scopes to find identifiers that refer to top-level
+/// bindings
+struct ScopeVisitor {
+    /// Names bound by each open scope, outermost (the module) first
+    scopes: Vec<HashSet<String>>,
+    occurrences: Vec<(Span, String, bool)>,
+    inner: HashSet<String>,
+    dynamic: HashSet<String>,
+    import_meta: bool,
+}
+
+impl ScopeVisitor {
+    fn resolve(&mut self, ident: &Ident, shorthand: bool) {
+        let name = ident.sym.as_ref();
+        match self.scopes.iter().rposition(|scope| scope.contains(name)) {
+            Some(0) => self.occurrences.push((ident.span, name.to_string(), shorthand)),
+            Some(_) => {}
+            // Globals count as captured, so no renamed binding shadows them
+            None => {
+                self.inner.insert(name.to_string());
+            }
+        }
+    }
+
+    fn scoped(&mut self, names: HashSet<String>, visit: impl FnOnce(&mut Self)) {
+        self.inner.extend(names.iter().cloned());
+        self.scopes.push(names);
+        visit(self);
+        self.scopes.pop();
+    }
+}
+
+impl Visit for ScopeVisitor {
+    fn visit_ident(&mut self, ident: &Ident) {
+        self.resolve(ident, false);
+    }
+
+    fn visit_prop(&mut self, prop: &Prop) {
+        match prop {
+            Prop::Shorthand(ident) => self.resolve(ident, true),
+            _ => prop.visit_children_with(self),
+        }
+    }
+
+    fn visit_assign_pat_prop(&mut self, prop: &AssignPatProp) {
+        self.resolve(&prop.key, true);
+        prop.value.visit_with(self);
+    }
+
+    fn visit_member_prop(&mut self, prop: &MemberProp) {
+        if let MemberProp::Computed(computed) = prop {
+            computed.visit_with(self);
+        }
+    }
+
+    fn visit_super_prop(&mut self, prop: &SuperProp) {
+        if let SuperProp::Computed(computed) = prop {
+            computed.visit_with(self);
+        }
+    }
+
+    fn visit_prop_name(&mut self, name: &PropName) {
+        if let PropName::Computed(computed) = name {
+            computed.visit_with(self);
+        }
+    }
+
+    fn visit_private_name(&mut self, _: &PrivateName) {}
+
+    fn visit_labeled_stmt(&mut self, stmt: &LabeledStmt) {
+        stmt.body.visit_with(self);
+    }
+
+    fn visit_break_stmt(&mut self, _: &BreakStmt) {}
+
+    fn visit_continue_stmt(&mut self, _: &ContinueStmt) {}
+
+    fn visit_jsx_element_name(&mut self, _: &JSXElementName) {}
+
+    fn visit_jsx_attr_name(&mut self, _: &JSXAttrName) {}
+
+    fn visit_meta_prop_expr(&mut self, meta: &MetaPropExpr) {
+        if meta.kind == MetaPropKind::ImportMeta {
+            self.import_meta = true;
+        }
+    }
+
+    fn visit_call_expr(&mut self, call: &CallExpr) {
+        if let (Callee::Import(_), [argument]) = (&call.callee, call.args.as_slice()) {
+            if let Expr::Lit(Lit::Str(s)) = argument.expr.as_ref() {
+                self.dynamic.insert(s.value.to_string());
+            }
+        }
+        call.visit_children_with(self);
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        let names = function_names(&function.params, function.body.as_ref());
+        self.scoped(names, |this| {
+            function.decorators.visit_with(this);
+            function.params.visit_with(this);
+            if let Some(body) = &function.body {
+                body.stmts.visit_with(this);
+            }
+        });
+    }
+
+    fn visit_arrow_expr(&mut self, arrow: &ArrowExpr) {
+        let body = match arrow.body.as_ref() {
+            BlockStmtOrExpr::BlockStmt(block) => Some(block),
+            BlockStmtOrExpr::Expr(_) => None,
+        };
+        let names = function_names(&arrow.params, body);
+        self.scoped(names, |this| {
+            arrow.params.visit_with(this);
+            match arrow.body.as_ref() {
+                BlockStmtOrExpr::BlockStmt(block) => block.stmts.visit_with(this),
+                BlockStmtOrExpr::Expr(expr) => expr.visit_with(this),
+            }
+        });
+    }
+
+    fn visit_constructor(&mut self, constructor: &Constructor) {
+        let names = function_names(&constructor.params, constructor.body.as_ref());
+        self.scoped(names, |this| {
+            constructor.params.visit_with(this);
+            if let Some(body) = &constructor.body {
+                body.stmts.visit_with(this);
+            }
+        });
+    }
+
+    fn visit_getter_prop(&mut self, getter: &GetterProp) {
+        getter.key.visit_with(self);
+        let names = function_names::<Pat>(&[], getter.body.as_ref());
+        self.scoped(names, |this| {
+            if let Some(body) = &getter.body {
+                body.stmts.visit_with(this);
+            }
+        });
+    }
+
+    fn visit_setter_prop(&mut self, setter: &SetterProp) {
+        setter.key.visit_with(self);
+        let names = function_names(std::slice::from_ref(setter.param.as_ref()), setter.body.as_ref());
+        self.scoped(names, |this| {
+            setter.param.visit_with(this);
+            if let Some(body) = &setter.body {
+                body.stmts.visit_with(this);
+            }
+        });
+    }
+
+    fn visit_static_block(&mut self, block: &StaticBlock) {
+        let names = function_names::<Pat>(&[], Some(&block.body));
+        self.scoped(names, |this| block.body.stmts.visit_with(this));
+    }
+
+    // Function and class expression names are visible only inside them
+    fn visit_fn_expr(&mut self, function: &FnExpr) {
+        let names = function.ident.iter().map(|ident| ident.sym.to_string()).collect();
+        self.scoped(names, |this| function.function.visit_with(this));
+    }
+
+    fn visit_class_expr(&mut self, class: &ClassExpr) {
+        let names = class.ident.iter().map(|ident| ident.sym.to_string()).collect();
+        self.scoped(names, |this| class.class.visit_with(this));
+    }
+
+    fn visit_block_stmt(&mut self, block: &BlockStmt) {
+        self.scoped(block_names(&block.stmts), |this| block.stmts.visit_with(this));
+    }
+
+    fn visit_for_stmt(&mut self, stmt: &ForStmt) {
+        let names = match &stmt.init {
+            Some(VarDeclOrExpr::VarDecl(var)) if var.kind != VarDeclKind::Var => var_names(var),
+            _ => Vec::new(),
+        };
+        self.scoped(names.into_iter().collect(), |this| stmt.visit_children_with(this));
+    }
+
+    fn visit_for_in_stmt(&mut self, stmt: &ForInStmt) {
+        let names = for_head_names(&stmt.left);
+        self.scoped(names, |this| stmt.visit_children_with(this));
+    }
+
+    fn visit_for_of_stmt(&mut self, stmt: &ForOfStmt) {
+        let names = for_head_names(&stmt.left);
+        self.scoped(names, |this| stmt.visit_children_with(this));
+    }
+
+    fn visit_catch_clause(&mut self, clause: &CatchClause) {
+        let names = pattern_names(clause.param.iter()).into_iter().collect();
+        self.scoped(names, |this| clause.visit_children_with(this));
+    }
+}
+
+fn for_head_names(head: &ForHead) -> HashSet<String> {
+    match head {
+        ForHead::VarDecl(var) if var.kind != VarDeclKind::Var => var_names(var).into_iter().collect(),
+        ForHead::UsingDecl(using) => pattern_names(using.decls.iter().map(|d| &d.name)).into_iter().collect(),
+        _ => HashSet::new(),
+    }
+}
+
+/// `var` declarations of a function body or module, not looking into
+/// nested functions
+#[derive(Default)]
+struct HoistedVars {
+    names: Vec<String>,
+}
+
+impl Visit for HoistedVars {
+    fn visit_var_decl(&mut self, var: &VarDecl) {
+        if var.kind == VarDeclKind::Var {
+            self.names.extend(var_names(var));
+        }
+    }
+
+    fn visit_expr(&mut self, _: &Expr) {}
+
+    fn visit_function(&mut self, _: &Function) {}
+
+    fn visit_class(&mut self, _: &Class) {}
+}
+
+#[derive(Default)]
+struct BindingCollector {
+    names: Vec<String>,
+}
+
+impl Visit for BindingCollector {
+    fn visit_binding_ident(&mut self, ident: &BindingIdent) {
+        self.names.push(ident.id.sym.to_string());
+    }
+
+    // Default values inside patterns are expressions, not bindings
+    fn visit_expr(&mut self, _: &Expr) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    fn hoist(modules: &[(&str, &str, &[(&str, &str)])]) -> Vec<Concatenation> {
+        let paths: Vec<PathBuf> = modules.iter().map(|(path, ..)| PathBuf::from(path)).collect();
+        let dependencies: Vec<BTreeMap<String, Resolution>> = modules.iter()
+            .map(|(_, _, deps)| {
+                deps.iter()
+                    .map(|(specifier, path)| (specifier.to_string(), Resolution::Module(PathBuf::from(path))))
+                    .collect()
+            })
+            .collect();
+        let graph: Vec<GraphModule> = modules.iter().enumerate()
+            .map(|(i, (_, code, _))| GraphModule {
+                path: &paths[i],
+                code,
+                dependencies: &dependencies[i],
+                side_effects: true,
+            })
+            .collect();
+        hoist_scopes(&graph, &[0])
+    }
+
+    #[test]
+    fn test_modules_share_a_scope() {
+        let result = hoist(&[
+            (
+                "index.mjs",
+                "import { double } from './math.mjs';\nimport lib from 'lib';\nexport const value = double(lib);",
+                &[("./math.mjs", "math.mjs"), ("lib", "node_modules/lib/index.js")],
+            ),
+            ("math.mjs", "export const double = (x) => x * 2;", &[]),
+        ]);
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].root, 0);
+        assert_eq!(result[0].members, vec![1, 0]);
+        assert_eq!(
+            result[0].code,
+            "import { default as lib } from \"lib\";\n\
+             // math.mjs\nconst double = (x) => x * 2;\n\
+             // index.mjs\nconst value = double(lib);\n\
+             export { value };\n"
+        );
+    }
+
+    #[test]
+    fn test_colliding_names_renamed() {
+        let result = hoist(&[
+            (
+                "index.mjs",
+                "import helper, { count } from './a.mjs';\nconst count$ = 1;\nfunction local() { return count; }\nexport default { count, helper, local, count$ };",
+                &[("./a.mjs", "a.mjs")],
+            ),
+            ("a.mjs", "let count = 0;\nfunction local(count) { return count; }\nexport { count };\nexport default function () { return local(count); }", &[]),
+        ]);
+
+        let code = &result[0].code;
+        // The first module keeps its names; the entry's clash is renamed
+        assert!(code.contains("let count = 0;\nfunction local(count) { return count; }"));
+        assert!(code.contains("var _default$1 = function () { return local(count); };"));
+        assert!(code.contains("function local$1() { return count; }"));
+        assert!(code.contains("var _default$2 = { count, helper: _default$1, local: local$1, count$ };"));
+        assert!(code.ends_with("export { _default$2 as default };\n"));
+    }
+
+    #[test]
+    fn test_shadowed_names_not_captured() {
+        let result = hoist(&[
+            (
+                "index.mjs",
+                "import { value } from './a.mjs';\nexport function read(data) { return [value, data]; }",
+                &[("./a.mjs", "a.mjs")],
+            ),
+            ("a.mjs", "const data = 1;\nexport const value = data;", &[]),
+        ]);
+
+        let code = &result[0].code;
+        assert!(code.contains("const data$1 = 1;\nconst value = data$1;"));
+        assert!(code.contains("function read(data) { return [value, data]; }"));
+        assert!(code.contains("export { read };"));
+    }
+
+    #[test]
+    fn test_commonjs_and_dynamic_imports_stay_wrapped() {
+        let result = hoist(&[
+            (
+                "index.mjs",
+                "import { a } from './a.mjs';\nimport('./lazy.mjs');\nexport const b = a + require('./cjs.js');",
+                &[("./a.mjs", "a.mjs"), ("./lazy.mjs", "lazy.mjs"), ("./cjs.js", "cjs.js")],
+            ),
+            ("a.mjs", "export const a = 1;", &[]),
+            ("lazy.mjs", "export const lazy = 1;", &[]),
+            ("cjs.js", "module.exports = 1;", &[]),
+        ]);
+
+        // The entry requires a CommonJS module, so it cannot be a root
+        assert!(result.is_empty());
+
+        let result = hoist(&[
+            (
+                "index.mjs",
+                "import { a } from './a.mjs';\nimport('./lazy.mjs');\nexport const b = a;",
+                &[("./a.mjs", "a.mjs"), ("./lazy.mjs", "lazy.mjs")],
+            ),
+            ("a.mjs", "export const a = 1;", &[]),
+            ("lazy.mjs", "export const lazy = 1;", &[]),
+        ]);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].members, vec![1, 0]);
+        assert!(result[0].code.contains("import('./lazy.mjs');"));
+    }
+
+    #[test]
+    fn test_re_exports_resolved() {
+        let result = hoist(&[
+            (
+                "index.mjs",
+                "export * from './barrel.mjs';\nexport { default as main } from './a.mjs';",
+                &[("./barrel.mjs", "barrel.mjs"), ("./a.mjs", "a.mjs")],
+            ),
+            (
+                "barrel.mjs",
+                "export { a as alpha } from './a.mjs';\nexport * from 'ext';",
+                &[("./a.mjs", "a.mjs"), ("ext", "node_modules/ext/index.js")],
+            ),
+            ("a.mjs", "export const a = 1;\nexport default 2;", &[]),
+        ]);
+
+        let code = &result[0].code;
+        assert!(code.starts_with("import \"ext\";\n"));
+        assert!(code.contains("var _default$1 = 2;"));
+        assert!(code.contains("export { a as alpha, _default$1 as main };"));
+        assert!(code.contains("export * from \"ext\";"));
+    }
+}
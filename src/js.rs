@@ -0,0 +1,464 @@
+//! JavaScript parsing and source-editing helpers
+//!
+//! Most of the pipeline works on source text. These helpers let the
+//! transformer and bundler use the swc parser to locate nodes precisely and
+//! then edit the original text by byte span, so untouched code keeps its
+//! formatting and comments.
+
+use std::collections::HashSet;
+use std::path::Path;
+use anyhow::{anyhow, Result};
+use swc_common::{BytePos, Span, Spanned};
+use swc_ecma_ast::{
+    AssignOp, BindingIdent, CallExpr, Callee, ClassDecl, EsVersion, Expr, FnDecl, ImportSpecifier, Lit, ModuleDecl,
+    MemberProp, NamedExport, Program, Prop, PropName, PropOrSpread, Stmt,
+};
+use swc_ecma_parser::{lexer::Lexer, EsConfig, Parser, StringInput, Syntax, TsConfig};
+use swc_ecma_visit::{Visit, VisitWith};
+
+/// Offset of the first byte of a parsed source. Position zero is reserved
+/// for dummy spans in swc, so parsed code starts at one.
+const START_POS: u32 = 1;
+
+/// Choose parser syntax based on file extension
+pub fn syntax_for_path(path: &Path) -> Syntax {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+        Some("ts") | Some("mts") | Some("cts") => Syntax::Typescript(TsConfig {
+            decorators: true,
+            ..Default::default()
+        }),
+        Some("tsx") => Syntax::Typescript(TsConfig {
+            tsx: true,
+            decorators: true,
+            ..Default::default()
+        }),
+        Some("jsx") => Syntax::Es(EsConfig {
+            jsx: true,
+            ..Default::default()
+        }),
+        _ => Syntax::Es(EsConfig {
+            allow_return_outside_function: true,
+            ..Default::default()
+        }),
+    }
+}
+
+/// Parse source as a program, letting the parser decide between script and
+/// module goal
+pub fn parse_program(code: &str, syntax: Syntax) -> Result<Program> {
+    let lexer = Lexer::new(
+        syntax,
+        EsVersion::latest(),
+        StringInput::new(code, BytePos(START_POS), BytePos(START_POS + code.len() as u32)),
+        None,
+    );
+
+    let mut parser = Parser::new_from(lexer);
+    parser.parse_program()
+        .map_err(|e| anyhow!("{} at byte {}", e.kind().msg(), span_start(e.span().lo)))
+}
+
+/// Every name bound anywhere in a program. Scopes are not tracked, so a
+/// name declared in any function counts as declared for the whole file;
+/// callers use this to avoid rewriting identifiers that may be local.
+pub fn declared_names(program: &Program) -> HashSet<String> {
+    let mut collector = DeclarationCollector { names: HashSet::new(), imports: true };
+    program.visit_with(&mut collector);
+    collector.names
+}
+
+/// Like [`declared_names`], but leaving out names bound by `import`
+/// declarations
+pub fn local_names(program: &Program) -> HashSet<String> {
+    let mut collector = DeclarationCollector { names: HashSet::new(), imports: false };
+    program.visit_with(&mut collector);
+    collector.names
+}
+
+struct DeclarationCollector {
+    names: HashSet<String>,
+    imports: bool,
+}
+
+impl Visit for DeclarationCollector {
+    fn visit_binding_ident(&mut self, ident: &BindingIdent) {
+        self.names.insert(ident.id.sym.to_string());
+    }
+
+    fn visit_fn_decl(&mut self, decl: &FnDecl) {
+        self.names.insert(decl.ident.sym.to_string());
+        decl.visit_children_with(self);
+    }
+
+    fn visit_class_decl(&mut self, decl: &ClassDecl) {
+        self.names.insert(decl.ident.sym.to_string());
+        decl.visit_children_with(self);
+    }
+
+    fn visit_import_specifier(&mut self, specifier: &ImportSpecifier) {
+        if !self.imports {
+            return;
+        }
+        let local = match specifier {
+            ImportSpecifier::Named(s) => &s.local,
+            ImportSpecifier::Default(s) => &s.local,
+            ImportSpecifier::Namespace(s) => &s.local,
+        };
+        self.names.insert(local.sym.to_string());
+    }
+}
+
+/// Specifiers of `require()` and `require.import()` calls with a string
+/// literal argument, in source order. A program that declares its own
+/// `require` has none.
+pub fn required_specifiers(program: &Program) -> Vec<String> {
+    collect_specifiers(program, false).specifiers
+}
+
+/// Specifiers of every module a program loads: `import` and
+/// `export ... from` sources, and `import()` and `require()` calls with a
+/// string literal argument, in source order
+pub fn module_specifiers(program: &Program) -> Vec<String> {
+    collect_specifiers(program, true).specifiers
+}
+
+/// Specifiers a program only loads on demand, through `import()` or
+/// `require.import()`, and never with a static import or `require()`
+pub fn dynamic_specifiers(program: &Program) -> Vec<String> {
+    let collector = collect_specifiers(program, true);
+    collector.deferred.into_iter()
+        .filter(|specifier| !collector.immediate.contains(specifier))
+        .collect()
+}
+
+/// Names a CommonJS module exports, found the way Node's CommonJS lexer
+/// finds them: in top-level `exports.name =` and `module.exports.name =`
+/// assignments, `Object.defineProperty(exports, "name", ...)` calls and
+/// the keys of an object literal assigned to `module.exports`. Names
+/// added any other way, e.g. in a loop, are not found.
+pub fn export_names(program: &Program) -> Vec<String> {
+    let statements: Vec<&Stmt> = match program {
+        Program::Script(script) => script.body.iter().collect(),
+        Program::Module(module) => module.body.iter().filter_map(|item| item.as_stmt()).collect(),
+    };
+
+    let mut names = Vec::new();
+    for statement in statements {
+        if let Stmt::Expr(statement) = statement {
+            collect_export_names(&statement.expr, &mut names);
+        }
+    }
+    names.retain(|name| name != "__esModule");
+    let mut seen = HashSet::new();
+    names.retain(|name| seen.insert(name.clone()));
+    names
+}
+
+fn collect_export_names(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Seq(seq) => {
+            for expr in &seq.exprs {
+                collect_export_names(expr, names);
+            }
+        }
+        Expr::Paren(paren) => collect_export_names(&paren.expr, names),
+        Expr::Assign(assign) if assign.op == AssignOp::Assign => {
+            match assign.left.as_expr() {
+                // A new exports object replaces the names found so far
+                Some(target) if is_module_exports(target) => {
+                    names.clear();
+                    if let Expr::Object(object) = assign.right.as_ref() {
+                        for prop in &object.props {
+                            let PropOrSpread::Prop(prop) = prop else { continue };
+                            let name = match prop.as_ref() {
+                                Prop::Shorthand(ident) => Some(ident.sym.to_string()),
+                                Prop::KeyValue(prop) => prop_name(&prop.key),
+                                Prop::Method(prop) => prop_name(&prop.key),
+                                _ => None,
+                            };
+                            names.extend(name);
+                        }
+                    }
+                    return;
+                }
+                Some(Expr::Member(member)) if is_exports_object(&member.obj) => {
+                    names.extend(member_name(&member.prop));
+                }
+                _ => {}
+            }
+            // `exports.a = exports.b = void 0`
+            collect_export_names(&assign.right, names);
+        }
+        Expr::Call(call) => {
+            let Callee::Expr(callee) = &call.callee else { return };
+            let Expr::Member(callee) = callee.as_ref() else { return };
+            let defines_property = matches!(callee.obj.as_ref(), Expr::Ident(ident) if ident.sym == *"Object")
+                && matches!(&callee.prop, MemberProp::Ident(prop) if prop.sym == *"defineProperty");
+            if let (true, [target, name, ..]) = (defines_property, call.args.as_slice()) {
+                if let (true, Expr::Lit(Lit::Str(name))) = (is_exports_object(&target.expr), name.expr.as_ref()) {
+                    names.push(name.value.to_string());
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether an expression is `module.exports`
+fn is_module_exports(expr: &Expr) -> bool {
+    let Expr::Member(member) = expr else { return false };
+    matches!(member.obj.as_ref(), Expr::Ident(ident) if ident.sym == *"module")
+        && member_name(&member.prop).as_deref() == Some("exports")
+}
+
+/// Whether an expression is `exports` or `module.exports`
+fn is_exports_object(expr: &Expr) -> bool {
+    matches!(expr, Expr::Ident(ident) if ident.sym == *"exports") || is_module_exports(expr)
+}
+
+/// Name of a property read with `.name` or `["name"]`
+fn member_name(prop: &MemberProp) -> Option<String> {
+    match prop {
+        MemberProp::Ident(ident) => Some(ident.sym.to_string()),
+        MemberProp::Computed(computed) => match computed.expr.as_ref() {
+            Expr::Lit(Lit::Str(name)) => Some(name.value.to_string()),
+            _ => None,
+        },
+        MemberProp::PrivateName(_) => None,
+    }
+}
+
+fn prop_name(key: &PropName) -> Option<String> {
+    match key {
+        PropName::Ident(ident) => Some(ident.sym.to_string()),
+        PropName::Str(name) => Some(name.value.to_string()),
+        _ => None,
+    }
+}
+
+fn collect_specifiers(program: &Program, imports: bool) -> RequireCollector {
+    let mut collector = RequireCollector {
+        specifiers: Vec::new(),
+        immediate: HashSet::new(),
+        deferred: Vec::new(),
+        requires: !declared_names(program).contains("require"),
+        imports,
+    };
+    program.visit_with(&mut collector);
+    collector
+}
+
+struct RequireCollector {
+    specifiers: Vec<String>,
+    /// Specifiers loaded when the module runs
+    immediate: HashSet<String>,
+    /// Specifiers loaded on demand, in source order
+    deferred: Vec<String>,
+    requires: bool,
+    imports: bool,
+}
+
+impl RequireCollector {
+    fn push(&mut self, specifier: &str, deferred: bool) {
+        if !self.specifiers.iter().any(|existing| existing == specifier) {
+            self.specifiers.push(specifier.to_string());
+        }
+        if !deferred {
+            self.immediate.insert(specifier.to_string());
+        } else if !self.deferred.iter().any(|existing| existing == specifier) {
+            self.deferred.push(specifier.to_string());
+        }
+    }
+}
+
+/// Whether a callee is `require.import`
+fn is_require_import(callee: &Expr) -> bool {
+    let Expr::Member(member) = callee else { return false };
+    matches!(member.obj.as_ref(), Expr::Ident(ident) if ident.sym == *"require")
+        && matches!(&member.prop, MemberProp::Ident(prop) if prop.sym == *"import")
+}
+
+impl Visit for RequireCollector {
+    fn visit_call_expr(&mut self, call: &CallExpr) {
+        let loads = match &call.callee {
+            Callee::Expr(callee) => match callee.as_ref() {
+                Expr::Ident(ident) if self.requires && ident.sym == *"require" => Some(false),
+                callee if self.requires && is_require_import(callee) => Some(true),
+                _ => None,
+            },
+            Callee::Import(_) if self.imports => Some(true),
+            _ => None,
+        };
+        if let (Some(deferred), [argument]) = (loads, call.args.as_slice()) {
+            if let Expr::Lit(Lit::Str(s)) = argument.expr.as_ref() {
+                self.push(&s.value, deferred);
+            }
+        }
+        call.visit_children_with(self);
+    }
+
+    fn visit_module_decl(&mut self, decl: &ModuleDecl) {
+        if self.imports {
+            match decl {
+                ModuleDecl::Import(import) if !import.type_only => self.push(&import.src.value, false),
+                ModuleDecl::ExportAll(export) if !export.type_only => self.push(&export.src.value, false),
+                ModuleDecl::ExportNamed(NamedExport { src: Some(src), type_only: false, .. }) => self.push(&src.value, false),
+                _ => {}
+            }
+        }
+        decl.visit_children_with(self);
+    }
+}
+
+/// Byte offset in the original source where a parsed position starts
+pub fn span_start(pos: BytePos) -> usize {
+    pos.0.saturating_sub(START_POS) as usize
+}
+
+/// Byte range in the original source covered by a span
+pub fn span_range(span: Span) -> std::ops::Range<usize> {
+    span_start(span.lo)..span_start(span.hi)
+}
+
+/// One-based line and column of a parsed position
+pub fn line_column(code: &str, pos: BytePos) -> (usize, usize) {
+    let offset = span_start(pos).min(code.len());
+    let before = &code[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Source text covered by a span
+pub fn span_text(code: &str, span: Span) -> &str {
+    &code[span_range(span)]
+}
+
+/// A set of non-overlapping text replacements applied in one pass
+#[derive(Debug, Default)]
+pub struct SourceEdits {
+    edits: Vec<(usize, usize, String)>,
+}
+
+impl SourceEdits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the text covered by a span
+    pub fn replace(&mut self, span: Span, text: impl Into<String>) {
+        let range = span_range(span);
+        self.edits.push((range.start, range.end, text.into()));
+    }
+
+    /// Replace the text between two parsed positions
+    pub fn replace_between(&mut self, lo: BytePos, hi: BytePos, text: impl Into<String>) {
+        let text = text.into();
+        if hi > lo || !text.is_empty() {
+            self.edits.push((span_start(lo), span_start(hi.max(lo)), text));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Apply all edits. Insertions go before replacements at the same
+    /// position. When edits overlap, the one that starts first (and is
+    /// widest) wins and the edits nested inside it are dropped.
+    pub fn apply(mut self, code: &str) -> String {
+        self.edits.sort_by(|a, b| {
+            a.0.cmp(&b.0)
+                .then((a.0 != a.1).cmp(&(b.0 != b.1)))
+                .then(b.1.cmp(&a.1))
+        });
+
+        let mut result = String::with_capacity(code.len());
+        let mut cursor = 0;
+
+        for (start, end, text) in self.edits {
+            if start < cursor || end > code.len() {
+                continue;
+            }
+            result.push_str(&code[cursor..start]);
+            result.push_str(&text);
+            cursor = end;
+        }
+
+        result.push_str(&code[cursor..]);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_text_matches_source() {
+        let code = "var answer = 42;";
+        let program = parse_program(code, syntax_for_path(Path::new("a.js"))).unwrap();
+        assert_eq!(span_text(code, program.span()), code);
+    }
+
+    #[test]
+    fn test_declared_names() {
+        let code = "import a from 'a'; function b(c) { var d; } class E {}";
+        let program = parse_program(code, syntax_for_path(Path::new("a.mjs"))).unwrap();
+        let names = declared_names(&program);
+        for name in ["a", "b", "c", "d", "E"] {
+            assert!(names.contains(name), "missing {}", name);
+        }
+        assert!(!local_names(&program).contains("a"));
+    }
+
+    #[test]
+    fn test_required_specifiers() {
+        let code = "var a = require('./a'); function f() { return require(\"b\") + require('./a'); } require(name);";
+        let program = parse_program(code, syntax_for_path(Path::new("a.js"))).unwrap();
+        assert_eq!(required_specifiers(&program), vec!["./a", "b"]);
+    }
+
+    #[test]
+    fn test_module_specifiers() {
+        let code = "import a from './a'; export * from './b'; export { c } from './c'; import('./d'); require('./a');";
+        let program = parse_program(code, syntax_for_path(Path::new("a.mjs"))).unwrap();
+        assert_eq!(module_specifiers(&program), vec!["./a", "./b", "./c", "./d"]);
+        assert_eq!(required_specifiers(&program), vec!["./a"]);
+    }
+
+    #[test]
+    fn test_dynamic_specifiers() {
+        let code = "require.import('./a'); import('./b'); require('./b'); import c from './c'; require.import(name);";
+        let program = parse_program(code, syntax_for_path(Path::new("a.mjs"))).unwrap();
+        assert_eq!(dynamic_specifiers(&program), vec!["./a"]);
+        assert_eq!(required_specifiers(&program), vec!["./a", "./b"]);
+    }
+
+    #[test]
+    fn test_export_names() {
+        let code = "Object.defineProperty(exports, \"__esModule\", { value: true });\n\
+                    Object.defineProperty(exports, \"a\", { get: function () { return a; } });\n\
+                    exports.b = exports[\"c\"] = void 0;\n\
+                    module.exports.d = 1;\n\
+                    function f() { exports.local = 1; }";
+        let program = parse_program(code, syntax_for_path(Path::new("a.js"))).unwrap();
+        assert_eq!(export_names(&program), ["a", "b", "c", "d"]);
+
+        let code = "exports.old = 1;\nmodule.exports = { a: 1, \"b\": 2, c, d() {} };\nmodule.exports.e = 3;";
+        let program = parse_program(code, syntax_for_path(Path::new("a.js"))).unwrap();
+        assert_eq!(export_names(&program), ["a", "b", "c", "d", "e"]);
+
+        let program = parse_program("module.exports = require('x');", syntax_for_path(Path::new("a.js"))).unwrap();
+        assert!(export_names(&program).is_empty());
+    }
+
+    #[test]
+    fn test_source_edits_drop_nested() {
+        let code = "abcdef";
+        let mut edits = SourceEdits::new();
+        edits.edits.push((1, 5, "X".to_string()));
+        edits.edits.push((2, 3, "Y".to_string()));
+        assert_eq!(edits.apply(code), "aXf");
+    }
+}
@@ -44,6 +44,24 @@ pub mod npm;
 pub mod output;
 pub mod polyfills;
 pub mod errors;
+pub mod js;
+pub mod defines;
+pub mod shims;
+pub mod fs_inline;
+pub mod stubs;
+pub mod assets;
+pub mod wasm;
+pub mod context;
+pub mod resolver;
+pub mod esm;
+pub mod treeshake;
+pub mod hoist;
+pub mod combine;
+pub mod duplicates;
+pub mod report;
+pub mod syntax;
+pub mod naming;
+pub mod cache;
 
 // Re-export main types for convenience
 pub use config::Config;
@@ -13,6 +13,24 @@ mod polyfills;
 mod npm;
 mod output;
 mod errors;
+mod js;
+mod defines;
+mod shims;
+mod fs_inline;
+mod stubs;
+mod assets;
+mod wasm;
+mod context;
+mod resolver;
+mod esm;
+mod treeshake;
+mod hoist;
+mod combine;
+mod duplicates;
+mod report;
+mod syntax;
+mod naming;
+mod cache;
 
 use cli::{Cli, Commands};
 use config::Config;
@@ -33,7 +51,7 @@ async fn main() -> Result<()> {
     // Handle commands
     match cli.command {
         Commands::Convert {
-            package,
+            packages,
             output,
             name,
             namespace,
@@ -42,14 +60,33 @@ async fn main() -> Result<()> {
             include_polyfills,
             exclude_dependencies,
             strategy,
+            format,
+            defines,
+            report,
+            reproducible,
             dry_run
         } => {
+            // Packages named on the command line are exposed under names
+            // derived from them; configured ones under their keys
+            let packages: Vec<(String, String)> = if packages.is_empty() {
+                config.bundle.packages.clone().into_iter().collect()
+            } else {
+                packages.iter().map(|package| (combine::exposed_name(package), package.clone())).collect()
+            };
+            if packages.is_empty() {
+                eprintln!("❌ No package to convert: name one or configure [bundle.packages]");
+                std::process::exit(1);
+            }
+
+            let format = format.unwrap_or(config.output.format);
             let converter = converter::Converter::new(config).await?;
 
             if dry_run {
                 info!("Dry run mode - analyzing package without conversion");
-                let analysis = converter.analyze(&package).await?;
-                println!("{}", serde_json::to_string_pretty(&analysis)?);
+                for (_, package) in &packages {
+                    let analysis = converter.analyze(package).await?;
+                    println!("{}", serde_json::to_string_pretty(&analysis)?);
+                }
                 return Ok(());
             }
 
@@ -62,13 +99,61 @@ async fn main() -> Result<()> {
                 include_polyfills,
                 exclude_dependencies,
                 bundle_strategy: strategy,
+                format,
+                defines: defines.into_iter().collect(),
+                exposed_packages: Vec::new(),
+                report,
+                reproducible,
             };
 
-            match converter.convert(&package, options).await {
+            let result = match packages.as_slice() {
+                [(_, package)] => converter.convert(package, options).await,
+                _ => converter.convert_packages(&packages, options).await,
+            };
+            match result {
                 Ok(result) => {
                     info!("Conversion completed successfully");
                     info!("Output: {}", result.output_path.display());
                     info!("Size: {} bytes", result.size);
+                    if result.stats.dependencies_deduplicated > 0 {
+                        info!("Shared dependencies deduplicated: {}", result.stats.dependencies_deduplicated);
+                    }
+                    if result.stats.modules_concatenated > 0 {
+                        info!("Modules concatenated: {}", result.stats.modules_concatenated);
+                    }
+                    for chunk in &result.chunks {
+                        info!("Chunk {}: {} ({} bytes)", chunk.name, chunk.path.display(), chunk.size);
+                    }
+                    if let Some(report) = &result.size_report {
+                        info!(
+                            "Bundle: {} bytes minified, {} gzipped",
+                            report.bundle.minified,
+                            report.bundle.gzip
+                        );
+                    }
+                    for duplicate in &result.duplicate_packages {
+                        warn!(
+                            "{} bundled at versions {} ({} bytes duplicated)",
+                            duplicate.name,
+                            duplicate.versions(),
+                            duplicate.wasted_size
+                        );
+                        for copy in &duplicate.copies {
+                            let required_by: Vec<String> = copy.required_by.iter()
+                                .map(|path| path.display().to_string())
+                                .collect();
+                            warn!("  {} at {}, required by {}", copy.version, copy.directory.display(), required_by.join(", "));
+                        }
+                    }
+                    for decision in &result.dependency_decisions {
+                        info!(
+                            "{} {}: {} bytes minified, {}",
+                            if decision.inlined { "Inlined" } else { "External" },
+                            decision.package,
+                            decision.minified_size,
+                            decision.reason
+                        );
+                    }
 
                     if !result.warnings.is_empty() {
                         warn!("Warnings during conversion:");
@@ -0,0 +1,154 @@
+//! Output file names from `output.naming_pattern`
+//!
+//! The pattern is expanded with the package name and version, the target,
+//! the strategy, the build date and a hash of the output, so new versions
+//! uploaded to OutSystems get new names and are not served from a cache.
+//! The hash is only known once the bundle is written out, while chunk
+//! scripts are named before that; they are named after the pattern with
+//! its hash placeholders left out.
+
+use std::sync::LazyLock;
+use regex::{Captures, Regex};
+use sha2::{Digest, Sha256};
+
+use crate::errors::{PaktoError, Result as PaktoResult};
+
+/// Placeholders `expand` accepts, for error messages
+const PLACEHOLDERS: &str = "{name}, {package}, {version}, {target}, {strategy}, {date}, {hash}, {hash:N}";
+
+/// A `{key}` or `{key:N}` placeholder
+static PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\{([a-z]+)(?::([0-9]+))?\}").expect("valid placeholder pattern")
+});
+
+/// Separators dropped along with a hash placeholder left out of a name
+const SEPARATORS: &[char] = &['.', '-', '_'];
+
+/// Values substituted into a naming pattern
+#[derive(Debug, Clone, Default)]
+pub struct NamingValues {
+    /// `--name`, or the package as given
+    pub name: String,
+    /// The package as given on the command line
+    pub package: String,
+    pub version: String,
+    pub target: String,
+    pub strategy: String,
+    /// Build date as `YYYY-MM-DD`
+    pub date: String,
+}
+
+/// Expand a naming pattern. Without a hash, `{hash}` placeholders are
+/// left out together with the separator before them, so
+/// `{name}.{hash:8}.js` becomes `{name}.js`.
+pub fn expand(pattern: &str, values: &NamingValues, hash: Option<&str>) -> PaktoResult<String> {
+    let mut name = String::with_capacity(pattern.len());
+    let mut last = 0;
+
+    for captures in PLACEHOLDER_REGEX.captures_iter(pattern) {
+        let whole = captures.get(0).expect("whole match");
+        let mut literal = &pattern[last..whole.start()];
+        last = whole.end();
+
+        let value = match (&captures[1], captures.get(2)) {
+            ("hash", length) => {
+                let length = match length {
+                    Some(length) => hash_length(length.as_str())?,
+                    None => None,
+                };
+                match hash {
+                    Some(hash) => length.map_or(hash, |length| &hash[..length.min(hash.len())]).to_string(),
+                    None => {
+                        // Drop one separator with the placeholder, before
+                        // it or, at the start of the name, after it
+                        match literal.strip_suffix(SEPARATORS) {
+                            Some(rest) => literal = rest,
+                            None if name.is_empty() && literal.is_empty() && pattern[last..].starts_with(SEPARATORS) => {
+                                last += 1;
+                            }
+                            None => {}
+                        }
+                        String::new()
+                    }
+                }
+            }
+            (key, None) => match key {
+                "name" => values.name.clone(),
+                "package" => values.package.clone(),
+                "version" => values.version.clone(),
+                "target" => values.target.clone(),
+                "strategy" => values.strategy.clone(),
+                "date" => values.date.clone(),
+                _ => return Err(unknown_placeholder(&captures)),
+            },
+            _ => return Err(unknown_placeholder(&captures)),
+        };
+        name.push_str(literal);
+        name.push_str(&value);
+    }
+    name.push_str(&pattern[last..]);
+    Ok(name)
+}
+
+/// Hex SHA-256 of the output, for `{hash}`
+pub fn content_hash(content: &[u8]) -> String {
+    Sha256::digest(content).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hash_length(length: &str) -> PaktoResult<Option<usize>> {
+    match length.parse::<usize>() {
+        Ok(length @ 1..=64) => Ok(Some(length)),
+        _ => Err(PaktoError::ConfigError {
+            message: format!("Invalid output.naming_pattern: hash length {} is not between 1 and 64", length),
+            source: None,
+        }),
+    }
+}
+
+fn unknown_placeholder(captures: &Captures) -> PaktoError {
+    PaktoError::ConfigError {
+        message: format!(
+            "Invalid output.naming_pattern: unknown placeholder {}; available: {}",
+            &captures[0],
+            PLACEHOLDERS
+        ),
+        source: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> NamingValues {
+        NamingValues {
+            name: "lib".to_string(),
+            package: "lib@2".to_string(),
+            version: "2.1.0".to_string(),
+            target: "es2015".to_string(),
+            strategy: "inline".to_string(),
+            date: "2024-05-01".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let hash = content_hash(b"bundle");
+        assert_eq!(hash.len(), 64);
+
+        let name = expand("{name}-{version}.{target}.{strategy}.{date}.js", &values(), None).unwrap();
+        assert_eq!(name, "lib-2.1.0.es2015.inline.2024-05-01.js");
+
+        let name = expand("{name}.{hash:8}.js", &values(), Some(&hash)).unwrap();
+        assert_eq!(name, format!("lib.{}.js", &hash[..8]));
+        assert_eq!(expand("{hash}.js", &values(), Some(&hash)).unwrap(), format!("{}.js", hash));
+
+        // Left out before the hash is known
+        assert_eq!(expand("{name}.{hash:8}.js", &values(), None).unwrap(), "lib.js");
+        assert_eq!(expand("{hash}-{version}.js", &values(), None).unwrap(), "2.1.0.js");
+
+        assert!(expand("{name}-{unknown}.js", &values(), None).is_err());
+        assert!(expand("{name}.{hash:0}.js", &values(), None).is_err());
+        assert!(expand("{version:3}.js", &values(), None).is_err());
+    }
+}
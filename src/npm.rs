@@ -1,5 +1,6 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 use anyhow::{Context, Result};
 use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT, AUTHORIZATION};
@@ -8,7 +9,7 @@ use tracing::{debug, info, warn};
 use url::Url;
 
 use crate::config::NpmConfig;
-use crate::converter::{PackageData, PackageInfo};
+use crate::converter::{FileContent, PackageData, PackageInfo};
 use crate::errors::{PaktoError, Result as PaktoResult};
 
 /// NPM registry client for fetching package information and downloads
@@ -33,7 +34,7 @@ pub struct NpmPackageMetadata {
 }
 
 /// Version-specific package information
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct NpmVersionInfo {
     pub name: String,
     pub version: String,
@@ -46,6 +47,8 @@ pub struct NpmVersionInfo {
     pub dev_dependencies: Option<HashMap<String, String>>,
     #[serde(rename = "peerDependencies")]
     pub peer_dependencies: Option<HashMap<String, String>>,
+    #[serde(rename = "optionalDependencies")]
+    pub optional_dependencies: Option<HashMap<String, String>>,
     pub keywords: Option<Vec<String>>,
     pub license: Option<serde_json::Value>,
     pub dist: NpmDistInfo,
@@ -53,7 +56,7 @@ pub struct NpmVersionInfo {
 }
 
 /// Distribution/download information
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct NpmDistInfo {
     pub tarball: String,
     pub shasum: String,
@@ -183,6 +186,7 @@ impl NpmClient {
             entry_points,
             dependencies: version_info.dependencies.clone().unwrap_or_default(),
             dev_dependencies: version_info.dev_dependencies.clone().unwrap_or_default(),
+            optional_dependencies: version_info.optional_dependencies.clone().unwrap_or_default(),
             keywords: version_info.keywords.clone().unwrap_or_default(),
             license: license_string,
         })
@@ -218,129 +222,144 @@ impl NpmClient {
                 version: version.clone(),
             })?;
 
-        // For now, create mock package data instead of downloading actual files
-        // In a production version, this would download and extract the tarball
-        let package_data = self.create_mock_package_data(version_info)?;
+        // Download the package, then the dependencies it requires at runtime
+        let mut files = self.fetch_tarball(&package_name.name, &version_info.dist).await?;
+        self.install_dependencies(version_info, &mut files).await?;
+
+        let package_json = match files.get(Path::new("package.json")) {
+            Some(content) => serde_json::from_slice(content.as_bytes())
+                .map_err(|e| PaktoError::ParseError {
+                    file: PathBuf::from("package.json"),
+                    message: format!("Failed to parse package.json of {}: {}", package_name.name, e),
+                    source: Some(Box::new(e)),
+                })?,
+            None => serde_json::to_value(version_info)?,
+        };
+
+        let package_data = PackageData {
+            total_size: files.values().map(|content| content.len()).sum(),
+            files,
+            package_json,
+        };
 
         // Cache the result
         let cache_key = format!("{}@{}", package_name.name, version);
-        self.cache_package_data(&cache_key, &package_data).await?;
+        self.cache_package_data(&cache_key, &package_data).await
+            .map_err(|e| PaktoError::CacheError {
+                message: format!("Failed to cache {}: {:#}", cache_key, e),
+                source: Some(e.into()),
+            })?;
 
         Ok(package_data)
     }
 
-    /// Create mock package data for development
-    fn create_mock_package_data(&self, version_info: &NpmVersionInfo) -> PaktoResult<PackageData> {
-        let mut files = HashMap::new();
-
-        // Create a mock main file
-        let main_file = version_info.main.as_deref().unwrap_or("index.js");
-        let mock_content = self.generate_mock_package_content(&version_info.name);
-        files.insert(PathBuf::from(main_file), mock_content);
+    /// Download a package tarball and extract its files, relative to the
+    /// package directory
+    async fn fetch_tarball(&self, package: &str, dist: &NpmDistInfo) -> PaktoResult<HashMap<PathBuf, FileContent>> {
+        debug!("Fetching tarball: {}", dist.tarball);
 
-        // Create package.json
-        let package_json = serde_json::to_value(version_info)
-            .context("Failed to serialize package.json")?;
-
-        Ok(PackageData {
-            total_size: 1024, // Mock size
-            files,
-            package_json,
+        let network_error = |source| PaktoError::NetworkError {
+            package: package.to_string(),
+            source,
+        };
+        let response = self.client
+            .get(&dist.tarball)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(network_error)?;
+        let bytes = response.bytes().await.map_err(network_error)?;
+
+        extract_tarball(&bytes).map_err(|e| PaktoError::ParseError {
+            file: PathBuf::from(&dist.tarball),
+            message: format!("Failed to extract the tarball of {}: {:#}", package, e),
+            source: Some(e.into()),
         })
     }
 
-    /// Generate mock package content for development
-    fn generate_mock_package_content(&self, package_name: &str) -> String {
-        match package_name {
-            "lodash" => r#"
-// Mock lodash implementation
-function map(collection, iteratee) {
-    return collection.map(iteratee);
-}
+    /// Install the runtime dependency tree under `node_modules` the way npm
+    /// lays it out: each package is hoisted to the top level unless a
+    /// different version already sits there, in which case it is nested
+    /// under the package that needs it
+    async fn install_dependencies(
+        &self,
+        root: &NpmVersionInfo,
+        files: &mut HashMap<PathBuf, FileContent>,
+    ) -> PaktoResult<()> {
+        let mut installed: HashMap<String, semver::Version> = HashMap::new();
+        let mut queue: VecDeque<(String, String, String)> = runtime_dependencies(root)
+            .into_iter()
+            .map(|(name, range)| (String::new(), name, range))
+            .collect();
+
+        while let Some((parent, name, spec)) = queue.pop_front() {
+            let Some((package, range)) = registry_spec(&name, &spec) else {
+                warn!(
+                    "Skipping {}@{} required by {}: only registry versions can be installed",
+                    name,
+                    spec,
+                    if parent.is_empty() { "the package" } else { &parent }
+                );
+                continue;
+            };
 
-function filter(collection, predicate) {
-    return collection.filter(predicate);
-}
+            // The copy Node would load from the parent's directory
+            let visible = node_modules_candidates(&parent, &name).into_iter()
+                .find(|directory| installed.contains_key(directory));
+            if let Some(directory) = &visible {
+                if range_matches(range, &installed[directory]) {
+                    continue;
+                }
+            }
 
-function reduce(collection, iteratee, accumulator) {
-    return collection.reduce(iteratee, accumulator);
-}
+            let version_info = self.resolve_version(package, range).await?;
+            let directory = match visible {
+                None => format!("node_modules/{}", name),
+                Some(_) => format!("{}/node_modules/{}", parent, name).trim_start_matches('/').to_string(),
+            };
+            if installed.contains_key(&directory) {
+                warn!("Conflicting versions of {} required at {}; keeping the first", name, directory);
+                continue;
+            }
 
-function pick(object, keys) {
-    const result = {};
-    keys.forEach(key => {
-        if (object.hasOwnProperty(key)) {
-            result[key] = object[key];
-        }
-    });
-    return result;
-}
+            debug!("Installing {}@{} at {}", name, version_info.version, directory);
+            let package_files = self.fetch_tarball(package, &version_info.dist).await?;
+            for (path, content) in package_files {
+                files.insert(Path::new(&directory).join(path), content);
+            }
 
-module.exports = {
-    map: map,
-    filter: filter,
-    reduce: reduce,
-    pick: pick
-};
-"#.to_string(),
-
-            "uuid" => r#"
-// Mock UUID implementation
-function v4() {
-    return 'xxxxxxxx-xxxx-4xxx-yxxx-xxxxxxxxxxxx'.replace(/[xy]/g, function(c) {
-        var r = Math.random() * 16 | 0;
-        var v = c == 'x' ? r : (r & 0x3 | 0x8);
-        return v.toString(16);
-    });
-}
+            let version = semver::Version::parse(&version_info.version)
+                .map_err(|_| PaktoError::VersionNotFound { package: name.clone(), version: version_info.version.clone() })?;
+            installed.insert(directory.clone(), version);
 
-module.exports = {
-    v4: v4
-};
-"#.to_string(),
-
-            "moment" => r#"
-// Mock moment implementation
-function moment(input) {
-    var date = input ? new Date(input) : new Date();
-    
-    return {
-        format: function(format) {
-            return date.toISOString();
-        },
-        valueOf: function() {
-            return date.getTime();
-        },
-        toDate: function() {
-            return date;
+            for (dependency, range) in runtime_dependencies(&version_info) {
+                queue.push_back((directory.clone(), dependency, range));
+            }
         }
-    };
-}
 
-module.exports = moment;
-"#.to_string(),
-
-            name if name.starts_with("is-") => {
-                let check_name = name.strip_prefix("is-").unwrap_or("value");
-                format!(r#"
-// Mock {} implementation
-module.exports = function(value) {{
-    // Simple type check for {}
-    return typeof value === '{}';
-}};
-"#, name, check_name, if check_name == "array" { "object" } else { check_name })
-            }
+        info!("Installed {} dependenc{}", installed.len(), if installed.len() == 1 { "y" } else { "ies" });
+        Ok(())
+    }
 
-            _ => format!(r#"
-// Mock implementation for {}
-var {} = {{
-    // Add mock functionality here
-    version: '1.0.0-mock'
-}};
+    /// Highest published version of a package matching a range or tag
+    async fn resolve_version(&self, name: &str, range: &str) -> PaktoResult<NpmVersionInfo> {
+        let metadata = self.get_package_metadata(name).await?;
+
+        let version = match metadata.dist_tags.get(range) {
+            Some(tagged) => Some(tagged.clone()),
+            None => metadata.versions.keys()
+                .filter_map(|version| semver::Version::parse(version).ok())
+                .filter(|version| range_matches(range, version))
+                .max()
+                .map(|version| version.to_string()),
+        };
 
-module.exports = {};
-"#, package_name, package_name.replace('-', '_'), package_name.replace('-', '_'))
-        }
+        version
+            .and_then(|version| metadata.versions.get(&version).cloned())
+            .ok_or_else(|| PaktoError::VersionNotFound {
+                package: name.to_string(),
+                version: range.to_string(),
+            })
     }
 
     /// Parse package name and version
@@ -508,6 +527,122 @@ struct ParsedPackageName {
     version: Option<String>,
 }
 
+/// Extract a gzipped package tarball. Entries sit under a single top-level
+/// directory, usually `package/`, which is stripped.
+fn extract_tarball(bytes: &[u8]) -> Result<HashMap<PathBuf, FileContent>> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(bytes));
+    let mut files = HashMap::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path: PathBuf = entry.path()?.components().skip(1).collect();
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        files.insert(path, FileContent::from_bytes(content));
+    }
+
+    Ok(files)
+}
+
+/// Dependencies a package needs at runtime. Optional dependencies are
+/// listed under `dependencies` too, and are left out.
+fn runtime_dependencies(version_info: &NpmVersionInfo) -> Vec<(String, String)> {
+    let optional = version_info.optional_dependencies.clone().unwrap_or_default();
+    let mut dependencies: Vec<(String, String)> = version_info.dependencies.clone()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(name, _)| !optional.contains_key(name))
+        .collect();
+    dependencies.sort();
+    dependencies
+}
+
+/// `node_modules` directories Node searches for a package from a package
+/// directory, nearest first
+fn node_modules_candidates(parent: &str, name: &str) -> Vec<String> {
+    let mut candidates = Vec::new();
+    let mut directory = parent.to_string();
+    loop {
+        if !directory.is_empty() {
+            candidates.push(format!("{}/node_modules/{}", directory, name));
+        }
+        match directory.rfind("/node_modules/") {
+            Some(index) => directory.truncate(index),
+            None if directory.is_empty() => break,
+            None => directory.clear(),
+        }
+    }
+    candidates.push(format!("node_modules/{}", name));
+    candidates
+}
+
+/// Whether a version satisfies an npm range such as `^1.2.0`,
+/// `>=1.0.0 <2`, `1.2.3 - 1.4` or `1.x || 2.x`
+pub(crate) fn range_matches(range: &str, version: &semver::Version) -> bool {
+    range.split("||").any(|alternative| {
+        npm_range_requirement(alternative.trim())
+            .is_some_and(|requirement| requirement.matches(version))
+    })
+}
+
+/// Package and range a dependency spec installs from the registry:
+/// `npm:real@^1` aliases install `real`. Git, file, link and tarball URL
+/// specs, and GitHub `user/repo` shorthands, are not installed from the
+/// registry and give `None`.
+fn registry_spec<'a>(name: &'a str, spec: &'a str) -> Option<(&'a str, &'a str)> {
+    if let Some(aliased) = spec.strip_prefix("npm:") {
+        // The version separator comes after a scope's leading `@`
+        return Some(match aliased.get(1..).and_then(|rest| rest.find('@')) {
+            Some(index) => (&aliased[..index + 1], &aliased[index + 2..]),
+            None => (aliased, "*"),
+        });
+    }
+    if spec.contains([':', '/']) {
+        return None;
+    }
+    Some((name, spec))
+}
+
+/// Translate one npm range to a semver requirement. npm separates
+/// comparators with spaces, treats a full bare version as exact and a
+/// partial one (`1.2`) as any version with that prefix.
+fn npm_range_requirement(range: &str) -> Option<semver::VersionReq> {
+    if range.is_empty() || range == "*" || range == "latest" {
+        return Some(semver::VersionReq::STAR);
+    }
+
+    if let Some((low, high)) = range.split_once(" - ") {
+        return semver::VersionReq::parse(&format!(">={}, <={}", low.trim(), high.trim())).ok();
+    }
+
+    let mut comparators: Vec<String> = Vec::new();
+    let mut pending_operator = String::new();
+    for token in range.split_whitespace() {
+        if token.chars().all(|c| matches!(c, '<' | '>' | '=' | '^' | '~')) {
+            pending_operator = token.to_string();
+            continue;
+        }
+        let token = format!("{}{}", std::mem::take(&mut pending_operator), token.trim_start_matches('v'));
+        let is_bare = token.chars().next().is_some_and(|c| c.is_ascii_digit())
+            && !token.contains(['x', 'X', '*']);
+        comparators.push(match token.split('.').count() {
+            3 if is_bare => format!("={}", token),
+            _ if is_bare => format!("~{}", token),
+            _ => token,
+        });
+    }
+
+    semver::VersionReq::parse(&comparators.join(", ")).ok()
+}
+
 // Make PackageData cloneable for caching
 impl Clone for PackageData {
     fn clone(&self) -> Self {
@@ -562,22 +697,69 @@ mod tests {
     }
 
     #[test]
-    fn test_mock_content_generation() {
-        let config = NpmConfig::default();
-        let client = NpmClient {
-            config,
-            client: reqwest::Client::new(),
-            cache_dir: PathBuf::new(),
-        };
+    fn test_npm_ranges() {
+        let version = |v: &str| semver::Version::parse(v).unwrap();
+
+        assert!(range_matches("^1.2.0", &version("1.9.0")));
+        assert!(!range_matches("^1.2.0", &version("2.0.0")));
+        assert!(range_matches("1.2.3", &version("1.2.3")));
+        assert!(!range_matches("1.2.3", &version("1.2.4")));
+        assert!(range_matches(">= 1.0.0 < 2", &version("1.5.0")));
+        assert!(range_matches("1.2.3 - 1.4.0", &version("1.4.0")));
+        assert!(range_matches("1.x || ^3.0.0", &version("3.1.0")));
+        assert!(range_matches("*", &version("0.0.1")));
+        assert!(!range_matches("~0.4.1", &version("0.5.0")));
+        assert!(range_matches("1.2", &version("1.2.7")));
+        assert!(!range_matches("1.2", &version("1.3.0")));
+        assert!(range_matches("1", &version("1.9.0")));
+        assert!(!range_matches("1", &version("2.0.0")));
+    }
+
+    #[test]
+    fn test_registry_spec() {
+        assert_eq!(registry_spec("a", "^1.0.0"), Some(("a", "^1.0.0")));
+        assert_eq!(registry_spec("a", "npm:b@^2"), Some(("b", "^2")));
+        assert_eq!(registry_spec("a", "npm:@scope/b@~1.2"), Some(("@scope/b", "~1.2")));
+        assert_eq!(registry_spec("a", "npm:b"), Some(("b", "*")));
+        assert_eq!(registry_spec("a", "git+https://github.com/u/a.git"), None);
+        assert_eq!(registry_spec("a", "file:../a"), None);
+        assert_eq!(registry_spec("a", "https://example.com/a.tgz"), None);
+        assert_eq!(registry_spec("a", "user/a#v1"), None);
+    }
 
-        let lodash_content = client.generate_mock_package_content("lodash");
-        assert!(lodash_content.contains("module.exports"));
-        assert!(lodash_content.contains("map"));
+    #[test]
+    fn test_node_modules_candidates() {
+        assert_eq!(node_modules_candidates("", "a"), vec!["node_modules/a"]);
+        assert_eq!(
+            node_modules_candidates("node_modules/b/node_modules/c", "a"),
+            vec![
+                "node_modules/b/node_modules/c/node_modules/a",
+                "node_modules/b/node_modules/a",
+                "node_modules/a",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_tarball() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in [("package/package.json", &b"{}"[..]), ("package/lib/index.js", b"module.exports = 1;")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, content).unwrap();
+        }
+        let tarball = builder.into_inner().unwrap();
 
-        let uuid_content = client.generate_mock_package_content("uuid");
-        assert!(uuid_content.contains("v4"));
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, &tarball).unwrap();
+        let files = extract_tarball(&encoder.finish().unwrap()).unwrap();
 
-        let is_array_content = client.generate_mock_package_content("is-array");
-        assert!(is_array_content.contains("module.exports"));
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files.get(Path::new("lib/index.js")),
+            Some(&FileContent::Text("module.exports = 1;".to_string()))
+        );
     }
 }
\ No newline at end of file
@@ -1,13 +1,34 @@
 use std::collections::HashMap;
+use std::path::Path;
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use handlebars::{Handlebars, Helper, Output, RenderContext, RenderError};
 use serde_json::{json, Value};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-use crate::config::Config;
+use walkdir::WalkDir;
+
+use crate::config::{Config, TemplateConfig};
 use crate::converter::{BundledCode, ConvertOptions, PackageInfo};
-use crate::cli::EsTarget;
+use crate::cli::{EsTarget, OutputFormat};
 use crate::errors::{PaktoError, Result as PaktoResult};
+use crate::syntax;
+
+/// Built-in templates and partials, by name. Each format's template wraps
+/// the bundle in the `factory` partial, a function returning the package's
+/// exports, and exposes them the way the format does.
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("banner", include_str!("../templates/banner.hbs")),
+    ("factory", include_str!("../templates/factory.hbs")),
+    ("expose", include_str!("../templates/expose.hbs")),
+    ("outsystems", include_str!("../templates/outsystems.hbs")),
+    ("umd", include_str!("../templates/umd.hbs")),
+    ("iife", include_str!("../templates/iife.hbs")),
+    ("esm", include_str!("../templates/esm.hbs")),
+    ("cjs", include_str!("../templates/cjs.hbs")),
+    ("amd", include_str!("../templates/amd.hbs")),
+    ("system", include_str!("../templates/system.hbs")),
+];
 
 /// Generates final output files using templates
 pub struct OutputGenerator {
@@ -27,6 +48,8 @@ struct TemplateContext {
     output_name: String,
     namespace: Option<String>,
     global_name: String,
+    /// Named exports of the ESM format
+    export_names: Vec<String>,
 
     // Code content
     bundled_code: String,
@@ -40,30 +63,37 @@ struct TemplateContext {
     // Features
     has_polyfills: bool,
     has_namespace: bool,
+    /// Whether the exports are added to the namespace one by one, as a
+    /// multi-package bundle's packages are
+    merges_into_namespace: bool,
     is_minified: bool,
 
     // Statistics
     original_size: usize,
     bundle_size: usize,
 
-    // Custom data
+    // Custom data, from `[templates.variables]`
     custom: HashMap<String, Value>,
 }
 
 impl OutputGenerator {
-    pub fn new(config: &Config) -> Self {
+    pub fn new(config: &Config) -> PaktoResult<Self> {
         let mut handlebars = Handlebars::new();
 
-        // Register built-in templates
+        // The output is JavaScript, not HTML
+        handlebars.register_escape_fn(handlebars::no_escape);
+
+        // Register built-in templates, then the user's over them
         Self::register_templates(&mut handlebars);
+        Self::register_user_templates(&mut handlebars, &config.templates)?;
 
         // Register helper functions
         Self::register_helpers(&mut handlebars);
 
-        Self {
+        Ok(Self {
             config: config.clone(),
             handlebars,
-        }
+        })
     }
 
     pub fn generate(
@@ -115,123 +145,98 @@ impl OutputGenerator {
             output_name: output_name.clone(),
             namespace: options.namespace.clone(),
             global_name,
+            export_names: bundled.exports.iter()
+                .filter(|name| is_export_name(name))
+                .cloned()
+                .collect(),
 
             bundled_code: main_code,
             polyfills_code,
 
-            generated_at: chrono::Utc::now().to_rfc3339(),
+            generated_at: build_time(options.reproducible).to_rfc3339(),
             generator_version: env!("CARGO_PKG_VERSION").to_string(),
             target_es_version: format!("{:?}", options.target_es_version),
 
             has_polyfills: !polyfills_code.trim().is_empty(),
             has_namespace: options.namespace.is_some(),
+            merges_into_namespace: options.namespace.is_some() && !options.exposed_packages.is_empty(),
             is_minified: options.minify,
 
             original_size: bundled.unminified_size,
             bundle_size: bundled.code.len(),
 
-            custom: HashMap::new(),
+            custom: self.config.templates.variables.clone(),
         })
     }
 
-    /// Select appropriate template based on options
-    fn select_template(&self, _options: &ConvertOptions) -> String {
-        // For OutSystems, we always use the OutSystems-specific template
-        "outsystems".to_string()
+    /// Select the template of the output format
+    fn select_template(&self, options: &ConvertOptions) -> String {
+        match options.format {
+            OutputFormat::OutSystems => "outsystems",
+            OutputFormat::Umd => "umd",
+            OutputFormat::Iife => "iife",
+            OutputFormat::Esm => "esm",
+            OutputFormat::Cjs => "cjs",
+            OutputFormat::Amd => "amd",
+            OutputFormat::System => "system",
+        }.to_string()
     }
 
-    /// Register built-in templates
+    /// Register the built-in templates and partials
     fn register_templates(handlebars: &mut Handlebars<'static>) {
-        // OutSystems-specific template (embedded fallback)
-        let outsystems_template = r#"/**
- * {{package_name}} v{{package_version}} - OutSystems Compatible
-{{#if package_description}} * {{package_description}}{{/if}}
- *
- * Generated by Pakto v{{generator_version}} on {{generated_at}}
- * Target: {{target_es_version}}
- *
- * This bundle is optimized for OutSystems platform
- */
-(function(global, factory) {
-  'use strict';
-
-  // Universal Module Definition (UMD) pattern for maximum compatibility
-  if (typeof module === 'object' && typeof module.exports === 'object') {
-    // Node.js environment
-    module.exports = factory();
-  } else if (typeof define === 'function' && define.amd) {
-    // AMD environment
-    define(factory);
-  } else {
-    // Browser globals - OutSystems target
-{{#if has_namespace}}
-    global.{{namespace}} = global.{{namespace}} || {};
-    global.{{namespace}}.{{global_name}} = factory();
-{{else}}
-    global.{{global_name}} = factory();
-{{/if}}
-  }
-})(typeof window !== 'undefined' ? window : this, function() {
-  'use strict';
-
-{{#if has_polyfills}}
-  // ================================================================
-  // Polyfills for Browser Compatibility
-  // ================================================================
-{{indent polyfills_code 2}}
-
-{{/if}}
-  // ================================================================
-  // Main Module Code
-  // ================================================================
-{{indent bundled_code 2}}
-
-  // ================================================================
-  // Module Exports
-  // ================================================================
-{{#if_not_empty bundled_code}}
-  return typeof module !== 'undefined' && module.exports ? module.exports : {};
-{{else}}
-  return {};
-{{/if_not_empty}}
-});
-
-{{comment "Bundle Information"}}
-{{comment (concat "Original size: " original_size " bytes")}}
-{{comment (concat "Bundle size: " bundle_size " bytes")}}
-{{#if is_minified}}
-{{comment "Code has been minified"}}
-{{/if}}"#;
-
-        handlebars.register_template_string("outsystems", outsystems_template)
-            .expect("Failed to register OutSystems template");
-
-        // UMD template
-        let umd_template = r#"(function (global, factory) {
-    typeof exports === 'object' && typeof module !== 'undefined' ? factory(exports) :
-    typeof define === 'function' && define.amd ? define(['exports'], factory) :
-    (global = global || self, factory(global.{{global_name}} = {}));
-}(this, (function (exports) { 'use strict';
-
-{{indent bundled_code 4}}
-
-})));
-"#;
-
-        handlebars.register_template_string("umd", umd_template)
-            .expect("Failed to register UMD template");
-
-        // IIFE template
-        let iife_template = r#"(function() {
-  'use strict';
+        for (name, template) in BUILTIN_TEMPLATES {
+            handlebars.register_template_string(name, *template)
+                .unwrap_or_else(|e| panic!("Failed to register built-in template {}: {}", name, e));
+        }
+    }
 
-{{indent bundled_code 2}}
+    /// Register the `.hbs` files of `templates.directory`, named after
+    /// their path in it without the extension, then `templates.overrides`.
+    /// Either replaces the built-in template or partial of the same name.
+    fn register_user_templates(handlebars: &mut Handlebars<'static>, config: &TemplateConfig) -> PaktoResult<()> {
+        if let Some(directory) = &config.directory {
+            let mut files = Vec::new();
+            for entry in WalkDir::new(directory) {
+                let entry = entry.map_err(|e| PaktoError::TemplateError {
+                    message: format!("Failed to read template directory {}: {}", directory.display(), e),
+                    template: directory.display().to_string(),
+                    source: Some(Box::new(e)),
+                })?;
+                if entry.file_type().is_file() && entry.path().extension().is_some_and(|ext| ext == "hbs") {
+                    files.push(entry.into_path());
+                }
+            }
+            files.sort();
+
+            for path in files {
+                let name = path.strip_prefix(directory).unwrap_or(&path)
+                    .with_extension("")
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                Self::register_template_file(handlebars, &name, &path)?;
+            }
+        }
 
-  return typeof module !== 'undefined' && module.exports ? module.exports : {};
-})();"#;
+        let mut overrides: Vec<(&String, &String)> = config.overrides.iter().collect();
+        overrides.sort();
+        for (name, path) in overrides {
+            if !BUILTIN_TEMPLATES.iter().any(|(builtin, _)| builtin == name) {
+                warn!("templates.overrides.{} does not replace a built-in template; it is only available as a partial", name);
+            }
+            Self::register_template_file(handlebars, name, Path::new(path))?;
+        }
+        Ok(())
+    }
 
-        handlebars.register_template_string("iife", iife_template)
-            .expect("Failed to register IIFE template");
+    fn register_template_file(handlebars: &mut Handlebars<'static>, name: &str, path: &Path) -> PaktoResult<()> {
+        debug!("Registering template {} from {}", name, path.display());
+        handlebars.register_template_file(name, path).map_err(|e| PaktoError::TemplateError {
+            message: format!("Failed to load template {} from {}: {}", name, path.display(), e),
+            template: name.to_string(),
+            source: Some(Box::new(e)),
+        })
     }
 
     /// Register helper functions for templates
@@ -315,11 +320,12 @@ impl OutputGenerator {
             processed = self.minify_code(&processed)?;
         }
 
-        // Clean up extra whitespace
-        processed = self.clean_whitespace(&processed);
+        // Only the end of the output is trimmed; module code inside it
+        // keeps its whitespace, which strings and templates depend on
+        processed.truncate(processed.trim_end().len());
 
         // Validate syntax
-        self.validate_output(&processed)?;
+        self.validate_output(&processed, options)?;
 
         Ok(processed)
     }
@@ -337,62 +343,45 @@ impl OutputGenerator {
         }
     }
 
-    /// Clean up whitespace in generated code
-    fn clean_whitespace(&self, code: &str) -> String {
-        // Remove trailing whitespace from each line
-        let lines: Vec<String> = code
-            .lines()
-            .map(|line| line.trim_end().to_string())
-            .collect();
-
-        // Remove excessive blank lines
-        let mut cleaned_lines = Vec::new();
-        let mut prev_was_empty = false;
-
-        for line in lines {
-            let is_empty = line.trim().is_empty();
-
-            if !is_empty || !prev_was_empty {
-                cleaned_lines.push(line);
-            }
-
-            prev_was_empty = is_empty;
+    /// Parse the generated output as a script, or a module for the ESM
+    /// format, for the selected target
+    fn validate_output(&self, code: &str, options: &ConvertOptions) -> PaktoResult<()> {
+        let file = options.output_path.as_deref().unwrap_or(Path::new("output.js"));
+        match options.format {
+            OutputFormat::Esm => syntax::check_module(code, &options.target_es_version, file),
+            _ => syntax::check_script(code, &options.target_es_version, file),
         }
-
-        cleaned_lines.join("\n")
     }
+}
 
-    /// Basic validation of generated output
-    fn validate_output(&self, code: &str) -> PaktoResult<()> {
-        // Check for balanced braces
-        let open_braces = code.matches('{').count();
-        let close_braces = code.matches('}').count();
-        if open_braces != close_braces {
-            return Err(PaktoError::TransformError {
-                message: format!("Unbalanced braces: {} open, {} close", open_braces, close_braces),
-                source: None,
-            });
-        }
+/// Whether an export name can be declared by the ESM wrapper: an
+/// identifier other than `default`, which the wrapper declares itself
+fn is_export_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && name != "default"
+        && !name.starts_with("__pakto_")
+}
 
-        // Check for balanced parentheses
-        let open_parens = code.matches('(').count();
-        let close_parens = code.matches(')').count();
-        if open_parens != close_parens {
-            return Err(PaktoError::TransformError {
-                message: format!("Unbalanced parentheses: {} open, {} close", open_parens, close_parens),
-                source: None,
-            });
-        }
+/// Time the output is dated with, in its header and file name
+pub(crate) fn build_time(reproducible: bool) -> DateTime<Utc> {
+    generation_time(std::env::var("SOURCE_DATE_EPOCH").ok().as_deref(), reproducible)
+}
 
-        // Check for basic syntax errors
-        if code.contains("undefined undefined") || code.contains("null null") {
-            return Err(PaktoError::TransformError {
-                message: "Possible syntax error detected in output".to_string(),
-                source: None,
-            });
+/// `SOURCE_DATE_EPOCH` takes precedence, as reproducible build tooling
+/// expects; a reproducible build without it is dated at the Unix epoch.
+fn generation_time(source_date_epoch: Option<&str>, reproducible: bool) -> DateTime<Utc> {
+    if let Some(value) = source_date_epoch {
+        match value.trim().parse::<i64>().ok().and_then(|secs| DateTime::from_timestamp(secs, 0)) {
+            Some(time) => return time,
+            None => warn!("Ignoring invalid SOURCE_DATE_EPOCH: {}", value),
         }
-
-        Ok(())
+    }
+    if reproducible {
+        DateTime::UNIX_EPOCH
+    } else {
+        Utc::now()
     }
 }
 
@@ -520,18 +509,20 @@ mod tests {
     use crate::config::Config;
     use crate::cli::EsTarget;
     use crate::converter::BundledCode;
+    use clap::ValueEnum;
+    use tempfile::TempDir;
 
     #[test]
     fn test_output_generator_creation() {
         let config = Config::default();
-        let generator = OutputGenerator::new(&config);
+        let generator = OutputGenerator::new(&config).unwrap();
         assert!(generator.handlebars.has_template("outsystems"));
     }
 
     #[test]
     fn test_sanitize_global_name() {
         let config = Config::default();
-        let generator = OutputGenerator::new(&config);
+        let generator = OutputGenerator::new(&config).unwrap();
 
         assert_eq!(generator.sanitize_global_name("my-package"), "MyPackage");
         assert_eq!(generator.sanitize_global_name("@types/node"), "TypesNode");
@@ -548,7 +539,7 @@ mod tests {
     #[test]
     fn test_extract_polyfills() {
         let config = Config::default();
-        let generator = OutputGenerator::new(&config);
+        let generator = OutputGenerator::new(&config).unwrap();
 
         let code = r#"
 some code
@@ -568,12 +559,18 @@ more code
     #[test]
     fn test_generate_output() {
         let config = Config::default();
-        let generator = OutputGenerator::new(&config);
+        let generator = OutputGenerator::new(&config).unwrap();
 
         let bundled_code = BundledCode {
             code: "var test = 'hello';".to_string(),
             bundled_dependencies: vec!["lodash".to_string()],
             unminified_size: 100,
+            stylesheet: None,
+            resources: vec![],
+            chunks: vec![],
+            dependency_decisions: vec![],
+            duplicate_packages: vec![],
+            exports: vec![],
         };
 
         let options = ConvertOptions {
@@ -585,6 +582,11 @@ more code
             include_polyfills: vec![],
             exclude_dependencies: vec![],
             bundle_strategy: crate::cli::BundleStrategy::Inline,
+            format: OutputFormat::OutSystems,
+            defines: HashMap::new(),
+            exposed_packages: vec![],
+            report: false,
+            reproducible: false,
         };
 
         let package_info = PackageInfo {
@@ -595,6 +597,7 @@ more code
             entry_points: vec!["index.js".to_string()],
             dependencies: std::collections::HashMap::new(),
             dev_dependencies: std::collections::HashMap::new(),
+            optional_dependencies: std::collections::HashMap::new(),
             keywords: vec![],
             license: Some("MIT".to_string()),
         };
@@ -607,4 +610,128 @@ more code
         assert!(output.contains("test-package"));
         assert!(output.contains("var test = 'hello';"));
     }
+
+    #[test]
+    fn test_output_formats() {
+        let generator = OutputGenerator::new(&Config::default()).unwrap();
+        let bundled_code = BundledCode {
+            code: "var module = { exports: { answer: 42 } };".to_string(),
+            bundled_dependencies: vec![],
+            unminified_size: 100,
+            stylesheet: None,
+            resources: vec![],
+            chunks: vec![],
+            dependency_decisions: vec![],
+            duplicate_packages: vec![],
+            exports: vec!["answer".to_string(), "default".to_string(), "not-an-identifier".to_string()],
+        };
+        let package_info = PackageInfo {
+            name: "answer".to_string(),
+            version: "1.0.0".to_string(),
+            description: None,
+            main: Some("index.js".to_string()),
+            entry_points: vec!["index.js".to_string()],
+            dependencies: HashMap::new(),
+            dev_dependencies: HashMap::new(),
+            optional_dependencies: HashMap::new(),
+            keywords: vec![],
+            license: None,
+        };
+        let generate = |format| {
+            let options = ConvertOptions { format, ..ConvertOptions::default() };
+            generator.generate(&bundled_code, &options, &package_info).unwrap()
+        };
+
+        // Every format wraps the same factory, parsed as its kind of file
+        for format in OutputFormat::value_variants() {
+            let output = generate(*format);
+            assert!(output.contains("var module = { exports: { answer: 42 } };"), "{:?}", format);
+            assert!(output.contains("return typeof module !== 'undefined' && module.exports ? module.exports : {};"));
+        }
+
+        let output = generate(OutputFormat::Umd);
+        assert!(output.contains("module.exports = factory();"));
+        assert!(output.contains("define([], factory);"));
+        assert!(output.contains("    global.Answer = factory();"));
+
+        let output = generate(OutputFormat::Iife);
+        assert!(output.contains("  global.Answer = factory();"));
+        assert!(!output.contains("module.exports = factory()"));
+
+        let output = generate(OutputFormat::Esm);
+        assert!(output.contains("export default __pakto_exports__ && __pakto_exports__.__esModule"));
+        assert!(output.contains("var __pakto_export_0__ = __pakto_exports__.answer;"));
+        assert!(output.contains("export { __pakto_export_0__ as answer };"));
+
+        assert!(generate(OutputFormat::Cjs).contains("module.exports = (function() {"));
+        assert!(generate(OutputFormat::Amd).contains("define([], function() {"));
+        assert!(generate(OutputFormat::System).contains("System.register([], function(_export) {"));
+    }
+
+    #[test]
+    fn test_user_templates() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path().join("templates");
+        std::fs::create_dir_all(directory.join("partials")).unwrap();
+        std::fs::write(directory.join("banner.hbs"), "// {{custom.team}} <{{custom.year}}>\n").unwrap();
+        std::fs::write(directory.join("partials/log.hbs"), "console.log('loaded {{package_name}}');\n").unwrap();
+        std::fs::write(directory.join("notes.txt"), "{{#if}}").unwrap();
+        let cjs = temp_dir.path().join("cjs.hbs");
+        std::fs::write(&cjs, "{{> banner}}\nmodule.exports = (function() {\n{{> factory}}\n})();\n{{> partials/log}}\n").unwrap();
+
+        let mut config = Config::default();
+        config.templates.directory = Some(directory);
+        config.templates.overrides.insert("cjs".to_string(), cjs.to_string_lossy().to_string());
+        config.templates.variables.insert("team".to_string(), json!("Platform & Tools"));
+        config.templates.variables.insert("year".to_string(), json!(2024));
+        let generator = OutputGenerator::new(&config).unwrap();
+
+        let bundled_code = BundledCode {
+            code: "var module = { exports: {} };".to_string(),
+            bundled_dependencies: vec![],
+            unminified_size: 0,
+            stylesheet: None,
+            resources: vec![],
+            chunks: vec![],
+            dependency_decisions: vec![],
+            duplicate_packages: vec![],
+            exports: vec![],
+        };
+        let package_info = PackageInfo {
+            name: "house".to_string(),
+            version: "1.0.0".to_string(),
+            description: None,
+            main: None,
+            entry_points: vec![],
+            dependencies: HashMap::new(),
+            dev_dependencies: HashMap::new(),
+            optional_dependencies: HashMap::new(),
+            keywords: vec![],
+            license: None,
+        };
+        let generate = |format| {
+            let options = ConvertOptions { format, ..ConvertOptions::default() };
+            generator.generate(&bundled_code, &options, &package_info).unwrap()
+        };
+
+        // The override replaces a format, the directory's banner the
+        // built-in partial every generic format includes
+        let output = generate(OutputFormat::Cjs);
+        assert!(output.starts_with("// Platform & Tools <2024>\nmodule.exports = (function() {"), "{}", output);
+        assert!(output.ends_with("console.log('loaded house');"), "{}", output);
+        assert!(generate(OutputFormat::Amd).starts_with("// Platform & Tools <2024>\ndefine([], function() {"));
+        assert!(generate(OutputFormat::OutSystems).contains("OutSystems Compatible"));
+
+        // Broken templates fail when loaded
+        std::fs::write(&cjs, "{{#if}}").unwrap();
+        assert!(matches!(OutputGenerator::new(&config), Err(PaktoError::TemplateError { template, .. }) if template == "cjs"));
+    }
+
+    #[test]
+    fn test_generation_time() {
+        assert_eq!(generation_time(Some("1700000000"), false).to_rfc3339(), "2023-11-14T22:13:20+00:00");
+        assert_eq!(generation_time(None, true).to_rfc3339(), "1970-01-01T00:00:00+00:00");
+        assert_eq!(generation_time(Some("soon"), true), DateTime::UNIX_EPOCH);
+        assert_ne!(generation_time(None, false), generation_time(None, true));
+    }
 }
\ No newline at end of file
@@ -39,6 +39,21 @@ impl PolyfillRegistry {
             include_str!("../polyfills/util.js").to_string()
         );
 
+        polyfills.insert(
+            "unavailable".to_string(),
+            include_str!("../polyfills/unavailable.js").to_string()
+        );
+
+        polyfills.insert(
+            "wasm".to_string(),
+            include_str!("../polyfills/wasm.js").to_string()
+        );
+
+        polyfills.insert(
+            "context".to_string(),
+            include_str!("../polyfills/context.js").to_string()
+        );
+
         Self { polyfills }
     }
 
@@ -51,7 +66,9 @@ impl PolyfillRegistry {
     }
 
     pub fn available_polyfills(&self) -> Vec<&String> {
-        self.polyfills.keys().collect()
+        let mut names: Vec<&String> = self.polyfills.keys().collect();
+        names.sort();
+        names
     }
 
     pub fn has_polyfill(&self, api: &str) -> bool {
@@ -0,0 +1,320 @@
+//! Bundle composition report
+//!
+//! Measures what each bundled module and package contributes to the
+//! bundle: its raw size as emitted, its minified size and its minified
+//! size after gzip, which is what a browser downloads. The report is
+//! written as JSON and as a standalone HTML treemap, and checked against
+//! the size budgets in `bundle.budgets`.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use serde::Serialize;
+
+use crate::config::{BudgetConfig, SizeMeasure};
+use crate::converter::ModuleSource;
+use crate::resolver;
+
+/// Package the polyfill block is reported under
+const POLYFILLS_PACKAGE: &str = "(polyfills)";
+
+/// Treemap page; the report is substituted for `__PAKTO_REPORT__`
+const HTML_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Bundle report</title>
+<style>
+body { font-family: sans-serif; margin: 0; padding: 16px; background: #fafafa; color: #222; }
+h1 { font-size: 18px; margin: 0 0 4px; }
+p { font-size: 13px; margin: 0 0 12px; color: #555; }
+#map { position: relative; height: 80vh; background: #fff; border: 1px solid #ccc; }
+.node { position: absolute; box-sizing: border-box; overflow: hidden; border: 1px solid #fff; padding: 2px; font-size: 11px; white-space: nowrap; }
+.package { border-width: 2px; font-weight: bold; }
+.module { font-weight: normal; }
+</style>
+</head>
+<body>
+<h1 id="title"></h1>
+<p id="summary"></p>
+<div id="map"></div>
+<script>
+(function () {
+  var report = __PAKTO_REPORT__;
+  var measure = 'minified';
+
+  function format(bytes) {
+    return bytes < 1024 ? bytes + ' B' : (bytes / 1024).toFixed(1) + ' KB';
+  }
+  function describe(name, sizes) {
+    return name + '\nraw ' + format(sizes.raw) + ', minified ' + format(sizes.minified) + ', gzip ' + format(sizes.gzip);
+  }
+  function slice(items, x, y, width, height, horizontal, place) {
+    var total = items.reduce(function (sum, item) { return sum + item.sizes[measure]; }, 0) || 1;
+    var offset = 0;
+    items.forEach(function (item) {
+      var part = item.sizes[measure] / total;
+      if (horizontal) {
+        place(item, x + offset * width, y, part * width, height);
+      } else {
+        place(item, x, y + offset * height, width, part * height);
+      }
+      offset += part;
+    });
+  }
+  function box(parent, className, x, y, width, height, label, title, color) {
+    var node = document.createElement('div');
+    node.className = className;
+    node.style.left = x + 'px';
+    node.style.top = y + 'px';
+    node.style.width = width + 'px';
+    node.style.height = height + 'px';
+    node.style.background = color;
+    node.title = title;
+    node.appendChild(document.createTextNode(label));
+    parent.appendChild(node);
+    return node;
+  }
+
+  document.title = report.name + ' bundle report';
+  document.getElementById('title').textContent = document.title;
+  document.getElementById('summary').textContent = describe('Bundle', report.bundle).replace('\n', ': ');
+
+  var map = document.getElementById('map');
+  var packages = report.packages.filter(function (item) { return item.sizes[measure] > 0; });
+  slice(packages, 0, 0, map.clientWidth, map.clientHeight, true, function (item, x, y, width, height) {
+    var color = 'hsl(' + (packages.indexOf(item) * 137) % 360 + ', 60%, 78%)';
+    var node = box(map, 'node package', x, y, width, height, item.name, describe(item.name, item.sizes), color);
+    var modules = report.modules.filter(function (module) {
+      return module.package === item.name && module.sizes[measure] > 0;
+    });
+    slice(modules, 0, 16, width - 4, height - 20, false, function (module, x, y, width, height) {
+      var label = module.path.split('/').pop();
+      box(node, 'node module', x, y, width, height, label, describe(module.path, module.sizes), color);
+    });
+  });
+})();
+</script>
+</body>
+</html>
+"#;
+
+/// Sizes of a piece of bundled code in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Sizes {
+    pub raw: usize,
+    pub minified: usize,
+    pub gzip: usize,
+}
+
+impl Sizes {
+    /// Measure code as emitted, minified, and minified then gzipped
+    pub fn measure(code: &str) -> Self {
+        let minified = minifier::js::minify(code).to_string();
+        Self {
+            raw: code.len(),
+            minified: minified.len(),
+            gzip: gzip_size(minified.as_bytes()),
+        }
+    }
+
+    pub fn get(&self, measure: SizeMeasure) -> usize {
+        match measure {
+            SizeMeasure::Raw => self.raw,
+            SizeMeasure::Minified => self.minified,
+            SizeMeasure::Gzip => self.gzip,
+        }
+    }
+}
+
+/// A bundled module
+#[derive(Debug, Clone, Serialize)]
+pub struct ModuleReport {
+    pub path: PathBuf,
+    /// Package the module belongs to
+    pub package: String,
+    pub sizes: Sizes,
+}
+
+/// The modules of one package together. Gzip is measured over the
+/// package's code as a whole, so it is less than the sum over modules.
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageReport {
+    pub name: String,
+    pub modules: usize,
+    pub sizes: Sizes,
+}
+
+/// What each package and module contributes to a bundle, largest first
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SizeReport {
+    /// Name of the converted package
+    pub name: String,
+    /// The bundle file as written
+    pub bundle: Sizes,
+    pub packages: Vec<PackageReport>,
+    pub modules: Vec<ModuleReport>,
+}
+
+/// Measure a bundle and the modules it contains. Files of the converted
+/// package are reported under `name`, the polyfill block under
+/// `(polyfills)`.
+pub fn size_report(name: &str, bundle: &str, modules: &[&ModuleSource], polyfills: &str) -> SizeReport {
+    let mut module_reports = Vec::new();
+    let mut package_code: BTreeMap<String, (usize, String)> = BTreeMap::new();
+
+    if !polyfills.trim().is_empty() {
+        package_code.insert(POLYFILLS_PACKAGE.to_string(), (0, polyfills.to_string()));
+    }
+    for module in modules {
+        let package = resolver::package_name_of(&module.path).unwrap_or_else(|| name.to_string());
+        let (count, code) = package_code.entry(package.clone()).or_default();
+        *count += 1;
+        code.push_str(&module.code);
+        code.push('\n');
+
+        module_reports.push(ModuleReport {
+            path: module.path.clone(),
+            package,
+            sizes: Sizes::measure(&module.code),
+        });
+    }
+
+    let mut packages: Vec<PackageReport> = package_code.into_iter()
+        .map(|(name, (modules, code))| PackageReport { name, modules, sizes: Sizes::measure(&code) })
+        .collect();
+    packages.sort_by(|a, b| b.sizes.minified.cmp(&a.sizes.minified).then_with(|| a.name.cmp(&b.name)));
+    module_reports.sort_by(|a, b| b.sizes.minified.cmp(&a.sizes.minified).then_with(|| a.path.cmp(&b.path)));
+
+    SizeReport {
+        name: name.to_string(),
+        bundle: Sizes::measure(bundle),
+        packages,
+        modules: module_reports,
+    }
+}
+
+impl SizeReport {
+    /// Standalone HTML page drawing the report as a treemap of packages
+    /// and their modules
+    pub fn to_html(&self) -> String {
+        let json = serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string());
+        // Keep the data from closing the script element
+        HTML_TEMPLATE.replace("__PAKTO_REPORT__", &json.replace("</", "<\\/"))
+    }
+
+    /// Packages and modules over their budget, described for an error
+    pub fn budget_violations(&self, budgets: &BudgetConfig) -> Vec<String> {
+        let measure = budgets.measure;
+        let mut violations = Vec::new();
+
+        for package in &self.packages {
+            let limit = budgets.packages.get(&package.name).copied().or(budgets.package);
+            if let Some(limit) = limit.filter(|limit| package.sizes.get(measure) > *limit) {
+                violations.push(format!(
+                    "package {} is {} bytes {}, over its {} byte budget",
+                    package.name,
+                    package.sizes.get(measure),
+                    measure_name(measure),
+                    limit
+                ));
+            }
+        }
+        for module in &self.modules {
+            let path = module.path.to_string_lossy().replace('\\', "/");
+            let limit = budgets.modules.get(&path).copied().or(budgets.module);
+            if let Some(limit) = limit.filter(|limit| module.sizes.get(measure) > *limit) {
+                violations.push(format!(
+                    "module {} is {} bytes {}, over its {} byte budget",
+                    path,
+                    module.sizes.get(measure),
+                    measure_name(measure),
+                    limit
+                ));
+            }
+        }
+        violations
+    }
+}
+
+fn measure_name(measure: SizeMeasure) -> &'static str {
+    match measure {
+        SizeMeasure::Raw => "raw",
+        SizeMeasure::Minified => "minified",
+        SizeMeasure::Gzip => "gzipped",
+    }
+}
+
+fn gzip_size(bytes: &[u8]) -> usize {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    match encoder.write_all(bytes).and_then(|_| encoder.finish()) {
+        Ok(compressed) => compressed.len(),
+        Err(_) => bytes.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn module(path: &str, code: &str) -> ModuleSource {
+        ModuleSource {
+            path: PathBuf::from(path),
+            code: code.to_string(),
+            dependencies: BTreeMap::new(),
+            dynamic_imports: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn test_size_report() {
+        let big = format!("module.exports = {:?};", "abc".repeat(500));
+        let modules = [
+            module("index.js", "module.exports   =   require('dep');"),
+            module("node_modules/dep/index.js", &big),
+            module("node_modules/dep/util.js", "module.exports = 1;"),
+        ];
+        let modules: Vec<&ModuleSource> = modules.iter().collect();
+        let report = size_report("app", "var bundle = 1;", &modules, "// === Polyfills ===\nvar p = 1;\n");
+
+        let names: Vec<&str> = report.packages.iter().map(|package| package.name.as_str()).collect();
+        assert_eq!(names, vec!["dep", "app", "(polyfills)"]);
+        assert_eq!(report.packages[0].modules, 2);
+        assert_eq!(report.modules[0].path, PathBuf::from("node_modules/dep/index.js"));
+        assert_eq!(report.modules[0].sizes.raw, big.len());
+        // Repetitive code compresses well
+        assert!(report.modules[0].sizes.gzip < report.modules[0].sizes.minified / 4);
+        let index = report.modules.iter().find(|module| module.path.as_path() == std::path::Path::new("index.js")).unwrap();
+        assert!(index.sizes.minified < index.sizes.raw);
+        assert_eq!(report.bundle.raw, "var bundle = 1;".len());
+
+        let html = report.to_html();
+        assert!(html.contains("\"name\":\"app\""));
+        assert!(!html.contains("__PAKTO_REPORT__"));
+    }
+
+    #[test]
+    fn test_budget_violations() {
+        let modules = [
+            module("index.js", "module.exports = require('dep');"),
+            module("node_modules/dep/index.js", &format!("module.exports = {:?};", "x".repeat(2000))),
+        ];
+        let modules: Vec<&ModuleSource> = modules.iter().collect();
+        let report = size_report("app", "", &modules, "");
+
+        let mut budgets = BudgetConfig::default();
+        assert!(report.budget_violations(&budgets).is_empty());
+
+        budgets.packages.insert("dep".to_string(), 1000);
+        budgets.module = Some(1500);
+        let violations = report.budget_violations(&budgets);
+        assert_eq!(violations.len(), 2);
+        assert!(violations[0].starts_with("package dep is 20"));
+        assert!(violations[1].starts_with("module node_modules/dep/index.js is 20"));
+        assert!(violations[1].ends_with("over its 1500 byte budget"));
+
+        budgets.measure = SizeMeasure::Gzip;
+        assert!(report.budget_violations(&budgets).is_empty());
+    }
+}
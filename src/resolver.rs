@@ -0,0 +1,573 @@
+//! Node.js module resolution over package files
+//!
+//! A downloaded package carries its dependencies under `node_modules`, laid
+//! out the way npm installs them. The resolver answers what a `require()`
+//! or `import` specifier names the way Node does: relative paths with
+//! extension and `index.js` probing, package `exports`, `browser` and
+//! `main` fields, and `node_modules` directories searched from the
+//! requiring file upwards. Browser-specific entries win, since the bundle
+//! runs in a browser. With deduplication on, a package installed at several
+//! versions resolves to the highest copy the requiring package accepts.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use serde_json::{Map, Value};
+use tracing::debug;
+
+use crate::converter::FileContent;
+use crate::fs_inline::normalize_path;
+use crate::npm;
+
+/// Node.js core modules. Subpaths such as `fs/promises` resolve through
+/// their first segment.
+const NODE_BUILTINS: &[&str] = &[
+    "assert", "async_hooks", "buffer", "child_process", "cluster", "console",
+    "constants", "crypto", "dgram", "diagnostics_channel", "dns", "domain",
+    "events", "fs", "http", "http2", "https", "inspector", "module", "net",
+    "os", "path", "perf_hooks", "process", "punycode", "querystring",
+    "readline", "repl", "stream", "string_decoder", "sys", "timers", "tls",
+    "trace_events", "tty", "url", "util", "v8", "vm", "wasi",
+    "worker_threads", "zlib",
+];
+
+/// Extensions tried, in order, when a specifier omits one
+const EXTENSIONS: &[&str] = &["js", "json", "mjs", "cjs"];
+
+/// Conditions matched in `exports` maps. The first key of a conditions
+/// object that is in this set wins, as in Node.
+const CONDITIONS: &[&str] = &["browser", "require", "import", "module", "default"];
+
+/// Package fields naming the entry module, in order of preference
+const MAIN_FIELDS: &[&str] = &["browser", "module", "main"];
+
+/// What a specifier resolves to
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Resolution {
+    /// A package file
+    Module(PathBuf),
+    /// A Node.js core module, without the `node:` prefix
+    Builtin(String),
+    /// A module the `browser` field replaces with `false`
+    Empty,
+    /// A dependency read from a global named in `bundle.externals`
+    External,
+}
+
+/// Package name of a bare specifier, e.g. `@scope/pkg` for
+/// `@scope/pkg/lib/x`. `None` for relative and absolute paths.
+pub(crate) fn package_name(specifier: &str) -> Option<&str> {
+    if specifier.starts_with('.') || specifier.starts_with('/') {
+        return None;
+    }
+    let segments = if specifier.starts_with('@') { 2 } else { 1 };
+    let end = specifier.match_indices('/')
+        .nth(segments - 1)
+        .map(|(i, _)| i)
+        .unwrap_or(specifier.len());
+    Some(&specifier[..end])
+}
+
+/// Name of a core module a specifier names, with any `node:` prefix
+/// removed
+pub fn builtin_name(specifier: &str) -> Option<&str> {
+    if let Some(name) = specifier.strip_prefix("node:") {
+        return Some(name);
+    }
+    let first = specifier.split('/').next().unwrap_or(specifier);
+    NODE_BUILTINS.contains(&first).then_some(specifier)
+}
+
+/// Package directory containing a path under `node_modules`, or the empty
+/// path for files of the root package
+pub fn package_root(path: &Path) -> PathBuf {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let Some(index) = path.rfind("node_modules/") else {
+        return PathBuf::new();
+    };
+    let start = index + "node_modules/".len();
+    match package_name(&path[start..]) {
+        Some(name) => PathBuf::from(&path[..start + name.len()]),
+        None => PathBuf::new(),
+    }
+}
+
+/// Name of the installed package a path belongs to, `None` for files of
+/// the root package
+pub fn package_name_of(path: &Path) -> Option<String> {
+    let root = package_root(path);
+    let root = root.to_string_lossy();
+    root.rsplit_once("node_modules/").map(|(_, name)| name.to_string())
+}
+
+/// Resolves specifiers against an in-memory package tree
+pub struct Resolver<'a> {
+    files: &'a HashMap<PathBuf, FileContent>,
+    /// Every directory that contains a file
+    directories: HashSet<String>,
+    /// Parsed `package.json` files by package directory
+    manifests: HashMap<String, Value>,
+    /// Installed copies of each package, by name, when deduplicating
+    copies: Option<HashMap<String, Vec<(String, semver::Version)>>>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(files: &'a HashMap<PathBuf, FileContent>) -> Self {
+        let mut directories = HashSet::new();
+        let mut manifests = HashMap::new();
+
+        for (path, content) in files {
+            let path = path.to_string_lossy().replace('\\', "/");
+            let mut directory = parent(&path);
+            loop {
+                if !directories.insert(directory.to_string()) || directory.is_empty() {
+                    break;
+                }
+                directory = parent(directory);
+            }
+
+            if path == "package.json" || path.ends_with("/package.json") {
+                if let Ok(manifest) = serde_json::from_slice(content.as_bytes()) {
+                    manifests.insert(parent(&path).to_string(), manifest);
+                }
+            }
+        }
+
+        Self { files, directories, manifests, copies: None }
+    }
+
+    /// Use a root `package.json` known from elsewhere, such as registry
+    /// metadata, when the files do not include one
+    pub fn with_root_manifest(mut self, manifest: &Value) -> Self {
+        self.manifests.entry(String::new()).or_insert_with(|| manifest.clone());
+        self
+    }
+
+    /// Resolve packages installed at several versions to the highest copy
+    /// within the range the requiring package declares
+    pub fn with_dedupe(mut self, dedupe: bool) -> Self {
+        if !dedupe {
+            return self;
+        }
+        let mut copies: HashMap<String, Vec<(String, semver::Version)>> = HashMap::new();
+        for (root, manifest) in &self.manifests {
+            // Only package roots, not nested package.json files
+            let manifest_path = join(root, "package.json");
+            if package_root(Path::new(&manifest_path)) != Path::new(root) {
+                continue;
+            }
+            let Some(name) = package_name_of(Path::new(&manifest_path)) else { continue };
+            let version = manifest.get("version")
+                .and_then(|v| v.as_str())
+                .and_then(|v| semver::Version::parse(v).ok());
+            if let Some(version) = version {
+                copies.entry(name).or_default().push((root.clone(), version));
+            }
+        }
+        copies.retain(|_, installed| installed.len() > 1);
+        self.copies = Some(copies);
+        self
+    }
+
+    /// Entry module of the root package
+    pub fn entry(&self) -> Option<PathBuf> {
+        let file = match self.manifest("").and_then(|m| m.get("exports")) {
+            Some(exports) => self.resolve_exports("", exports, "."),
+            None => self.load_directory(""),
+        }?;
+        match self.browser_file("", file) {
+            Resolution::Module(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Resolve a specifier required by a package file. `None` when nothing
+    /// in the package tree matches.
+    pub fn resolve(&self, from: &Path, specifier: &str) -> Option<Resolution> {
+        let from = from.to_string_lossy().replace('\\', "/");
+        let root = package_root(Path::new(&from)).to_string_lossy().into_owned();
+
+        // The requiring package's browser field may swap a package out;
+        // relative entries are matched by file below
+        let replacement = self.browser_map(&root)
+            .filter(|_| !is_relative(specifier))
+            .and_then(|map| map.get(specifier));
+        if let Some(replacement) = replacement {
+            match replacement {
+                Value::Bool(false) => return Some(Resolution::Empty),
+                Value::String(target) if is_relative(target) => {
+                    let file = self.load(&join(&root, target))?;
+                    return Some(Resolution::Module(PathBuf::from(file)));
+                }
+                Value::String(target) => return self.resolve_bare(&from, target),
+                _ => {}
+            }
+        }
+
+        if is_relative(specifier) {
+            let file = self.load(&join(parent(&from), specifier))?;
+            return Some(self.browser_file(&root, file));
+        }
+        if specifier.starts_with('/') {
+            return None;
+        }
+
+        self.resolve_bare(&from, specifier)
+    }
+
+    /// Resolve a core module or a package in `node_modules`
+    fn resolve_bare(&self, from: &str, specifier: &str) -> Option<Resolution> {
+        if let Some(name) = builtin_name(specifier) {
+            return Some(Resolution::Builtin(name.to_string()));
+        }
+
+        let name = package_name(specifier)?;
+        let subpath = &specifier[name.len()..];
+
+        for modules in node_modules_directories(parent(from)) {
+            let root = join(&modules, name);
+            if !self.directories.contains(&root) {
+                continue;
+            }
+            let root = self.deduplicated(from, name, root);
+
+            let exports = self.manifest(&root).and_then(|m| m.get("exports"));
+            let file = match exports {
+                Some(exports) => self.resolve_exports(&root, exports, &format!(".{}", subpath)),
+                None if subpath.is_empty() => self.load_directory(&root),
+                None => self.load(&join(&root, &format!(".{}", subpath))),
+            }?;
+            return Some(self.browser_file(&root, file));
+        }
+
+        None
+    }
+
+    /// The installed copy of a package to load instead of `root`: the
+    /// highest version the requiring package's declared range accepts
+    fn deduplicated(&self, from: &str, name: &str, root: String) -> String {
+        let Some(installed) = self.copies.as_ref().and_then(|copies| copies.get(name)) else {
+            return root;
+        };
+        let requirer = package_root(Path::new(from)).to_string_lossy().into_owned();
+        let range = self.manifest(&requirer).and_then(|manifest| {
+            ["dependencies", "peerDependencies", "optionalDependencies"].iter()
+                .find_map(|field| manifest.get(*field)?.get(name)?.as_str())
+        });
+        let Some(range) = range else { return root };
+
+        // Of copies at the same version, the first by path wins
+        let best = installed.iter()
+            .filter(|(_, version)| npm::range_matches(range, version))
+            .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)));
+        match best {
+            Some((copy, _)) if *copy != root => {
+                debug!("Deduplicating {} for {}: {} instead of {}", name, from, copy, root);
+                copy.clone()
+            }
+            _ => root,
+        }
+    }
+
+    /// Apply a package's browser field to one of its files
+    fn browser_file(&self, root: &str, file: String) -> Resolution {
+        let Some(map) = self.browser_map(root) else {
+            return Resolution::Module(PathBuf::from(file));
+        };
+
+        for (key, replacement) in map {
+            if !is_relative(key) || self.load(&join(root, key)).as_deref() != Some(file.as_str()) {
+                continue;
+            }
+            match replacement {
+                Value::Bool(false) => return Resolution::Empty,
+                Value::String(target) => {
+                    if let Some(target) = self.load(&join(root, target)) {
+                        return Resolution::Module(PathBuf::from(target));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Resolution::Module(PathBuf::from(file))
+    }
+
+    /// Object form of a package's `browser` field
+    fn browser_map(&self, root: &str) -> Option<&Map<String, Value>> {
+        self.manifest(root)?.get("browser")?.as_object()
+    }
+
+    fn manifest(&self, root: &str) -> Option<&Value> {
+        self.manifests.get(root)
+    }
+
+    /// Resolve a subpath through a package's `exports` field
+    fn resolve_exports(&self, root: &str, exports: &Value, subpath: &str) -> Option<String> {
+        let is_subpath_map = exports.as_object()
+            .is_some_and(|map| map.keys().any(|key| key.starts_with('.')));
+        if !is_subpath_map {
+            return (subpath == ".").then(|| self.resolve_target(root, exports, None)).flatten();
+        }
+
+        let map = exports.as_object()?;
+        if let Some(target) = map.get(subpath) {
+            return self.resolve_target(root, target, None);
+        }
+
+        // "./features/*": "./src/features/*.js", longest prefix first
+        let mut patterns: Vec<(&String, &Value)> = map.iter()
+            .filter(|(key, _)| key.contains('*') || key.ends_with('/'))
+            .collect();
+        patterns.sort_by_key(|(key, _)| std::cmp::Reverse(key.len()));
+
+        patterns.into_iter().find_map(|(key, target)| {
+            let (prefix, suffix) = key.split_once('*').unwrap_or((key.as_str(), ""));
+            let matched = subpath.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            self.resolve_target(root, target, Some(matched))
+        })
+    }
+
+    /// Resolve an `exports` target: a path, an array of fallbacks or an
+    /// object of conditions
+    fn resolve_target(&self, root: &str, target: &Value, pattern: Option<&str>) -> Option<String> {
+        match target {
+            Value::String(path) => {
+                let path = match pattern {
+                    Some(matched) if path.contains('*') => path.replace('*', matched),
+                    Some(matched) if path.ends_with('/') => format!("{}{}", path, matched),
+                    _ => path.clone(),
+                };
+                is_relative(&path).then(|| self.load(&join(root, &path))).flatten()
+            }
+            Value::Array(targets) => targets.iter()
+                .find_map(|target| self.resolve_target(root, target, pattern)),
+            Value::Object(conditions) => conditions.iter()
+                .filter(|(condition, _)| CONDITIONS.contains(&condition.as_str()))
+                .find_map(|(_, target)| self.resolve_target(root, target, pattern)),
+            _ => None,
+        }
+    }
+
+    /// Load a path as a file, then as a directory
+    fn load(&self, path: &str) -> Option<String> {
+        self.load_file(path).or_else(|| self.load_directory(path))
+    }
+
+    fn load_file(&self, path: &str) -> Option<String> {
+        if self.is_file(path) {
+            return Some(path.to_string());
+        }
+        EXTENSIONS.iter()
+            .map(|extension| format!("{}.{}", path, extension))
+            .find(|candidate| self.is_file(candidate))
+    }
+
+    /// Load a directory through its `package.json` entry fields, then its
+    /// index file
+    fn load_directory(&self, directory: &str) -> Option<String> {
+        if let Some(manifest) = self.manifest(directory) {
+            let entry = MAIN_FIELDS.iter()
+                .filter_map(|field| manifest.get(*field)?.as_str())
+                .find_map(|main| {
+                    let path = join(directory, main);
+                    self.load_file(&path).or_else(|| self.load_index(&path))
+                });
+            if entry.is_some() {
+                return entry;
+            }
+        }
+        self.load_index(directory)
+    }
+
+    fn load_index(&self, directory: &str) -> Option<String> {
+        self.load_file(&join(directory, "index"))
+    }
+
+    fn is_file(&self, path: &str) -> bool {
+        !path.is_empty() && self.files.contains_key(Path::new(path))
+    }
+}
+
+fn is_relative(specifier: &str) -> bool {
+    specifier == "." || specifier == ".."
+        || specifier.starts_with("./") || specifier.starts_with("../")
+}
+
+/// Directory part of a forward-slash path, empty at the package root
+fn parent(path: &str) -> &str {
+    path.rfind('/').map(|i| &path[..i]).unwrap_or("")
+}
+
+/// Join a relative path onto a directory, normalizing `.` and `..`
+fn join(directory: &str, relative: &str) -> String {
+    normalize_path(&format!("{}/{}", directory, relative))
+        .trim_start_matches('/')
+        .to_string()
+}
+
+/// `node_modules` directories searched from a directory upwards, nearest
+/// first
+fn node_modules_directories(directory: &str) -> Vec<String> {
+    let mut directories = Vec::new();
+    let mut current = directory;
+    loop {
+        if !(current == "node_modules" || current.ends_with("/node_modules")) {
+            directories.push(join(current, "node_modules"));
+        }
+        if current.is_empty() {
+            break;
+        }
+        current = parent(current);
+    }
+    directories
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(entries: &[(&str, &str)]) -> HashMap<PathBuf, FileContent> {
+        entries.iter()
+            .map(|(path, content)| (PathBuf::from(path), FileContent::Text(content.to_string())))
+            .collect()
+    }
+
+    fn module(path: &str) -> Option<Resolution> {
+        Some(Resolution::Module(PathBuf::from(path)))
+    }
+
+    #[test]
+    fn test_package_name() {
+        assert_eq!(package_name("lodash/map"), Some("lodash"));
+        assert_eq!(package_name("@scope/pkg/lib/x"), Some("@scope/pkg"));
+        assert_eq!(package_name("./local"), None);
+    }
+
+    #[test]
+    fn test_relative_resolution() {
+        let files = files(&[
+            ("package.json", r#"{"name": "app", "main": "lib/index"}"#),
+            ("lib/index.js", ""),
+            ("lib/util.js", ""),
+            ("lib/data.json", "{}"),
+            ("lib/helpers/index.js", ""),
+        ]);
+        let resolver = Resolver::new(&files);
+        let from = Path::new("lib/index.js");
+
+        assert_eq!(resolver.entry(), Some(PathBuf::from("lib/index.js")));
+        assert_eq!(resolver.resolve(from, "./util"), module("lib/util.js"));
+        assert_eq!(resolver.resolve(from, "./data"), module("lib/data.json"));
+        assert_eq!(resolver.resolve(from, "./helpers"), module("lib/helpers/index.js"));
+        assert_eq!(resolver.resolve(Path::new("lib/helpers/index.js"), ".."), module("lib/index.js"));
+        assert_eq!(resolver.resolve(from, "./missing"), None);
+    }
+
+    #[test]
+    fn test_node_modules_resolution() {
+        let files = files(&[
+            ("index.js", ""),
+            ("node_modules/a/package.json", r#"{"main": "./main.js"}"#),
+            ("node_modules/a/main.js", ""),
+            ("node_modules/a/extra.js", ""),
+            ("node_modules/b/index.js", ""),
+            ("node_modules/b/node_modules/a/index.js", ""),
+            ("node_modules/@scope/c/index.js", ""),
+        ]);
+        let resolver = Resolver::new(&files);
+
+        assert_eq!(resolver.resolve(Path::new("index.js"), "a"), module("node_modules/a/main.js"));
+        assert_eq!(resolver.resolve(Path::new("index.js"), "a/extra"), module("node_modules/a/extra.js"));
+        assert_eq!(resolver.resolve(Path::new("node_modules/b/index.js"), "a"), module("node_modules/b/node_modules/a/index.js"));
+        assert_eq!(resolver.resolve(Path::new("node_modules/a/main.js"), "@scope/c"), module("node_modules/@scope/c/index.js"));
+        assert_eq!(resolver.resolve(Path::new("index.js"), "path"), Some(Resolution::Builtin("path".to_string())));
+        assert_eq!(resolver.resolve(Path::new("index.js"), "node:fs/promises"), Some(Resolution::Builtin("fs/promises".to_string())));
+        assert_eq!(resolver.resolve(Path::new("index.js"), "missing"), None);
+    }
+
+    #[test]
+    fn test_exports_conditions() {
+        let files = files(&[
+            ("index.js", ""),
+            ("node_modules/x/package.json", r#"{
+                "main": "./legacy.js",
+                "exports": {
+                    ".": { "node": "./node.js", "browser": "./browser.js", "default": "./index.js" },
+                    "./feature": [{ "require": "./feature.cjs" }, "./feature.js"],
+                    "./utils/*": "./src/utils/*.js"
+                }
+            }"#),
+            ("node_modules/x/node.js", ""),
+            ("node_modules/x/browser.js", ""),
+            ("node_modules/x/index.js", ""),
+            ("node_modules/x/legacy.js", ""),
+            ("node_modules/x/feature.cjs", ""),
+            ("node_modules/x/src/utils/format.js", ""),
+        ]);
+        let resolver = Resolver::new(&files);
+        let from = Path::new("index.js");
+
+        assert_eq!(resolver.resolve(from, "x"), module("node_modules/x/browser.js"));
+        assert_eq!(resolver.resolve(from, "x/feature"), module("node_modules/x/feature.cjs"));
+        assert_eq!(resolver.resolve(from, "x/utils/format"), module("node_modules/x/src/utils/format.js"));
+        assert_eq!(resolver.resolve(from, "x/legacy.js"), None);
+    }
+
+    #[test]
+    fn test_browser_field() {
+        let files = files(&[
+            ("package.json", r#"{"main": "index.js", "browser": {"./lib/node.js": "./lib/web.js", "ws": false, "./lib/fs.js": false}}"#),
+            ("index.js", ""),
+            ("lib/node.js", ""),
+            ("lib/web.js", ""),
+            ("lib/fs.js", ""),
+            ("node_modules/y/package.json", r#"{"main": "server.js", "browser": "client.js"}"#),
+            ("node_modules/y/server.js", ""),
+            ("node_modules/y/client.js", ""),
+        ]);
+        let resolver = Resolver::new(&files);
+        let from = Path::new("index.js");
+
+        assert_eq!(resolver.resolve(from, "./lib/node"), module("lib/web.js"));
+        assert_eq!(resolver.resolve(from, "./lib/fs.js"), Some(Resolution::Empty));
+        assert_eq!(resolver.resolve(from, "ws"), Some(Resolution::Empty));
+        assert_eq!(resolver.resolve(from, "y"), module("node_modules/y/client.js"));
+    }
+
+    #[test]
+    fn test_package_root() {
+        assert_eq!(package_name_of(Path::new("lib/a.js")), None);
+        assert_eq!(package_name_of(Path::new("node_modules/a/node_modules/@s/b/c.js")).as_deref(), Some("@s/b"));
+        assert_eq!(package_root(Path::new("lib/a.js")), PathBuf::new());
+        assert_eq!(package_root(Path::new("node_modules/a/lib/b.js")), PathBuf::from("node_modules/a"));
+        assert_eq!(
+            package_root(Path::new("node_modules/a/node_modules/@s/b/c.js")),
+            PathBuf::from("node_modules/a/node_modules/@s/b")
+        );
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let files = files(&[
+            ("package.json", r#"{"main": "index.js", "dependencies": {"tslib": "^2.0.0"}}"#),
+            ("index.js", ""),
+            ("node_modules/tslib/package.json", r#"{"version": "2.1.0"}"#),
+            ("node_modules/tslib/tslib.js", ""),
+            ("node_modules/a/package.json", r#"{"version": "1.0.0", "dependencies": {"tslib": "^2.4.0"}}"#),
+            ("node_modules/a/node_modules/tslib/package.json", r#"{"version": "2.6.2"}"#),
+            ("node_modules/a/node_modules/tslib/tslib.js", ""),
+            ("node_modules/b/package.json", r#"{"version": "1.0.0", "dependencies": {"tslib": "^1.9.0"}}"#),
+            ("node_modules/b/node_modules/tslib/package.json", r#"{"version": "1.14.1"}"#),
+            ("node_modules/b/node_modules/tslib/tslib.js", ""),
+        ]);
+
+        let resolver = Resolver::new(&files);
+        assert_eq!(resolver.resolve(Path::new("index.js"), "tslib/tslib.js"), module("node_modules/tslib/tslib.js"));
+
+        let resolver = Resolver::new(&files).with_dedupe(true);
+        // ^2.0.0 takes the newer nested copy, ^1.9.0 keeps its own
+        assert_eq!(resolver.resolve(Path::new("index.js"), "tslib/tslib.js"), module("node_modules/a/node_modules/tslib/tslib.js"));
+        assert_eq!(resolver.resolve(Path::new("node_modules/a/index.js"), "tslib/tslib.js"), module("node_modules/a/node_modules/tslib/tslib.js"));
+        assert_eq!(resolver.resolve(Path::new("node_modules/b/index.js"), "tslib/tslib.js"), module("node_modules/b/node_modules/tslib/tslib.js"));
+    }
+}
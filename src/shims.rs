@@ -0,0 +1,290 @@
+//! Node module-scope shims
+//!
+//! Node gives every module `__dirname`, `__filename`, `global`,
+//! `setImmediate` and `process.nextTick` without an import. Browsers have
+//! none of them, so each module that references one gets a small shim
+//! injected at its top. Only the shims a module actually uses are added.
+
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+use anyhow::Result;
+use swc_common::Spanned;
+use swc_ecma_ast::*;
+use swc_ecma_parser::Syntax;
+use swc_ecma_visit::{Visit, VisitWith};
+
+use crate::js::{self, SourceEdits};
+
+/// Identifier that replaces `process.nextTick` in shimmed modules
+const NEXT_TICK_NAME: &str = "__paktoNextTick";
+
+/// Module-scope shims that can be injected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Shim {
+    Global,
+    Dirname,
+    Filename,
+    SetImmediate,
+    NextTick,
+}
+
+impl Shim {
+    /// Name of the Node global the shim stands in for
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Global => "global",
+            Self::Dirname => "__dirname",
+            Self::Filename => "__filename",
+            Self::SetImmediate => "setImmediate",
+            Self::NextTick => "process.nextTick",
+        }
+    }
+
+    fn needs_microtask(&self) -> bool {
+        matches!(self, Self::SetImmediate | Self::NextTick)
+    }
+
+    /// Declaration injected at the top of the module. `__dirname` and
+    /// `__filename` are inlined as string literals instead, so they stay
+    /// correct when several modules share one scope.
+    fn prologue(&self) -> Option<String> {
+        match self {
+            Self::Global => Some(
+                "var global = typeof globalThis !== 'undefined' ? globalThis : \
+                 typeof window !== 'undefined' ? window : \
+                 typeof self !== 'undefined' ? self : {};".to_string(),
+            ),
+            Self::SetImmediate => Some(
+                "var setImmediate = function (fn) { \
+                 __paktoMicrotask(fn, Array.prototype.slice.call(arguments, 1)); };".to_string(),
+            ),
+            Self::NextTick => Some(format!(
+                "var {} = function (fn) {{ \
+                 __paktoMicrotask(fn, Array.prototype.slice.call(arguments, 1)); }};",
+                NEXT_TICK_NAME
+            )),
+            Self::Dirname | Self::Filename => None,
+        }
+    }
+}
+
+/// Queue a callback as a microtask, falling back to a timer where promises
+/// are unavailable
+const MICROTASK_HELPER: &str = "function __paktoMicrotask(fn, args) { \
+    var run = function () { fn.apply(null, args); }; \
+    if (typeof Promise === 'function') { Promise.resolve().then(run); } else { setTimeout(run, 0); } }";
+
+/// A module with its shims applied
+#[derive(Debug)]
+pub struct ShimmedModule {
+    pub code: String,
+    pub shims: Vec<Shim>,
+}
+
+/// Inject the shims a module references. `module_path` is the virtual path
+/// reported through `__filename`, and its parent through `__dirname`.
+pub fn inject_module_shims(code: &str, syntax: Syntax, module_path: &Path) -> Result<ShimmedModule> {
+    let candidates = ["global", "__dirname", "__filename", "setImmediate", "nextTick"];
+    if !candidates.iter().any(|name| code.contains(name)) {
+        return Ok(ShimmedModule { code: code.to_string(), shims: Vec::new() });
+    }
+
+    let program = js::parse_program(code, syntax)?;
+
+    let filename = virtual_path(module_path);
+    let dirname = module_path.parent()
+        .map(virtual_path)
+        .filter(|dir| !dir.is_empty())
+        .unwrap_or_else(|| "/".to_string());
+
+    let mut finder = ShimFinder {
+        declared: js::declared_names(&program),
+        filename: serde_json::to_string(&filename)?,
+        dirname: serde_json::to_string(&dirname)?,
+        shims: BTreeSet::new(),
+        edits: SourceEdits::new(),
+    };
+    program.visit_with(&mut finder);
+
+    if finder.shims.is_empty() {
+        return Ok(ShimmedModule { code: code.to_string(), shims: Vec::new() });
+    }
+
+    let mut prologue = String::new();
+    if finder.shims.iter().any(Shim::needs_microtask) {
+        prologue.push_str(MICROTASK_HELPER);
+        prologue.push('\n');
+    }
+    for shim in &finder.shims {
+        if let Some(declaration) = shim.prologue() {
+            prologue.push_str(&declaration);
+            prologue.push('\n');
+        }
+    }
+
+    if !prologue.is_empty() {
+        // Keep directives such as 'use strict' first
+        let insert_at = directive_end(&program).unwrap_or(program.span().lo);
+        let separator = if insert_at == program.span().lo { "" } else { "\n" };
+        finder.edits.replace_between(insert_at, insert_at, format!("{}{}", separator, prologue));
+    }
+
+    Ok(ShimmedModule {
+        code: finder.edits.apply(code),
+        shims: finder.shims.into_iter().collect(),
+    })
+}
+
+/// Path as seen by the module, always absolute with forward slashes
+fn virtual_path(path: &Path) -> String {
+    let joined = path.components()
+        .filter_map(|c| match c {
+            std::path::Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+
+    if joined.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", joined)
+    }
+}
+
+/// Position right after the last leading directive of a program
+fn directive_end(program: &Program) -> Option<swc_common::BytePos> {
+    let is_directive = |stmt: &Stmt| matches!(
+        stmt,
+        Stmt::Expr(ExprStmt { expr, .. }) if matches!(expr.as_ref(), Expr::Lit(Lit::Str(_)))
+    );
+
+    match program {
+        Program::Script(script) => script.body.iter()
+            .take_while(|stmt| is_directive(stmt))
+            .last()
+            .map(|stmt| stmt.span().hi),
+        Program::Module(module) => module.body.iter()
+            .take_while(|item| matches!(item, ModuleItem::Stmt(stmt) if is_directive(stmt)))
+            .last()
+            .map(|item| item.span().hi),
+    }
+}
+
+/// Visitor that finds free references to Node module-scope globals
+struct ShimFinder {
+    declared: HashSet<String>,
+    filename: String,
+    dirname: String,
+    shims: BTreeSet<Shim>,
+    edits: SourceEdits,
+}
+
+impl ShimFinder {
+    fn free_shim(&self, ident: &Ident) -> Option<Shim> {
+        let shim = match &*ident.sym {
+            "global" => Shim::Global,
+            "__dirname" => Shim::Dirname,
+            "__filename" => Shim::Filename,
+            "setImmediate" => Shim::SetImmediate,
+            _ => return None,
+        };
+        (!self.declared.contains(&*ident.sym)).then_some(shim)
+    }
+
+    fn inline_value(&self, shim: Shim) -> Option<&str> {
+        match shim {
+            Shim::Dirname => Some(&self.dirname),
+            Shim::Filename => Some(&self.filename),
+            _ => None,
+        }
+    }
+
+    fn is_next_tick(&self, member: &MemberExpr) -> bool {
+        matches!(member.obj.as_ref(), Expr::Ident(obj) if obj.sym == *"process")
+            && matches!(&member.prop, MemberProp::Ident(prop) if prop.sym == *"nextTick")
+            && !self.declared.contains("process")
+    }
+}
+
+impl Visit for ShimFinder {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Ident(ident) => {
+                if let Some(shim) = self.free_shim(ident) {
+                    if let Some(value) = self.inline_value(shim) {
+                        self.edits.replace(ident.span, value.to_string());
+                    }
+                    self.shims.insert(shim);
+                }
+            }
+            Expr::Member(member) if self.is_next_tick(member) => {
+                self.edits.replace(member.span, NEXT_TICK_NAME);
+                self.shims.insert(Shim::NextTick);
+            }
+            _ => expr.visit_children_with(self),
+        }
+    }
+
+    fn visit_prop(&mut self, prop: &Prop) {
+        if let Prop::Shorthand(ident) = prop {
+            if let Some(shim) = self.free_shim(ident) {
+                if let Some(value) = self.inline_value(shim) {
+                    self.edits.replace(ident.span, format!("{}: {}", ident.sym, value));
+                }
+                self.shims.insert(shim);
+                return;
+            }
+        }
+        prop.visit_children_with(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shim(code: &str, path: &str) -> ShimmedModule {
+        inject_module_shims(code, js::syntax_for_path(Path::new("test.js")), Path::new(path)).unwrap()
+    }
+
+    #[test]
+    fn test_no_shims_for_plain_code() {
+        let result = shim("var a = 1;", "index.js");
+        assert!(result.shims.is_empty());
+        assert_eq!(result.code, "var a = 1;");
+    }
+
+    #[test]
+    fn test_inlines_module_paths() {
+        let result = shim("var p = __dirname + '/data'; var f = { __filename };", "node_modules/pkg/lib/a.js");
+        assert!(result.code.contains("var p = \"/node_modules/pkg/lib\" + '/data';"));
+        assert!(result.code.contains("{ __filename: \"/node_modules/pkg/lib/a.js\" }"));
+        assert_eq!(result.shims, vec![Shim::Dirname, Shim::Filename]);
+    }
+
+    #[test]
+    fn test_injects_only_referenced_shims() {
+        let result = shim("'use strict';\nglobal.x = 1;", "index.js");
+        assert_eq!(result.shims, vec![Shim::Global]);
+        assert!(result.code.starts_with("'use strict';\nvar global = "));
+        assert!(!result.code.contains("setImmediate"));
+        assert!(!result.code.contains("__paktoMicrotask"));
+    }
+
+    #[test]
+    fn test_next_tick_uses_microtask() {
+        let result = shim("process.nextTick(done); setImmediate(done);", "index.js");
+        assert_eq!(result.shims, vec![Shim::SetImmediate, Shim::NextTick]);
+        assert!(result.code.contains("__paktoNextTick(done);"));
+        assert!(result.code.contains("function __paktoMicrotask"));
+        assert!(result.code.contains("var setImmediate = function (fn) { __paktoMicrotask("));
+        assert!(js::parse_program(&result.code, js::syntax_for_path(Path::new("test.js"))).is_ok());
+    }
+
+    #[test]
+    fn test_local_declarations_are_not_shimmed() {
+        let result = shim("function f(global) { return global; }", "index.js");
+        assert!(result.shims.is_empty());
+    }
+}
@@ -0,0 +1,332 @@
+//! Throwing stand-ins for Node.js modules that cannot run in OutSystems
+//!
+//! Requiring or importing an incompatible module such as `fs` is replaced
+//! with an inert object built by the `unavailable` polyfill. Its methods
+//! throw a descriptive error, so the bundle stays valid and only fails if
+//! the Node-only code path actually runs.
+//!
+//! Optional requires are different: code such as
+//! `try { require('fsevents') } catch (e) {}` expects the require itself to
+//! fail. Those requires, requires behind the environment checks the
+//! analyzer treats as guards, and any require of an `optionalDependencies`
+//! entry, are replaced with a call that throws `MODULE_NOT_FOUND` right
+//! away, so the package takes its fallback path.
+
+use std::collections::HashSet;
+use anyhow::Result;
+use swc_ecma_ast::*;
+use swc_ecma_parser::Syntax;
+use swc_ecma_visit::{Visit, VisitWith};
+
+use crate::analyzer::{is_environment_check, NodeApiRegistry};
+use crate::js::{self, SourceEdits};
+use crate::resolver::package_name;
+
+/// Polyfill providing the stand-in module factory
+pub const UNAVAILABLE_POLYFILL: &str = "unavailable";
+
+/// Dependencies a package declares in `package.json`
+#[derive(Debug, Default)]
+pub(crate) struct DeclaredDependencies {
+    /// Regular and peer dependencies, expected to be available
+    available: HashSet<String>,
+    /// Optional dependencies, never bundled
+    optional: HashSet<String>,
+}
+
+impl DeclaredDependencies {
+    pub(crate) fn from_package_json(package_json: &serde_json::Value) -> Self {
+        let names = |field: &str| -> HashSet<String> {
+            package_json.get(field)
+                .and_then(|v| v.as_object())
+                .map(|obj| obj.keys().cloned().collect())
+                .unwrap_or_default()
+        };
+
+        let optional = names("optionalDependencies");
+        let available = names("dependencies").into_iter()
+            .chain(names("peerDependencies"))
+            .filter(|name| !optional.contains(name))
+            .collect();
+
+        Self { available, optional }
+    }
+}
+
+/// A module with its incompatible imports replaced
+#[derive(Debug)]
+pub struct StubbedModule {
+    pub code: String,
+    /// Incompatible modules that were replaced
+    pub modules: Vec<String>,
+    /// Optional modules whose requires now throw
+    pub missing: Vec<String>,
+}
+
+/// Replace `require()` calls and imports of incompatible Node.js modules
+/// with throwing stand-ins, and optional requires with calls that throw
+pub(crate) fn stub_incompatible_modules(
+    code: &str,
+    syntax: Syntax,
+    registry: &NodeApiRegistry,
+    dependencies: &DeclaredDependencies,
+) -> Result<StubbedModule> {
+    let program = js::parse_program(code, syntax)?;
+
+    let mut stubber = ModuleStubber {
+        registry,
+        dependencies,
+        try_depth: 0,
+        environment_guard_depth: 0,
+        edits: SourceEdits::new(),
+        modules: Vec::new(),
+        missing: Vec::new(),
+    };
+    program.visit_with(&mut stubber);
+
+    stubber.modules.sort();
+    stubber.modules.dedup();
+    stubber.missing.sort();
+    stubber.missing.dedup();
+
+    Ok(StubbedModule {
+        code: stubber.edits.apply(code),
+        modules: stubber.modules,
+        missing: stubber.missing,
+    })
+}
+
+/// Visitor that rewrites references to incompatible modules
+struct ModuleStubber<'a> {
+    registry: &'a NodeApiRegistry,
+    dependencies: &'a DeclaredDependencies,
+    /// Number of `try` blocks around the current node
+    try_depth: usize,
+    /// Number of environment checks guarding the current node
+    environment_guard_depth: usize,
+    edits: SourceEdits,
+    modules: Vec<String>,
+    missing: Vec<String>,
+}
+
+impl ModuleStubber<'_> {
+    /// Registry name of an incompatible module specifier, accepting the
+    /// `node:` prefix
+    fn incompatible_module(&self, specifier: &str) -> Option<String> {
+        let name = specifier.strip_prefix("node:").unwrap_or(specifier);
+        self.registry.is_incompatible(name).then(|| name.to_string())
+    }
+
+    /// Whether a require should fail at require time: the module is an
+    /// optional dependency, or a require guarded by try/catch or an
+    /// environment check of a module that will not be in the bundle
+    fn is_missing(&self, specifier: &str) -> bool {
+        let name = specifier.strip_prefix("node:").unwrap_or(specifier);
+        let Some(package) = package_name(name) else { return false };
+
+        if self.dependencies.optional.contains(package) {
+            return true;
+        }
+        if self.try_depth == 0 && self.environment_guard_depth == 0 {
+            return false;
+        }
+        if self.registry.is_node_api(package) {
+            return self.registry.is_incompatible(package);
+        }
+        !self.dependencies.available.contains(package)
+    }
+
+    /// Visit a node guarded by an environment check
+    fn visit_guarded<N: for<'n> VisitWith<ModuleStubber<'n>>>(&mut self, node: &N) {
+        self.environment_guard_depth += 1;
+        node.visit_with(self);
+        self.environment_guard_depth -= 1;
+    }
+
+    /// Expression that throws when the module is required
+    fn missing_expression(&mut self, specifier: &str) -> String {
+        self.missing.push(specifier.to_string());
+        format!("MissingModulePolyfill({})", serde_json::Value::String(specifier.to_string()))
+    }
+
+    /// Expression that builds the stand-in for a module
+    fn stub_expression(&mut self, module: &str) -> String {
+        self.modules.push(module.to_string());
+        let methods = self.registry.unavailable_methods(module);
+        format!(
+            "UnavailableModulePolyfill({}, {})",
+            serde_json::Value::String(module.to_string()),
+            serde_json::to_string(methods).unwrap_or_else(|_| "[]".to_string())
+        )
+    }
+}
+
+impl Visit for ModuleStubber<'_> {
+    fn visit_call_expr(&mut self, call: &CallExpr) {
+        if let Callee::Expr(callee) = &call.callee {
+            if matches!(callee.as_ref(), Expr::Ident(ident) if ident.sym == *"require") {
+                if let Some(Expr::Lit(Lit::Str(s))) = call.args.first().map(|arg| arg.expr.as_ref()) {
+                    if self.is_missing(&s.value) {
+                        let missing = self.missing_expression(&s.value);
+                        self.edits.replace(call.span, missing);
+                        return;
+                    }
+                    if let Some(module) = self.incompatible_module(&s.value) {
+                        let stub = self.stub_expression(&module);
+                        self.edits.replace(call.span, stub);
+                        return;
+                    }
+                }
+            }
+        }
+        call.visit_children_with(self);
+    }
+
+    fn visit_try_stmt(&mut self, stmt: &TryStmt) {
+        self.try_depth += 1;
+        stmt.block.visit_with(self);
+        self.try_depth -= 1;
+
+        stmt.handler.visit_with(self);
+        stmt.finalizer.visit_with(self);
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &IfStmt) {
+        let guarded = is_environment_check(&stmt.test);
+        if guarded {
+            self.environment_guard_depth += 1;
+        }
+        stmt.visit_children_with(self);
+        if guarded {
+            self.environment_guard_depth -= 1;
+        }
+    }
+
+    fn visit_cond_expr(&mut self, cond: &CondExpr) {
+        if is_environment_check(&cond.test) {
+            self.visit_guarded(&cond.cons);
+            self.visit_guarded(&cond.alt);
+            return;
+        }
+        cond.visit_children_with(self);
+    }
+
+    fn visit_bin_expr(&mut self, bin: &BinExpr) {
+        // typeof require === 'function' && require('x')
+        if matches!(bin.op, BinaryOp::LogicalAnd | BinaryOp::LogicalOr) && is_environment_check(&bin.left) {
+            bin.left.visit_with(self);
+            self.visit_guarded(&bin.right);
+            return;
+        }
+        bin.visit_children_with(self);
+    }
+
+    fn visit_import_decl(&mut self, import: &ImportDecl) {
+        if import.type_only {
+            return;
+        }
+        let stub = if self.is_missing(&import.src.value) {
+            self.missing_expression(&import.src.value)
+        } else {
+            let Some(module) = self.incompatible_module(&import.src.value) else { return };
+            self.stub_expression(&module)
+        };
+
+        let declarations: Vec<String> = import.specifiers.iter()
+            .map(|specifier| match specifier {
+                ImportSpecifier::Default(s) => format!("var {} = {};", s.local.sym, stub),
+                ImportSpecifier::Namespace(s) => format!("var {} = {};", s.local.sym, stub),
+                ImportSpecifier::Named(s) => {
+                    let imported = match &s.imported {
+                        Some(ModuleExportName::Ident(ident)) => ident.sym.to_string(),
+                        Some(ModuleExportName::Str(name)) => name.value.to_string(),
+                        None => s.local.sym.to_string(),
+                    };
+                    format!(
+                        "var {} = {}[{}];",
+                        s.local.sym,
+                        stub,
+                        serde_json::Value::String(imported)
+                    )
+                }
+            })
+            .collect();
+
+        self.edits.replace(import.span, declarations.join(" "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn stub(code: &str) -> StubbedModule {
+        stub_with(code, &DeclaredDependencies::default())
+    }
+
+    fn stub_with(code: &str, dependencies: &DeclaredDependencies) -> StubbedModule {
+        let syntax = js::syntax_for_path(Path::new("index.js"));
+        stub_incompatible_modules(code, syntax, &NodeApiRegistry::new(), dependencies).unwrap()
+    }
+
+    #[test]
+    fn test_stubs_require() {
+        let code = "var fs = require('fs');\nvar crypto = require('crypto');";
+        let result = stub(code);
+        assert_eq!(result.modules, vec!["fs".to_string()]);
+        assert!(result.code.starts_with("var fs = UnavailableModulePolyfill(\"fs\", ["));
+        assert!(result.code.contains("\"readFile\""));
+        assert!(result.code.contains("require('crypto')"));
+    }
+
+    #[test]
+    fn test_multiline_usage_stays_valid() {
+        let code = "var out = require('child_process')\n  .execSync('ls', {\n    encoding: 'utf8'\n  });";
+        let result = stub(code);
+        assert_eq!(result.modules, vec!["child_process".to_string()]);
+        assert!(js::parse_program(&result.code, js::syntax_for_path(Path::new("index.js"))).is_ok());
+    }
+
+    #[test]
+    fn test_stubs_imports() {
+        let code = "import os from 'node:os';\nimport { readFileSync as read } from 'fs';\nimport 'net';";
+        let result = stub(code);
+        assert_eq!(result.modules, vec!["fs".to_string(), "net".to_string(), "os".to_string()]);
+        assert!(result.code.contains("var os = UnavailableModulePolyfill(\"os\""));
+        assert!(result.code.contains("var read = UnavailableModulePolyfill(\"fs\""));
+        assert!(result.code.contains(")[\"readFileSync\"];"));
+        assert!(!result.code.contains("import"));
+    }
+
+    #[test]
+    fn test_guarded_requires_throw() {
+        let package_json = serde_json::json!({
+            "dependencies": { "debug": "^4.0.0" },
+            "optionalDependencies": { "fsevents": "^2.0.0" }
+        });
+        let dependencies = DeclaredDependencies::from_package_json(&package_json);
+        let code = "var events = require('fsevents');\n\
+                    try { var fs = require('fs'); var enc = require('encoding'); var debug = require('debug'); } catch (e) {}\n\
+                    var cp = require('child_process');";
+        let result = stub_with(code, &dependencies);
+
+        assert_eq!(result.missing, vec!["encoding".to_string(), "fs".to_string(), "fsevents".to_string()]);
+        assert_eq!(result.modules, vec!["child_process".to_string()]);
+        assert!(result.code.contains("var events = MissingModulePolyfill(\"fsevents\");"));
+        assert!(result.code.contains("var fs = MissingModulePolyfill(\"fs\");"));
+        assert!(result.code.contains("require('debug')"));
+    }
+
+    #[test]
+    fn test_environment_guarded_requires_throw() {
+        let code = "if (typeof window === 'undefined') require('fs');\n\
+                    var os = typeof require === 'function' && require('os');\n\
+                    var net = require('net');";
+        let result = stub(code);
+
+        assert_eq!(result.missing, vec!["fs".to_string(), "os".to_string()]);
+        assert_eq!(result.modules, vec!["net".to_string()]);
+        assert!(result.code.starts_with("if (typeof window === 'undefined') MissingModulePolyfill(\"fs\");"));
+    }
+}
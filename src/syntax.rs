@@ -0,0 +1,389 @@
+//! Syntax validation of generated scripts
+//!
+//! Bundles and chunks are parsed as classic scripts, the way a browser
+//! loads them, or as modules for the ESM format, and walked for syntax newer than the ECMAScript target:
+//! an ES5 bundle with an arrow function parses fine but fails on the
+//! browsers the target was chosen for. Problems are reported with their
+//! line and column and a frame of the surrounding code.
+
+use std::path::Path;
+use swc_common::{BytePos, Span, Spanned};
+use swc_ecma_ast::{
+    ArrowExpr, AssignExpr, AssignOp, AwaitExpr, BinExpr, BinaryOp, CatchClause, Class, ClassMember, EsVersion,
+    ExprOrSpread, ForOfStmt, Function, Lit, MetaPropExpr, MetaPropKind, ObjectPatProp, OptChainExpr, Pat, Prop, PropName,
+    Program, PropOrSpread, TaggedTpl, Tpl, VarDecl, VarDeclKind, YieldExpr,
+};
+use swc_ecma_parser::{lexer::Lexer, EsConfig, Parser, StringInput, Syntax};
+use swc_ecma_visit::{Visit, VisitWith};
+
+use crate::cli::EsTarget;
+use crate::errors::{PaktoError, Result as PaktoResult};
+use crate::js;
+
+/// Characters of a long line shown on each side of the reported column
+const FRAME_CONTEXT: usize = 40;
+
+/// Parse code as a script and check it only uses syntax `target` supports
+pub fn check_script(code: &str, target: &EsTarget, file: &Path) -> PaktoResult<()> {
+    check(code, target, file, false)
+}
+
+/// Parse code as a module and check it only uses syntax `target`
+/// supports. `import` and `export` declarations are allowed whatever the
+/// target, as whatever loads the module supports them.
+pub fn check_module(code: &str, target: &EsTarget, file: &Path) -> PaktoResult<()> {
+    check(code, target, file, true)
+}
+
+fn check(code: &str, target: &EsTarget, file: &Path, module: bool) -> PaktoResult<()> {
+    let lexer = Lexer::new(
+        Syntax::Es(EsConfig::default()),
+        EsVersion::latest(),
+        StringInput::new(code, BytePos(1), BytePos(1 + code.len() as u32)),
+        None,
+    );
+    let mut parser = Parser::new_from(lexer);
+    let program = if module {
+        parser.parse_module().map(Program::Module)
+    } else {
+        parser.parse_script().map(Program::Script)
+    };
+    let program = match (program, parser.take_errors().into_iter().next()) {
+        (Err(error), _) | (Ok(_), Some(error)) => {
+            return Err(syntax_error(code, file, error.span().lo, error.kind().msg().as_ref()));
+        }
+        (Ok(program), None) => program,
+    };
+
+    let mut features = Features { target: level(target), found: None };
+    program.visit_with(&mut features);
+    match features.found {
+        Some((span, feature, required)) => Err(syntax_error(
+            code,
+            file,
+            span.lo,
+            &format!("{} requires {} but the target is {}", feature, name(required), name(level(target))),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Source lines around a position, marking the line and column
+pub fn code_frame(code: &str, line: usize, column: usize) -> String {
+    let lines: Vec<&str> = code.lines().collect();
+    let first = line.saturating_sub(3);
+    let last = (line + 2).min(lines.len());
+    let width = last.to_string().len();
+
+    let mut frame = String::new();
+    for (index, text) in lines.iter().enumerate().take(last).skip(first) {
+        let number = index + 1;
+        let marker = if number == line { ">" } else { " " };
+        // Minified bundles are one long line; show the part that matters
+        let chars: Vec<char> = text.chars().collect();
+        let start = if number == line { column.saturating_sub(FRAME_CONTEXT + 1) } else { 0 };
+        let end = (start + 2 * FRAME_CONTEXT).min(chars.len());
+        let mut shown: String = chars[start.min(end)..end].iter().collect();
+        if start > 0 {
+            shown.insert(0, '…');
+        }
+        if end < chars.len() {
+            shown.push('…');
+        }
+        frame.push_str(&format!("{} {:>width$} | {}\n", marker, number, shown, width = width));
+
+        if number == line {
+            let offset = column - 1 - start + usize::from(start > 0);
+            frame.push_str(&format!("  {:>width$} | {}^\n", "", " ".repeat(offset), width = width));
+        }
+    }
+    frame
+}
+
+fn syntax_error(code: &str, file: &Path, pos: BytePos, message: &str) -> PaktoError {
+    let (line, column) = js::line_column(code, pos);
+    PaktoError::ParseError {
+        file: file.to_path_buf(),
+        message: format!("{} at {}:{}\n{}", message, line, column, code_frame(code, line, column)),
+        source: None,
+    }
+}
+
+/// Rank of a target; syntax is allowed up to the target's rank. ES2019
+/// additions need ES2020, the next target after ES2018.
+fn level(target: &EsTarget) -> u8 {
+    match target {
+        EsTarget::Es5 => 0,
+        EsTarget::Es2015 => 1,
+        EsTarget::Es2017 => 2,
+        EsTarget::Es2018 => 3,
+        EsTarget::Es2020 => 4,
+        EsTarget::EsNext => 5,
+    }
+}
+
+fn name(level: u8) -> &'static str {
+    match level {
+        0 => "ES5",
+        1 => "ES2015",
+        2 => "ES2017",
+        3 => "ES2018",
+        4 => "ES2020",
+        _ => "ESNext",
+    }
+}
+
+const ES2015: u8 = 1;
+const ES2017: u8 = 2;
+const ES2018: u8 = 3;
+const ES2020: u8 = 4;
+const ESNEXT: u8 = 5;
+
+/// Finds the first syntax a script uses above the target
+struct Features {
+    target: u8,
+    found: Option<(Span, &'static str, u8)>,
+}
+
+impl Features {
+    fn require(&mut self, span: Span, feature: &'static str, level: u8) {
+        if level > self.target && self.found.is_none_or(|(found, _, _)| span.lo < found.lo) {
+            self.found = Some((span, feature, level));
+        }
+    }
+}
+
+impl Visit for Features {
+    fn visit_arrow_expr(&mut self, arrow: &ArrowExpr) {
+        self.require(arrow.span, "An arrow function", ES2015);
+        if arrow.is_async {
+            self.require(arrow.span, "An async function", ES2017);
+        }
+        arrow.visit_children_with(self);
+    }
+
+    fn visit_class(&mut self, class: &Class) {
+        self.require(class.span, "A class", ES2015);
+        class.visit_children_with(self);
+    }
+
+    fn visit_class_member(&mut self, member: &ClassMember) {
+        match member {
+            ClassMember::ClassProp(prop) => self.require(prop.span, "A class field", ESNEXT),
+            ClassMember::PrivateProp(prop) => self.require(prop.span, "A private class field", ESNEXT),
+            ClassMember::PrivateMethod(method) => self.require(method.span, "A private method", ESNEXT),
+            ClassMember::StaticBlock(block) => self.require(block.span, "A static block", ESNEXT),
+            _ => {}
+        }
+        member.visit_children_with(self);
+    }
+
+    fn visit_var_decl(&mut self, decl: &VarDecl) {
+        match decl.kind {
+            VarDeclKind::Let => self.require(decl.span, "`let`", ES2015),
+            VarDeclKind::Const => self.require(decl.span, "`const`", ES2015),
+            VarDeclKind::Var => {}
+        }
+        decl.visit_children_with(self);
+    }
+
+    fn visit_tpl(&mut self, tpl: &Tpl) {
+        self.require(tpl.span, "A template literal", ES2015);
+        tpl.visit_children_with(self);
+    }
+
+    fn visit_tagged_tpl(&mut self, tpl: &TaggedTpl) {
+        self.require(tpl.span, "A tagged template", ES2015);
+        tpl.visit_children_with(self);
+    }
+
+    fn visit_pat(&mut self, pat: &Pat) {
+        match pat {
+            Pat::Array(array) => self.require(array.span, "Destructuring", ES2015),
+            Pat::Object(object) => self.require(object.span, "Destructuring", ES2015),
+            Pat::Rest(rest) => self.require(rest.span, "A rest parameter", ES2015),
+            Pat::Assign(assign) => self.require(assign.span, "A default value", ES2015),
+            _ => {}
+        }
+        pat.visit_children_with(self);
+    }
+
+    fn visit_object_pat_prop(&mut self, prop: &ObjectPatProp) {
+        if let ObjectPatProp::Rest(rest) = prop {
+            self.require(rest.span, "Object rest", ES2018);
+        }
+        prop.visit_children_with(self);
+    }
+
+    fn visit_expr_or_spread(&mut self, arg: &ExprOrSpread) {
+        if let Some(span) = arg.spread {
+            self.require(span, "Spread", ES2015);
+        }
+        arg.visit_children_with(self);
+    }
+
+    fn visit_prop_or_spread(&mut self, prop: &PropOrSpread) {
+        match prop {
+            PropOrSpread::Spread(spread) => self.require(spread.dot3_token, "Object spread", ES2018),
+            PropOrSpread::Prop(prop) => match &**prop {
+                Prop::Shorthand(ident) => self.require(ident.span, "A shorthand property", ES2015),
+                Prop::Method(method) => self.require(method.function.span, "A method definition", ES2015),
+                _ => {}
+            },
+        }
+        prop.visit_children_with(self);
+    }
+
+    fn visit_prop_name(&mut self, name: &PropName) {
+        if let PropName::Computed(computed) = name {
+            self.require(computed.span, "A computed property name", ES2015);
+        }
+        name.visit_children_with(self);
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        if function.is_async && function.is_generator {
+            self.require(function.span, "An async generator", ES2018);
+        } else if function.is_async {
+            self.require(function.span, "An async function", ES2017);
+        } else if function.is_generator {
+            self.require(function.span, "A generator", ES2015);
+        }
+        function.visit_children_with(self);
+    }
+
+    fn visit_for_of_stmt(&mut self, stmt: &ForOfStmt) {
+        if stmt.is_await {
+            self.require(stmt.span, "`for await`", ES2018);
+        } else {
+            self.require(stmt.span, "`for...of`", ES2015);
+        }
+        stmt.visit_children_with(self);
+    }
+
+    fn visit_yield_expr(&mut self, expr: &YieldExpr) {
+        self.require(expr.span, "`yield`", ES2015);
+        expr.visit_children_with(self);
+    }
+
+    fn visit_await_expr(&mut self, expr: &AwaitExpr) {
+        self.require(expr.span, "`await`", ES2017);
+        expr.visit_children_with(self);
+    }
+
+    fn visit_meta_prop_expr(&mut self, expr: &MetaPropExpr) {
+        match expr.kind {
+            MetaPropKind::NewTarget => self.require(expr.span, "`new.target`", ES2015),
+            MetaPropKind::ImportMeta => self.require(expr.span, "`import.meta`", ES2020),
+        }
+        expr.visit_children_with(self);
+    }
+
+    fn visit_bin_expr(&mut self, expr: &BinExpr) {
+        match expr.op {
+            BinaryOp::Exp => self.require(expr.span, "The `**` operator", ES2017),
+            BinaryOp::NullishCoalescing => self.require(expr.span, "The `??` operator", ES2020),
+            _ => {}
+        }
+        expr.visit_children_with(self);
+    }
+
+    fn visit_assign_expr(&mut self, expr: &AssignExpr) {
+        match expr.op {
+            AssignOp::ExpAssign => self.require(expr.span, "The `**=` operator", ES2017),
+            AssignOp::AndAssign | AssignOp::OrAssign | AssignOp::NullishAssign => {
+                self.require(expr.span, "Logical assignment", ESNEXT)
+            }
+            _ => {}
+        }
+        expr.visit_children_with(self);
+    }
+
+    fn visit_opt_chain_expr(&mut self, expr: &OptChainExpr) {
+        self.require(expr.span, "Optional chaining", ES2020);
+        expr.visit_children_with(self);
+    }
+
+    fn visit_lit(&mut self, lit: &Lit) {
+        match lit {
+            Lit::BigInt(big) => self.require(big.span, "A BigInt literal", ES2020),
+            Lit::Num(number) if number.raw.as_ref().is_some_and(|raw| raw.contains('_')) => {
+                self.require(number.span, "A numeric separator", ESNEXT)
+            }
+            _ => {}
+        }
+        lit.visit_children_with(self);
+    }
+
+    fn visit_catch_clause(&mut self, clause: &CatchClause) {
+        if clause.param.is_none() {
+            self.require(clause.span, "An optional catch binding", ES2020);
+        }
+        clause.visit_children_with(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(code: &str, target: EsTarget) -> Result<(), String> {
+        check_script(code, &target, Path::new("bundle.js")).map_err(|e| match e {
+            PaktoError::ParseError { message, .. } => message,
+            other => other.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_braces_in_strings() {
+        // Counting braces would reject all of these
+        assert!(check("var a = '{'; var b = /\\(/; var c = \"}}\";", EsTarget::Es5).is_ok());
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let error = check("var a = 1;\nvar b = {;\nvar c = 3;\n", EsTarget::Es5).unwrap_err();
+        assert!(error.contains("at 2:10"), "{}", error);
+        assert!(error.contains("> 2 | var b = {;\n    |          ^\n"), "{}", error);
+        assert!(error.contains("  1 | var a = 1;\n"));
+    }
+
+    #[test]
+    fn test_syntax_above_target() {
+        let error = check("var a = 1;\nvar f = function () { return [1].map((x) => x * 2); };", EsTarget::Es5).unwrap_err();
+        assert!(error.starts_with("An arrow function requires ES2015 but the target is ES5 at 2:38"), "{}", error);
+        assert!(check("const f = (x) => x * 2;", EsTarget::Es2015).is_ok());
+
+        let error = check("var a = b?.c;", EsTarget::Es2018).unwrap_err();
+        assert!(error.starts_with("Optional chaining requires ES2020"), "{}", error);
+        assert!(check("var a = b?.c ?? 1;", EsTarget::Es2020).is_ok());
+        assert!(check("async function f() { await g(); }", EsTarget::Es2015).is_err());
+        assert!(check("try { f(); } catch { }", EsTarget::Es2018).is_err());
+        assert!(check("var o = { ...a };", EsTarget::Es2017).is_err());
+    }
+
+    #[test]
+    fn test_module() {
+        let code = "var a = (function () { return 1; })();\nexport default a;\nexport { a as b };";
+        assert!(check(code, EsTarget::Es5).is_err());
+        assert!(check_module(code, &EsTarget::Es5, Path::new("bundle.mjs")).is_ok());
+        assert!(check_module("export const a = 1;", &EsTarget::Es5, Path::new("bundle.mjs")).is_err());
+
+        let error = check_module("var url = import.meta.url;", &EsTarget::Es2018, Path::new("bundle.mjs")).unwrap_err().to_string();
+        assert!(error.contains("`import.meta` requires ES2020"), "{}", error);
+        assert!(check_module("var url = import.meta.url;", &EsTarget::Es2020, Path::new("bundle.mjs")).is_ok());
+        assert!(check("function F() { return new.target; }", EsTarget::Es2015).is_ok());
+    }
+
+    #[test]
+    fn test_code_frame_long_line() {
+        let line = format!("{}var x = ;{}", "a;".repeat(100), "b;".repeat(100));
+        let frame = code_frame(&line, 1, 209);
+        assert!(frame.starts_with("> 1 | …"));
+        assert!(frame.contains("var x = ;"));
+        let marker = frame.lines().nth(1).unwrap();
+        let caret = marker.find('^').unwrap();
+        let source = frame.lines().next().unwrap();
+        assert_eq!(source.chars().nth(marker[..caret].chars().count()), Some(';'));
+    }
+}
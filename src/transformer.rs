@@ -1,28 +1,69 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 use anyhow::{Context, Result};
 use tracing::{debug, info, warn};
+use rayon::prelude::*;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-use crate::config::Config;
-use crate::converter::{PackageData, TransformedPackage, ConvertOptions, AnalysisResult};
+use crate::config::{Config, WasmMode};
+use crate::converter::{PackageData, TransformedPackage, ConvertOptions, AnalysisResult, Stylesheet, WasmModule, ModuleSource, FileContent, EntryGroup};
 use crate::cli::EsTarget;
-use crate::errors::{PaktoError, Result as PaktoResult};
+use crate::errors::{CodeLocation, CompatibilityIssue, PaktoError, Result as PaktoResult};
 use crate::polyfills::PolyfillRegistry;
-
-/// Simplified transformer for initial development
-/// This version uses regex-based transformations instead of full AST parsing
+use crate::{assets, context, defines, duplicates, esm, fs_inline, hoist, js, resolver, shims, stubs, treeshake, wasm};
+use crate::assets::AssetLoader;
+use crate::context::ContextFilter;
+use crate::stubs::DeclaredDependencies;
+use crate::analyzer::NodeApiRegistry;
+use crate::cache::{BuildCache, CacheKey};
+use crate::fs_inline::UnresolvedRead;
+use crate::resolver::{Resolution, Resolver};
+use crate::treeshake::{GraphModule, SideEffects};
+
+/// `require('x')` calls, pointed at polyfills when linking
+static REQUIRE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"require\s*\(\s*['"`]([^'"`]+)['"`]\s*\)"#).expect("valid require pattern")
+});
+
+/// `process.env` accesses, pointed at the process polyfill
+static PROCESS_ENV_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\bprocess\.env\b").expect("valid process.env pattern")
+});
+
+/// Transforms the modules a package entry can reach for the browser: each
+/// file goes through the AST passes (defines, file inlining, assets, shims,
+/// stand-ins), and the results are linked, tree-shaken and hoisted
 pub struct CodeTransformer {
     config: Config,
     polyfills: PolyfillRegistry,
+    node_apis: NodeApiRegistry,
+    cache: BuildCache,
+}
+
+/// A module after the per-file passes, before its module syntax is linked
+struct PreparedModule {
+    path: PathBuf,
+    code: String,
+    /// Whether the code still needs linking; assets and files that failed
+    /// to transform are bundled as they are
+    javascript: bool,
+    dependencies: BTreeMap<String, Resolution>,
+    polyfills_used: Vec<String>,
 }
 
 /// Module transformation result
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct ModuleTransformResult {
     code: String,
     polyfills_used: Vec<String>,
     source_map: Option<String>,
+    unresolved_reads: Vec<UnresolvedRead>,
+    styles: Vec<PathBuf>,
+    wasm_modules: Vec<PathBuf>,
+    /// Where WebAssembly files are read synchronously
+    wasm_reads: Vec<CodeLocation>,
 }
 
 impl CodeTransformer {
@@ -30,6 +71,8 @@ impl CodeTransformer {
         Self {
             config: config.clone(),
             polyfills: PolyfillRegistry::new(),
+            node_apis: NodeApiRegistry::new(),
+            cache: BuildCache::new(&config.cache),
         }
     }
 
@@ -41,117 +84,402 @@ impl CodeTransformer {
     ) -> PaktoResult<TransformedPackage> {
         info!("Starting code transformation");
 
-        let mut transformed_files = HashMap::new();
+        let mut prepared = Vec::new();
         let mut files_processed = 0;
-        let mut all_polyfills = Vec::new();
-
-        // Transform each file
-        for (path, content) in &package_data.files {
-            if self.should_transform_file(path) {
-                debug!("Transforming file: {}", path.display());
+        let mut unresolved_reads = Vec::new();
+        let mut wasm_reads = Vec::new();
+        let mut style_paths: Vec<PathBuf> = Vec::new();
+        let mut wasm_paths: Vec<PathBuf> = Vec::new();
+        let defines = self.resolve_defines(options)?;
+        let context_filter = ContextFilter::new(&self.config.bundle.context)
+            .map_err(|e| PaktoError::ConfigError {
+                message: format!("Invalid bundle.context glob: {}", e),
+                source: None,
+            })?;
+
+        // Follow imports and requires from the package entry, transforming
+        // each module the bundle can reach
+        let resolver = Resolver::new(&package_data.files)
+            .with_root_manifest(&package_data.package_json)
+            .with_dedupe(self.config.bundle.dedupe);
+        let entry = resolver.entry().ok_or_else(|| PaktoError::TransformError {
+            message: format!("No entry module found for {}", analysis.package_info.name),
+            source: None,
+        })?;
+
+        // Configured entry groups are followed like the package entry
+        let entry_groups = self.entry_groups(&resolver)?;
+        let mut entry_paths = vec![entry];
+        for (_, path) in entry_groups.iter().flat_map(|group| &group.entries) {
+            if !entry_paths.contains(path) {
+                entry_paths.push(path.clone());
+            }
+        }
 
-                match self.transform_file(path, content, options, analysis).await {
-                    Ok(result) => {
-                        transformed_files.insert(path.clone(), result.code);
-                        all_polyfills.extend(result.polyfills_used);
-                        files_processed += 1;
+        // Transforming a module reads other package files, so its cached
+        // result is keyed on the whole package as well as the options
+        let (cache_hits, cache_misses) = (self.cache.hits(), self.cache.misses());
+        let transform_inputs = CacheKey::new("transform-inputs")
+            .with_files(&package_data.files)
+            .with_json(&defines.iter().collect::<BTreeMap<_, _>>())
+            .with_json(&self.config.bundle.context)
+            .with_json(&self.config.loaders.iter().collect::<BTreeMap<_, _>>())
+            .with(&analysis.package_info.name)
+            .digest();
+
+        // Modules are transformed a breadth-first level at a time, in
+        // parallel; results are taken in queue order, so the module order
+        // never depends on scheduling
+        let mut level: Vec<PathBuf> = entry_paths.clone();
+        let mut visited: HashSet<PathBuf> = entry_paths.iter().cloned().collect();
+
+        while !level.is_empty() {
+            let results: Vec<Option<Result<ModuleTransformResult>>> = level.par_iter()
+                .map(|path| {
+                    let content = package_data.files.get(path)?;
+                    if !self.should_transform_file(path) {
+                        return None;
+                    }
+                    debug!("Transforming file: {}", path.display());
+                    let content = String::from_utf8_lossy(content.as_bytes());
+
+                    let key = CacheKey::new("transform")
+                        .with(&transform_inputs)
+                        .with(path.to_string_lossy().as_bytes());
+                    Some(match self.cache.get::<ModuleTransformResult>(&key) {
+                        Some(result) => Ok(result),
+                        None => self.transform_file(path, &content, package_data, &defines, &context_filter, analysis)
+                            .inspect(|result| self.cache.put(&key, result)),
+                    })
+                })
+                .collect();
+
+            let mut transformed = Vec::new();
+            for (path, result) in level.into_iter().zip(results) {
+                let Some(content) = package_data.files.get(&path) else { continue };
+                let mut polyfills_used = Vec::new();
+
+                let (code, javascript) = match result {
+                    Some(Ok(result)) => {
+                        polyfills_used.extend(result.polyfills_used);
+                        unresolved_reads.extend(result.unresolved_reads);
+                        wasm_reads.extend(result.wasm_reads);
+                        for style in result.styles {
+                            if !style_paths.contains(&style) {
+                                style_paths.push(style);
+                            }
+                        }
+                        wasm_paths.extend(result.wasm_modules);
+                        (result.code, true)
                     }
-                    Err(e) => {
+                    Some(Err(e)) => {
                         warn!("Failed to transform file {}: {}", path.display(), e);
                         // Include original file as fallback
-                        transformed_files.insert(path.clone(), content.clone());
-                        files_processed += 1;
+                        (String::from_utf8_lossy(content.as_bytes()).into_owned(), false)
+                    }
+                    None => {
+                        let code = self.asset_module(&path, content, &mut style_paths, &mut wasm_paths, &mut polyfills_used)?;
+                        (code, false)
+                    }
+                };
+                files_processed += 1;
+                transformed.push((path, code, javascript, polyfills_used));
+            }
+
+            let resolved: Vec<BTreeMap<String, Resolution>> = transformed.par_iter()
+                .map(|(path, code, _, _)| self.resolve_imports(path, code, &resolver))
+                .collect();
+
+            level = Vec::new();
+            for ((path, code, javascript, polyfills_used), dependencies) in transformed.into_iter().zip(resolved) {
+                for resolution in dependencies.values() {
+                    if let Resolution::Module(dependency) = resolution {
+                        if visited.insert(dependency.clone()) {
+                            level.push(dependency.clone());
+                        }
                     }
                 }
+                prepared.push(PreparedModule { path, code, javascript, dependencies, polyfills_used });
             }
         }
 
-        // Bundle all files into a single module
-        let bundled_code = self.bundle_files(transformed_files, options, analysis)?;
+        if options.bundle_strategy.tree_shakes() {
+            prepared = self.tree_shake(prepared, package_data, &entry_paths);
+        }
+
+        let mut concatenated_modules = 0;
+        if self.config.bundle.hoists_scopes(&options.bundle_strategy) {
+            (prepared, concatenated_modules) = self.hoist_scopes(prepared, package_data, &entry_paths);
+        }
+
+        let linked: Vec<(ModuleSource, Vec<String>)> = prepared.into_par_iter()
+            .map(|module| self.finish_module(module))
+            .collect();
+        let mut modules = Vec::new();
+        let mut all_polyfills = Vec::new();
+        for (module, polyfills_used) in linked {
+            all_polyfills.extend(polyfills_used);
+            modules.push(module);
+        }
+
+        // File reads that cannot be inlined still call the throwing fs
+        // stand-in, so they are reported as errors where they happen
+        unresolved_reads.sort_by_key(|read| read.to_string());
+        let mut issues: Vec<CompatibilityIssue> = unresolved_reads.into_iter()
+            .map(|read| {
+                CompatibilityIssue::error(read.reason)
+                    .with_location(read.location)
+                    .with_suggestion("The read throws at runtime; pass the file's content to the package another way")
+                    .with_api("fs")
+            })
+            .collect();
+
+        // Separate WebAssembly files are fetched, so their bytes are not
+        // there for a synchronous read
+        if self.config.bundle.wasm == WasmMode::Separate {
+            wasm_reads.sort_by_key(|location| location.to_string());
+            issues.extend(wasm_reads.into_iter().map(|location| {
+                CompatibilityIssue::warning(
+                    "fs.readFileSync of a WebAssembly file throws at runtime when bundle.wasm is \"separate\"",
+                )
+                .with_location(location)
+                .with_suggestion("Set bundle.wasm to \"embed\", or load the module asynchronously")
+                .with_api("fs")
+            }));
+        }
 
-        // Inject required polyfills
-        let final_code = self.inject_polyfills(&bundled_code, &all_polyfills, options)?;
+        all_polyfills.sort();
+        all_polyfills.dedup();
+        let polyfills = self.polyfill_block(&all_polyfills);
+
+        let styles = style_paths.into_iter()
+            .filter_map(|path| {
+                let content = package_data.files.get(&path)?;
+                Some(Stylesheet {
+                    content: String::from_utf8_lossy(content.as_bytes()).into_owned(),
+                    path,
+                })
+            })
+            .collect();
+
+        wasm_paths.sort();
+        wasm_paths.dedup();
+        let wasm_modules = wasm_paths.into_iter()
+            .filter_map(|path| {
+                let content = package_data.files.get(&path)?;
+                Some(WasmModule {
+                    content: content.as_bytes().to_vec(),
+                    path,
+                })
+            })
+            .collect();
+
+        let duplicate_packages = duplicates::find_duplicates(&modules, |root| {
+            self.package_manifest(root, package_data)
+                .get("version")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string()
+        });
+
+        debug!(
+            "Build cache: {} module result(s) reused, {} computed",
+            self.cache.hits() - cache_hits,
+            self.cache.misses() - cache_misses
+        );
 
         Ok(TransformedPackage {
             files_processed,
-            code: final_code,
+            code: polyfills,
+            modules,
             source_map: None,
+            styles,
+            wasm_modules,
+            concatenated_modules,
+            entry_groups,
+            bundled_packages: bundled_dependencies(&package_data.package_json),
+            duplicate_packages,
+            issues,
         })
     }
 
-    /// Transform a single file using regex-based approach
-    async fn transform_file(
+    /// Resolve the modules of the configured entry groups from the package
+    /// root
+    fn entry_groups(&self, resolver: &Resolver) -> PaktoResult<Vec<EntryGroup>> {
+        let root = Path::new("package.json");
+        self.config.bundle.split.entries.iter()
+            .map(|(name, specifiers)| {
+                let entries = specifiers.iter()
+                    .map(|specifier| match resolver.resolve(root, specifier) {
+                        Some(Resolution::Module(path)) => Ok((specifier.clone(), path)),
+                        _ => Err(PaktoError::ConfigError {
+                            message: format!("Entry '{}' of chunk '{}' does not resolve to a package module", specifier, name),
+                            source: None,
+                        }),
+                    })
+                    .collect::<PaktoResult<Vec<_>>>()?;
+                Ok(EntryGroup { name: name.clone(), entries })
+            })
+            .collect()
+    }
+
+    /// Merge configured and command-line defines. Minified builds default
+    /// `process.env.NODE_ENV` to `"production"`.
+    fn resolve_defines(&self, options: &ConvertOptions) -> PaktoResult<HashMap<String, serde_json::Value>> {
+        let mut defines = self.config.defines.clone();
+
+        if let Some(key) = defines.keys().filter(|key| !defines::is_valid_define_key(key)).min() {
+            return Err(PaktoError::ConfigError {
+                message: format!("Invalid define key '{}': expected an identifier or dotted path", key),
+                source: None,
+            });
+        }
+
+        defines.extend(options.defines.clone());
+
+        if options.minify {
+            defines.entry(defines::NODE_ENV_DEFINE.to_string())
+                .or_insert_with(|| serde_json::Value::String("production".to_string()));
+        }
+
+        Ok(defines)
+    }
+
+    /// Run the per-file passes over a single module
+    fn transform_file(
         &self,
         path: &Path,
         content: &str,
-        _options: &ConvertOptions,
-        _analysis: &AnalysisResult,
+        package_data: &PackageData,
+        defines: &HashMap<String, serde_json::Value>,
+        context_filter: &ContextFilter,
+        analysis: &AnalysisResult,
     ) -> Result<ModuleTransformResult> {
         let mut transformed_code = content.to_string();
         let mut polyfills_used = Vec::new();
 
-        // Transform require() calls for Node.js APIs
-        let require_regex = Regex::new(r#"require\s*\(\s*['"`]([^'"`]+)['"`]\s*\)"#)?;
-        transformed_code = require_regex.replace_all(&transformed_code, |caps: &regex::Captures| {
-            let module_name = &caps[1];
-            match module_name {
-                "crypto" => {
-                    polyfills_used.push("crypto".to_string());
-                    "cryptoPolyfill".to_string()
-                }
-                "buffer" => {
+        // Substitute defines and drop unreachable branches before anything
+        // else sees the code
+        if !defines.is_empty() {
+            match defines::apply_defines(&transformed_code, js::syntax_for_path(path), defines) {
+                Ok(code) => transformed_code = code,
+                Err(e) => warn!("Defines not applied to {}, it does not parse: {}", path.display(), e),
+            }
+        }
+
+        // Inline package files read through fs at build time
+        let mut unresolved_reads = Vec::new();
+        let mut wasm_reads = Vec::new();
+        let mut wasm_modules = Vec::new();
+        match fs_inline::inline_file_reads(&transformed_code, js::syntax_for_path(path), path, &package_data.files) {
+            Ok(inlined) => {
+                if inlined.uses_buffer {
                     polyfills_used.push("buffer".to_string());
-                    "BufferPolyfill".to_string()
-                }
-                "events" => {
-                    polyfills_used.push("events".to_string());
-                    "EventEmitterPolyfill".to_string()
                 }
-                "process" => {
-                    polyfills_used.push("process".to_string());
-                    "processPolyfill".to_string()
+                for file in &inlined.inlined {
+                    debug!("Inlined {} into {}", file.display(), path.display());
                 }
-                _ => caps[0].to_string(),
+                unresolved_reads = inlined.unresolved;
+                wasm_reads = inlined.wasm_reads;
+                wasm_modules.extend(inlined.wasm_modules);
+                transformed_code = inlined.code;
             }
-        }).to_string();
+            Err(e) => debug!("Skipping fs inlining for {}: {}", path.display(), e),
+        }
 
-        // Transform ES6 import statements
-        let import_regex = Regex::new(r#"import\s+.*?\s+from\s+['"`]([^'"`]+)['"`]"#)?;
-        transformed_code = import_regex.replace_all(&transformed_code, |caps: &regex::Captures| {
-            let module_name = &caps[1];
-            match module_name {
-                "crypto" => {
-                    polyfills_used.push("crypto".to_string());
-                    caps[0].replace(module_name, "cryptoPolyfill")
-                }
-                "buffer" => {
-                    polyfills_used.push("buffer".to_string());
-                    caps[0].replace(module_name, "BufferPolyfill")
+        // Map dynamic requires with a static prefix to the files they can
+        // load; JSON entries are then inlined like any other asset
+        match context::rewrite_context_requires(
+            &transformed_code,
+            js::syntax_for_path(path),
+            path,
+            &package_data.files,
+            context_filter,
+        ) {
+            Ok(rewritten) => {
+                if !rewritten.contexts.is_empty() {
+                    polyfills_used.push(context::CONTEXT_POLYFILL.to_string());
                 }
-                "events" => {
-                    polyfills_used.push("events".to_string());
-                    caps[0].replace(module_name, "EventEmitterPolyfill")
+                for module in &rewritten.contexts {
+                    debug!(
+                        "Context module {}* in {}: {} file(s)",
+                        module.prefix,
+                        path.display(),
+                        module.files.len()
+                    );
                 }
-                "process" => {
-                    polyfills_used.push("process".to_string());
-                    caps[0].replace(module_name, "processPolyfill")
+                transformed_code = rewritten.code;
+            }
+            Err(e) => debug!("Skipping context modules for {}: {}", path.display(), e),
+        }
+
+        // Replace imports of JSON, text and binary files with their values
+        let mut styles = Vec::new();
+        match assets::inline_asset_imports(
+            &transformed_code,
+            js::syntax_for_path(path),
+            path,
+            &package_data.files,
+            &self.config.loaders,
+        ) {
+            Ok(inlined) => {
+                for asset in &inlined.assets {
+                    debug!("Inlined asset {} into {}", asset.display(), path.display());
                 }
-                _ => caps[0].to_string(),
+                styles = inlined.styles;
+                wasm_modules.extend(inlined.wasm_modules);
+                transformed_code = inlined.code;
             }
-        }).to_string();
+            Err(e) => warn!("Failed to inline assets into {}: {}", path.display(), e),
+        }
 
-        // Transform process.env access
-        let process_env_regex = Regex::new(r"\bprocess\.env\b")?;
-        if process_env_regex.is_match(&transformed_code) {
-            polyfills_used.push("process".to_string());
-            transformed_code = process_env_regex.replace_all(&transformed_code, "processPolyfill.env").to_string();
+        // Load WebAssembly modules through the loader instead of fetching
+        // them next to the script
+        match wasm::rewrite_wasm_loading(&transformed_code, js::syntax_for_path(path), path, &package_data.files) {
+            Ok(loading) => {
+                wasm_modules.extend(loading.modules);
+                transformed_code = loading.code;
+            }
+            Err(e) => debug!("Skipping WebAssembly loading for {}: {}", path.display(), e),
+        }
+        if !wasm_modules.is_empty() {
+            polyfills_used.push(wasm::WASM_POLYFILL.to_string());
         }
 
-        // Basic CommonJS to browser module transformation
-        transformed_code = self.transform_commonjs_to_browser(&transformed_code)?;
+        // Provide Node module-scope globals the module references
+        let module_path = Path::new("node_modules").join(&analysis.package_info.name).join(path);
+        match shims::inject_module_shims(&transformed_code, js::syntax_for_path(path), &module_path) {
+            Ok(shimmed) => {
+                if !shimmed.shims.is_empty() {
+                    debug!(
+                        "Injected shims into {}: {}",
+                        path.display(),
+                        shimmed.shims.iter().map(|s| s.name()).collect::<Vec<_>>().join(", ")
+                    );
+                }
+                transformed_code = shimmed.code;
+            }
+            Err(e) => debug!("Skipping module shims for {}: {}", path.display(), e),
+        }
 
-        // Remove or comment out incompatible Node.js API usage
-        transformed_code = self.handle_incompatible_apis(&transformed_code)?;
+        // Replace incompatible Node.js modules with stand-ins that throw
+        // when used, and make optional requires fail at require time
+        let dependencies = self.declared_dependencies(path, package_data);
+        match stubs::stub_incompatible_modules(&transformed_code, js::syntax_for_path(path), &self.node_apis, &dependencies) {
+            Ok(stubbed) => {
+                if !stubbed.modules.is_empty() {
+                    debug!("Stubbed {} in {}", stubbed.modules.join(", "), path.display());
+                }
+                if !stubbed.missing.is_empty() {
+                    debug!("Optional requires of {} in {} now throw", stubbed.missing.join(", "), path.display());
+                }
+                if !stubbed.modules.is_empty() || !stubbed.missing.is_empty() {
+                    polyfills_used.push(stubs::UNAVAILABLE_POLYFILL.to_string());
+                }
+                transformed_code = stubbed.code;
+            }
+            Err(e) => debug!("Skipping module stubs for {}: {}", path.display(), e),
+        }
 
         polyfills_used.sort();
         polyfills_used.dedup();
@@ -160,206 +488,296 @@ impl CodeTransformer {
             code: transformed_code,
             polyfills_used,
             source_map: None,
+            unresolved_reads,
+            styles,
+            wasm_modules,
+            wasm_reads,
         })
     }
 
-    /// Transform CommonJS modules to browser-compatible format
-    fn transform_commonjs_to_browser(&self, code: &str) -> Result<String> {
-        let mut transformed = code.to_string();
+    /// Link a prepared module and drop the dependencies its linked code no
+    /// longer requires, returning it with the polyfills it uses
+    fn finish_module(&self, module: PreparedModule) -> (ModuleSource, Vec<String>) {
+        let PreparedModule { path, mut code, javascript, mut dependencies, mut polyfills_used } = module;
+
+        if javascript {
+            let key = CacheKey::new("link")
+                .with(path.to_string_lossy().as_bytes())
+                .with(&code);
+            let linked = match self.cache.get::<(String, Vec<String>)>(&key) {
+                Some(linked) => Ok(linked),
+                None => self.link_module(&path, &code).inspect(|linked| self.cache.put(&key, linked)),
+            };
+            match linked {
+                Ok((linked, linked_polyfills)) => {
+                    polyfills_used.extend(linked_polyfills);
+                    code = linked;
+                }
+                Err(e) => warn!("Failed to link module {}: {}", path.display(), e),
+            }
+        }
 
-        // Wrap in IIFE if it looks like a CommonJS module
-        if code.contains("module.exports") || code.contains("exports.") {
-            transformed = format!(
-                "(function(module, exports) {{\n{}\nreturn module.exports;\n}})({{exports: {{}}}}, {{}});",
-                transformed
+        // Requires of polyfilled APIs no longer load a module
+        let mut dynamic_imports = BTreeSet::new();
+        if let Ok(program) = js::parse_program(&code, js::syntax_for_path(&path)) {
+            let required = js::required_specifiers(&program);
+            dependencies.retain(|specifier, _| required.contains(specifier));
+            dynamic_imports.extend(
+                js::dynamic_specifiers(&program).into_iter()
+                    .filter(|specifier| dependencies.contains_key(specifier)),
             );
         }
-
-        // Transform module.exports = ... to return ...
-        let module_exports_regex = Regex::new(r"module\.exports\s*=\s*")?;
-        if module_exports_regex.is_match(&transformed) {
-            // For simple cases, just replace with return
-            // More complex cases would need proper AST parsing
-            transformed = module_exports_regex.replace(&transformed, "return ").to_string();
+        // Core modules without a polyfill load as stand-ins
+        if dependencies.values().any(|resolution| matches!(resolution, Resolution::Builtin(_))) {
+            polyfills_used.push(stubs::UNAVAILABLE_POLYFILL.to_string());
         }
 
-        Ok(transformed)
+        (ModuleSource { path, code, dependencies, dynamic_imports }, polyfills_used)
     }
 
-    /// Handle incompatible Node.js APIs
-    fn handle_incompatible_apis(&self, code: &str) -> Result<String> {
-        let mut transformed = code.to_string();
+    /// Convert a transformed module's imports and exports for the module
+    /// runtime and point requires of Node.js APIs at their polyfills
+    fn link_module(&self, path: &Path, code: &str) -> Result<(String, Vec<String>)> {
+        let mut transformed_code = code.to_string();
+        let mut polyfills_used = Vec::new();
 
-        // Comment out file system operations
-        let fs_regex = Regex::new(r".*require\s*\(\s*['\"']fs['\"']\s*\).*")?;
-        transformed = fs_regex.replace_all(&transformed, "// $0 // File system not available in browser").to_string();
+        // Link imports and exports through the module runtime
+        match esm::convert_to_commonjs(&transformed_code, js::syntax_for_path(path), path) {
+            Ok(code) => transformed_code = code,
+            Err(e) => warn!("Failed to convert ES module syntax in {}: {}", path.display(), e),
+        }
 
-        // Comment out child_process operations
-        let child_process_regex = Regex::new(r".*require\s*\(\s*['\"']child_process['\"']\s*\).*")?;
-        transformed = child_process_regex.replace_all(&transformed, "// $0 // Child processes not available in browser").to_string();
+        // Transform require() calls for Node.js APIs
+        transformed_code = REQUIRE_REGEX.replace_all(&transformed_code, |caps: &regex::Captures| {
+            let module_name = caps[1].trim_start_matches("node:");
+            match module_name {
+                "crypto" => {
+                    polyfills_used.push("crypto".to_string());
+                    "cryptoPolyfill".to_string()
+                }
+                "buffer" => {
+                    polyfills_used.push("buffer".to_string());
+                    "BufferPolyfill".to_string()
+                }
+                "events" => {
+                    polyfills_used.push("events".to_string());
+                    "EventEmitterPolyfill".to_string()
+                }
+                "process" => {
+                    polyfills_used.push("process".to_string());
+                    "processPolyfill".to_string()
+                }
+                "path" => {
+                    polyfills_used.push("path".to_string());
+                    "pathPolyfill".to_string()
+                }
+                "util" => {
+                    polyfills_used.push("util".to_string());
+                    "utilPolyfill".to_string()
+                }
+                _ => caps[0].to_string(),
+            }
+        }).to_string();
 
-        // Comment out os operations
-        let os_regex = Regex::new(r".*require\s*\(\s*['\"']os['\"']\s*\).*")?;
-        transformed = os_regex.replace_all(&transformed, "// $0 // OS module not available in browser").to_string();
+        // Transform process.env access
+        if PROCESS_ENV_REGEX.is_match(&transformed_code) {
+            polyfills_used.push("process".to_string());
+            transformed_code = PROCESS_ENV_REGEX.replace_all(&transformed_code, "processPolyfill.env").to_string();
+        }
 
-        Ok(transformed)
+        Ok((transformed_code, polyfills_used))
     }
 
-    /// Bundle multiple files into a single module
-    fn bundle_files(
-        &self,
-        files: HashMap<PathBuf, String>,
-        options: &ConvertOptions,
-        analysis: &AnalysisResult,
-    ) -> PaktoResult<String> {
-        debug!("Bundling {} files", files.len());
-
-        let mut bundled_code = String::new();
-
-        // Generate module header
-        bundled_code.push_str(&self.generate_module_header(options, analysis)?);
+    /// Dependencies declared by the package a module belongs to
+    fn declared_dependencies(&self, path: &Path, package_data: &PackageData) -> DeclaredDependencies {
+        let manifest = self.package_manifest(&resolver::package_root(path), package_data);
+        DeclaredDependencies::from_package_json(&manifest)
+    }
 
-        // Add all file contents
-        for (path, content) in files {
-            bundled_code.push_str(&format!(
-                "\n  // === {} ===\n",
-                path.display()
-            ));
+    /// Whether a module may have side effects, going by its package's
+    /// `sideEffects` field
+    fn has_side_effects(&self, path: &Path, package_data: &PackageData) -> bool {
+        let root = resolver::package_root(path);
+        let manifest = self.package_manifest(&root, package_data);
+        SideEffects::from_package_json(&manifest).has_side_effects(path.strip_prefix(&root).unwrap_or(path))
+    }
 
-            // Indent the content to match the IIFE structure
-            for line in content.lines() {
-                bundled_code.push_str("  ");
-                bundled_code.push_str(line);
-                bundled_code.push('\n');
-            }
-            bundled_code.push('\n');
+    /// package.json of the package installed at a root, or of the converted
+    /// package for the empty root
+    fn package_manifest(&self, root: &Path, package_data: &PackageData) -> serde_json::Value {
+        if root.as_os_str().is_empty() {
+            return package_data.package_json.clone();
         }
-
-        // Generate module footer
-        bundled_code.push_str(&self.generate_module_footer(options, analysis)?);
-
-        Ok(bundled_code)
+        package_data.files.get(&root.join("package.json"))
+            .and_then(|content| serde_json::from_slice(content.as_bytes()).ok())
+            .unwrap_or_default()
     }
 
-    /// Generate module header
-    fn generate_module_header(
+    /// Module for a file that is not JavaScript, exporting the value its
+    /// loader produces
+    fn asset_module(
         &self,
-        options: &ConvertOptions,
-        analysis: &AnalysisResult,
+        path: &Path,
+        content: &FileContent,
+        style_paths: &mut Vec<PathBuf>,
+        wasm_paths: &mut Vec<PathBuf>,
+        polyfills: &mut Vec<String>,
     ) -> PaktoResult<String> {
-        let mut header = String::new();
-
-        // Add header comment
-        header.push_str(&format!(
-            "/**\n * {} - OutSystems Compatible Bundle\n",
-            analysis.package_info.name
-        ));
-        if let Some(ref description) = analysis.package_info.description {
-            header.push_str(&format!(" * {}\n", description));
-        }
-        header.push_str(&format!(" * Version: {}\n", analysis.package_info.version));
-        header.push_str(" * Generated by Pakto\n");
-        header.push_str(" */\n");
-
-        // Start UMD wrapper
-        header.push_str("(function(global, factory) {\n");
-        header.push_str("  'use strict';\n\n");
-
-        // UMD pattern
-        header.push_str("  if (typeof module === 'object' && typeof module.exports === 'object') {\n");
-        header.push_str("    module.exports = factory();\n");
-        header.push_str("  } else if (typeof define === 'function' && define.amd) {\n");
-        header.push_str("    define(factory);\n");
-        header.push_str("  } else {\n");
-
-        let global_name = options.name.as_deref()
-            .unwrap_or(&analysis.package_info.name)
-            .replace(['-', '@', '/'], "_");
-
-        if let Some(ref namespace) = options.namespace {
-            header.push_str(&format!("    global.{} = global.{} || {{}};\n", namespace, namespace));
-            header.push_str(&format!("    global.{}.{} = factory();\n", namespace, global_name));
-        } else {
-            header.push_str(&format!("    global.{} = factory();\n", global_name));
+        let Some(loader) = assets::loader_for(path, &self.config.loaders) else {
+            warn!("No loader for {}, bundling an empty module", path.display());
+            return Ok("module.exports = {};".to_string());
+        };
+
+        match loader {
+            AssetLoader::Css => {
+                if !style_paths.iter().any(|style| style == path) {
+                    style_paths.push(path.to_path_buf());
+                }
+            }
+            AssetLoader::Wasm => {
+                wasm_paths.push(path.to_path_buf());
+                polyfills.push(wasm::WASM_POLYFILL.to_string());
+            }
+            _ => {}
         }
 
-        header.push_str("  }\n");
-        header.push_str("})(typeof window !== 'undefined' ? window : this, function() {\n");
-        header.push_str("  'use strict';\n\n");
-
-        Ok(header)
+        let expression = assets::asset_expression(loader, path, content)
+            .map_err(|e| PaktoError::TransformError {
+                message: format!("Failed to load {}: {}", path.display(), e),
+                source: None,
+            })?;
+        Ok(format!("module.exports = {};", expression))
     }
 
-    /// Generate module footer
-    fn generate_module_footer(
-        &self,
-        _options: &ConvertOptions,
-        _analysis: &AnalysisResult,
-    ) -> PaktoResult<String> {
-        let mut footer = String::new();
+    /// The prepared modules as a graph for whole-graph passes, entry first
+    fn module_graph<'a>(&self, prepared: &'a [PreparedModule], package_data: &PackageData) -> Vec<GraphModule<'a>> {
+        prepared.iter()
+            .map(|module| GraphModule {
+                path: &module.path,
+                code: &module.code,
+                dependencies: &module.dependencies,
+                side_effects: self.has_side_effects(&module.path, package_data),
+            })
+            .collect()
+    }
 
-        footer.push_str("\n  // Return the module\n");
-        footer.push_str("  return typeof module !== 'undefined' && module.exports ? module.exports : {};\n");
-        footer.push_str("});\n");
+    /// Graph indices of the entry modules
+    fn entry_indices(prepared: &[PreparedModule], entry_paths: &[PathBuf]) -> Vec<usize> {
+        prepared.iter()
+            .enumerate()
+            .filter(|(_, module)| entry_paths.contains(&module.path))
+            .map(|(index, _)| index)
+            .collect()
+    }
 
-        Ok(footer)
+    /// Remove unused exports, statements and side-effect-free modules
+    fn tree_shake(&self, prepared: Vec<PreparedModule>, package_data: &PackageData, entry_paths: &[PathBuf]) -> Vec<PreparedModule> {
+        let entries = Self::entry_indices(&prepared, entry_paths);
+        let shaking = treeshake::tree_shake(&self.module_graph(&prepared, package_data), &entries);
+
+        let removed_modules = shaking.modules.iter().filter(|code| code.is_none()).count();
+        info!(
+            "Tree shaking removed {} statement(s) and {} module(s)",
+            shaking.removed_statements,
+            removed_modules
+        );
+
+        prepared.into_iter()
+            .zip(shaking.modules)
+            .filter_map(|(module, code)| Some(PreparedModule { code: code?, ..module }))
+            .collect()
     }
 
-    /// Inject polyfills into the code
-    fn inject_polyfills(
+    /// Merge groups of ES modules into the scope of their root module,
+    /// returning the remaining modules and how many were merged away
+    fn hoist_scopes(
         &self,
-        code: &str,
-        polyfills_needed: &[String],
-        _options: &ConvertOptions,
-    ) -> PaktoResult<String> {
-        if polyfills_needed.is_empty() {
-            return Ok(code.to_string());
+        prepared: Vec<PreparedModule>,
+        package_data: &PackageData,
+        entry_paths: &[PathBuf],
+    ) -> (Vec<PreparedModule>, usize) {
+        let entries = Self::entry_indices(&prepared, entry_paths);
+        let concatenations = hoist::hoist_scopes(&self.module_graph(&prepared, package_data), &entries);
+        if concatenations.is_empty() {
+            return (prepared, 0);
         }
 
-        debug!("Injecting polyfills: {:?}", polyfills_needed);
-
-        let mut polyfilled_code = String::new();
-
-        // Find insertion point (after factory function starts)
-        let lines: Vec<&str> = code.lines().collect();
-        let mut injection_point = 0;
-
-        for (i, line) in lines.iter().enumerate() {
-            if line.contains("'use strict';") && line.contains("  ") {
-                injection_point = i + 1;
-                break;
+        let mut modules: Vec<Option<PreparedModule>> = prepared.into_iter().map(Some).collect();
+        let mut merged = 0;
+        for concatenation in &concatenations {
+            let members: Vec<PreparedModule> = concatenation.members.iter()
+                .filter_map(|&member| modules[member].take())
+                .collect();
+            let Some(root) = members.last() else { continue };
+            merged += members.len() - 1;
+
+            let mut module = PreparedModule {
+                path: root.path.clone(),
+                code: concatenation.code.clone(),
+                javascript: true,
+                dependencies: BTreeMap::new(),
+                polyfills_used: Vec::new(),
+            };
+            for member in members {
+                module.dependencies.extend(member.dependencies);
+                module.polyfills_used.extend(member.polyfills_used);
             }
+            modules[concatenation.root] = Some(module);
         }
 
-        // Add lines up to injection point
-        for (i, line) in lines.iter().enumerate() {
-            polyfilled_code.push_str(line);
-            polyfilled_code.push('\n');
-
-            if i == injection_point {
-                polyfilled_code.push_str("\n  // === Polyfills ===\n");
+        info!(
+            "Scope hoisting merged {} module(s) into {} scope(s)",
+            merged,
+            concatenations.len()
+        );
+        (modules.into_iter().flatten().collect(), merged)
+    }
 
-                for polyfill_name in polyfills_needed {
-                    if let Some(polyfill_code) = self.polyfills.get_polyfill(polyfill_name) {
-                        polyfilled_code.push_str(&format!("  // Polyfill: {}\n", polyfill_name));
+    /// Resolve the modules a transformed module imports or requires
+    fn resolve_imports(&self, path: &Path, code: &str, resolver: &Resolver) -> BTreeMap<String, Resolution> {
+        let specifiers = match js::parse_program(code, js::syntax_for_path(path)) {
+            Ok(program) => js::module_specifiers(&program),
+            Err(e) => {
+                warn!("Failed to parse {} for its imports: {}", path.display(), e);
+                Vec::new()
+            }
+        };
 
-                        // Indent polyfill code
-                        for polyfill_line in polyfill_code.lines() {
-                            if !polyfill_line.trim().is_empty() {
-                                polyfilled_code.push_str("  ");
-                                polyfilled_code.push_str(polyfill_line);
-                            }
-                            polyfilled_code.push('\n');
-                        }
-                        polyfilled_code.push('\n');
+        specifiers.into_iter()
+            .filter_map(|specifier| {
+                // Configured externals are read from globals, never resolved
+                if self.config.bundle.external_global(&specifier).is_some() {
+                    return Some((specifier, Resolution::External));
+                }
+                match resolver.resolve(path, &specifier) {
+                    Some(resolution) => Some((specifier, resolution)),
+                    None => {
+                        warn!("Cannot resolve '{}' from {}; requiring it will throw", specifier, path.display());
+                        None
                     }
                 }
+            })
+            .collect()
+    }
 
-                polyfilled_code.push_str("  // === End Polyfills ===\n\n");
-            }
+    /// Polyfill code between the markers the output generator looks for
+    fn polyfill_block(&self, polyfills_needed: &[String]) -> String {
+        if polyfills_needed.is_empty() {
+            return String::new();
         }
 
-        Ok(polyfilled_code)
+        debug!("Injecting polyfills: {:?}", polyfills_needed);
+
+        let mut block = String::from("// === Polyfills ===\n");
+        for polyfill_name in polyfills_needed {
+            if let Some(polyfill_code) = self.polyfills.get_polyfill(polyfill_name) {
+                block.push_str(&format!("// Polyfill: {}\n", polyfill_name));
+                block.push_str(polyfill_code.trim_end());
+                block.push_str("\n\n");
+            }
+        }
+        block.push_str("// === End Polyfills ===\n");
+        block
     }
 
     /// Check if file should be transformed
@@ -372,6 +790,16 @@ impl CodeTransformer {
     }
 }
 
+/// Packages a manifest lists under `bundleDependencies` (or
+/// `bundledDependencies`), which ship with it
+fn bundled_dependencies(manifest: &serde_json::Value) -> Vec<String> {
+    ["bundleDependencies", "bundledDependencies"].iter()
+        .filter_map(|field| manifest.get(*field).and_then(|v| v.as_array()))
+        .flatten()
+        .filter_map(|name| name.as_str().map(str::to_string))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -395,20 +823,6 @@ mod tests {
         assert!(!transformer.should_transform_file(Path::new("test.json")));
     }
 
-    #[test]
-    fn test_commonjs_transformation() {
-        let config = Config::default();
-        let transformer = CodeTransformer::new(&config);
-
-        let input = r#"
-const crypto = require('crypto');
-module.exports = { hash: crypto.createHash };
-"#;
-
-        let result = transformer.transform_commonjs_to_browser(input).unwrap();
-        assert!(result.contains("(function(module, exports)"));
-    }
-
     #[test]
     fn test_polyfill_detection() {
         let config = Config::default();
@@ -423,4 +837,174 @@ const buffer = require('buffer');
         assert!(input.contains("crypto"));
         assert!(input.contains("buffer"));
     }
-}
\ No newline at end of file
+
+    fn analysis(name: &str) -> AnalysisResult {
+        use crate::converter::{DependencyAnalysis, EstimatedSize, PackageInfo};
+
+        AnalysisResult {
+            package_info: PackageInfo {
+                name: name.to_string(),
+                version: "1.0.0".to_string(),
+                description: None,
+                main: None,
+                entry_points: vec![],
+                dependencies: HashMap::new(),
+                dev_dependencies: HashMap::new(),
+                optional_dependencies: HashMap::new(),
+                keywords: vec![],
+                license: None,
+            },
+            compatibility_issues: vec![],
+            required_polyfills: vec![],
+            dependency_analysis: DependencyAnalysis {
+                total_dependencies: 0,
+                problematic_dependencies: vec![],
+                browser_compatible: vec![],
+                needs_polyfills: vec![],
+                circular_dependencies: vec![],
+            },
+            estimated_size: EstimatedSize { min_size: 0, max_size: 0, with_polyfills: 0, minified: 0 },
+            compatibility_score: 1.0,
+            feasible: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transforms_reachable_modules() {
+        let package_json = serde_json::json!({ "name": "app", "main": "lib/index.js", "dependencies": { "dep": "^1.0.0" } });
+        let mut files = HashMap::new();
+        for (path, content) in [
+            ("package.json", package_json.to_string()),
+            ("lib/index.js", "import dep from 'dep';\nimport { join } from 'path';\nexport default function run() { return join(dep, require('./data.json').name); }".to_string()),
+            ("lib/data.json", "{ \"name\": \"data\" }".to_string()),
+            ("lib/unused.js", "module.exports = 1;".to_string()),
+            ("node_modules/dep/package.json", "{ \"name\": \"dep\", \"main\": \"main.js\" }".to_string()),
+            ("node_modules/dep/main.js", "module.exports = 'dep';".to_string()),
+        ] {
+            files.insert(PathBuf::from(path), FileContent::Text(content));
+        }
+        let package_data = PackageData { total_size: 0, files, package_json };
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.cache.directory = temp_dir.path().to_path_buf();
+        let transformer = CodeTransformer::new(&config);
+        let transformed = transformer
+            .transform_package(&package_data, &ConvertOptions::default(), &analysis("app"))
+            .await
+            .unwrap();
+
+        let paths: Vec<&Path> = transformed.modules.iter().map(|m| m.path.as_path()).collect();
+        assert_eq!(paths, vec![Path::new("lib/index.js"), Path::new("node_modules/dep/main.js")]);
+
+        let entry = &transformed.modules[0];
+        assert_eq!(
+            entry.dependencies.get("dep"),
+            Some(&Resolution::Module(PathBuf::from("node_modules/dep/main.js")))
+        );
+        assert!(entry.code.contains("var _pakto_import_1 = pathPolyfill;"));
+        assert!(entry.code.contains("({\"name\":\"data\"}).name"));
+        assert!(transformed.code.contains("// Polyfill: path"));
+    }
+
+    #[tokio::test]
+    async fn test_reports_unresolved_reads() {
+        let package_json = serde_json::json!({ "name": "app", "main": "index.js" });
+        let mut files = HashMap::new();
+        for (path, content) in [
+            ("package.json", package_json.to_string()),
+            ("index.js", "var fs = require('fs');\nmodule.exports = fs.readFileSync(process.argv[2], 'utf8');".to_string()),
+        ] {
+            files.insert(PathBuf::from(path), FileContent::Text(content));
+        }
+        let package_data = PackageData { total_size: 0, files, package_json };
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.cache.directory = temp_dir.path().to_path_buf();
+        let transformed = CodeTransformer::new(&config)
+            .transform_package(&package_data, &ConvertOptions::default(), &analysis("app"))
+            .await
+            .unwrap();
+
+        // The read is left to the throwing fs stand-in and reported
+        assert_eq!(transformed.issues.len(), 1);
+        let issue = &transformed.issues[0];
+        assert!(matches!(issue.level, crate::errors::IssueLevel::Error));
+        assert_eq!(issue.location.as_ref().and_then(|l| l.line), Some(2));
+        assert!(transformed.modules[0].code.contains("readFileSync"));
+    }
+
+    #[tokio::test]
+    async fn test_warns_on_separate_wasm_reads() {
+        let package_json = serde_json::json!({ "name": "app", "main": "index.js" });
+        let mut files = HashMap::new();
+        files.insert(PathBuf::from("package.json"), FileContent::Text(package_json.to_string()));
+        files.insert(
+            PathBuf::from("index.js"),
+            FileContent::Text("var fs = require('fs');\nmodule.exports = fs.readFileSync(__dirname + '/core.wasm');".to_string()),
+        );
+        files.insert(PathBuf::from("core.wasm"), FileContent::Binary(vec![0x00, 0x61, 0x73, 0x6d]));
+        let package_data = PackageData { total_size: 0, files, package_json };
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.cache.directory = temp_dir.path().to_path_buf();
+        let embedded = CodeTransformer::new(&config)
+            .transform_package(&package_data, &ConvertOptions::default(), &analysis("app"))
+            .await
+            .unwrap();
+        assert!(embedded.issues.is_empty());
+
+        config.bundle.wasm = WasmMode::Separate;
+        let separate = CodeTransformer::new(&config)
+            .transform_package(&package_data, &ConvertOptions::default(), &analysis("app"))
+            .await
+            .unwrap();
+        assert_eq!(separate.issues.len(), 1);
+        assert!(separate.issues[0].message.contains("bundle.wasm"));
+        assert_eq!(separate.issues[0].location.as_ref().and_then(|l| l.line), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_build_cache_reuse() {
+        let package_json = serde_json::json!({ "name": "app", "main": "index.js" });
+        let files = |util: &str| -> HashMap<PathBuf, FileContent> {
+            [
+                ("package.json", package_json.to_string()),
+                ("index.js", "import { twice } from './util.js';\nexport default twice(2);".to_string()),
+                ("util.js", util.to_string()),
+            ]
+            .into_iter()
+            .map(|(path, content)| (PathBuf::from(path), FileContent::Text(content)))
+            .collect()
+        };
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.cache.directory = temp_dir.path().to_path_buf();
+        let transformer = CodeTransformer::new(&config);
+        let transform = |files| {
+            let package_data = PackageData { total_size: 0, files, package_json: package_json.clone() };
+            let transformer = &transformer;
+            async move {
+                transformer.transform_package(&package_data, &ConvertOptions::default(), &analysis("app")).await.unwrap()
+            }
+        };
+
+        let first = transform(files("export function twice(x) { return x * 2; }")).await;
+        let computed = transformer.cache.misses();
+        assert!(computed > 0);
+        assert_eq!(transformer.cache.hits(), 0);
+
+        // A rebuild reuses every transform and link result
+        let second = transform(files("export function twice(x) { return x * 2; }")).await;
+        assert_eq!(transformer.cache.hits(), computed);
+        assert_eq!(transformer.cache.misses(), computed);
+        let code = |transformed: &TransformedPackage| transformed.modules.iter().map(|m| m.code.clone()).collect::<Vec<_>>();
+        assert_eq!(code(&first), code(&second));
+
+        // A changed file is transformed again
+        let changed = transform(files("export function twice(x) { return x + x; }")).await;
+        assert!(code(&changed).concat().contains("x + x"));
+    }
+}
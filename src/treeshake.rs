@@ -0,0 +1,853 @@
+//! Tree shaking for ES module graphs
+//!
+//! Runs over the package's module graph before ES module syntax is
+//! converted. Starting from every export of the entries, used exports are
+//! followed through imports and re-exports, and each module keeps only the
+//! top-level statements that have side effects or that a kept statement
+//! refers to. Modules nothing uses are dropped when their package declares
+//! them free of side effects with `sideEffects` in package.json.
+//!
+//! CommonJS modules and modules that cannot be analysed are kept whole, and
+//! everything they require is treated as fully used.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use anyhow::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde_json::Value;
+use swc_common::{Span, Spanned};
+use swc_ecma_ast::*;
+use swc_ecma_visit::{Visit, VisitWith};
+
+use crate::js::{self, SourceEdits};
+use crate::resolver::Resolution;
+
+/// A module of the graph, before ES module conversion
+pub struct GraphModule<'a> {
+    pub path: &'a Path,
+    pub code: &'a str,
+    pub dependencies: &'a BTreeMap<String, Resolution>,
+    /// Whether loading the module may have effects beyond its exports
+    pub side_effects: bool,
+}
+
+/// Result of shaking a module graph
+#[derive(Debug)]
+pub struct TreeShaking {
+    /// Shaken code of each module, in graph order; `None` for modules the
+    /// bundle no longer needs
+    pub modules: Vec<Option<String>>,
+    /// Number of top-level statements removed
+    pub removed_statements: usize,
+}
+
+/// A package's `sideEffects` field
+#[derive(Debug)]
+pub enum SideEffects {
+    /// No field, or `true`: any module may have side effects
+    All,
+    /// `false`: no module has side effects
+    None,
+    /// Only modules matching the listed globs have side effects
+    Files(GlobSet),
+}
+
+impl SideEffects {
+    pub fn from_package_json(manifest: &Value) -> Self {
+        match manifest.get("sideEffects") {
+            Some(Value::Bool(false)) => Self::None,
+            Some(Value::Array(patterns)) => {
+                let mut builder = GlobSetBuilder::new();
+                for pattern in patterns.iter().filter_map(Value::as_str) {
+                    let pattern = pattern.trim_start_matches("./");
+                    // Patterns without a slash match the file name anywhere
+                    let pattern = if pattern.contains('/') {
+                        pattern.to_string()
+                    } else {
+                        format!("**/{}", pattern)
+                    };
+                    if let Ok(glob) = Glob::new(&pattern) {
+                        builder.add(glob);
+                    }
+                }
+                builder.build().map(Self::Files).unwrap_or(Self::All)
+            }
+            _ => Self::All,
+        }
+    }
+
+    /// Whether a module, given by its path inside the package, may have
+    /// side effects
+    pub fn has_side_effects(&self, path: &Path) -> bool {
+        match self {
+            Self::All => true,
+            Self::None => false,
+            Self::Files(globs) => globs.is_match(path),
+        }
+    }
+}
+
+/// Remove unused exports, statements and modules from a module graph
+pub fn tree_shake(modules: &[GraphModule], entries: &[usize]) -> TreeShaking {
+    let graph = Graph {
+        modules,
+        analyses: modules.iter().map(|module| analyze(module.path, module.code)).collect(),
+        index: modules.iter().enumerate().map(|(i, module)| (module.path, i)).collect(),
+    };
+
+    let mut state = State {
+        live: vec![false; modules.len()],
+        used: (0..modules.len()).map(|_| UsedExports::default()).collect(),
+        changed: false,
+    };
+    // Everything an entry exports is the package's public API
+    for &entry in entries.iter().filter(|&&entry| entry < modules.len()) {
+        state.use_all(entry);
+    }
+
+    // Used exports only grow, so marking until nothing changes converges
+    let mut live_items: Vec<HashSet<usize>> = vec![HashSet::new(); modules.len()];
+    loop {
+        state.changed = false;
+        for module in 0..modules.len() {
+            if state.live[module] {
+                live_items[module] = graph.mark(module, &mut state);
+            }
+        }
+        if !state.changed {
+            break;
+        }
+    }
+
+    let mut removed_statements = 0;
+    let shaken = (0..modules.len())
+        .map(|module| {
+            if !state.live[module] {
+                return None;
+            }
+            match &graph.analyses[module] {
+                Analysis::Opaque => Some(modules[module].code.to_string()),
+                Analysis::Esm(esm) => {
+                    let (code, removed) = graph.shake(module, esm, &live_items[module], &state);
+                    removed_statements += removed;
+                    Some(code)
+                }
+            }
+        })
+        .collect();
+
+    TreeShaking { modules: shaken, removed_statements }
+}
+
+/// What is known about a module's top-level structure
+enum Analysis {
+    /// CommonJS, unparseable, or using module features that cannot be
+    /// followed statically
+    Opaque,
+    Esm(EsmModule),
+}
+
+#[derive(Default)]
+struct EsmModule {
+    items: Vec<Item>,
+    imports: Vec<Import>,
+    /// Import index and imported name (`None` for a namespace) of each
+    /// imported binding
+    bindings: HashMap<String, (usize, Option<String>)>,
+    /// Items declaring each top-level name
+    declarers: HashMap<String, Vec<usize>>,
+    exports: HashMap<String, Export>,
+    /// `export *` statements
+    stars: Vec<Import>,
+    /// `export { ... }` statements
+    named_exports: Vec<NamedExports>,
+}
+
+/// A top-level statement that can be removed
+struct Item {
+    span: Span,
+    references: HashSet<String>,
+    /// Modules loaded with `require()` or `import()`
+    requires: Vec<String>,
+    side_effects: bool,
+}
+
+/// A statement loading another module
+struct Import {
+    span: Span,
+    specifier: String,
+}
+
+enum Export {
+    Item(usize),
+    Local(String),
+    ReExport { specifier: String, name: String },
+    Namespace(String),
+}
+
+struct NamedExports {
+    span: Span,
+    /// The `from '...'` module, if any
+    source: Option<(String, Span)>,
+    /// Exported name and source text of each specifier
+    specifiers: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+struct UsedExports {
+    all: bool,
+    names: HashSet<String>,
+}
+
+impl UsedExports {
+    fn contains(&self, name: &str) -> bool {
+        self.all || self.names.contains(name)
+    }
+}
+
+struct State {
+    live: Vec<bool>,
+    used: Vec<UsedExports>,
+    changed: bool,
+}
+
+impl State {
+    fn use_module(&mut self, module: usize) {
+        if !self.live[module] {
+            self.live[module] = true;
+            self.changed = true;
+        }
+    }
+
+    fn use_export(&mut self, module: usize, name: &str) {
+        self.use_module(module);
+        let used = &mut self.used[module];
+        if !used.all && used.names.insert(name.to_string()) {
+            self.changed = true;
+        }
+    }
+
+    fn use_all(&mut self, module: usize) {
+        self.use_module(module);
+        if !self.used[module].all {
+            self.used[module].all = true;
+            self.changed = true;
+        }
+    }
+}
+
+struct Graph<'a> {
+    modules: &'a [GraphModule<'a>],
+    analyses: Vec<Analysis>,
+    index: HashMap<&'a Path, usize>,
+}
+
+impl Graph<'_> {
+    /// Graph index of the module a specifier loads, if it is part of the
+    /// graph
+    fn target(&self, module: usize, specifier: &str) -> Option<usize> {
+        match self.modules[module].dependencies.get(specifier)? {
+            Resolution::Module(path) => self.index.get(path.as_path()).copied(),
+            _ => None,
+        }
+    }
+
+    /// Whether a module may provide an export through its own exports or
+    /// an `export *`
+    fn provides(&self, module: usize, name: &str, visited: &mut HashSet<usize>) -> bool {
+        if !visited.insert(module) {
+            return false;
+        }
+        match &self.analyses[module] {
+            Analysis::Opaque => true,
+            Analysis::Esm(esm) => {
+                esm.exports.contains_key(name)
+                    || esm.stars.iter().any(|star| {
+                        self.target(module, &star.specifier)
+                            .is_some_and(|target| self.provides(target, name, visited))
+                    })
+            }
+        }
+    }
+
+    /// Propagate a live module's used exports to its dependencies and
+    /// return the items it keeps
+    fn mark(&self, module: usize, state: &mut State) -> HashSet<usize> {
+        let esm = match &self.analyses[module] {
+            Analysis::Esm(esm) => esm,
+            Analysis::Opaque => {
+                for resolution in self.modules[module].dependencies.values() {
+                    if let Resolution::Module(path) = resolution {
+                        if let Some(&target) = self.index.get(path.as_path()) {
+                            state.use_all(target);
+                        }
+                    }
+                }
+                return HashSet::new();
+            }
+        };
+
+        // Loading a module with side effects runs them even when none of
+        // its exports are used
+        let sources = esm.imports.iter()
+            .chain(&esm.stars)
+            .map(|import| import.specifier.as_str())
+            .chain(esm.named_exports.iter().filter_map(|named| named.source.as_ref().map(|(s, _)| s.as_str())));
+        for specifier in sources {
+            if let Some(target) = self.target(module, specifier) {
+                if self.modules[target].side_effects {
+                    state.use_module(target);
+                }
+            }
+        }
+
+        let mut pending: Vec<usize> = esm.items.iter().enumerate()
+            .filter(|(_, item)| item.side_effects)
+            .map(|(index, _)| index)
+            .collect();
+
+        let requested: Vec<String> = if state.used[module].all {
+            for star in &esm.stars {
+                if let Some(target) = self.target(module, &star.specifier) {
+                    state.use_all(target);
+                }
+            }
+            esm.exports.keys().cloned().collect()
+        } else {
+            state.used[module].names.iter().cloned().collect()
+        };
+
+        for name in &requested {
+            match esm.exports.get(name) {
+                Some(Export::Item(index)) => pending.push(*index),
+                Some(Export::Local(local)) => self.use_local(module, esm, local, &mut pending, state),
+                Some(Export::ReExport { specifier, name }) => {
+                    if let Some(target) = self.target(module, specifier) {
+                        state.use_export(target, name);
+                    }
+                }
+                Some(Export::Namespace(specifier)) => {
+                    if let Some(target) = self.target(module, specifier) {
+                        state.use_all(target);
+                    }
+                }
+                // `export *` never re-exports a default
+                None if name != "default" => {
+                    for star in &esm.stars {
+                        if let Some(target) = self.target(module, &star.specifier) {
+                            if self.provides(target, name, &mut HashSet::new()) {
+                                state.use_export(target, name);
+                            }
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+
+        let mut live = HashSet::new();
+        while let Some(index) = pending.pop() {
+            if !live.insert(index) {
+                continue;
+            }
+            let item = &esm.items[index];
+            for name in &item.references {
+                self.use_local(module, esm, name, &mut pending, state);
+            }
+            for specifier in &item.requires {
+                if let Some(target) = self.target(module, specifier) {
+                    state.use_all(target);
+                }
+            }
+        }
+        live
+    }
+
+    /// Keep whatever a top-level name refers to: the items declaring it,
+    /// or the export an import binds it to
+    fn use_local(&self, module: usize, esm: &EsmModule, name: &str, pending: &mut Vec<usize>, state: &mut State) {
+        if let Some(declarers) = esm.declarers.get(name) {
+            pending.extend(declarers);
+        }
+        if let Some((import, imported)) = esm.bindings.get(name) {
+            if let Some(target) = self.target(module, &esm.imports[*import].specifier) {
+                match imported {
+                    Some(imported) => state.use_export(target, imported),
+                    None => state.use_all(target),
+                }
+            }
+        }
+    }
+
+    /// Remove a module's unused statements, imports and exports
+    fn shake(&self, module: usize, esm: &EsmModule, live_items: &HashSet<usize>, state: &State) -> (String, usize) {
+        let code = self.modules[module].code;
+        let used = &state.used[module];
+        let is_live = |specifier: &str| self.target(module, specifier).is_none_or(|target| state.live[target]);
+        let mut edits = SourceEdits::new();
+        let mut removed = 0;
+
+        for (index, item) in esm.items.iter().enumerate() {
+            if !live_items.contains(&index) {
+                edits.replace(item.span, "");
+                removed += 1;
+            }
+        }
+
+        for import in esm.imports.iter().chain(&esm.stars) {
+            if !is_live(&import.specifier) {
+                edits.replace(import.span, "");
+                removed += 1;
+            }
+        }
+
+        for named in &esm.named_exports {
+            let kept: Vec<&str> = named.specifiers.iter()
+                .filter(|(exported, _)| used.contains(exported))
+                .map(|(_, text)| text.as_str())
+                .collect();
+            if kept.len() == named.specifiers.len() {
+                continue;
+            }
+
+            let replacement = match (&named.source, kept.is_empty()) {
+                (None, true) => String::new(),
+                (None, false) => format!("export {{ {} }};", kept.join(", ")),
+                // Still load a re-exported module that has to run
+                (Some((specifier, source)), true) if is_live(specifier) => {
+                    format!("import {};", js::span_text(code, *source))
+                }
+                (Some(_), true) => String::new(),
+                (Some((_, source)), false) => {
+                    format!("export {{ {} }} from {};", kept.join(", "), js::span_text(code, *source))
+                }
+            };
+            if replacement.is_empty() {
+                removed += 1;
+            }
+            edits.replace(named.span, replacement);
+        }
+
+        (edits.apply(code), removed)
+    }
+}
+
+/// Collect a module's top-level structure, or decide it cannot be shaken
+fn analyze(path: &Path, code: &str) -> Analysis {
+    match analyze_module(path, code) {
+        Ok(Some(esm)) => Analysis::Esm(esm),
+        _ => Analysis::Opaque,
+    }
+}
+
+fn analyze_module(path: &Path, code: &str) -> Result<Option<EsmModule>> {
+    let program = js::parse_program(code, js::syntax_for_path(path))?;
+    let Program::Module(module) = &program else {
+        return Ok(None);
+    };
+    if !module.body.iter().any(|item| matches!(item, ModuleItem::ModuleDecl(_))) {
+        return Ok(None);
+    }
+
+    let mut esm = EsmModule::default();
+    for item in &module.body {
+        match item {
+            ModuleItem::Stmt(Stmt::Decl(decl)) => {
+                esm.push_item(decl.span(), decl, decl_names(decl), !is_pure_decl(decl));
+            }
+            ModuleItem::Stmt(stmt) => {
+                esm.push_item(stmt.span(), stmt, Vec::new(), !matches!(stmt, Stmt::Empty(_)));
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => {
+                if import.type_only {
+                    continue;
+                }
+                let index = esm.imports.len();
+                esm.imports.push(Import { span: import.span, specifier: import.src.value.to_string() });
+                for specifier in &import.specifiers {
+                    let (local, imported) = match specifier {
+                        ImportSpecifier::Named(s) if s.is_type_only => continue,
+                        ImportSpecifier::Named(s) => (
+                            &s.local,
+                            Some(s.imported.as_ref().map(export_name).unwrap_or_else(|| s.local.sym.to_string())),
+                        ),
+                        ImportSpecifier::Default(s) => (&s.local, Some("default".to_string())),
+                        ImportSpecifier::Namespace(s) => (&s.local, None),
+                    };
+                    esm.bindings.insert(local.sym.to_string(), (index, imported));
+                }
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export)) => {
+                let names = decl_names(&export.decl);
+                let index = esm.push_item(export.span, &export.decl, names.clone(), !is_pure_decl(&export.decl));
+                for name in names {
+                    esm.exports.insert(name, Export::Item(index));
+                }
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultDecl(export)) => {
+                let name = match &export.decl {
+                    DefaultDecl::Class(class) => class.ident.as_ref(),
+                    DefaultDecl::Fn(function) => function.ident.as_ref(),
+                    DefaultDecl::TsInterfaceDecl(_) => continue,
+                };
+                let pure = match &export.decl {
+                    DefaultDecl::Class(class) => is_pure_class(&class.class),
+                    _ => true,
+                };
+                let names = name.map(|ident| vec![ident.sym.to_string()]).unwrap_or_default();
+                let index = esm.push_item(export.span, &export.decl, names, !pure);
+                esm.exports.insert("default".to_string(), Export::Item(index));
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultExpr(export)) => {
+                let index = esm.push_item(export.span, &export.expr, Vec::new(), !is_pure(&export.expr));
+                esm.exports.insert("default".to_string(), Export::Item(index));
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportAll(export)) => {
+                if !export.type_only {
+                    esm.stars.push(Import { span: export.span, specifier: export.src.value.to_string() });
+                }
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(export)) => {
+                if export.type_only {
+                    continue;
+                }
+                let source = export.src.as_ref().map(|src| (src.value.to_string(), src.span));
+                let mut specifiers = Vec::new();
+                for specifier in &export.specifiers {
+                    let (exported, target) = match specifier {
+                        ExportSpecifier::Named(s) if s.is_type_only => continue,
+                        ExportSpecifier::Named(s) => {
+                            let orig = export_name(&s.orig);
+                            let exported = s.exported.as_ref().map(export_name).unwrap_or_else(|| orig.clone());
+                            let target = match &source {
+                                Some((specifier, _)) => Export::ReExport { specifier: specifier.clone(), name: orig },
+                                None => Export::Local(orig),
+                            };
+                            (exported, target)
+                        }
+                        ExportSpecifier::Namespace(s) => {
+                            let Some((specifier, _)) = &source else { continue };
+                            (export_name(&s.name), Export::Namespace(specifier.clone()))
+                        }
+                        ExportSpecifier::Default(s) => {
+                            let Some((specifier, _)) = &source else { continue };
+                            (
+                                s.exported.sym.to_string(),
+                                Export::ReExport { specifier: specifier.clone(), name: "default".to_string() },
+                            )
+                        }
+                    };
+                    specifiers.push((exported.clone(), js::span_text(code, specifier.span()).to_string()));
+                    esm.exports.insert(exported, target);
+                }
+                esm.named_exports.push(NamedExports { span: export.span, source, specifiers });
+            }
+            // TypeScript module forms are left alone
+            ModuleItem::ModuleDecl(_) => return Ok(None),
+        }
+    }
+
+    // Modules mixing in CommonJS or eval cannot be followed statically
+    let dynamic = esm.items.iter()
+        .flat_map(|item| &item.references)
+        .any(|name| {
+            matches!(name.as_str(), "module" | "exports" | "eval") && !esm.declarers.contains_key(name)
+        });
+    if dynamic {
+        return Ok(None);
+    }
+
+    Ok(Some(esm))
+}
+
+impl EsmModule {
+    fn push_item<N: VisitWith<ReferenceCollector>>(
+        &mut self,
+        span: Span,
+        node: &N,
+        declares: Vec<String>,
+        side_effects: bool,
+    ) -> usize {
+        let mut collector = ReferenceCollector::default();
+        node.visit_with(&mut collector);
+
+        let index = self.items.len();
+        for name in declares {
+            self.declarers.entry(name).or_default().push(index);
+        }
+        self.items.push(Item {
+            span,
+            references: collector.references,
+            requires: collector.requires,
+            side_effects,
+        });
+        index
+    }
+}
+
+fn export_name(name: &ModuleExportName) -> String {
+    match name {
+        ModuleExportName::Ident(ident) => ident.sym.to_string(),
+        ModuleExportName::Str(s) => s.value.to_string(),
+    }
+}
+
+/// Top-level names a declaration binds
+fn decl_names(decl: &Decl) -> Vec<String> {
+    match decl {
+        Decl::Fn(function) => vec![function.ident.sym.to_string()],
+        Decl::Class(class) => vec![class.ident.sym.to_string()],
+        Decl::Var(var) => {
+            let mut collector = BindingCollector::default();
+            for declarator in &var.decls {
+                declarator.name.visit_with(&mut collector);
+            }
+            collector.names
+        }
+        Decl::Using(using) => {
+            let mut collector = BindingCollector::default();
+            for declarator in &using.decls {
+                declarator.name.visit_with(&mut collector);
+            }
+            collector.names
+        }
+        Decl::TsEnum(e) => vec![e.id.sym.to_string()],
+        _ => Vec::new(),
+    }
+}
+
+fn is_pure_decl(decl: &Decl) -> bool {
+    match decl {
+        Decl::Fn(_) => true,
+        Decl::Class(class) => is_pure_class(&class.class),
+        Decl::Var(var) => var.decls.iter().all(|d| d.init.as_ref().is_none_or(|init| is_pure(init))),
+        Decl::Using(_) | Decl::TsEnum(_) | Decl::TsModule(_) => false,
+        Decl::TsInterface(_) | Decl::TsTypeAlias(_) => true,
+    }
+}
+
+/// Whether evaluating an expression cannot have observable effects.
+/// Calls, `new`, property reads and assignments are all assumed impure.
+fn is_pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(_) | Expr::Ident(_) | Expr::This(_) | Expr::Fn(_) | Expr::Arrow(_) => true,
+        Expr::Class(class) => is_pure_class(&class.class),
+        Expr::Tpl(tpl) => tpl.exprs.iter().all(|e| is_pure(e)),
+        Expr::Array(array) => array.elems.iter().flatten().all(|e| e.spread.is_none() && is_pure(&e.expr)),
+        Expr::Object(object) => object.props.iter().all(|prop| match prop {
+            PropOrSpread::Spread(_) => false,
+            PropOrSpread::Prop(prop) => match prop.as_ref() {
+                Prop::Shorthand(_) => true,
+                Prop::KeyValue(kv) => is_pure_key(&kv.key) && is_pure(&kv.value),
+                Prop::Getter(p) => is_pure_key(&p.key),
+                Prop::Setter(p) => is_pure_key(&p.key),
+                Prop::Method(p) => is_pure_key(&p.key),
+                Prop::Assign(_) => false,
+            },
+        }),
+        Expr::Unary(unary) => unary.op != UnaryOp::Delete && is_pure(&unary.arg),
+        Expr::Bin(bin) => is_pure(&bin.left) && is_pure(&bin.right),
+        Expr::Cond(cond) => is_pure(&cond.test) && is_pure(&cond.cons) && is_pure(&cond.alt),
+        Expr::Paren(paren) => is_pure(&paren.expr),
+        Expr::Seq(seq) => seq.exprs.iter().all(|e| is_pure(e)),
+        _ => false,
+    }
+}
+
+fn is_pure_key(key: &PropName) -> bool {
+    match key {
+        PropName::Computed(computed) => is_pure(&computed.expr),
+        _ => true,
+    }
+}
+
+fn is_pure_class(class: &Class) -> bool {
+    class.decorators.is_empty()
+        && class.super_class.as_ref().is_none_or(|super_class| matches!(super_class.as_ref(), Expr::Ident(_)))
+        && class.body.iter().all(|member| match member {
+            ClassMember::Constructor(_) | ClassMember::Empty(_) | ClassMember::TsIndexSignature(_) => true,
+            ClassMember::Method(method) => is_pure_key(&method.key),
+            ClassMember::PrivateMethod(_) => true,
+            ClassMember::ClassProp(prop) => {
+                prop.decorators.is_empty()
+                    && is_pure_key(&prop.key)
+                    && (!prop.is_static || prop.value.as_ref().is_none_or(|value| is_pure(value)))
+            }
+            ClassMember::PrivateProp(prop) => {
+                !prop.is_static || prop.value.as_ref().is_none_or(|value| is_pure(value))
+            }
+            ClassMember::StaticBlock(_) | ClassMember::AutoAccessor(_) => false,
+        })
+}
+
+/// Identifiers a statement may refer to, and modules it loads. Scopes are
+/// not tracked, so a name shadowed inside a function still counts.
+#[derive(Default)]
+struct ReferenceCollector {
+    references: HashSet<String>,
+    requires: Vec<String>,
+}
+
+impl Visit for ReferenceCollector {
+    fn visit_ident(&mut self, ident: &Ident) {
+        self.references.insert(ident.sym.to_string());
+    }
+
+    fn visit_member_prop(&mut self, prop: &MemberProp) {
+        if let MemberProp::Computed(computed) = prop {
+            computed.visit_with(self);
+        }
+    }
+
+    fn visit_prop_name(&mut self, name: &PropName) {
+        if let PropName::Computed(computed) = name {
+            computed.visit_with(self);
+        }
+    }
+
+    fn visit_call_expr(&mut self, call: &CallExpr) {
+        let loads = match &call.callee {
+            Callee::Import(_) => true,
+            Callee::Expr(callee) => matches!(callee.as_ref(), Expr::Ident(ident) if ident.sym == *"require"),
+            Callee::Super(_) => false,
+        };
+        if let (true, [argument]) = (loads, call.args.as_slice()) {
+            if let Expr::Lit(Lit::Str(s)) = argument.expr.as_ref() {
+                self.requires.push(s.value.to_string());
+            }
+        }
+        call.visit_children_with(self);
+    }
+}
+
+#[derive(Default)]
+struct BindingCollector {
+    names: Vec<String>,
+}
+
+impl Visit for BindingCollector {
+    fn visit_binding_ident(&mut self, ident: &BindingIdent) {
+        self.names.push(ident.id.sym.to_string());
+    }
+
+    // Default values are expressions, not bindings
+    fn visit_expr(&mut self, _: &Expr) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn shake(modules: &[(&str, &str, &[(&str, &str)], bool)]) -> TreeShaking {
+        let paths: Vec<PathBuf> = modules.iter().map(|(path, ..)| PathBuf::from(path)).collect();
+        let dependencies: Vec<BTreeMap<String, Resolution>> = modules.iter()
+            .map(|(_, _, deps, _)| {
+                deps.iter()
+                    .map(|(specifier, path)| (specifier.to_string(), Resolution::Module(PathBuf::from(path))))
+                    .collect()
+            })
+            .collect();
+        let graph: Vec<GraphModule> = modules.iter().enumerate()
+            .map(|(i, (_, code, _, side_effects))| GraphModule {
+                path: &paths[i],
+                code,
+                dependencies: &dependencies[i],
+                side_effects: *side_effects,
+            })
+            .collect();
+        tree_shake(&graph, &[0])
+    }
+
+    #[test]
+    fn test_unused_exports_removed() {
+        let result = shake(&[
+            ("index.mjs", "import { used } from './lib.mjs';\nexport const answer = used();", &[("./lib.mjs", "lib.mjs")], true),
+            (
+                "lib.mjs",
+                "const helper = () => 1;\nexport function used() { return helper(); }\nexport function unused() { return 2; }\nconst orphan = 3;",
+                &[],
+                false,
+            ),
+        ]);
+
+        let lib = result.modules[1].as_ref().unwrap();
+        assert!(lib.contains("function used"));
+        assert!(lib.contains("const helper"));
+        assert!(!lib.contains("unused"));
+        assert!(!lib.contains("orphan"));
+        assert_eq!(result.removed_statements, 2);
+    }
+
+    #[test]
+    fn test_side_effect_free_modules_dropped() {
+        let result = shake(&[
+            (
+                "index.mjs",
+                "import { a } from './pure.mjs';\nimport './setup.mjs';\nexport default 1;",
+                &[("./pure.mjs", "pure.mjs"), ("./setup.mjs", "setup.mjs")],
+                true,
+            ),
+            ("pure.mjs", "export const a = 1;", &[], false),
+            ("setup.mjs", "window.ready = true;", &[], true),
+        ]);
+
+        assert!(!result.modules[0].as_ref().unwrap().contains("pure.mjs"));
+        assert!(result.modules[1].is_none());
+        assert!(result.modules[2].is_some());
+    }
+
+    #[test]
+    fn test_re_exports_followed() {
+        let result = shake(&[
+            (
+                "index.mjs",
+                "import { b } from './barrel.mjs';\nconsole.log(b);",
+                &[("./barrel.mjs", "barrel.mjs")],
+                true,
+            ),
+            (
+                "barrel.mjs",
+                "export { a } from './a.mjs';\nexport * from './b.mjs';",
+                &[("./a.mjs", "a.mjs"), ("./b.mjs", "b.mjs")],
+                false,
+            ),
+            ("a.mjs", "export const a = 1;", &[], false),
+            ("b.mjs", "export const b = 2;\nexport const c = 3;", &[], false),
+        ]);
+
+        assert!(result.modules[2].is_none());
+        let barrel = result.modules[1].as_ref().unwrap();
+        assert!(!barrel.contains("./a.mjs"));
+        assert!(barrel.contains("export * from './b.mjs'"));
+        let b = result.modules[3].as_ref().unwrap();
+        assert!(b.contains("const b"));
+        assert!(!b.contains("const c"));
+    }
+
+    #[test]
+    fn test_commonjs_kept_whole() {
+        let result = shake(&[
+            ("index.js", "var lib = require('./lib.mjs');\nfunction unused() {}", &[("./lib.mjs", "lib.mjs")], true),
+            ("lib.mjs", "export const a = 1;\nexport const b = 2;", &[], false),
+        ]);
+
+        assert!(result.modules[0].as_ref().unwrap().contains("function unused"));
+        let lib = result.modules[1].as_ref().unwrap();
+        assert!(lib.contains("const a") && lib.contains("const b"));
+    }
+
+    #[test]
+    fn test_side_effects_field() {
+        let all = SideEffects::from_package_json(&serde_json::json!({}));
+        assert!(all.has_side_effects(Path::new("index.js")));
+
+        let none = SideEffects::from_package_json(&serde_json::json!({ "sideEffects": false }));
+        assert!(!none.has_side_effects(Path::new("index.js")));
+
+        let files = SideEffects::from_package_json(&serde_json::json!({ "sideEffects": ["*.css", "./src/polyfill.js"] }));
+        assert!(files.has_side_effects(Path::new("dist/theme.css")));
+        assert!(files.has_side_effects(Path::new("src/polyfill.js")));
+        assert!(!files.has_side_effects(Path::new("src/index.js")));
+    }
+}
@@ -0,0 +1,287 @@
+//! WebAssembly modules shipped by packages
+//!
+//! Packages built with wasm-pack, emscripten and similar tools load a
+//! `.wasm` file next to their JavaScript, either with
+//! `fs.readFileSync(path.join(__dirname, 'x.wasm'))` or with
+//! `fetch(new URL('x.wasm', import.meta.url))`. Neither path exists once the
+//! package is an OutSystems script, so the loading code is rewritten to go
+//! through the `wasm` polyfill. The bundler then embeds each module as
+//! base64 or emits it as a separate resource, according to `bundle.wasm`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use swc_common::Spanned;
+use swc_ecma_ast::*;
+use swc_ecma_parser::Syntax;
+use swc_ecma_visit::{Visit, VisitWith};
+
+use crate::assets::resolve_relative;
+use crate::converter::FileContent;
+use crate::js::{self, SourceEdits};
+
+/// Polyfill that loads and instantiates bundled modules
+pub const WASM_POLYFILL: &str = "wasm";
+
+/// Global the bundle fills with module sources before any module loads
+const WASM_REGISTRY: &str = "__paktoWasmModules";
+
+/// Whether a path names a WebAssembly binary
+pub fn is_wasm(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("wasm"))
+}
+
+/// Name a module is registered under: its package path with forward slashes
+pub fn module_name(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// Expression producing the embedded bytes of a module as a `Uint8Array`
+pub fn bytes_expression(path: &Path) -> String {
+    format!("WasmPolyfill.bytes({})", serde_json::Value::String(module_name(path)))
+}
+
+/// Code that registers module sources for the polyfill. Each source is
+/// either `{ "base64": ... }` or `{ "url": ... }`.
+pub fn registry_code(sources: &[(String, serde_json::Value)]) -> String {
+    let mut code = String::from("(function(modules) {\n");
+    for (name, source) in sources {
+        code.push_str(&format!(
+            "  modules[{}] = {};\n",
+            serde_json::Value::String(name.clone()),
+            source
+        ));
+    }
+    code.push_str(&format!(
+        "}})(window.{registry} = window.{registry} || {{}});\n",
+        registry = WASM_REGISTRY
+    ));
+    code
+}
+
+/// A module with its WebAssembly loading rewritten
+#[derive(Debug)]
+pub struct WasmLoading {
+    pub code: String,
+    /// WebAssembly files the module loads
+    pub modules: Vec<PathBuf>,
+}
+
+/// Rewrite `fetch()` and streaming instantiation of package `.wasm` files
+/// to load them through the `wasm` polyfill
+pub fn rewrite_wasm_loading(
+    code: &str,
+    syntax: Syntax,
+    module_path: &Path,
+    files: &HashMap<PathBuf, FileContent>,
+) -> Result<WasmLoading> {
+    if !code.contains(".wasm") {
+        return Ok(WasmLoading { code: code.to_string(), modules: Vec::new() });
+    }
+
+    let program = js::parse_program(code, syntax)?;
+
+    let mut rewriter = LoadingRewriter {
+        module_path,
+        files,
+        edits: SourceEdits::new(),
+        modules: Vec::new(),
+    };
+    program.visit_with(&mut rewriter);
+
+    rewriter.modules.sort();
+    rewriter.modules.dedup();
+
+    Ok(WasmLoading {
+        code: rewriter.edits.apply(code),
+        modules: rewriter.modules,
+    })
+}
+
+/// Visitor that redirects WebAssembly loading to the polyfill
+struct LoadingRewriter<'a> {
+    module_path: &'a Path,
+    files: &'a HashMap<PathBuf, FileContent>,
+    edits: SourceEdits,
+    modules: Vec<PathBuf>,
+}
+
+impl LoadingRewriter<'_> {
+    /// Package file referenced by a URL expression: a string literal,
+    /// `new URL('x.wasm', import.meta.url)` or its `.href`
+    fn wasm_reference(&self, expr: &Expr) -> Option<PathBuf> {
+        match expr {
+            Expr::Paren(paren) => self.wasm_reference(&paren.expr),
+            Expr::Lit(Lit::Str(s)) => self.resolve(&s.value),
+            Expr::Member(member) if matches!(&member.prop, MemberProp::Ident(prop) if prop.sym == *"href") => {
+                self.wasm_reference(&member.obj)
+            }
+            Expr::New(new) => {
+                let is_url = matches!(new.callee.as_ref(), Expr::Ident(ident) if ident.sym == *"URL");
+                let args = new.args.as_deref().unwrap_or_default();
+                match (is_url, args.first(), args.get(1)) {
+                    (true, Some(specifier), Some(base)) if is_import_meta_url(&base.expr) => {
+                        match specifier.expr.as_ref() {
+                            Expr::Lit(Lit::Str(s)) => self.resolve(&s.value),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Resolve a URL relative to the module, keeping it only if it names a
+    /// WebAssembly file in the package
+    fn resolve(&self, url: &str) -> Option<PathBuf> {
+        let relative = if url.starts_with("./") || url.starts_with("../") {
+            url.to_string()
+        } else if url.contains(':') || url.starts_with('/') {
+            return None;
+        } else {
+            format!("./{}", url)
+        };
+
+        let path = resolve_relative(self.module_path, &relative)?;
+        (is_wasm(&path) && self.files.contains_key(&path)).then_some(path)
+    }
+
+    /// File fetched by a `fetch(url)` call
+    fn fetched_file(&self, expr: &Expr) -> Option<PathBuf> {
+        match expr {
+            Expr::Paren(paren) => self.fetched_file(&paren.expr),
+            Expr::Call(call) => self.fetched_by(call),
+            _ => None,
+        }
+    }
+
+    fn fetched_by(&self, call: &CallExpr) -> Option<PathBuf> {
+        if !is_global_call(call, "fetch") {
+            return None;
+        }
+        call.args.first()
+            .filter(|arg| arg.spread.is_none())
+            .and_then(|arg| self.wasm_reference(&arg.expr))
+    }
+
+    fn polyfill_call(&mut self, method: &str, path: PathBuf) -> String {
+        let call = format!("WasmPolyfill.{}({}", method, serde_json::Value::String(module_name(&path)));
+        self.modules.push(path);
+        call
+    }
+}
+
+impl Visit for LoadingRewriter<'_> {
+    fn visit_call_expr(&mut self, call: &CallExpr) {
+        // WebAssembly.instantiateStreaming(fetch(url), imports) keeps its
+        // remaining arguments, only the callee and source change
+        let streaming = webassembly_method(call)
+            .and_then(|method| match method {
+                "instantiateStreaming" => Some("instantiate"),
+                "compileStreaming" => Some("compile"),
+                _ => None,
+            });
+        if let (Some(method), Some(source)) = (streaming, call.args.first()) {
+            let path = self.fetched_file(&source.expr)
+                .or_else(|| self.wasm_reference(&source.expr));
+            if let Some(path) = path {
+                let replacement = self.polyfill_call(method, path);
+                self.edits.replace_between(call.span.lo, source.expr.span().hi, replacement);
+                for arg in &call.args[1..] {
+                    arg.visit_with(self);
+                }
+                return;
+            }
+        }
+
+        if let Some(path) = self.fetched_by(call) {
+            let replacement = format!("{})", self.polyfill_call("response", path));
+            self.edits.replace(call.span, replacement);
+            return;
+        }
+
+        call.visit_children_with(self);
+    }
+}
+
+/// Name of the `WebAssembly` method a call invokes
+fn webassembly_method(call: &CallExpr) -> Option<&str> {
+    let Callee::Expr(callee) = &call.callee else { return None };
+    let Expr::Member(member) = callee.as_ref() else { return None };
+    if !matches!(member.obj.as_ref(), Expr::Ident(obj) if obj.sym == *"WebAssembly") {
+        return None;
+    }
+    match &member.prop {
+        MemberProp::Ident(prop) => Some(prop.sym.as_ref()),
+        _ => None,
+    }
+}
+
+/// Whether a call invokes a global function by name, e.g. `fetch(...)`
+fn is_global_call(call: &CallExpr, name: &str) -> bool {
+    matches!(&call.callee, Callee::Expr(callee)
+        if matches!(callee.as_ref(), Expr::Ident(ident) if ident.sym == *name))
+}
+
+/// Whether an expression is `import.meta.url`
+fn is_import_meta_url(expr: &Expr) -> bool {
+    matches!(expr, Expr::Member(member)
+        if matches!(member.obj.as_ref(), Expr::MetaProp(meta) if meta.kind == MetaPropKind::ImportMeta)
+            && matches!(&member.prop, MemberProp::Ident(prop) if prop.sym == *"url"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> HashMap<PathBuf, FileContent> {
+        let mut files = HashMap::new();
+        files.insert(PathBuf::from("pkg/lib_bg.wasm"), FileContent::Binary(vec![0, 97, 115, 109]));
+        files
+    }
+
+    fn rewrite(code: &str) -> WasmLoading {
+        let syntax = js::syntax_for_path(Path::new("index.mjs"));
+        rewrite_wasm_loading(code, syntax, Path::new("pkg/lib.js"), &files()).unwrap()
+    }
+
+    #[test]
+    fn test_rewrites_streaming_instantiation() {
+        let code = "const { instance } = await WebAssembly.instantiateStreaming(fetch(new URL('lib_bg.wasm', import.meta.url)), imports);";
+        let result = rewrite(code);
+        assert_eq!(result.modules, vec![PathBuf::from("pkg/lib_bg.wasm")]);
+        assert_eq!(
+            result.code,
+            "const { instance } = await WasmPolyfill.instantiate(\"pkg/lib_bg.wasm\", imports);"
+        );
+    }
+
+    #[test]
+    fn test_rewrites_fetch() {
+        let code = "fetch('./lib_bg.wasm').then(r => r.arrayBuffer()); fetch('/api/data');";
+        let result = rewrite(code);
+        assert!(result.code.starts_with("WasmPolyfill.response(\"pkg/lib_bg.wasm\").then("));
+        assert!(result.code.contains("fetch('/api/data')"));
+    }
+
+    #[test]
+    fn test_ignores_files_outside_package() {
+        let result = rewrite("WebAssembly.instantiateStreaming(fetch('other.wasm'));");
+        assert!(result.modules.is_empty());
+        assert!(result.code.contains("instantiateStreaming"));
+    }
+
+    #[test]
+    fn test_registry_code() {
+        let code = registry_code(&[(
+            "pkg/lib_bg.wasm".to_string(),
+            serde_json::json!({ "base64": "AGFzbQ==" }),
+        )]);
+        assert!(code.contains("modules[\"pkg/lib_bg.wasm\"] = {\"base64\":\"AGFzbQ==\"};"));
+        assert!(code.contains("window.__paktoWasmModules = window.__paktoWasmModules || {}"));
+    }
+}